@@ -0,0 +1,184 @@
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::Multiaddr;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Decides which remotes may connect to the listeners of a [`Node`](crate::Node), see [`Node::with_address_filter`](crate::Node::with_address_filter).
+///
+/// A remote is rejected if its IP is in any of the denied networks, or if there are allowed networks and its IP is in none of them.
+/// Remotes without an IP address, e.g. on a memory transport, are always accepted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl AddressFilter {
+    /// Only accept remotes from the given network, in addition to other allowed networks.
+    pub fn allow(mut self, network: Cidr) -> Self {
+        self.allow.push(network);
+
+        self
+    }
+
+    /// Reject remotes from the given network, even if it is part of an allowed network.
+    pub fn deny(mut self, network: Cidr) -> Self {
+        self.deny.push(network);
+
+        self
+    }
+
+    pub fn is_allowed(&self, remote_address: &Multiaddr) -> bool {
+        let ip = match remote_address.iter().find_map(|protocol| match protocol {
+            Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+            Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        }) {
+            Some(ip) => ip,
+            None => return true,
+        };
+
+        self.is_ip_allowed(ip)
+    }
+
+    pub(crate) fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))
+    }
+}
+
+/// An IPv4 or IPv6 network in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`.
+///
+/// A single address without a prefix length denotes a network of just that address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Invalid network {0}, expected CIDR notation like 10.0.0.0/8")]
+pub struct InvalidCidr(String);
+
+impl Cidr {
+    pub fn new(address: IpAddr, prefix_length: u8) -> Result<Self, InvalidCidr> {
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        if prefix_length > max {
+            return Err(InvalidCidr(format!("{address}/{prefix_length}")));
+        }
+
+        Ok(Self {
+            address,
+            prefix_length,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_length))
+                    .unwrap_or(0);
+
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_length))
+                    .unwrap_or(0);
+
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            // IPv4-mapped IPv6 addresses are matched against IPv4 networks.
+            (IpAddr::V4(_), IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+                Some(ip) => self.contains(IpAddr::V4(ip)),
+                None => false,
+            },
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCidr(s.to_owned());
+
+        let (address, prefix_length) = match s.split_once('/') {
+            Some((address, prefix_length)) => {
+                let address = address.parse::<IpAddr>().map_err(|_| invalid())?;
+                let prefix_length = prefix_length.parse::<u8>().map_err(|_| invalid())?;
+
+                (address, prefix_length)
+            }
+            None => {
+                let address = s.parse::<IpAddr>().map_err(|_| invalid())?;
+                let prefix_length = if address.is_ipv4() { 32 } else { 128 };
+
+                (address, prefix_length)
+            }
+        };
+
+        Cidr::new(address, prefix_length).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_contains_addresses_within_prefix() {
+        let network = "10.1.0.0/16".parse::<Cidr>().unwrap();
+
+        assert!(network.contains(ip("10.1.255.3")));
+        assert!(!network.contains(ip("10.2.0.1")));
+        assert!(network.contains(ip("::ffff:10.1.0.1")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        assert!("fd00::/8".parse::<Cidr>().unwrap().contains(ip("fd12::1")));
+        assert!(!"fd00::/8".parse::<Cidr>().unwrap().contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn invalid_cidr_is_rejected() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert_eq!(
+            "192.168.1.1".parse::<Cidr>().unwrap().to_string(),
+            "192.168.1.1/32"
+        );
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let filter = AddressFilter::default()
+            .allow("10.0.0.0/8".parse().unwrap())
+            .deny("10.66.0.0/16".parse().unwrap());
+
+        assert!(filter.is_ip_allowed(ip("10.1.2.3")));
+        assert!(!filter.is_ip_allowed(ip("10.66.2.3")));
+        assert!(!filter.is_ip_allowed(ip("192.168.1.1")));
+        assert!(filter.is_allowed(&"/memory/1234".parse().unwrap()));
+        assert!(!filter.is_allowed(&"/ip4/192.168.1.1/tcp/80".parse().unwrap()));
+    }
+}
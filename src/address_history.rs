@@ -0,0 +1,91 @@
+use crate::multiaddress_ext::MultiaddrExt as _;
+use crate::stats::MAX_RETAINED_DISCONNECTED_PEERS;
+use libp2p_core::{Multiaddr, PeerId};
+use std::collections::{HashMap, VecDeque};
+
+/// The remote address each peer last connected from, see [`Event::AddressChanged`](crate::Event::AddressChanged).
+///
+/// Addresses of disconnected peers are retained like their statistics, i.e. for the [`MAX_RETAINED_DISCONNECTED_PEERS`] most recently disconnected peers.
+#[derive(Default)]
+pub(crate) struct AddressHistory {
+    addresses: HashMap<PeerId, Multiaddr>,
+    /// Peers without a connection, the one that disconnected first at the front.
+    disconnected: VecDeque<PeerId>,
+}
+
+impl AddressHistory {
+    /// Remembers the address of the first connection to a peer that had none, returning the previous address if it points to a different host.
+    ///
+    /// Addresses without a host are not remembered, see [`MultiaddrExt::host`](crate::multiaddress_ext::MultiaddrExt::host).
+    pub(crate) fn connected(&mut self, peer: PeerId, address: Multiaddr) -> Option<Multiaddr> {
+        self.disconnected
+            .retain(|disconnected| *disconnected != peer);
+
+        let host = address.host()?;
+        let previous = self.addresses.insert(peer, address)?;
+
+        if previous.host() == Some(host) {
+            return None;
+        }
+
+        Some(previous)
+    }
+
+    pub(crate) fn disconnected(&mut self, peer: PeerId) {
+        if !self.addresses.contains_key(&peer) {
+            return;
+        }
+
+        self.disconnected.push_back(peer);
+
+        while self.disconnected.len() > MAX_RETAINED_DISCONNECTED_PEERS {
+            if let Some(peer) = self.disconnected.pop_front() {
+                self.addresses.remove(&peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_different_host_counts_as_change() {
+        let mut history = AddressHistory::default();
+        let peer = PeerId::random();
+
+        assert_eq!(
+            history.connected(peer, "/ip4/1.2.3.4/tcp/4001".parse().unwrap()),
+            None
+        );
+        assert_eq!(
+            history.connected(peer, "/ip4/1.2.3.4/tcp/4002".parse().unwrap()),
+            None
+        );
+        assert_eq!(
+            history.connected(peer, "/ip4/5.6.7.8/tcp/4001".parse().unwrap()),
+            Some("/ip4/1.2.3.4/tcp/4002".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn addresses_of_disconnected_peers_are_pruned() {
+        let mut history = AddressHistory::default();
+        let first = PeerId::random();
+        history.connected(first, "/ip4/1.2.3.4/tcp/4001".parse().unwrap());
+        history.disconnected(first);
+
+        for _ in 0..MAX_RETAINED_DISCONNECTED_PEERS {
+            let peer = PeerId::random();
+            history.connected(peer, "/ip4/1.2.3.4/tcp/4001".parse().unwrap());
+            history.disconnected(peer);
+        }
+
+        assert_eq!(history.addresses.len(), MAX_RETAINED_DISCONNECTED_PEERS);
+        assert_eq!(
+            history.connected(first, "/ip4/5.6.7.8/tcp/4001".parse().unwrap()),
+            None
+        );
+    }
+}
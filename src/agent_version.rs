@@ -0,0 +1,69 @@
+use anyhow::{bail, Context as _, Result};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const PROTOCOL: &str = "/libp2p-xtra/agent-version/1.0.0";
+
+/// The maximum length of an agent version in bytes.
+pub const MAX_AGENT_VERSION_SIZE: usize = 255;
+
+/// Sends our agent version to the other node as a single length byte followed by the UTF-8 encoded version.
+pub(crate) async fn send<S>(mut stream: S, agent_version: &str) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    if agent_version.len() > MAX_AGENT_VERSION_SIZE {
+        bail!(
+            "Agent version of {} bytes exceeds maximum of {MAX_AGENT_VERSION_SIZE} bytes",
+            agent_version.len()
+        );
+    }
+
+    stream.write_all(&[agent_version.len() as u8]).await?;
+    stream.write_all(agent_version.as_bytes()).await?;
+    stream.flush().await?;
+    stream.close().await?;
+
+    Ok(())
+}
+
+/// Reads the agent version sent by the other node.
+pub(crate) async fn receive<S>(mut stream: S) -> Result<String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut len = [0u8; 1];
+    stream
+        .read_exact(&mut len)
+        .await
+        .context("Failed to read length of agent version")?;
+
+    let mut agent_version = vec![0u8; len[0] as usize];
+    stream
+        .read_exact(&mut agent_version)
+        .await
+        .context("Failed to read agent version")?;
+
+    String::from_utf8(agent_version).context("Agent version is not UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+
+    #[tokio::test]
+    async fn agent_version_round_trips() {
+        let mut buffer = Cursor::new(Vec::new());
+        send(&mut buffer, "my-daemon/1.2.3").await.unwrap();
+
+        buffer.set_position(0);
+        assert_eq!(receive(&mut buffer).await.unwrap(), "my-daemon/1.2.3");
+    }
+
+    #[tokio::test]
+    async fn oversized_agent_version_is_not_sent() {
+        let mut buffer = Cursor::new(Vec::new());
+
+        assert!(send(&mut buffer, &"x".repeat(256)).await.is_err());
+    }
+}
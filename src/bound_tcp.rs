@@ -0,0 +1,368 @@
+use crate::compat::Compat;
+use crate::tcp_options::TcpOptions;
+use futures::future::{BoxFuture, Pending};
+use futures::stream::BoxStream;
+use futures::FutureExt;
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::transport::{ListenerEvent, TransportError};
+use libp2p_core::{Multiaddr, Transport};
+use socket2::SockRef;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpSocket;
+
+/// A transport that dials `/ip4` and `/ip6` TCP addresses from a chosen local address.
+///
+/// Multi-homed servers or hosts that must only egress through a VPN interface bind their outbound connections to the address of that interface.
+/// The source address is configured for all dials with [`BoundTcpTransport::with_source`] and can be overridden for a single dial with [`BoundTcpTransport::bind_next_dial`].
+/// Without a source address, the operating system picks one as usual.
+/// On Linux, dials can also be bound to an interface with [`BoundTcpTransport::with_interface`], which takes effect even if the routing table would pick another one.
+/// Socket options such as `TCP_NODELAY` or the buffer sizes are configured with [`BoundTcpTransport::with_options`].
+/// The transport can only dial, listening is not supported.
+#[derive(Clone, Default)]
+pub struct BoundTcpTransport {
+    source: Option<IpAddr>,
+    source_port: Option<u16>,
+    options: TcpOptions,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    interface: Option<String>,
+    next_dials: Arc<Mutex<HashMap<Multiaddr, NextDial>>>,
+    next_binding: Arc<AtomicU64>,
+}
+
+struct NextDial {
+    binding: u64,
+    source: IpAddr,
+}
+
+/// Keeps the binding of [`BoundTcpTransport::bind_next_dial`] in place until dropped.
+///
+/// Dropping it removes the binding if no dial used it, so it cannot apply to a later, unrelated dial of the same address.
+#[must_use = "the binding is removed once this is dropped"]
+pub struct NextDialBinding {
+    next_dials: Arc<Mutex<HashMap<Multiaddr, NextDial>>>,
+    address: Multiaddr,
+    binding: u64,
+}
+
+impl Drop for NextDialBinding {
+    fn drop(&mut self) {
+        let mut next_dials = self.next_dials.lock().expect("not poisoned");
+
+        // The binding might have been used and replaced by a newer one in the meantime.
+        if next_dials
+            .get(&self.address)
+            .map_or(false, |next_dial| next_dial.binding == self.binding)
+        {
+            next_dials.remove(&self.address);
+        }
+    }
+}
+
+impl BoundTcpTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds all outbound connections to the given local address.
+    pub fn with_source(mut self, source: IpAddr) -> Self {
+        self.source = Some(source);
+
+        self
+    }
+
+    /// Binds all outbound connections to the given local port, typically the port the node listens on.
+    ///
+    /// Remote peers then observe the listen port as the source of the connection, so observed external addresses become dialable through NATs that preserve ports.
+    /// Binding to a port that is already in use requires [`TcpOptions::with_port_reuse`] on both this transport and the listener.
+    pub fn with_source_port(mut self, port: u16) -> Self {
+        self.source_port = Some(port);
+
+        self
+    }
+
+    /// Binds all outbound connections to the network interface of the given name, e.g. `wg0`, through `SO_BINDTODEVICE`.
+    ///
+    /// Connections then only leave through that interface, even if the routing table would send them elsewhere.
+    /// Dials fail if the interface does not exist. On Linux before 5.7, this requires `CAP_NET_RAW`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn with_interface(mut self, interface: impl Into<String>) -> Self {
+        self.interface = Some(interface.into());
+
+        self
+    }
+
+    /// Applies the given socket options to all outbound connections.
+    pub fn with_options(mut self, options: TcpOptions) -> Self {
+        self.options = options;
+
+        self
+    }
+
+    /// Binds the next dial of the given address to the given local address instead of the one set with [`BoundTcpTransport::with_source`].
+    ///
+    /// The transport is cloned into the [`Node`](crate::Node), so keep a clone around to call this before sending [`Connect`](crate::Connect) with the same address.
+    /// The binding applies to the first dial of exactly this address while the returned [`NextDialBinding`] is alive.
+    /// Hold on to it until the `Connect` returns, and drop it afterwards so an unused binding does not linger.
+    pub fn bind_next_dial(&self, address: Multiaddr, source: IpAddr) -> NextDialBinding {
+        let binding = self.next_binding.fetch_add(1, Ordering::Relaxed);
+        self.next_dials
+            .lock()
+            .expect("not poisoned")
+            .insert(address.clone(), NextDial { binding, source });
+
+        NextDialBinding {
+            next_dials: self.next_dials.clone(),
+            address,
+            binding,
+        }
+    }
+
+    fn source_for(&self, address: &Multiaddr) -> Option<IpAddr> {
+        self.next_dials
+            .lock()
+            .expect("not poisoned")
+            .remove(address)
+            .map(|next_dial| next_dial.source)
+            .or(self.source)
+    }
+}
+
+impl Transport for BoundTcpTransport {
+    type Output = BoundTcpStream;
+    type Error = io::Error;
+    type Listener =
+        BoxStream<'static, Result<ListenerEvent<Self::ListenerUpgrade, Self::Error>, Self::Error>>;
+    type ListenerUpgrade = Pending<Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        let target = match socket_address(&addr) {
+            Some(target) => target,
+            None => return Err(TransportError::MultiaddrNotSupported(addr)),
+        };
+        let source = self.source_for(&addr);
+        let source_port = self.source_port;
+        let options = self.options;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let interface = self.interface.clone();
+
+        Ok(async move {
+            let socket = match target {
+                SocketAddr::V4(_) => TcpSocket::new_v4()?,
+                SocketAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            options.apply(SockRef::from(&socket), target.is_ipv4())?;
+
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            if let Some(interface) = &interface {
+                SockRef::from(&socket).bind_device(Some(interface.as_bytes()))?;
+            }
+
+            if let Some(source) = source {
+                if source.is_ipv4() != target.is_ipv4() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Cannot dial {target} from {source} of a different address family"),
+                    ));
+                }
+            }
+
+            if source.is_some() || source_port.is_some() {
+                let ip = source.unwrap_or(match target {
+                    SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                });
+
+                socket.bind(SocketAddr::new(ip, source_port.unwrap_or(0)))?;
+            }
+
+            let stream = socket.connect(target).await?;
+
+            Ok(Compat::new(stream))
+        }
+        .boxed())
+    }
+
+    fn dial_as_listener(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        self.dial(addr)
+    }
+
+    fn address_translation(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+/// A connection established by [`BoundTcpTransport`].
+pub type BoundTcpStream = Compat<tokio::net::TcpStream>;
+
+/// Extracts the socket address to dial from an address like `/ip4/<ip>/tcp/<port>`, optionally followed by `/p2p/<peer-id>`.
+fn socket_address(addr: &Multiaddr) -> Option<SocketAddr> {
+    let mut protocols = addr.iter();
+
+    let ip = match protocols.next()? {
+        Protocol::Ip4(ip) => IpAddr::V4(ip),
+        Protocol::Ip6(ip) => IpAddr::V6(ip),
+        _ => return None,
+    };
+    let port = match protocols.next()? {
+        Protocol::Tcp(port) => port,
+        _ => return None,
+    };
+
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => Some(SocketAddr::new(ip, port)),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn rejects_dns_address() {
+        let result = BoundTcpTransport::new().dial("/dns/example.com/tcp/4001".parse().unwrap());
+
+        assert!(matches!(
+            result,
+            Err(TransportError::MultiaddrNotSupported(_))
+        ))
+    }
+
+    // Other platforms only route 127.0.0.1 to the loopback interface by default.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn binds_dials_to_source_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!(
+            "/ip4/127.0.0.1/tcp/{}",
+            listener.local_addr().unwrap().port()
+        )
+        .parse::<Multiaddr>()
+        .unwrap();
+        let transport = BoundTcpTransport::new().with_source("127.0.0.2".parse().unwrap());
+
+        transport
+            .clone()
+            .dial(address.clone())
+            .unwrap()
+            .await
+            .unwrap();
+        let (_, remote) = listener.accept().await.unwrap();
+        assert_eq!(remote.ip(), "127.0.0.2".parse::<IpAddr>().unwrap());
+
+        let binding = transport.bind_next_dial(address.clone(), "127.0.0.3".parse().unwrap());
+        transport
+            .clone()
+            .dial(address.clone())
+            .unwrap()
+            .await
+            .unwrap();
+        drop(binding);
+        let (_, remote) = listener.accept().await.unwrap();
+        assert_eq!(remote.ip(), "127.0.0.3".parse::<IpAddr>().unwrap());
+
+        transport
+            .clone()
+            .dial(address.clone())
+            .unwrap()
+            .await
+            .unwrap();
+        let (_, remote) = listener.accept().await.unwrap();
+        assert_eq!(remote.ip(), "127.0.0.2".parse::<IpAddr>().unwrap());
+
+        drop(transport.bind_next_dial(address.clone(), "127.0.0.3".parse().unwrap()));
+        transport.dial(address).unwrap().await.unwrap();
+        let (_, remote) = listener.accept().await.unwrap();
+        assert_eq!(
+            remote.ip(),
+            "127.0.0.2".parse::<IpAddr>().unwrap(),
+            "dropped binding must not apply"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn dial_through_unknown_interface_fails() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!(
+            "/ip4/127.0.0.1/tcp/{}",
+            listener.local_addr().unwrap().port()
+        )
+        .parse::<Multiaddr>()
+        .unwrap();
+
+        let result = BoundTcpTransport::new()
+            .with_interface("does-not-exist0")
+            .dial(address)
+            .unwrap()
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn applies_socket_options_to_dials() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!(
+            "/ip4/127.0.0.1/tcp/{}",
+            listener.local_addr().unwrap().port()
+        )
+        .parse::<Multiaddr>()
+        .unwrap();
+        let transport = BoundTcpTransport::new().with_options(
+            TcpOptions::new()
+                .with_nodelay(true)
+                .with_keepalive(Duration::from_secs(30))
+                .with_linger(Duration::from_secs(1)),
+        );
+
+        let stream = transport.dial(address).unwrap().await.unwrap().into_inner();
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.linger().unwrap(), Some(Duration::from_secs(1)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dials_from_listen_port() {
+        let options = TcpOptions::new().with_port_reuse(true);
+        let own_listener = options
+            .bind_listener("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+        let listen_port = own_listener.local_addr().unwrap().port();
+
+        let remote = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("/ip4/127.0.0.1/tcp/{}", remote.local_addr().unwrap().port())
+            .parse::<Multiaddr>()
+            .unwrap();
+        let transport = BoundTcpTransport::new()
+            .with_source_port(listen_port)
+            .with_options(options);
+
+        transport.dial(address).unwrap().await.unwrap();
+
+        let (_, observed) = remote.accept().await.unwrap();
+        assert_eq!(observed.port(), listen_port);
+    }
+}
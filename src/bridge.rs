@@ -0,0 +1,156 @@
+use crate::{Connect, Disconnect, Error, NewInboundSubstream, Node, NodeExt, Substream};
+use anyhow::{Context as _, Result};
+use futures::channel::{mpsc, oneshot};
+use futures::future::BoxFuture;
+use futures::{FutureExt, SinkExt, Stream, StreamExt};
+use libp2p_core::{Multiaddr, PeerId};
+use xtra::Address;
+
+/// Connects a [`Node`](crate::Node) to a handler living in an actor framework other than xtra, see [`Node::with_bridge`](crate::Node::with_bridge).
+///
+/// The bridge hands over notifications about new inbound substreams.
+/// Commands go the other way as [`NodeCommand`]s, see [`serve_commands`] and, with the `actix` feature, `ActixNode`.
+///
+/// Implementations are provided for `futures` mpsc senders and, with the `actix` feature, for actix recipients.
+pub trait ActorBridge: Send + Sync + 'static {
+    /// Hands the substream to the actor, failing if the actor is gone.
+    fn deliver(&self, substream: NewInboundSubstream) -> BoxFuture<'static, Result<()>>;
+}
+
+impl ActorBridge for mpsc::Sender<NewInboundSubstream> {
+    fn deliver(&self, substream: NewInboundSubstream) -> BoxFuture<'static, Result<()>> {
+        let mut sender = self.clone();
+
+        async move {
+            sender
+                .send(substream)
+                .await
+                .context("Receiver of inbound substreams is gone")
+        }
+        .boxed()
+    }
+}
+
+impl ActorBridge for mpsc::UnboundedSender<NewInboundSubstream> {
+    fn deliver(&self, substream: NewInboundSubstream) -> BoxFuture<'static, Result<()>> {
+        let result = self
+            .unbounded_send(substream)
+            .map_err(|_| anyhow::anyhow!("Receiver of inbound substreams is gone"));
+
+        futures::future::ready(result).boxed()
+    }
+}
+
+#[cfg(feature = "actix")]
+impl actix::Message for NewInboundSubstream {
+    type Result = ();
+}
+
+#[cfg(feature = "actix")]
+impl ActorBridge for actix::Recipient<NewInboundSubstream> {
+    fn deliver(&self, substream: NewInboundSubstream) -> BoxFuture<'static, Result<()>> {
+        let request = self.send(substream);
+
+        async move {
+            request
+                .await
+                .context("Actor handling inbound substreams is gone")
+        }
+        .boxed()
+    }
+}
+
+/// A command for the [`Node`] from an actor of another framework, see [`serve_commands`].
+///
+/// The result is sent back through the `reply` channel. Dropping the receiving end is fine if the result is not needed.
+pub enum NodeCommand {
+    /// See [`Connect`].
+    Connect {
+        address: Multiaddr,
+        reply: oneshot::Sender<Result<(), Error>>,
+    },
+    /// Connects to the given address if necessary and opens a substream, see [`NodeExt::connect_and_open`].
+    ConnectAndOpen {
+        address: Multiaddr,
+        protocol: &'static str,
+        reply: oneshot::Sender<Result<Substream, Error>>,
+    },
+    /// See [`Disconnect`].
+    Disconnect(PeerId),
+}
+
+/// Executes the given commands against the node until the stream ends, e.g. because all senders of an mpsc channel are dropped.
+///
+/// Commands are executed concurrently, so a slow [`NodeCommand::ConnectAndOpen`] does not hold back the others.
+pub async fn serve_commands(node: Address<Node>, commands: impl Stream<Item = NodeCommand>) {
+    commands
+        .for_each_concurrent(None, |command| execute(node.clone(), command))
+        .await
+}
+
+async fn execute(node: Address<Node>, command: NodeCommand) {
+    match command {
+        NodeCommand::Connect { address, reply } => {
+            let result = node
+                .send(Connect(address))
+                .await
+                .unwrap_or(Err(Error::Stopped));
+            let _ = reply.send(result);
+        }
+        NodeCommand::ConnectAndOpen {
+            address,
+            protocol,
+            reply,
+        } => {
+            let result = node.connect_and_open(address, protocol).await;
+            let _ = reply.send(result);
+        }
+        NodeCommand::Disconnect(peer) => {
+            let _ = node.send(Disconnect(peer)).await;
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
+impl actix::Message for NodeCommand {
+    type Result = ();
+}
+
+/// An actix actor forwarding [`NodeCommand`]s to the [`Node`], so actix code can treat the node like any other actor.
+#[cfg(feature = "actix")]
+pub struct ActixNode(pub Address<Node>);
+
+#[cfg(feature = "actix")]
+impl actix::Actor for ActixNode {
+    type Context = actix::Context<Self>;
+}
+
+#[cfg(feature = "actix")]
+impl actix::Handler<NodeCommand> for ActixNode {
+    type Result = ();
+
+    fn handle(&mut self, command: NodeCommand, ctx: &mut Self::Context) {
+        use actix::{AsyncContext as _, WrapFuture as _};
+
+        ctx.spawn(execute(self.0.clone(), command).into_actor(self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_bridge<B: ActorBridge>() {}
+
+    #[test]
+    fn channels_are_bridges() {
+        assert_bridge::<mpsc::Sender<NewInboundSubstream>>();
+        assert_bridge::<mpsc::UnboundedSender<NewInboundSubstream>>();
+    }
+
+    #[cfg(feature = "actix")]
+    #[test]
+    fn actix_recipients_are_bridges() {
+        assert_bridge::<actix::Recipient<NewInboundSubstream>>();
+    }
+}
@@ -0,0 +1,442 @@
+//! Capture of the plaintext traffic of connections for offline analysis, see [`Node::with_capture`](crate::Node::with_capture).
+//!
+//! A capture starts with [`CAPTURE_MAGIC`], followed by records of the following layout, all integers big-endian:
+//!
+//! | Field      | Size     | Description                                                      |
+//! |------------|----------|------------------------------------------------------------------|
+//! | timestamp  | 8        | Microseconds since the UNIX epoch                                |
+//! | connection | 8        | Identifies the connection within the capture, starting at 0      |
+//! | kind       | 1        | 0: opened as dialer, 1: opened as listener, 2: inbound, 3: outbound |
+//! | length     | 4        | Length of the payload                                            |
+//! | payload    | `length` | The remote address of an opened connection, the bytes otherwise  |
+//!
+//! The remote address of an opened connection is a binary [`Multiaddr`] ending in the [`PeerId`] of the remote.
+//! Inbound data is recorded after decryption and outbound data before encryption, i.e. it contains multistream-select and yamux framing.
+//!
+//! Use [`export_pcap`] to inspect a capture in Wireshark or tcpdump.
+
+use crate::multiaddress_ext::MultiaddrExt as _;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::{Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The first bytes of every capture.
+pub const CAPTURE_MAGIC: [u8; 8] = *b"L2XCAP01";
+
+/// A single record of a capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub timestamp: SystemTime,
+    pub connection: u64,
+    pub kind: CaptureKind,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaptureKind {
+    /// The connection was authenticated, the payload is the remote address.
+    Opened { dialer: bool },
+    /// Bytes received from the remote, after decryption.
+    Inbound,
+    /// Bytes sent to the remote, before encryption.
+    Outbound,
+}
+
+impl CaptureKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            CaptureKind::Opened { dialer: true } => 0,
+            CaptureKind::Opened { dialer: false } => 1,
+            CaptureKind::Inbound => 2,
+            CaptureKind::Outbound => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        Ok(match byte {
+            0 => CaptureKind::Opened { dialer: true },
+            1 => CaptureKind::Opened { dialer: false },
+            2 => CaptureKind::Inbound,
+            3 => CaptureKind::Outbound,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown capture record kind {other}"),
+                ))
+            }
+        })
+    }
+}
+
+impl CaptureRecord {
+    /// Reads the next record of a capture, returning `None` at the end of the capture.
+    ///
+    /// The reader must be positioned after [`CAPTURE_MAGIC`], see [`CaptureRecord::read_magic`].
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut header = [0u8; 21];
+        match reader.read_exact(&mut header[..1]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        reader.read_exact(&mut header[1..])?;
+
+        let timestamp = u64::from_be_bytes(header[0..8].try_into().expect("8 bytes"));
+        let connection = u64::from_be_bytes(header[8..16].try_into().expect("8 bytes"));
+        let kind = CaptureKind::from_byte(header[16])?;
+        let length = u32::from_be_bytes(header[17..21].try_into().expect("4 bytes"));
+
+        let mut payload = vec![0u8; length as usize];
+        reader.read_exact(&mut payload)?;
+
+        Ok(Some(Self {
+            timestamp: UNIX_EPOCH + Duration::from_micros(timestamp),
+            connection,
+            kind,
+            payload,
+        }))
+    }
+
+    /// Reads and checks the [`CAPTURE_MAGIC`] at the start of a capture.
+    pub fn read_magic(reader: &mut impl Read) -> io::Result<()> {
+        let mut magic = [0u8; CAPTURE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+
+        if magic != CAPTURE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a capture, magic does not match",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn encode(connection: u64, kind: CaptureKind, payload: &[u8]) -> Vec<u8> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let mut record = Vec::with_capacity(21 + payload.len());
+        record.extend_from_slice(&timestamp.to_be_bytes());
+        record.extend_from_slice(&connection.to_be_bytes());
+        record.push(kind.to_byte());
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(payload);
+
+        record
+    }
+}
+
+/// The capture configured on a [`Node`](crate::Node), shared with all of its connections.
+#[derive(Clone, Default)]
+pub struct CaptureSlot {
+    inner: Arc<RwLock<Option<Arc<Sink>>>>,
+}
+
+struct Sink {
+    writer: Mutex<Option<Box<dyn Write + Send>>>,
+    next_connection: AtomicU64,
+}
+
+impl CaptureSlot {
+    pub fn set(&self, mut writer: Box<dyn Write + Send>) {
+        let writer = match writer.write_all(&CAPTURE_MAGIC) {
+            Ok(()) => Some(writer),
+            Err(e) => {
+                tracing::warn!("Failed to start capture: {}", e);
+                None
+            }
+        };
+
+        *self.inner.write().expect("not poisoned") = Some(Arc::new(Sink {
+            writer: Mutex::new(writer),
+            next_connection: AtomicU64::default(),
+        }));
+    }
+
+    /// Starts capturing the given connection if a capture is configured.
+    pub fn open<C>(
+        &self,
+        conn: C,
+        peer: PeerId,
+        remote_address: &Multiaddr,
+        dialer: bool,
+    ) -> Captured<C> {
+        let sink = self.inner.read().expect("not poisoned").clone();
+        let connection = match &sink {
+            Some(sink) => {
+                let connection = sink.next_connection.fetch_add(1, Ordering::Relaxed);
+
+                let mut address = remote_address.clone();
+                if address.clone().extract_peer_id().is_none() {
+                    address.push(Protocol::P2p(peer.into()));
+                }
+                sink.write(
+                    connection,
+                    CaptureKind::Opened { dialer },
+                    &address.to_vec(),
+                );
+
+                connection
+            }
+            None => 0,
+        };
+
+        Captured {
+            inner: conn,
+            sink,
+            connection,
+        }
+    }
+}
+
+impl Sink {
+    /// Writes a record, giving up on the capture once writing fails.
+    fn write(&self, connection: u64, kind: CaptureKind, payload: &[u8]) {
+        let mut writer = self.writer.lock().expect("not poisoned");
+        let result = match writer.as_mut() {
+            Some(writer) => writer.write_all(&CaptureRecord::encode(connection, kind, payload)),
+            None => return,
+        };
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to write capture, stopping capture: {}", e);
+            *writer = None;
+        }
+    }
+}
+
+/// The maximum payload of a single packet in a pcap export, i.e. the maximum IPv4 packet minus IPv4 and TCP headers.
+const MAX_PCAP_PAYLOAD: usize = u16::MAX as usize - 40;
+
+/// Converts a capture into the pcap format, so it can be inspected with tools like Wireshark.
+///
+/// Every connection becomes a TCP stream between `10.0.0.1` (the local node) and `10.0.0.2` (the remote) without handshake.
+/// The remote listens on port 4001 and the local port is derived from the connection, so tools can reassemble the streams, e.g. through "Follow TCP Stream".
+/// The records of opened connections carry no traffic and are left out.
+pub fn export_pcap(mut capture: impl Read, mut pcap: impl Write) -> io::Result<()> {
+    const LINKTYPE_RAW: u32 = 101;
+
+    CaptureRecord::read_magic(&mut capture)?;
+
+    pcap.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+    pcap.write_all(&2u16.to_le_bytes())?;
+    pcap.write_all(&4u16.to_le_bytes())?;
+    pcap.write_all(&0i32.to_le_bytes())?;
+    pcap.write_all(&0u32.to_le_bytes())?;
+    pcap.write_all(&(u16::MAX as u32).to_le_bytes())?;
+    pcap.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+
+    // Next sequence number per connection and direction.
+    let mut sequence_numbers = HashMap::<(u64, bool), u32>::new();
+
+    while let Some(record) = CaptureRecord::read_from(&mut capture)? {
+        let outbound = match record.kind {
+            CaptureKind::Opened { .. } => continue,
+            CaptureKind::Inbound => false,
+            CaptureKind::Outbound => true,
+        };
+        let timestamp = record
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        for chunk in record.payload.chunks(MAX_PCAP_PAYLOAD) {
+            let ack = *sequence_numbers
+                .entry((record.connection, !outbound))
+                .or_insert(1);
+            let seq = sequence_numbers
+                .entry((record.connection, outbound))
+                .or_insert(1);
+            let packet = tcp_packet(record.connection, outbound, *seq, ack, chunk);
+            *seq = seq.wrapping_add(chunk.len() as u32);
+
+            pcap.write_all(&(timestamp.as_secs() as u32).to_le_bytes())?;
+            pcap.write_all(&timestamp.subsec_micros().to_le_bytes())?;
+            pcap.write_all(&(packet.len() as u32).to_le_bytes())?;
+            pcap.write_all(&(packet.len() as u32).to_le_bytes())?;
+            pcap.write_all(&packet)?;
+        }
+    }
+
+    pcap.flush()
+}
+
+/// Encodes an IPv4 packet with a TCP segment carrying the payload, leaving the TCP checksum empty.
+fn tcp_packet(connection: u64, outbound: bool, seq: u32, ack: u32, payload: &[u8]) -> Vec<u8> {
+    const LOCAL: [u8; 4] = [10, 0, 0, 1];
+    const REMOTE: [u8; 4] = [10, 0, 0, 2];
+    const REMOTE_PORT: u16 = 4001;
+
+    let local_port = 10_000 + (connection % 50_000) as u16;
+    let (source, destination, source_port, destination_port) = if outbound {
+        (LOCAL, REMOTE, local_port, REMOTE_PORT)
+    } else {
+        (REMOTE, LOCAL, REMOTE_PORT, local_port)
+    };
+
+    let mut packet = Vec::with_capacity(40 + payload.len());
+
+    // IPv4 header, don't fragment, TTL 64, protocol TCP.
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+    packet.extend_from_slice(&source);
+    packet.extend_from_slice(&destination);
+    let checksum = ipv4_checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    // TCP header, PSH and ACK set.
+    packet.extend_from_slice(&source_port.to_be_bytes());
+    packet.extend_from_slice(&destination_port.to_be_bytes());
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(&ack.to_be_bytes());
+    packet.extend_from_slice(&[0x50, 0x18]);
+    packet.extend_from_slice(&u16::MAX.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0]);
+
+    packet.extend_from_slice(payload);
+
+    packet
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum = header
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// A connection that records the bytes read and written into the capture of the [`Node`](crate::Node).
+pub struct Captured<C> {
+    inner: C,
+    sink: Option<Arc<Sink>>,
+    connection: u64,
+}
+
+impl<C> AsyncRead for Captured<C>
+where
+    C: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        if let (Some(sink), true) = (&self.sink, n > 0) {
+            sink.write(self.connection, CaptureKind::Inbound, &buf[..n]);
+        }
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<C> AsyncWrite for Captured<C>
+where
+    C: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        if let (Some(sink), true) = (&self.sink, n > 0) {
+            sink.write(self.connection, CaptureKind::Outbound, &buf[..n]);
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_roundtrip() {
+        let mut capture = CAPTURE_MAGIC.to_vec();
+        capture.extend(CaptureRecord::encode(
+            3,
+            CaptureKind::Opened { dialer: false },
+            b"addr",
+        ));
+        capture.extend(CaptureRecord::encode(3, CaptureKind::Outbound, b"hello"));
+
+        let mut reader = capture.as_slice();
+        CaptureRecord::read_magic(&mut reader).unwrap();
+
+        let opened = CaptureRecord::read_from(&mut reader).unwrap().unwrap();
+        assert_eq!(opened.connection, 3);
+        assert_eq!(opened.kind, CaptureKind::Opened { dialer: false });
+        assert_eq!(opened.payload, b"addr");
+
+        let outbound = CaptureRecord::read_from(&mut reader).unwrap().unwrap();
+        assert_eq!(outbound.kind, CaptureKind::Outbound);
+        assert_eq!(outbound.payload, b"hello");
+        assert!(outbound.timestamp >= opened.timestamp);
+
+        assert!(CaptureRecord::read_from(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn pcap_export_contains_tcp_stream_per_connection() {
+        let mut capture = CAPTURE_MAGIC.to_vec();
+        capture.extend(CaptureRecord::encode(
+            1,
+            CaptureKind::Opened { dialer: true },
+            b"addr",
+        ));
+        capture.extend(CaptureRecord::encode(1, CaptureKind::Outbound, b"hello"));
+        capture.extend(CaptureRecord::encode(1, CaptureKind::Inbound, b"world!"));
+        capture.extend(CaptureRecord::encode(1, CaptureKind::Outbound, b"bye"));
+
+        let mut pcap = Vec::new();
+        export_pcap(capture.as_slice(), &mut pcap).unwrap();
+
+        assert_eq!(pcap[..4], 0xa1b2_c3d4u32.to_le_bytes());
+        assert_eq!(pcap[20..24], 101u32.to_le_bytes());
+
+        let mut packets = Vec::new();
+        let mut rest = &pcap[24..];
+        while !rest.is_empty() {
+            let length = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+            packets.push(rest[16..16 + length].to_vec());
+            rest = &rest[16 + length..];
+        }
+
+        assert_eq!(packets.len(), 3);
+        assert_eq!(&packets[0][40..], b"hello");
+        assert_eq!(ipv4_checksum(&packets[0][..20]), 0);
+        // Destination port of the inbound packet is the source port of the outbound one.
+        assert_eq!(packets[1][22..24], packets[0][20..22]);
+        // The second outbound segment continues after "hello" and acknowledges "world!".
+        let seq = u32::from_be_bytes(packets[2][24..28].try_into().unwrap());
+        let ack = u32::from_be_bytes(packets[2][28..32].try_into().unwrap());
+        assert_eq!(seq, 1 + 5);
+        assert_eq!(ack, 1 + 6);
+    }
+}
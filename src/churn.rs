@@ -0,0 +1,205 @@
+//! A test scenario that keeps connecting, disconnecting and restarting nodes, see [`ChurnScenario`].
+//!
+//! Only available with the `test-support` feature, which the tests of this crate enable through a dev-dependency on the crate itself.
+
+use crate::{Connect, Disconnect, Drain, GetConnectionStats, ListenOn, Node, Stop};
+use anyhow::{bail, Context as _, Result};
+use libp2p_core::identity::Keypair;
+use libp2p_core::{Multiaddr, PeerId};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use xtra::spawn::TokioGlobalSpawnExt;
+use xtra::{Actor, Address};
+
+/// Runs a set of nodes through a random but reproducible schedule of connects, disconnects and restarts.
+///
+/// After every step, the nodes are given time to settle and the assertions of the caller run, e.g. opening substreams of the protocol under test.
+/// At the end, every pair of nodes must agree on whether they are connected.
+/// Then each connection is closed from one side only, and every node must notice and report that it has no connections left.
+/// Failures name the seed and step, so a failing schedule can be replayed with the same seed.
+/// The schedule is derived with [`ChaCha8Rng`], whose output is stable across platforms and versions of `rand`.
+pub struct ChurnScenario {
+    seed: u64,
+    nodes: usize,
+    steps: usize,
+    settle_time: Duration,
+}
+
+/// A node taking part in a [`ChurnScenario`].
+///
+/// Restarts stop the node and start a new one with the same identity, listening on a new address.
+#[derive(Clone)]
+pub struct ChurnNode {
+    pub peer: PeerId,
+    pub address: Multiaddr,
+    pub node: Address<Node>,
+    identity: Keypair,
+}
+
+/// A single step of a [`ChurnScenario`], referring to nodes by their index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChurnAction {
+    Connect { from: usize, to: usize },
+    Disconnect { from: usize, to: usize },
+    Restart(usize),
+}
+
+impl ChurnScenario {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            nodes: 4,
+            steps: 50,
+            settle_time: Duration::from_millis(100),
+        }
+    }
+
+    pub fn with_nodes(mut self, nodes: usize) -> Self {
+        self.nodes = nodes.max(2);
+
+        self
+    }
+
+    pub fn with_steps(mut self, steps: usize) -> Self {
+        self.steps = steps;
+
+        self
+    }
+
+    /// How long to wait after every step before running the assertions.
+    pub fn with_settle_time(mut self, settle_time: Duration) -> Self {
+        self.settle_time = settle_time;
+
+        self
+    }
+
+    /// The schedule of this scenario, derived from the seed alone.
+    pub fn schedule(&self) -> Vec<ChurnAction> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+
+        (0..self.steps)
+            .map(|_| {
+                let from = rng.gen_range(0..self.nodes);
+                let to = (from + rng.gen_range(1..self.nodes)) % self.nodes;
+
+                match rng.gen_range(0..10) {
+                    0..=4 => ChurnAction::Connect { from, to },
+                    5..=7 => ChurnAction::Disconnect { from, to },
+                    _ => ChurnAction::Restart(from),
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the scenario on nodes built by `make_node` on top of a memory transport.
+    ///
+    /// `make_node` is called with the identity of the node, for the initial nodes as well as on every restart.
+    /// `assert` is called after every step with the current nodes.
+    pub async fn run<F, A, Fut>(self, mut make_node: F, mut assert: A) -> Result<()>
+    where
+        F: FnMut(Keypair) -> Node,
+        A: FnMut(&[ChurnNode]) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let seed = self.seed;
+        let mut nodes = Vec::with_capacity(self.nodes);
+        for _ in 0..self.nodes {
+            nodes.push(start(&mut make_node, Keypair::generate_ed25519()).await);
+        }
+        tokio::time::sleep(self.settle_time).await;
+
+        for (step, action) in self.schedule().into_iter().enumerate() {
+            tracing::debug!(seed, step, ?action, "Churn");
+
+            async {
+                match action {
+                    ChurnAction::Connect { from, to } => {
+                        // Dials may fail while the remote restarts, which is part of the churn.
+                        let _ = nodes[from]
+                            .node
+                            .send(Connect(nodes[to].address.clone()))
+                            .await?;
+                    }
+                    ChurnAction::Disconnect { from, to } => {
+                        nodes[from].node.send(Disconnect(nodes[to].peer)).await?;
+                    }
+                    ChurnAction::Restart(index) => {
+                        nodes[index]
+                            .node
+                            .send(Drain {
+                                deadline: Instant::now(),
+                            })
+                            .await?;
+                        nodes[index].node.send(Stop).await?;
+                        let identity = nodes[index].identity.clone();
+                        nodes[index] = start(&mut make_node, identity).await;
+                    }
+                }
+                tokio::time::sleep(self.settle_time).await;
+
+                assert(&nodes).await
+            }
+            .await
+            .with_context(|| {
+                format!("Step {step} ({action:?}) of churn scenario with seed {seed}")
+            })?;
+        }
+
+        let mut connected_peers = Vec::with_capacity(nodes.len());
+        for node in &nodes {
+            connected_peers.push(node.node.send(GetConnectionStats).await?.connected_peers);
+        }
+        for (a, a_peers) in connected_peers.iter().enumerate() {
+            for (b, b_peers) in connected_peers.iter().enumerate() {
+                if a_peers.contains(&nodes[b].peer) != b_peers.contains(&nodes[a].peer) {
+                    bail!("Nodes {a} and {b} disagree on whether they are connected in churn scenario with seed {seed}");
+                }
+            }
+        }
+
+        // Disconnecting from one side only, so the other side has to notice.
+        for (index, from) in nodes.iter().enumerate() {
+            for to in &nodes[index + 1..] {
+                from.node.send(Disconnect(to.peer)).await?;
+            }
+        }
+        tokio::time::sleep(self.settle_time).await;
+
+        for (index, node) in nodes.iter().enumerate() {
+            let stats = node.node.send(GetConnectionStats).await?;
+
+            if !stats.connections.is_empty() || !stats.connected_peers.is_empty() {
+                bail!(
+                    "Node {index} still tracks {} connections to {} peers after disconnecting from all peers in churn scenario with seed {seed}",
+                    stats.connections.len(),
+                    stats.connected_peers.len()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn start<F>(make_node: &mut F, identity: Keypair) -> ChurnNode
+where
+    F: FnMut(Keypair) -> Node,
+{
+    let peer = identity.public().to_peer_id();
+    let address = format!("/memory/{}", rand::random::<u64>())
+        .parse::<Multiaddr>()
+        .expect("valid memory address");
+    let node = make_node(identity.clone()).create(None).spawn_global();
+    let _ = node.send(ListenOn(address.clone())).await;
+
+    ChurnNode {
+        peer,
+        address: format!("{address}/p2p/{peer}")
+            .parse()
+            .expect("valid address with peer ID"),
+        node,
+        identity,
+    }
+}
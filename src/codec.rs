@@ -0,0 +1,471 @@
+use crate::memory::MemoryHandle;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The default maximum size of a single frame, see [`LengthDelimited::with_max_frame_size`].
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// The size of the big-endian `u64` length prefix of every frame.
+const LENGTH_PREFIX_SIZE: usize = 8;
+
+/// The minimum number of bytes requested from the underlying stream per read.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Length-delimited framing on top of a substream.
+///
+/// Every frame is prefixed with its length as a big-endian `u64`, which is the same format as `asynchronous_codec::LengthCodec`.
+/// Received frames are handed out as [`Bytes`] referencing the receive buffer without copying the payload.
+/// Sent frames are copied behind their length prefix once, so each frame reaches the muxer in a single write rather than as a separate prefix and body.
+///
+/// Frames too large to hold in memory can be streamed instead, see [`LengthDelimited::next_frame_reader`] and [`LengthDelimited::send_frame_from`].
+pub struct LengthDelimited<S> {
+    stream: S,
+    read_buffer: BytesMut,
+    max_frame_size: usize,
+    /// The number of bytes of the current streamed frame that were not read yet.
+    unread_body: u64,
+    memory: Option<MemoryHandle>,
+}
+
+/// The body of a single frame, read directly from the underlying stream, see [`LengthDelimited::next_frame_reader`].
+pub struct FrameReader<'a, S> {
+    framed: &'a mut LengthDelimited<S>,
+}
+
+impl<S> LengthDelimited<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            read_buffer: BytesMut::new(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            unread_body: 0,
+            memory: None,
+        }
+    }
+
+    /// Fail on frames larger than `bytes` in either direction. Defaults to [`DEFAULT_MAX_FRAME_SIZE`].
+    ///
+    /// This includes frames sent with [`LengthDelimited::send_frame_from`], raise the limit on both ends to stream larger ones.
+    /// Frames received through [`LengthDelimited::next_frame_reader`] may be larger since they are not buffered, but skipping an unread body of more than `bytes` fails instead of draining it.
+    pub fn with_max_frame_size(mut self, bytes: usize) -> Self {
+        self.max_frame_size = bytes;
+
+        self
+    }
+
+    /// Report the size of the receive buffer to the memory account of the connection, see [`Substream::memory_handle`](crate::Substream::memory_handle).
+    pub fn with_memory_handle(mut self, handle: MemoryHandle) -> Self {
+        self.memory = Some(handle);
+
+        self
+    }
+
+    /// Returns the underlying stream.
+    ///
+    /// Bytes that were already received but not handed out as a frame are lost.
+    pub fn into_inner(self) -> S {
+        if let Some(memory) = &self.memory {
+            memory.set_buffered(0);
+        }
+
+        self.stream
+    }
+
+    fn check_frame_size(&self, len: usize) -> io::Result<()> {
+        if len > self.max_frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Frame of {len} bytes exceeds maximum of {} bytes",
+                    self.max_frame_size
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<S> LengthDelimited<S>
+where
+    S: AsyncRead + Unpin,
+{
+    /// Receives the next frame, returning `None` if the stream ended cleanly in between frames.
+    ///
+    /// This is cancellation-safe, i.e. no data is lost if the returned future is dropped before completion.
+    pub async fn next_frame(&mut self) -> io::Result<Option<Bytes>> {
+        self.discard_unread_body().await?;
+
+        loop {
+            if let Some(len) = self.buffered_frame_len()? {
+                if self.read_buffer.len() >= LENGTH_PREFIX_SIZE + len {
+                    self.read_buffer.advance(LENGTH_PREFIX_SIZE);
+
+                    return Ok(Some(self.read_buffer.split_to(len).freeze()));
+                }
+
+                // Allocate the whole frame at once instead of growing the buffer chunk by chunk.
+                self.read_buffer
+                    .reserve(LENGTH_PREFIX_SIZE + len - self.read_buffer.len());
+            }
+
+            if self.fill_read_buffer().await? == 0 {
+                if self.read_buffer.is_empty() {
+                    return Ok(None);
+                }
+
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// Receives the header of the next frame and returns a reader for its body, returning `None` if the stream ended cleanly in between frames.
+    ///
+    /// The body is read straight from the underlying stream, which allows processing frames of arbitrary size without holding them in memory.
+    /// Whatever is left unread of the body is skipped when receiving the next frame.
+    pub async fn next_frame_reader(&mut self) -> io::Result<Option<FrameReader<'_, S>>> {
+        self.discard_unread_body().await?;
+
+        while self.read_buffer.len() < LENGTH_PREFIX_SIZE {
+            if self.fill_read_buffer().await? == 0 {
+                if self.read_buffer.is_empty() {
+                    return Ok(None);
+                }
+
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+
+        self.unread_body = self.read_buffer.get_u64();
+
+        Ok(Some(FrameReader { framed: self }))
+    }
+
+    /// Returns the length of the next frame if its prefix was received already.
+    fn buffered_frame_len(&self) -> io::Result<Option<usize>> {
+        if self.read_buffer.len() < LENGTH_PREFIX_SIZE {
+            return Ok(None);
+        }
+
+        let mut prefix = [0u8; LENGTH_PREFIX_SIZE];
+        prefix.copy_from_slice(&self.read_buffer[..LENGTH_PREFIX_SIZE]);
+        let len = usize::try_from(u64::from_be_bytes(prefix)).unwrap_or(usize::MAX);
+        self.check_frame_size(len)?;
+
+        Ok(Some(len))
+    }
+
+    /// Skips what is left of the body of the previous streamed frame.
+    ///
+    /// The remote chooses the size of streamed frames, so skipping is bounded by the maximum frame size to not read an arbitrary amount of data nobody asked for.
+    async fn discard_unread_body(&mut self) -> io::Result<()> {
+        if self.unread_body > self.max_frame_size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Refusing to skip {} unread bytes of a frame, exceeds maximum frame size of {} bytes",
+                    self.unread_body, self.max_frame_size
+                ),
+            ));
+        }
+
+        if self.unread_body > 0 {
+            futures::io::copy(FrameReader { framed: self }, &mut futures::io::sink()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn fill_read_buffer(&mut self) -> io::Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_fill_read_buffer(cx)).await
+    }
+
+    /// Reads into the spare capacity of the receive buffer.
+    ///
+    /// The buffer is restored before returning, which keeps [`LengthDelimited::next_frame`] cancellation-safe.
+    fn poll_fill_read_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let filled = self.read_buffer.len();
+        self.read_buffer.resize(filled + READ_CHUNK_SIZE, 0);
+
+        let result = Pin::new(&mut self.stream).poll_read(cx, &mut self.read_buffer[filled..]);
+        let n = match &result {
+            Poll::Ready(Ok(n)) => *n,
+            _ => 0,
+        };
+        self.read_buffer.truncate(filled + n);
+
+        if let Some(memory) = &self.memory {
+            memory.set_buffered(self.read_buffer.capacity());
+        }
+
+        result
+    }
+}
+
+impl<S> LengthDelimited<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    /// Sends the given frame and flushes the stream.
+    pub async fn send_frame(&mut self, frame: impl Into<Bytes>) -> io::Result<()> {
+        let frame = frame.into();
+        self.check_frame_size(frame.len())?;
+
+        let mut buffer = BytesMut::with_capacity(LENGTH_PREFIX_SIZE + frame.len());
+        buffer.put_u64(frame.len() as u64);
+        buffer.extend_from_slice(&frame);
+
+        self.stream.write_all(&buffer).await?;
+        self.stream.flush().await
+    }
+
+    /// Sends a frame of `len` bytes, streaming its body from the given reader.
+    ///
+    /// Fails if the reader ends before `len` bytes were read, in which case the frame is truncated and the stream should no longer be used.
+    /// Frames larger than the maximum frame size are rejected before anything is sent, see [`LengthDelimited::with_max_frame_size`].
+    pub async fn send_frame_from<R>(&mut self, len: u64, body: R) -> io::Result<()>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.check_frame_size(usize::try_from(len).unwrap_or(usize::MAX))?;
+
+        self.stream.write_all(&len.to_be_bytes()).await?;
+
+        let written = futures::io::copy(body.take(len), &mut self.stream).await?;
+        if written < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Frame body ended after {written} of {len} bytes"),
+            ));
+        }
+
+        self.stream.flush().await
+    }
+
+    /// Closes the underlying stream.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.stream.close().await
+    }
+}
+
+impl<S> FrameReader<'_, S> {
+    /// Returns the number of bytes of the body that were not read yet.
+    pub fn remaining(&self) -> u64 {
+        self.framed.unread_body
+    }
+}
+
+impl<S> AsyncRead for FrameReader<'_, S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let framed = &mut *self.get_mut().framed;
+        let max = buf
+            .len()
+            .min(usize::try_from(framed.unread_body).unwrap_or(usize::MAX));
+
+        if max == 0 {
+            return Poll::Ready(Ok(0));
+        }
+
+        let n = if framed.read_buffer.is_empty() {
+            let n = futures::ready!(Pin::new(&mut framed.stream).poll_read(cx, &mut buf[..max]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+
+            n
+        } else {
+            let n = max.min(framed.read_buffer.len());
+            framed.read_buffer.copy_to_slice(&mut buf[..n]);
+
+            n
+        };
+        framed.unread_body -= n as u64;
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+
+    #[tokio::test]
+    async fn frames_survive_roundtrip() {
+        let mut writer = LengthDelimited::new(Cursor::new(Vec::new()));
+        writer
+            .send_frame(Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        writer.send_frame(Bytes::new()).await.unwrap();
+        writer.send_frame(vec![42u8; 20_000]).await.unwrap();
+
+        let mut reader = LengthDelimited::new(Cursor::new(writer.into_inner().into_inner()));
+
+        assert_eq!(reader.next_frame().await.unwrap().unwrap(), "hello");
+        assert_eq!(reader.next_frame().await.unwrap().unwrap(), "");
+        assert_eq!(
+            reader.next_frame().await.unwrap().unwrap(),
+            vec![42u8; 20_000]
+        );
+        assert!(reader.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn prefix_and_body_are_written_at_once() {
+        let mut writer = LengthDelimited::new(WriteLog::default());
+        writer
+            .send_frame(Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+
+        assert_eq!(writer.into_inner().writes, vec![13]);
+    }
+
+    #[tokio::test]
+    async fn large_frames_can_be_streamed_past_the_frame_size_limit() {
+        let body = (0..3 * 1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut writer =
+            LengthDelimited::new(Cursor::new(Vec::new())).with_max_frame_size(body.len());
+        writer
+            .send_frame_from(body.len() as u64, body.as_slice())
+            .await
+            .unwrap();
+        writer
+            .send_frame(Bytes::from_static(b"next"))
+            .await
+            .unwrap();
+
+        let mut reader = LengthDelimited::new(Cursor::new(writer.into_inner().into_inner()))
+            .with_max_frame_size(1024);
+        let mut received = Vec::new();
+        reader
+            .next_frame_reader()
+            .await
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut received)
+            .await
+            .unwrap();
+
+        assert_eq!(received, body);
+        assert_eq!(reader.next_frame().await.unwrap().unwrap(), "next");
+    }
+
+    #[tokio::test]
+    async fn unread_body_is_skipped() {
+        let mut writer = LengthDelimited::new(Cursor::new(Vec::new()));
+        writer.send_frame(vec![1u8; 100_000]).await.unwrap();
+        writer
+            .send_frame(Bytes::from_static(b"next"))
+            .await
+            .unwrap();
+
+        let mut reader = LengthDelimited::new(Cursor::new(writer.into_inner().into_inner()));
+        let mut frame = reader.next_frame_reader().await.unwrap().unwrap();
+        let mut start = [0u8; 10];
+        frame.read_exact(&mut start).await.unwrap();
+
+        assert_eq!(frame.remaining(), 100_000 - 10);
+        assert_eq!(reader.next_frame().await.unwrap().unwrap(), "next");
+    }
+
+    #[tokio::test]
+    async fn large_unread_body_is_not_skipped() {
+        let mut writer = LengthDelimited::new(Cursor::new(Vec::new()));
+        writer.send_frame(vec![1u8; 100_000]).await.unwrap();
+        writer
+            .send_frame(Bytes::from_static(b"next"))
+            .await
+            .unwrap();
+
+        let mut reader = LengthDelimited::new(Cursor::new(writer.into_inner().into_inner()))
+            .with_max_frame_size(1024);
+        reader.next_frame_reader().await.unwrap().unwrap();
+        let error = reader.next_frame().await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn oversized_streamed_frame_is_not_sent() {
+        let mut writer = LengthDelimited::new(Cursor::new(Vec::new())).with_max_frame_size(4);
+        let error = writer
+            .send_frame_from(10, [0u8; 10].as_slice())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(writer.into_inner().into_inner().is_empty());
+    }
+
+    #[tokio::test]
+    async fn body_shorter_than_announced_is_an_error() {
+        let mut writer = LengthDelimited::new(Cursor::new(Vec::new()));
+        let error = writer
+            .send_frame_from(10, b"short".as_slice())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_is_rejected_before_it_is_received() {
+        let mut input = (1024u64).to_be_bytes().to_vec();
+        input.extend_from_slice(b"only a few bytes");
+
+        let mut reader = LengthDelimited::new(Cursor::new(input)).with_max_frame_size(16);
+        let error = reader.next_frame().await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_is_an_error() {
+        let mut input = (10u64).to_be_bytes().to_vec();
+        input.extend_from_slice(b"short");
+
+        let mut reader = LengthDelimited::new(Cursor::new(input));
+        let error = reader.next_frame().await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// Records the size of every write.
+    #[derive(Default)]
+    struct WriteLog {
+        writes: Vec<usize>,
+    }
+
+    impl AsyncWrite for WriteLog {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.writes.push(buf.len());
+
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
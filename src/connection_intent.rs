@@ -0,0 +1,271 @@
+use crate::libp2p_stream::{multiplex, YAMUX_PROTOCOL};
+use crate::stats::Counters;
+use futures::future::BoxFuture;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt};
+use libp2p_core::upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeInfo};
+use libp2p_core::{Endpoint, PeerId};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+/// The variant of the yamux upgrade in which the dialer declares the protocols it intends to use before multiplexing starts.
+const INTENT_PROTOCOL: &[u8] = b"/libp2p-xtra/yamux-intent/1.0.0";
+
+/// The maximum size of the encoded intent in bytes.
+const MAX_INTENT_SIZE: usize = 4096;
+
+const ACCEPTED: u8 = 1;
+const REJECTED: u8 = 0;
+
+/// Negotiates the multiplexer of a connection, optionally declaring the intent of the dialer, see [`Node::with_connection_intent`](crate::Node::with_connection_intent).
+///
+/// A dialer with an intent proposes [`INTENT_PROTOCOL`] first and falls back to plain yamux for listeners that do not know it.
+/// An empty intent counts as none.
+/// Listeners always offer both and reject the connection if none of the intended protocols is among their supported inbound protocols, counting the rejection in `counters`.
+pub(crate) struct MultiplexUpgrade {
+    offer_intent: bool,
+    intent: Vec<&'static str>,
+    supported_inbound_protocols: Vec<&'static str>,
+    counters: Counters,
+}
+
+impl MultiplexUpgrade {
+    pub(crate) fn new(
+        endpoint: Endpoint,
+        intent: Option<Vec<&'static str>>,
+        supported_inbound_protocols: Vec<&'static str>,
+        counters: Counters,
+    ) -> Self {
+        let intent = intent.filter(|protocols| !protocols.is_empty());
+
+        Self {
+            offer_intent: endpoint == Endpoint::Listener || intent.is_some(),
+            intent: intent.unwrap_or_default(),
+            supported_inbound_protocols,
+            counters,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum IntentError {
+    #[error("Listener supports none of the intended protocols")]
+    Rejected,
+    #[error("None of the intended protocols {0:?} are supported")]
+    NoOverlap(Vec<String>),
+    #[error("Failed to exchange connection intent")]
+    Io(#[from] io::Error),
+}
+
+impl UpgradeInfo for MultiplexUpgrade {
+    type Info = &'static [u8];
+    type InfoIter = Vec<&'static [u8]>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        if self.offer_intent {
+            vec![INTENT_PROTOCOL, YAMUX_PROTOCOL]
+        } else {
+            vec![YAMUX_PROTOCOL]
+        }
+    }
+}
+
+impl<C> InboundUpgrade<C> for MultiplexUpgrade
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = yamux::Connection<C>;
+    type Error = IntentError;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, mut socket: C, info: Self::Info) -> Self::Future {
+        async move {
+            if info == INTENT_PROTOCOL {
+                let intent = read_intent(&mut socket).await?;
+                let accepted = intent.is_empty()
+                    || intent.iter().any(|protocol| {
+                        self.supported_inbound_protocols
+                            .iter()
+                            .any(|supported| supported == protocol)
+                    });
+
+                socket
+                    .write_all(&[if accepted { ACCEPTED } else { REJECTED }])
+                    .await?;
+                socket.flush().await?;
+
+                if !accepted {
+                    self.counters.connection_intent_rejected();
+                    return Err(IntentError::NoOverlap(intent));
+                }
+            }
+
+            Ok(multiplex(socket, Endpoint::Listener))
+        }
+        .boxed()
+    }
+}
+
+impl<C> OutboundUpgrade<C> for MultiplexUpgrade
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = yamux::Connection<C>;
+    type Error = IntentError;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, mut socket: C, info: Self::Info) -> Self::Future {
+        async move {
+            if info == INTENT_PROTOCOL {
+                write_intent(&mut socket, &self.intent).await?;
+
+                let mut response = [0u8; 1];
+                socket.read_exact(&mut response).await?;
+                if response[0] != ACCEPTED {
+                    return Err(IntentError::Rejected);
+                }
+            }
+
+            Ok(multiplex(socket, Endpoint::Dialer))
+        }
+        .boxed()
+    }
+}
+
+/// Writes the intended protocols as a big-endian `u16` length followed by the protocols, separated by newlines.
+async fn write_intent<C>(socket: &mut C, protocols: &[&'static str]) -> io::Result<()>
+where
+    C: AsyncWrite + Unpin,
+{
+    let intent = protocols.join("\n");
+    if intent.len() > MAX_INTENT_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Intent of {} bytes exceeds maximum of {MAX_INTENT_SIZE} bytes",
+                intent.len()
+            ),
+        ));
+    }
+
+    socket
+        .write_all(&(intent.len() as u16).to_be_bytes())
+        .await?;
+    socket.write_all(intent.as_bytes()).await?;
+    socket.flush().await
+}
+
+async fn read_intent<C>(socket: &mut C) -> io::Result<Vec<String>>
+where
+    C: AsyncRead + Unpin,
+{
+    let mut len = [0u8; 2];
+    socket.read_exact(&mut len).await?;
+    let len = u16::from_be_bytes(len) as usize;
+    if len > MAX_INTENT_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Intent of {len} bytes exceeds maximum of {MAX_INTENT_SIZE} bytes"),
+        ));
+    }
+
+    let mut intent = vec![0u8; len];
+    socket.read_exact(&mut intent).await?;
+    let intent =
+        String::from_utf8(intent).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(intent
+        .split('\n')
+        .filter(|protocol| !protocol.is_empty())
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+/// The protocols outbound connections of a [`Node`](crate::Node) are declared for, shared with its transport.
+#[derive(Clone, Default)]
+pub struct ConnectionIntentSlot {
+    inner: Arc<RwLock<Intents>>,
+}
+
+#[derive(Default)]
+struct Intents {
+    default: Option<Vec<&'static str>>,
+    /// The intent of the ongoing dial to a peer, see [`ConnectWithIntent`](crate::ConnectWithIntent).
+    dials: HashMap<PeerId, Vec<&'static str>>,
+}
+
+impl ConnectionIntentSlot {
+    pub fn set(&self, protocols: Vec<&'static str>) {
+        self.inner.write().expect("not poisoned").default = Some(protocols);
+    }
+
+    /// Declares the intent of the next connection to `peer`, taking precedence over the one of all connections.
+    pub fn set_for(&self, peer: PeerId, protocols: Vec<&'static str>) {
+        self.inner
+            .write()
+            .expect("not poisoned")
+            .dials
+            .insert(peer, protocols);
+    }
+
+    pub fn clear_for(&self, peer: &PeerId) {
+        self.inner.write().expect("not poisoned").dials.remove(peer);
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<Vec<&'static str>> {
+        let intents = self.inner.read().expect("not poisoned");
+
+        intents
+            .dials
+            .get(peer)
+            .or(intents.default.as_ref())
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    #[test]
+    fn intent_of_a_dial_takes_precedence() {
+        let slot = ConnectionIntentSlot::default();
+        let peer = PeerId::random();
+        let other = PeerId::random();
+        slot.set(vec!["/hello-world/1.0.0"]);
+        slot.set_for(peer, vec![]);
+
+        assert_eq!(slot.get(&peer), Some(vec![]));
+        assert_eq!(slot.get(&other), Some(vec!["/hello-world/1.0.0"]));
+
+        slot.clear_for(&peer);
+        assert_eq!(slot.get(&peer), Some(vec!["/hello-world/1.0.0"]));
+    }
+
+    #[test]
+    fn empty_intent_is_no_intent() {
+        let upgrade =
+            MultiplexUpgrade::new(Endpoint::Dialer, Some(vec![]), vec![], Counters::default());
+
+        assert_eq!(upgrade.protocol_info(), vec![YAMUX_PROTOCOL]);
+    }
+
+    #[test]
+    fn intent_round_trips() {
+        block_on(async {
+            let mut buffer = Cursor::new(Vec::new());
+            write_intent(&mut buffer, &["/hello-world/1.0.0", "/ping/1.0.0"])
+                .await
+                .unwrap();
+
+            buffer.set_position(0);
+            assert_eq!(
+                read_intent(&mut buffer).await.unwrap(),
+                ["/hello-world/1.0.0", "/ping/1.0.0"]
+            );
+        });
+    }
+}
@@ -0,0 +1,299 @@
+//! Writes the [`Event`]s of a [`Node`](crate::Node) as JSON lines, e.g. to a file or stdout.
+//!
+//! Every line is an object with the `timestamp_ms` (milliseconds since the UNIX epoch) and the `event` type in `snake_case`, followed by the fields of the event.
+//! Field names are stable: peers and addresses are strings, connections are numbers and kinds or reasons are `snake_case` strings.
+//!
+//! ```text
+//! {"timestamp_ms":1700000000000,"event":"connection_established","peer":"12D3KooW...","connection":3,"endpoint":"dialer"}
+//! ```
+
+use crate::{ConnectionId, Event};
+use libp2p_core::{Endpoint, Multiaddr, PeerId};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xtra_productivity::xtra_productivity;
+
+/// An actor that writes every [`Event`] it receives as a line of JSON to the given writer.
+///
+/// Register it through [`Subscribe`](crate::Subscribe).
+/// Writing is best-effort: failures are logged and the event is skipped.
+pub struct EventLog {
+    writer: Box<dyn Write + Send>,
+}
+
+impl EventLog {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+        }
+    }
+}
+
+#[xtra_productivity(message_impl = false)]
+impl EventLog {
+    async fn handle(&mut self, event: Event) {
+        let mut line = to_json(&event).to_string();
+        line.push('\n');
+
+        if let Err(e) = self
+            .writer
+            .write_all(line.as_bytes())
+            .and_then(|()| self.writer.flush())
+        {
+            tracing::warn!("Failed to write event to event log: {}", e);
+        }
+    }
+}
+
+impl xtra::Actor for EventLog {}
+
+/// Converts the event into a JSON object with stable field names.
+pub fn to_json(event: &Event) -> Value {
+    let (name, fields) = match event {
+        Event::ConnectionEstablished {
+            peer,
+            connection,
+            endpoint,
+        } => (
+            "connection_established",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "endpoint": match endpoint {
+                    Endpoint::Dialer => "dialer",
+                    Endpoint::Listener => "listener",
+                },
+            }),
+        ),
+        Event::ConnectionClosed { peer, connection } => (
+            "connection_closed",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+            }),
+        ),
+        Event::PeerDisconnected { peer } => ("peer_disconnected", json!({ "peer": peer_id(peer) })),
+        Event::Ready => ("ready", json!({})),
+        Event::ConnectionError {
+            peer,
+            connection,
+            kind,
+            error,
+        } => (
+            "connection_error",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "kind": snake_case(kind),
+                "error": error,
+            }),
+        ),
+        Event::OutgoingConnectionError {
+            peer,
+            connection,
+            address,
+            error_kind,
+        } => (
+            "outgoing_connection_error",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "address": multiaddr(address),
+                "error_kind": snake_case(error_kind),
+            }),
+        ),
+        Event::SubstreamClosed {
+            peer,
+            connection,
+            protocol,
+            reason,
+        } => (
+            "substream_closed",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "protocol": protocol,
+                "reason": snake_case(reason),
+            }),
+        ),
+        Event::InboundSubstreamHandlerTimeout {
+            peer,
+            connection,
+            protocol,
+        } => (
+            "inbound_substream_handler_timeout",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "protocol": protocol,
+            }),
+        ),
+        Event::UnsupportedProtocolProposed { peer, connection } => (
+            "unsupported_protocol_proposed",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+            }),
+        ),
+        Event::DeprecatedProtocolNegotiated {
+            peer,
+            connection,
+            protocol,
+            remote_address,
+        } => (
+            "deprecated_protocol_negotiated",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "protocol": protocol,
+                "remote_address": remote_address.as_ref().map(multiaddr),
+            }),
+        ),
+        Event::HandlerPanicked {
+            peer,
+            connection,
+            protocol,
+            message,
+        } => (
+            "handler_panicked",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "protocol": protocol,
+                "message": message,
+            }),
+        ),
+        Event::SessionResumed { peer, connection } => (
+            "session_resumed",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+            }),
+        ),
+        Event::InboundSubstreamsStalled {
+            peer,
+            connection,
+            waiting,
+        } => (
+            "inbound_substreams_stalled",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "waiting_ms": waiting.as_millis() as u64,
+            }),
+        ),
+        Event::QuotaExceeded {
+            peer,
+            connection,
+            protocol,
+            kind,
+        } => (
+            "quota_exceeded",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "protocol": protocol,
+                "kind": snake_case(kind),
+            }),
+        ),
+        Event::AddressChanged { peer, old, new } => (
+            "address_changed",
+            json!({
+                "peer": peer_id(peer),
+                "old": multiaddr(old),
+                "new": multiaddr(new),
+            }),
+        ),
+        Event::IdentityRotated { old, new } => (
+            "identity_rotated",
+            json!({
+                "old": peer_id(old),
+                "new": peer_id(new),
+            }),
+        ),
+        Event::AgentVersionReceived {
+            peer,
+            connection,
+            agent_version,
+        } => (
+            "agent_version_received",
+            json!({
+                "peer": peer_id(peer),
+                "connection": connection_id(connection),
+                "agent_version": agent_version,
+            }),
+        ),
+    };
+
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let mut object = Map::new();
+    object.insert("timestamp_ms".to_owned(), json!(timestamp_ms));
+    object.insert("event".to_owned(), json!(name));
+    if let Value::Object(fields) = fields {
+        object.extend(fields);
+    }
+
+    Value::Object(object)
+}
+
+fn peer_id(peer: &PeerId) -> String {
+    peer.to_string()
+}
+
+fn connection_id(connection: &ConnectionId) -> u64 {
+    connection.0
+}
+
+fn multiaddr(address: &Multiaddr) -> String {
+    address.to_string()
+}
+
+/// Converts the name of a fieldless enum variant, e.g. `PeerIdMismatch` into `peer_id_mismatch`.
+fn snake_case(kind: &impl fmt::Debug) -> String {
+    let mut snake_case = String::new();
+
+    for (i, c) in format!("{kind:?}").chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake_case.push('_');
+            }
+            snake_case.extend(c.to_lowercase());
+        } else {
+            snake_case.push(c);
+        }
+    }
+
+    snake_case
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DialErrorKind;
+
+    #[test]
+    fn events_have_stable_field_names() {
+        let peer = PeerId::random();
+        let event = Event::OutgoingConnectionError {
+            peer,
+            connection: ConnectionId(7),
+            address: "/memory/1234".parse().unwrap(),
+            error_kind: DialErrorKind::PeerIdMismatch,
+        };
+
+        let json = to_json(&event);
+
+        assert_eq!(json["event"], "outgoing_connection_error");
+        assert_eq!(json["peer"], peer.to_string());
+        assert_eq!(json["connection"], 7);
+        assert_eq!(json["address"], "/memory/1234");
+        assert_eq!(json["error_kind"], "peer_id_mismatch");
+        assert!(json["timestamp_ms"].is_u64());
+    }
+}
@@ -0,0 +1,115 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A type map of arbitrary data attached to a connection.
+///
+/// At most one value per type can be stored.
+/// Cloning is cheap and all clones refer to the same data, i.e. a value inserted by one handler is visible to all other handlers of the same connection.
+#[derive(Clone, Default)]
+pub struct Extensions {
+    inner: Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>>,
+}
+
+impl Extensions {
+    /// Inserts a value, returning the previous value of the same type if there was one.
+    pub fn insert<T: Send + 'static>(&self, value: T) -> Option<T> {
+        self.inner
+            .lock()
+            .expect("not poisoned")
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// Returns a clone of the value of the given type.
+    pub fn get<T: Clone + Send + 'static>(&self) -> Option<T> {
+        self.inner
+            .lock()
+            .expect("not poisoned")
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    }
+
+    /// Removes the value of the given type and returns it.
+    pub fn remove<T: Send + 'static>(&self) -> Option<T> {
+        self.inner
+            .lock()
+            .expect("not poisoned")
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn contains<T: Send + 'static>(&self) -> bool {
+        self.inner
+            .lock()
+            .expect("not poisoned")
+            .contains_key(&TypeId::of::<T>())
+    }
+
+    /// Moves all values of `other` into `self`, overwriting values of the same type.
+    pub(crate) fn absorb(&self, other: &Extensions) {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
+            return;
+        }
+
+        let values = std::mem::take(&mut *other.inner.lock().expect("not poisoned"));
+
+        self.inner.lock().expect("not poisoned").extend(values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct SessionParameters {
+        version: u8,
+    }
+
+    #[test]
+    fn values_are_shared_between_clones() {
+        let extensions = Extensions::default();
+        let clone = extensions.clone();
+
+        extensions.insert(SessionParameters { version: 1 });
+
+        assert_eq!(
+            clone.get::<SessionParameters>(),
+            Some(SessionParameters { version: 1 })
+        );
+    }
+
+    #[test]
+    fn insert_returns_previous_value_of_same_type() {
+        let extensions = Extensions::default();
+
+        extensions.insert(SessionParameters { version: 1 });
+        let previous = extensions.insert(SessionParameters { version: 2 });
+
+        assert_eq!(previous, Some(SessionParameters { version: 1 }));
+        assert_eq!(
+            extensions.remove::<SessionParameters>(),
+            Some(SessionParameters { version: 2 })
+        );
+        assert!(!extensions.contains::<SessionParameters>());
+    }
+
+    #[test]
+    fn absorb_moves_values_over() {
+        let previous = Extensions::default();
+        previous.insert(SessionParameters { version: 1 });
+
+        let current = Extensions::default();
+        current.absorb(&previous);
+
+        assert_eq!(
+            current.get::<SessionParameters>(),
+            Some(SessionParameters { version: 1 })
+        );
+        assert!(!previous.contains::<SessionParameters>());
+    }
+}
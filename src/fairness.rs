@@ -0,0 +1,302 @@
+//! Weighted fair scheduling of substream writes between the protocols of a connection.
+//!
+//! Every protocol has a virtual time which advances by the number of bytes written divided by the weight of the protocol.
+//! While several protocols are writing, a write may only proceed if its protocol is not ahead of the others by more than one chunk.
+//! Writes are split into chunks so a bulk transfer cannot block the connection with a single large frame.
+//!
+//! A protocol counts as writing if it asked for or completed a write within the last [`CONTENTION_WINDOW`].
+//! Protocols whose write is stuck in the muxer, e.g. because the peer does not read, do not count, so they cannot hold back the others.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// The maximum number of bytes handed to the muxer in a single write while fair scheduling is enabled.
+pub const FAIR_SCHEDULING_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Resolution of the virtual time, allows weights to have an effect on small writes.
+const SCALE: u64 = 256;
+
+/// How far a protocol may be ahead of the others, in units of virtual time.
+const QUANTUM: u64 = FAIR_SCHEDULING_CHUNK_SIZE as u64 * SCALE;
+
+/// How long a protocol counts as writing after it last asked for or completed a write.
+const CONTENTION_WINDOW: Duration = Duration::from_millis(10);
+
+/// Shares the write capacity of a connection between its protocols according to their weights.
+///
+/// See [`Node::with_fair_scheduling`](crate::Node::with_fair_scheduling).
+#[derive(Clone)]
+pub(crate) struct WriteScheduler {
+    weights: Arc<HashMap<&'static str, u32>>,
+    state: Arc<Mutex<State>>,
+}
+
+/// The scheduling state of a single substream, see [`WriteScheduler::poll_acquire`].
+#[derive(Default)]
+pub(crate) struct WriteTicket {
+    /// Whether the substream was granted a write that has not completed yet, i.e. is pending in the muxer.
+    granted: bool,
+    /// Wakes a gated substream once the protocols holding it back might have stopped writing.
+    recheck: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+#[derive(Default)]
+struct State {
+    lanes: HashMap<&'static str, Lane>,
+    /// The virtual time at which the last completed write started.
+    clock: u64,
+    gated: Vec<Waker>,
+}
+
+#[derive(Default)]
+struct Lane {
+    virtual_time: u64,
+    /// When a substream of the protocol last asked for or completed a write.
+    last_active: Option<Instant>,
+    /// The number of substreams of the protocol with a write pending in the muxer.
+    blocked: usize,
+}
+
+impl Lane {
+    fn is_contending(&self, now: Instant) -> bool {
+        let recently_active = self.last_active.map_or(false, |last_active| {
+            now.duration_since(last_active) < CONTENTION_WINDOW
+        });
+
+        recently_active && self.blocked == 0
+    }
+}
+
+impl State {
+    /// The smallest virtual time of all protocols that are writing, except for the given one.
+    fn min_virtual_time_except(&self, protocol: &'static str, now: Instant) -> Option<u64> {
+        self.lanes
+            .iter()
+            .filter(|(other, lane)| **other != protocol && lane.is_contending(now))
+            .map(|(_, lane)| lane.virtual_time)
+            .min()
+    }
+
+    fn wake_gated(&mut self) {
+        for waker in self.gated.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl WriteScheduler {
+    /// Protocols without a weight get a weight of 1.
+    pub(crate) fn new(weights: Arc<HashMap<&'static str, u32>>) -> Self {
+        Self {
+            weights,
+            state: Arc::default(),
+        }
+    }
+
+    /// Waits until the given protocol may write and returns how many bytes of `len` it may write.
+    ///
+    /// Once this returned, the write must be reported through [`WriteScheduler::complete`], until then the protocol counts as blocked in the muxer.
+    pub(crate) fn poll_acquire(
+        &self,
+        protocol: &'static str,
+        ticket: &mut WriteTicket,
+        cx: &mut Context<'_>,
+        len: usize,
+    ) -> Poll<usize> {
+        let len = len.min(FAIR_SCHEDULING_CHUNK_SIZE);
+
+        // The muxer was not ready for the write we granted earlier, which is not our call to hold back.
+        if ticket.granted {
+            return Poll::Ready(len);
+        }
+
+        loop {
+            if self.try_acquire(protocol, cx) {
+                ticket.granted = true;
+                ticket.recheck = None;
+
+                return Poll::Ready(len);
+            }
+
+            // Protocols holding us back are only woken by their own writes, so recheck once they might have gone quiet.
+            let recheck = ticket
+                .recheck
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(CONTENTION_WINDOW)));
+            if recheck.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            ticket.recheck = None;
+        }
+    }
+
+    fn try_acquire(&self, protocol: &'static str, cx: &mut Context<'_>) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("not poisoned");
+        let others = state.min_virtual_time_except(protocol, now);
+        let clock = state.clock;
+        let lane = state.lanes.entry(protocol).or_default();
+
+        // A protocol that was idle must not catch up on the capacity it did not use.
+        if !lane.is_contending(now) {
+            lane.virtual_time = lane.virtual_time.max(clock);
+        }
+        lane.last_active = Some(now);
+
+        match others {
+            Some(others) if lane.virtual_time > others.saturating_add(QUANTUM) => {
+                state.gated.push(cx.waker().clone());
+
+                false
+            }
+            _ => {
+                lane.blocked += 1;
+
+                true
+            }
+        }
+    }
+
+    /// Accounts for `written` bytes of the given protocol and ends the write granted through the ticket, if any.
+    pub(crate) fn complete(
+        &self,
+        protocol: &'static str,
+        ticket: &mut WriteTicket,
+        written: usize,
+    ) {
+        let weight = u64::from(self.weights.get(protocol).copied().unwrap_or(1).max(1));
+        let mut state = self.state.lock().expect("not poisoned");
+        let lane = state.lanes.entry(protocol).or_default();
+
+        let started = lane.virtual_time;
+        lane.virtual_time = lane
+            .virtual_time
+            .saturating_add(written as u64 * SCALE / weight);
+        lane.last_active = Some(Instant::now());
+        if std::mem::take(&mut ticket.granted) {
+            lane.blocked -= 1;
+        }
+        ticket.recheck = None;
+
+        state.clock = state.clock.max(started);
+        state.wake_gated();
+    }
+}
+
+impl WriteTicket {
+    /// Whether a write was granted that has to be reported through [`WriteScheduler::complete`].
+    pub(crate) fn is_granted(&self) -> bool {
+        self.granted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+
+    #[tokio::test]
+    async fn bulk_protocol_yields_to_interactive_protocol_while_it_is_writing() {
+        let scheduler = WriteScheduler::new(Arc::new(HashMap::from([("/interactive", 4)])));
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut bulk = WriteTicket::default();
+        let mut interactive = WriteTicket::default();
+
+        // Without contention, bulk writes proceed in chunks.
+        for _ in 0..4 {
+            let len = scheduler.poll_acquire("/bulk", &mut bulk, &mut cx, 1024 * 1024);
+            assert_eq!(len, Poll::Ready(FAIR_SCHEDULING_CHUNK_SIZE));
+            scheduler.complete("/bulk", &mut bulk, FAIR_SCHEDULING_CHUNK_SIZE);
+        }
+
+        // The interactive protocol starts at the virtual time of the last bulk write instead of being far behind.
+        assert!(scheduler
+            .poll_acquire("/interactive", &mut interactive, &mut cx, 100)
+            .is_ready());
+        scheduler.complete("/interactive", &mut interactive, 100);
+        assert!(scheduler
+            .poll_acquire("/bulk", &mut bulk, &mut cx, 1024 * 1024)
+            .is_ready());
+        scheduler.complete("/bulk", &mut bulk, FAIR_SCHEDULING_CHUNK_SIZE);
+
+        // Bulk is now more than one chunk ahead of the interactive protocol, which just wrote.
+        assert!(scheduler
+            .poll_acquire("/bulk", &mut bulk, &mut cx, 1024 * 1024)
+            .is_pending());
+
+        assert!(scheduler
+            .poll_acquire("/interactive", &mut interactive, &mut cx, 100 * 1024)
+            .is_ready());
+        scheduler.complete("/interactive", &mut interactive, 100 * 1024);
+        assert!(scheduler
+            .poll_acquire("/bulk", &mut bulk, &mut cx, 1024 * 1024)
+            .is_ready());
+    }
+
+    #[tokio::test]
+    async fn protocol_stuck_in_the_muxer_does_not_hold_back_others() {
+        let scheduler = WriteScheduler::new(Arc::default());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut stuck = WriteTicket::default();
+        let mut active = WriteTicket::default();
+
+        assert!(scheduler
+            .poll_acquire("/stuck", &mut stuck, &mut cx, 100)
+            .is_ready());
+
+        // The write of `/stuck` never completes, as if the peer did not read.
+        for _ in 0..100 {
+            let len = scheduler.poll_acquire("/active", &mut active, &mut cx, 1024 * 1024);
+            assert_eq!(len, Poll::Ready(FAIR_SCHEDULING_CHUNK_SIZE));
+            scheduler.complete("/active", &mut active, FAIR_SCHEDULING_CHUNK_SIZE);
+        }
+    }
+
+    #[tokio::test]
+    async fn protocol_that_went_quiet_does_not_hold_back_others() {
+        let scheduler = WriteScheduler::new(Arc::default());
+        let mut quiet = WriteTicket::default();
+        let mut active = WriteTicket::default();
+
+        futures::future::poll_fn(|cx| scheduler.poll_acquire("/quiet", &mut quiet, cx, 100)).await;
+        scheduler.complete("/quiet", &mut quiet, 100);
+
+        let transfer = async {
+            for _ in 0..100 {
+                futures::future::poll_fn(|cx| {
+                    scheduler.poll_acquire("/active", &mut active, cx, 1024 * 1024)
+                })
+                .await;
+                scheduler.complete("/active", &mut active, FAIR_SCHEDULING_CHUNK_SIZE);
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(1), transfer)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn weights_divide_capacity_proportionally() {
+        let scheduler = WriteScheduler::new(Arc::new(HashMap::from([("/heavy", 3)])));
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut heavy = WriteTicket::default();
+        let mut light = WriteTicket::default();
+        let mut written = HashMap::<&str, usize>::new();
+
+        for _ in 0..2000 {
+            for (protocol, ticket) in [("/heavy", &mut heavy), ("/light", &mut light)] {
+                if let Poll::Ready(len) = scheduler.poll_acquire(protocol, ticket, &mut cx, 1024) {
+                    scheduler.complete(protocol, ticket, len);
+                    *written.entry(protocol).or_default() += len;
+                }
+            }
+        }
+
+        let ratio = written["/heavy"] as f64 / written["/light"] as f64;
+        assert!((2.5..=3.5).contains(&ratio), "ratio was {ratio}");
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{ActorBridge, NewInboundSubstream};
+use anyhow::{Context as _, Result};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use xtra::message_channel::{MessageChannel, StrongMessageChannel};
+
+/// How [`Node::with_handlers`](crate::Node::with_handlers) picks one of several handlers of the same protocol.
+///
+/// Every substream is delivered to exactly one handler, handlers that are gone are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchStrategy {
+    /// Handlers take turns.
+    RoundRobin,
+    /// The handler with the fewest substreams that were delivered but not handled yet.
+    ///
+    /// Ties are broken in turns, like [`DispatchStrategy::RoundRobin`].
+    LeastBusy,
+}
+
+/// Spreads the inbound substreams of a protocol over several handlers.
+///
+/// A handler is busy with a substream until it returns from handling it.
+pub(crate) struct FanOut {
+    handlers: Vec<Handler>,
+    strategy: DispatchStrategy,
+    next: AtomicUsize,
+    max_in_flight: Option<usize>,
+}
+
+struct Handler {
+    channel: Box<dyn StrongMessageChannel<NewInboundSubstream>>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl FanOut {
+    pub(crate) fn new(
+        handlers: Vec<Box<dyn StrongMessageChannel<NewInboundSubstream>>>,
+        strategy: DispatchStrategy,
+    ) -> Self {
+        Self {
+            handlers: handlers
+                .into_iter()
+                .map(|channel| Handler {
+                    channel,
+                    in_flight: Arc::default(),
+                })
+                .collect(),
+            strategy,
+            next: AtomicUsize::default(),
+            max_in_flight: None,
+        }
+    }
+
+    /// Skip handlers that are busy with `max` substreams already.
+    pub(crate) fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = Some(max.max(1));
+
+        self
+    }
+
+    /// Hands the substream to one of the handlers, returning when that handler is done with it.
+    ///
+    /// Hands the substream back if there is no handler to take it, i.e. all of them are gone or at their limit.
+    pub(crate) fn try_deliver(
+        &self,
+        substream: NewInboundSubstream,
+    ) -> Result<BoxFuture<'static, Result<()>>, NewInboundSubstream> {
+        let handler = match self.pick() {
+            Some(handler) => handler,
+            None => return Err(substream),
+        };
+
+        let in_flight = handler.in_flight.clone();
+        in_flight.fetch_add(1, Ordering::Relaxed);
+        let send = handler.channel.send(substream);
+
+        Ok(async move {
+            let result = send.await;
+            in_flight.fetch_sub(1, Ordering::Relaxed);
+
+            result.context("Handler of inbound substreams is gone")
+        }
+        .boxed())
+    }
+
+    fn pick(&self) -> Option<&Handler> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let max_in_flight = self.max_in_flight.unwrap_or(usize::MAX);
+        let mut candidates = (0..self.handlers.len())
+            .map(|offset| &self.handlers[(start + offset) % self.handlers.len()])
+            .filter(|handler| handler.channel.is_connected())
+            .filter(|handler| handler.in_flight.load(Ordering::Relaxed) < max_in_flight);
+
+        match self.strategy {
+            DispatchStrategy::RoundRobin => candidates.next(),
+            DispatchStrategy::LeastBusy => {
+                candidates.min_by_key(|handler| handler.in_flight.load(Ordering::Relaxed))
+            }
+        }
+    }
+}
+
+impl ActorBridge for FanOut {
+    fn deliver(&self, substream: NewInboundSubstream) -> BoxFuture<'static, Result<()>> {
+        self.try_deliver(substream).unwrap_or_else(|_| {
+            futures::future::ready(Err(anyhow::anyhow!(
+                "All handlers of the protocol are gone"
+            )))
+            .boxed()
+        })
+    }
+}
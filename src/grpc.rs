@@ -0,0 +1,71 @@
+use crate::{Error, NewInboundSubstream, Node, OpenSubstream, Substream, TokioCompat};
+use futures::channel::mpsc;
+use futures::{Stream, StreamExt};
+use libp2p_core::PeerId;
+use std::io;
+use tonic::transport::server::Connected;
+use tonic::transport::{Channel, Endpoint, Uri};
+use xtra::Address;
+use xtra_productivity::xtra_productivity;
+
+/// A substream carrying a single HTTP/2 connection of a gRPC client or server.
+pub type GrpcStream = TokioCompat<Substream>;
+
+/// Accepts inbound substreams of a protocol and hands them to a [`tonic`] server.
+///
+/// Register the actor as the handler of the gRPC protocol and pass the incoming stream returned by [`grpc_listener`] to `Server::serve_with_incoming`.
+/// Services can retrieve the [`PeerId`] of the client from the request extensions.
+pub struct GrpcListener {
+    sender: mpsc::UnboundedSender<GrpcStream>,
+}
+
+/// Creates a [`GrpcListener`] together with the stream of connections to serve.
+pub fn grpc_listener() -> (
+    GrpcListener,
+    impl Stream<Item = Result<GrpcStream, io::Error>> + Send + 'static,
+) {
+    let (sender, receiver) = mpsc::unbounded();
+
+    (GrpcListener { sender }, receiver.map(Ok))
+}
+
+/// Creates a [`tonic`] [`Channel`] that runs its HTTP/2 connection over a substream of the given protocol to `peer`.
+///
+/// Whenever the channel (re-)connects, a new substream is opened through the given [`Node`].
+/// Requests fail while we are not connected to the peer.
+pub fn grpc_channel(node: Address<Node>, peer: PeerId, protocol: &'static str) -> Channel {
+    let connector = tower::service_fn(move |_: Uri| {
+        let node = node.clone();
+
+        async move {
+            let stream = node
+                .send(OpenSubstream::single_protocol(peer, protocol))
+                .await
+                .map_err(|_| Error::NoConnection(peer))??;
+
+            Ok::<_, Error>(stream.compat())
+        }
+    });
+
+    // The URI is only used for the `:authority` pseudo-header, the connector ignores it.
+    Endpoint::from_static("http://libp2p").connect_with_connector_lazy(connector)
+}
+
+#[xtra_productivity(message_impl = false)]
+impl GrpcListener {
+    async fn handle(&mut self, msg: NewInboundSubstream) {
+        if self.sender.unbounded_send(msg.stream.compat()).is_err() {
+            tracing::debug!(peer = %msg.peer, "Dropping gRPC substream because server is gone");
+        }
+    }
+}
+
+impl xtra::Actor for GrpcListener {}
+
+impl Connected for GrpcStream {
+    type ConnectInfo = PeerId;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.get_ref().peer()
+    }
+}
@@ -0,0 +1,169 @@
+use crate::stats::Counters;
+use futures::{AsyncRead, AsyncWrite};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// The default number of bytes a remote may send before the handshake completes, see [`Node::with_max_handshake_size`](crate::Node::with_max_handshake_size).
+pub const DEFAULT_MAX_HANDSHAKE_SIZE: usize = 16 * 1024;
+
+/// A connection that fails reads once the remote sent more than `max` bytes before the handshake completed.
+///
+/// This covers everything before authentication, i.e. the protocol negotiation for the handshake and the handshake messages themselves.
+/// Once the handshake is done, the limit is lifted through the [`LiftHandle`].
+/// Exceeding the limit is counted in the given [`Counters`].
+pub struct HandshakeLimited<C> {
+    inner: C,
+    remaining: usize,
+    max: usize,
+    lifted: Arc<AtomicBool>,
+    exceeded: bool,
+    counters: Counters,
+}
+
+/// Lifts the limit of a [`HandshakeLimited`] connection.
+#[derive(Clone)]
+pub struct LiftHandle(Arc<AtomicBool>);
+
+impl<C> HandshakeLimited<C> {
+    pub fn new(inner: C, max: usize, counters: Counters) -> Self {
+        Self {
+            inner,
+            remaining: max,
+            max,
+            lifted: Arc::default(),
+            exceeded: false,
+            counters,
+        }
+    }
+
+    pub fn lift_handle(&self) -> LiftHandle {
+        LiftHandle(self.lifted.clone())
+    }
+}
+
+impl LiftHandle {
+    pub fn lift(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Holds the handshake size limit of a [`Node`](crate::Node), shared with its transport.
+#[derive(Clone)]
+pub struct HandshakeLimitSlot {
+    inner: Arc<AtomicUsize>,
+}
+
+impl HandshakeLimitSlot {
+    pub fn set(&self, max: usize) {
+        self.inner.store(max, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.inner.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for HandshakeLimitSlot {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(AtomicUsize::new(DEFAULT_MAX_HANDSHAKE_SIZE)),
+        }
+    }
+}
+
+impl<C> AsyncRead for HandshakeLimited<C>
+where
+    C: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.lifted.load(Ordering::Relaxed) {
+            return Pin::new(&mut self.inner).poll_read(cx, buf);
+        }
+
+        if self.exceeded {
+            return Poll::Ready(Err(limit_exceeded(self.max)));
+        }
+
+        // Never read more than one byte past the limit, this way an oversized message is detected without receiving it in full.
+        let len = buf.len().min(self.remaining.saturating_add(1));
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf[..len]))?;
+
+        match self.remaining.checked_sub(n) {
+            Some(remaining) => {
+                self.remaining = remaining;
+
+                Poll::Ready(Ok(n))
+            }
+            None => {
+                self.exceeded = true;
+                self.counters.oversized_handshake_rejected();
+
+                Poll::Ready(Err(limit_exceeded(self.max)))
+            }
+        }
+    }
+}
+
+impl<C> AsyncWrite for HandshakeLimited<C>
+where
+    C: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+fn limit_exceeded(max: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Remote sent more than {max} bytes during handshake"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use futures::AsyncReadExt;
+
+    #[tokio::test]
+    async fn fails_and_counts_once_limit_is_exceeded() {
+        let counters = Counters::default();
+        let mut conn = HandshakeLimited::new(Cursor::new(vec![0u8; 64]), 32, counters.clone());
+
+        let mut buf = [0u8; 32];
+        conn.read_exact(&mut buf).await.unwrap();
+        let error = conn.read_exact(&mut buf).await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(counters.read(false).4, 1);
+    }
+
+    #[tokio::test]
+    async fn no_limit_after_lifting() {
+        let mut conn = HandshakeLimited::new(Cursor::new(vec![0u8; 64]), 32, Counters::default());
+        conn.lift_handle().lift();
+
+        let mut buf = [0u8; 64];
+        conn.read_exact(&mut buf).await.unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Overall health of a [`Node`](crate::Node), see [`GetHealth`](crate::GetHealth).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Health {
+    Healthy,
+    /// The node works but misses one of its targets, e.g. it has fewer peers than desired or many dials fail.
+    Degraded,
+    /// The node cannot fulfil its purpose, e.g. none of its listeners are bound or it has no peers at all.
+    Unhealthy,
+}
+
+/// What [`GetHealth`](crate::GetHealth) judges the [`Node`](crate::Node) against, see [`Node::with_health_thresholds`](crate::Node::with_health_thresholds).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthThresholds {
+    /// The node is degraded with fewer connected peers and unhealthy without any.
+    ///
+    /// Defaults to 0, i.e. the number of peers does not matter.
+    pub target_peers: usize,
+    /// The node is degraded once more than this fraction of recent dials failed.
+    pub max_dial_failure_rate: f64,
+    /// How far back dials count towards the failure rate.
+    pub dial_window: Duration,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            target_peers: 0,
+            max_dial_failure_rate: 0.5,
+            dial_window: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// The health of a [`Node`](crate::Node) together with the figures it was derived from.
+///
+/// This is meant to back a health endpoint of the host application, e.g. answering with a non-success status code unless [`Health::Healthy`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    pub health: Health,
+    /// The number of listeners accepting connections.
+    pub active_listeners: usize,
+    /// The number of listeners that failed and were not restarted since.
+    pub failed_listeners: usize,
+    pub connected_peers: usize,
+    pub target_peers: usize,
+    /// The fraction of dials that failed within [`HealthThresholds::dial_window`], 0 if there were none.
+    pub dial_failure_rate: f64,
+}
+
+impl HealthThresholds {
+    pub(crate) fn evaluate(
+        &self,
+        active_listeners: usize,
+        failed_listeners: usize,
+        connected_peers: usize,
+        dial_failure_rate: f64,
+    ) -> HealthReport {
+        let health = if (failed_listeners > 0 && active_listeners == 0)
+            || (self.target_peers > 0 && connected_peers == 0)
+        {
+            Health::Unhealthy
+        } else if failed_listeners > 0
+            || connected_peers < self.target_peers
+            || dial_failure_rate > self.max_dial_failure_rate
+        {
+            Health::Degraded
+        } else {
+            Health::Healthy
+        };
+
+        HealthReport {
+            health,
+            active_listeners,
+            failed_listeners,
+            connected_peers,
+            target_peers: self.target_peers,
+            dial_failure_rate,
+        }
+    }
+}
+
+/// The outcomes of recent dials.
+#[derive(Default)]
+pub(crate) struct DialHistory {
+    outcomes: VecDeque<(Instant, bool)>,
+}
+
+impl DialHistory {
+    /// Only this many outcomes are kept, regardless of the window.
+    const CAPACITY: usize = 1024;
+
+    pub(crate) fn record(&mut self, succeeded: bool) {
+        if self.outcomes.len() == Self::CAPACITY {
+            self.outcomes.pop_front();
+        }
+
+        self.outcomes.push_back((Instant::now(), succeeded));
+    }
+
+    pub(crate) fn failure_rate(&self, window: Duration) -> f64 {
+        let now = Instant::now();
+        let (total, failed) = self
+            .outcomes
+            .iter()
+            .filter(|(at, _)| now.saturating_duration_since(*at) <= window)
+            .fold((0, 0), |(total, failed), (_, succeeded)| {
+                (total + 1, failed + usize::from(!succeeded))
+            });
+
+        if total == 0 {
+            return 0.0;
+        }
+
+        failed as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_all_peers_or_listeners_is_unhealthy() {
+        let thresholds = HealthThresholds {
+            target_peers: 3,
+            ..HealthThresholds::default()
+        };
+
+        assert_eq!(thresholds.evaluate(1, 0, 0, 0.0).health, Health::Unhealthy);
+        assert_eq!(thresholds.evaluate(0, 1, 3, 0.0).health, Health::Unhealthy);
+    }
+
+    #[test]
+    fn missing_targets_is_degraded() {
+        let thresholds = HealthThresholds {
+            target_peers: 3,
+            ..HealthThresholds::default()
+        };
+
+        assert_eq!(thresholds.evaluate(1, 0, 2, 0.0).health, Health::Degraded);
+        assert_eq!(thresholds.evaluate(1, 1, 3, 0.0).health, Health::Degraded);
+        assert_eq!(thresholds.evaluate(1, 0, 3, 0.6).health, Health::Degraded);
+        assert_eq!(thresholds.evaluate(1, 0, 3, 0.5).health, Health::Healthy);
+    }
+
+    #[test]
+    fn failure_rate_only_considers_dials_within_window() {
+        let mut history = DialHistory::default();
+        history
+            .outcomes
+            .push_back((Instant::now() - Duration::from_secs(60), false));
+        history.record(true);
+        history.record(false);
+
+        assert_eq!(history.failure_rate(Duration::from_secs(10)), 0.5);
+        assert_eq!(history.failure_rate(Duration::from_secs(120)), 2.0 / 3.0);
+    }
+}
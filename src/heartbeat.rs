@@ -0,0 +1,105 @@
+use anyhow::{bail, Context as _, Result};
+use futures::io::{ReadHalf, WriteHalf};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::time::Duration;
+use void::Void;
+
+pub const PROTOCOL: &str = "/libp2p-xtra/heartbeat/1.0.0";
+
+/// The maximum size of a heartbeat payload in bytes.
+pub const MAX_PAYLOAD_SIZE: usize = 1024;
+
+/// Runs the heartbeat protocol on the given substream.
+///
+/// The protocol is symmetric: both sides send a heartbeat carrying the payload returned by `payload` every `interval`.
+/// Every heartbeat received from the other side is passed to `on_heartbeat`.
+/// This is useful for exchanging small pieces of application state like the version of an order book.
+///
+/// Only returns once the substream fails or the other side misses `max_missed` consecutive heartbeats.
+/// The caller is expected to treat the connection as dead at that point, e.g. by sending [`Disconnect`](crate::Disconnect).
+pub async fn run<S, P, H>(
+    stream: S,
+    interval: Duration,
+    max_missed: u32,
+    payload: P,
+    on_heartbeat: H,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    P: FnMut() -> Vec<u8>,
+    H: FnMut(Vec<u8>),
+{
+    let (reader, writer) = stream.split();
+
+    let (never, _) = futures::future::try_join(
+        send_heartbeats(writer, interval, payload),
+        receive_heartbeats(reader, interval * max_missed, max_missed, on_heartbeat),
+    )
+    .await?;
+
+    match never {}
+}
+
+async fn send_heartbeats<S, P>(
+    mut writer: WriteHalf<S>,
+    interval: Duration,
+    mut payload: P,
+) -> Result<Void>
+where
+    S: AsyncWrite + Unpin,
+    P: FnMut() -> Vec<u8>,
+{
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        let payload = payload();
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            bail!(
+                "Heartbeat payload of {} bytes exceeds maximum of {MAX_PAYLOAD_SIZE} bytes",
+                payload.len()
+            );
+        }
+
+        writer
+            .write_all(&(payload.len() as u16).to_be_bytes())
+            .await?;
+        writer.write_all(&payload).await?;
+        writer.flush().await?;
+    }
+}
+
+async fn receive_heartbeats<S, H>(
+    mut reader: ReadHalf<S>,
+    deadline: Duration,
+    max_missed: u32,
+    mut on_heartbeat: H,
+) -> Result<Void>
+where
+    S: AsyncRead + Unpin,
+    H: FnMut(Vec<u8>),
+{
+    loop {
+        let payload = tokio::time::timeout(deadline, async {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len).await?;
+
+            let len = u16::from_be_bytes(len) as usize;
+            if len > MAX_PAYLOAD_SIZE {
+                bail!(
+                    "Heartbeat payload of {len} bytes exceeds maximum of {MAX_PAYLOAD_SIZE} bytes"
+                );
+            }
+
+            let mut payload = vec![0u8; len];
+            reader.read_exact(&mut payload).await?;
+
+            anyhow::Ok(payload)
+        })
+        .await
+        .with_context(|| format!("Missed {max_missed} heartbeats"))??;
+
+        on_heartbeat(payload);
+    }
+}
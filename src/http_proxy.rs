@@ -0,0 +1,276 @@
+use crate::compat::Compat;
+use futures::future::{BoxFuture, Pending};
+use futures::stream::BoxStream;
+use futures::{AsyncReadExt, AsyncWriteExt, FutureExt};
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::transport::{ListenerEvent, TransportError};
+use libp2p_core::{Multiaddr, Transport};
+use std::io;
+use std::net::SocketAddr;
+
+/// The maximum size of the response headers we accept from the proxy.
+const MAX_RESPONSE_SIZE: usize = 8 * 1024;
+
+/// A transport that dials `/tcp` addresses through an HTTP proxy using the `CONNECT` method.
+///
+/// Many enterprise networks only allow outgoing connections through such a proxy.
+/// The transport can only dial, listening is not supported.
+/// Like any other transport, it is meant to be passed to [`Node::new`](crate::Node::new) which applies the usual upgrades on top.
+#[derive(Clone)]
+pub struct HttpConnectTransport {
+    proxy: SocketAddr,
+    credentials: Option<(String, String)>,
+}
+
+impl HttpConnectTransport {
+    pub fn new(proxy: SocketAddr) -> Self {
+        Self {
+            proxy,
+            credentials: None,
+        }
+    }
+
+    /// Authenticates with the proxy using HTTP basic authentication.
+    pub fn with_basic_auth(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+}
+
+impl Transport for HttpConnectTransport {
+    type Output = HttpProxyStream;
+    type Error = io::Error;
+    type Listener =
+        BoxStream<'static, Result<ListenerEvent<Self::ListenerUpgrade, Self::Error>, Self::Error>>;
+    type ListenerUpgrade = Pending<Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        let target = match tunnel_target(&addr) {
+            Some(target) => target,
+            None => return Err(TransportError::MultiaddrNotSupported(addr)),
+        };
+
+        Ok(async move {
+            let stream = tokio::net::TcpStream::connect(self.proxy).await?;
+            let mut stream = Compat::new(stream);
+
+            let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+            if let Some((username, password)) = &self.credentials {
+                let credentials = base64(format!("{username}:{password}").as_bytes());
+                request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+            }
+            request.push_str("\r\n");
+
+            stream.write_all(request.as_bytes()).await?;
+            stream.flush().await?;
+
+            let response = read_response_head(&mut stream).await?;
+            let status = response
+                .lines()
+                .next()
+                .and_then(|status_line| status_line.split_whitespace().nth(1))
+                .unwrap_or_default();
+
+            if status != "200" {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("Proxy refused to connect to {target} with status {status}"),
+                ));
+            }
+
+            Ok(stream)
+        }
+        .boxed())
+    }
+
+    fn dial_as_listener(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        self.dial(addr)
+    }
+
+    fn address_translation(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+/// A connection established by [`HttpConnectTransport`].
+pub type HttpProxyStream = Compat<tokio::net::TcpStream>;
+
+/// Extracts the `host:port` to tunnel to from an address like `/dns/<host>/tcp/<port>`, optionally followed by `/p2p/<peer-id>`.
+fn tunnel_target(addr: &Multiaddr) -> Option<String> {
+    let mut protocols = addr.iter();
+
+    let host = match protocols.next()? {
+        Protocol::Ip4(ip) => ip.to_string(),
+        Protocol::Ip6(ip) => format!("[{ip}]"),
+        Protocol::Dns(host) | Protocol::Dns4(host) | Protocol::Dns6(host) => host.into_owned(),
+        _ => return None,
+    };
+    let port = match protocols.next()? {
+        Protocol::Tcp(port) => port,
+        _ => return None,
+    };
+
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => Some(format!("{host}:{port}")),
+        Some(_) => None,
+    }
+}
+
+/// Reads the status line and headers of the proxy's response.
+///
+/// Reads byte by byte so we never consume data that already belongs to the tunnel.
+async fn read_response_head(stream: &mut HttpProxyStream) -> io::Result<String> {
+    let mut head = Vec::new();
+
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_RESPONSE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Proxy response exceeds maximum size",
+            ));
+        }
+
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+
+    String::from_utf8(head).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let indices = [
+            bytes[0] >> 2,
+            (bytes[0] & 0b11) << 4 | bytes[1] >> 4,
+            (bytes[1] & 0b1111) << 2 | bytes[2] >> 6,
+            bytes[2] & 0b111111,
+        ];
+
+        for (i, index) in indices.iter().enumerate() {
+            if i <= chunk.len() {
+                output.push(ALPHABET[*index as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    #[test]
+    fn encodes_base64_with_padding() {
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64(b"user:pa"), "dXNlcjpwYQ==");
+        assert_eq!(base64(b"user:pas"), "dXNlcjpwYXM=");
+    }
+
+    #[test]
+    fn rejects_non_tcp_address() {
+        let result = HttpConnectTransport::new("127.0.0.1:3128".parse().unwrap())
+            .dial("/memory/10000".parse().unwrap());
+
+        assert!(matches!(
+            result,
+            Err(TransportError::MultiaddrNotSupported(_))
+        ))
+    }
+
+    #[tokio::test]
+    async fn tunnels_through_proxy_with_basic_auth() {
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_address = proxy.local_addr().unwrap();
+
+        let proxy_task = tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+
+            String::from_utf8(request).unwrap()
+        });
+
+        let mut stream = HttpConnectTransport::new(proxy_address)
+            .with_basic_auth("user", "pass")
+            .dial("/dns/example.com/tcp/4001".parse().unwrap())
+            .unwrap()
+            .await
+            .unwrap();
+
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        let request = proxy_task.await.unwrap();
+
+        assert_eq!(&buf, b"hello");
+        assert_eq!(
+            request,
+            "CONNECT example.com:4001 HTTP/1.1\r\nHost: example.com:4001\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_if_proxy_refuses() {
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_address = proxy.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let error = HttpConnectTransport::new(proxy_address)
+            .dial("/ip4/10.0.0.1/tcp/4001".parse().unwrap())
+            .unwrap()
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Turns inbound connections away before their handshake once the inbound connection limit is reached, see [`Node::with_max_inbound_connections`](crate::Node::with_max_inbound_connections).
+///
+/// Whether a connection may take a reserved slot or replace an existing connection depends on the peer, which is only known after the handshake.
+/// While the limit is reached, a single handshake at a time is let through so that known peers can still get in, without letting strangers keep us busy with handshakes.
+#[derive(Clone)]
+pub(crate) struct InboundGate {
+    inner: Arc<GateState>,
+}
+
+struct GateState {
+    /// `usize::MAX` if there is no limit.
+    max: AtomicUsize,
+    established: AtomicUsize,
+    handshaking: AtomicUsize,
+}
+
+/// Marks an inbound handshake as in progress until dropped.
+pub(crate) struct HandshakePermit {
+    inner: Arc<GateState>,
+}
+
+impl Default for InboundGate {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(GateState {
+                max: AtomicUsize::new(usize::MAX),
+                established: AtomicUsize::new(0),
+                handshaking: AtomicUsize::new(0),
+            }),
+        }
+    }
+}
+
+impl InboundGate {
+    pub(crate) fn set_max(&self, max: Option<usize>) {
+        self.inner
+            .max
+            .store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// Updates the number of established inbound connections.
+    pub(crate) fn set_established(&self, established: usize) {
+        self.inner.established.store(established, Ordering::Relaxed);
+    }
+
+    /// Admits a handshake unless the limit is reached and another handshake is already in progress.
+    pub(crate) fn try_admit(&self) -> Option<HandshakePermit> {
+        let handshaking = self.inner.handshaking.fetch_add(1, Ordering::Relaxed);
+        let permit = HandshakePermit {
+            inner: self.inner.clone(),
+        };
+
+        let full = self.inner.established.load(Ordering::Relaxed)
+            >= self.inner.max.load(Ordering::Relaxed);
+        if full && handshaking > 0 {
+            return None;
+        }
+
+        Some(permit)
+    }
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        self.inner.handshaking.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admits_one_handshake_at_a_time_once_full() {
+        let gate = InboundGate::default();
+        gate.set_max(Some(2));
+        gate.set_established(1);
+
+        let first = gate.try_admit().unwrap();
+        let second = gate.try_admit().unwrap();
+
+        gate.set_established(2);
+        assert!(gate.try_admit().is_none());
+
+        drop((first, second));
+        let _only = gate.try_admit().unwrap();
+        assert!(gate.try_admit().is_none());
+    }
+}
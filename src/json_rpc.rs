@@ -0,0 +1,451 @@
+//! JSON-RPC 2.0 over a substream.
+//!
+//! Every JSON-RPC message is sent as a single frame of [`LengthDelimited`].
+//! A [`Client`] sends requests and notifications on the substream it opened, the other side answers them with [`serve`].
+//! Requests are processed concurrently, so multiple calls can be in flight at the same time, up to [`MAX_CONCURRENT_REQUESTS`] per substream.
+
+use crate::LengthDelimited;
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
+use futures::channel::{mpsc, oneshot};
+use futures::stream::FuturesUnordered;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, FutureExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_tasks::Tasks;
+
+const VERSION: &str = "2.0";
+
+/// The number of requests and notifications [`serve`] handles at the same time.
+///
+/// Once reached, no further messages are read from the substream until one of them completes, which pushes back on the client through the flow control of the substream.
+pub const MAX_CONCURRENT_REQUESTS: usize = 64;
+
+/// An error object as defined by the JSON-RPC 2.0 specification.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, thiserror::Error)]
+#[error("JSON-RPC error {code}: {message}")]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(Self::METHOD_NOT_FOUND, format!("Method {method} not found"))
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(Self::INVALID_PARAMS, message)
+    }
+
+    pub fn internal_error(message: impl Into<String>) -> Self {
+        Self::new(Self::INTERNAL_ERROR, message)
+    }
+}
+
+/// Why a call through a [`Client`] failed.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error("Substream closed before a response was received")]
+    Closed,
+    #[error("Failed to (de)serialize message")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Handles the requests and notifications received through [`serve`].
+#[async_trait]
+pub trait Handler: Send + Sync + 'static {
+    /// Handles a request, the returned value is sent back as its result.
+    async fn call(&self, method: String, params: Value) -> Result<Value, RpcError>;
+
+    /// Handles a notification, i.e. a request without ID that is not answered.
+    ///
+    /// By default, notifications are ignored.
+    async fn notify(&self, _method: String, _params: Value) {}
+}
+
+/// Sends requests and notifications to a [`serve`]d substream.
+///
+/// The substream is driven by a background task which is stopped once the client is dropped.
+pub struct Client {
+    outgoing: mpsc::UnboundedSender<Value>,
+    pending: Pending,
+    next_id: AtomicU64,
+    _tasks: Tasks,
+}
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, RpcError>>>>>;
+
+#[derive(Serialize, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Response {
+    jsonrpc: String,
+    id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl Response {
+    fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        Self {
+            jsonrpc: VERSION.to_owned(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+impl Client {
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (reader, writer) = stream.split();
+        let (outgoing, mut outgoing_receiver) = mpsc::unbounded::<Value>();
+        let pending = Pending::default();
+
+        let mut tasks = Tasks::default();
+        tasks.add_fallible(
+            async move {
+                let mut writer = LengthDelimited::new(writer);
+
+                while let Some(message) = outgoing_receiver.next().await {
+                    writer.send_frame(serde_json::to_vec(&message)?).await?;
+                }
+
+                anyhow::Ok(())
+            },
+            |e| async move { tracing::debug!("Failed to send JSON-RPC message: {:#}", e) },
+        );
+        tasks.add_fallible(
+            {
+                let pending = pending.clone();
+
+                async move {
+                    let result = receive_responses(reader, &pending).await;
+
+                    // Dropping the senders fails all calls still waiting for a response.
+                    pending.lock().expect("not poisoned").clear();
+
+                    result
+                }
+            },
+            |e| async move { tracing::debug!("Failed to receive JSON-RPC message: {:#}", e) },
+        );
+
+        Self {
+            outgoing,
+            pending,
+            next_id: AtomicU64::new(0),
+            _tasks: tasks,
+        }
+    }
+
+    /// Calls the given method and waits for its result.
+    pub async fn call<P, R>(&self, method: &str, params: P) -> Result<R, Error>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = serde_json::to_value(Request {
+            jsonrpc: VERSION.to_owned(),
+            id: Some(id.into()),
+            method: method.to_owned(),
+            params: serde_json::to_value(params)?,
+        })?;
+
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("not poisoned")
+            .insert(id, sender);
+
+        if self.outgoing.unbounded_send(request).is_err() {
+            self.pending.lock().expect("not poisoned").remove(&id);
+            return Err(Error::Closed);
+        }
+
+        let result = receiver.await.map_err(|_| Error::Closed)??;
+
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Sends a notification, i.e. a request that is not answered.
+    pub fn notify<P>(&self, method: &str, params: P) -> Result<(), Error>
+    where
+        P: Serialize,
+    {
+        let notification = serde_json::to_value(Request {
+            jsonrpc: VERSION.to_owned(),
+            id: None,
+            method: method.to_owned(),
+            params: serde_json::to_value(params)?,
+        })?;
+
+        self.outgoing
+            .unbounded_send(notification)
+            .map_err(|_| Error::Closed)
+    }
+}
+
+async fn receive_responses<S>(reader: S, pending: &Pending) -> Result<()>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut reader = LengthDelimited::new(reader);
+
+    while let Some(frame) = reader.next_frame().await? {
+        let response = match serde_json::from_slice::<Response>(&frame) {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::debug!("Ignoring malformed JSON-RPC response: {}", e);
+                continue;
+            }
+        };
+        let id = match response.id.as_u64() {
+            Some(id) => id,
+            None => continue,
+        };
+        let outcome = match response.error {
+            Some(error) => Err(error),
+            None => Ok(response.result.unwrap_or_default()),
+        };
+
+        if let Some(caller) = pending.lock().expect("not poisoned").remove(&id) {
+            let _ = caller.send(outcome);
+        }
+    }
+
+    Ok(())
+}
+
+/// Answers the requests and notifications arriving on the given substream with the given [`Handler`].
+///
+/// Requests are handled concurrently, up to [`MAX_CONCURRENT_REQUESTS`] at a time, and answered in the order they complete.
+/// Only returns once the substream is closed or fails.
+pub async fn serve<S, H>(stream: S, handler: H) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    H: Handler,
+{
+    let handler = Arc::new(handler);
+    let mut framed = LengthDelimited::new(stream);
+    let mut in_flight = FuturesUnordered::new();
+
+    loop {
+        if in_flight.len() >= MAX_CONCURRENT_REQUESTS {
+            let response = match in_flight.select_next_some().await {
+                Some(response) => response,
+                None => continue,
+            };
+
+            framed
+                .send_frame(serde_json::to_vec(&response)?)
+                .await
+                .context("Failed to send JSON-RPC response")?;
+            continue;
+        }
+
+        let response = futures::select! {
+            frame = framed.next_frame().fuse() => {
+                let frame = match frame.context("Failed to receive JSON-RPC request")? {
+                    Some(frame) => frame,
+                    None => break,
+                };
+
+                match serde_json::from_slice::<Request>(&frame) {
+                    Ok(request) if request.jsonrpc == VERSION => {
+                        let handler = handler.clone();
+
+                        match request.id {
+                            Some(id) => in_flight.push(
+                                async move {
+                                    let outcome = handler.call(request.method, request.params).await;
+
+                                    Some(Response::new(id, outcome))
+                                }
+                                .boxed(),
+                            ),
+                            None => in_flight.push(
+                                async move {
+                                    handler.notify(request.method, request.params).await;
+
+                                    None
+                                }
+                                .boxed(),
+                            ),
+                        }
+
+                        continue;
+                    }
+                    Ok(request) => Response::new(
+                        request.id.unwrap_or_default(),
+                        Err(RpcError::new(RpcError::INVALID_REQUEST, "Unsupported JSON-RPC version")),
+                    ),
+                    Err(e) => Response::new(
+                        Value::Null,
+                        Err(RpcError::new(RpcError::PARSE_ERROR, e.to_string())),
+                    ),
+                }
+            }
+            response = in_flight.select_next_some() => match response {
+                Some(response) => response,
+                None => continue,
+            },
+        };
+
+        framed
+            .send_frame(serde_json::to_vec(&response)?)
+            .await
+            .context("Failed to send JSON-RPC response")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct Calculator;
+
+    #[async_trait]
+    impl Handler for Calculator {
+        async fn call(&self, method: String, params: Value) -> Result<Value, RpcError> {
+            match method.as_str() {
+                "add" => {
+                    let (a, b) = serde_json::from_value::<(i64, i64)>(params)
+                        .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+
+                    Ok((a + b).into())
+                }
+                "sleep" => {
+                    let millis = params.as_u64().unwrap_or_default();
+                    tokio::time::sleep(Duration::from_millis(millis)).await;
+
+                    Ok(millis.into())
+                }
+                method => Err(RpcError::method_not_found(method)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_calls_are_correlated_by_id() {
+        let (client_io, server_io) = tokio::io::duplex(1024);
+        tokio::spawn(serve(crate::compat::Compat::new(server_io), Calculator));
+        let client = Client::new(crate::compat::Compat::new(client_io));
+
+        let (slow, fast, sum) = futures::join!(
+            client.call::<_, u64>("sleep", 200),
+            client.call::<_, u64>("sleep", 10),
+            client.call::<_, i64>("add", (1, 2)),
+        );
+
+        assert_eq!(slow.unwrap(), 200);
+        assert_eq!(fast.unwrap(), 10);
+        assert_eq!(sum.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_limit_wait_for_a_slot() {
+        struct Blocking {
+            started: mpsc::UnboundedSender<()>,
+            release: Arc<tokio::sync::Semaphore>,
+        }
+
+        #[async_trait]
+        impl Handler for Blocking {
+            async fn call(&self, _: String, _: Value) -> Result<Value, RpcError> {
+                let _ = self.started.unbounded_send(());
+                self.release.acquire().await.unwrap().forget();
+
+                Ok(Value::Null)
+            }
+        }
+
+        let (started, mut started_receiver) = mpsc::unbounded();
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(serve(
+            crate::compat::Compat::new(server_io),
+            Blocking {
+                started,
+                release: release.clone(),
+            },
+        ));
+        let client = Arc::new(Client::new(crate::compat::Compat::new(client_io)));
+        for _ in 0..MAX_CONCURRENT_REQUESTS + 1 {
+            let client = client.clone();
+            tokio::spawn(async move { client.call::<_, Value>("block", ()).await });
+        }
+
+        for _ in 0..MAX_CONCURRENT_REQUESTS {
+            started_receiver.next().await.unwrap();
+        }
+        let beyond_limit =
+            tokio::time::timeout(Duration::from_millis(100), started_receiver.next()).await;
+        assert!(beyond_limit.is_err());
+
+        release.add_permits(1);
+        started_receiver.next().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unknown_method_yields_rpc_error() {
+        let (client_io, server_io) = tokio::io::duplex(1024);
+        tokio::spawn(serve(crate::compat::Compat::new(server_io), Calculator));
+        let client = Client::new(crate::compat::Compat::new(client_io));
+
+        let error = client.call::<_, Value>("divide", (1, 0)).await.unwrap_err();
+
+        assert!(matches!(
+            error,
+            Error::Rpc(RpcError {
+                code: RpcError::METHOD_NOT_FOUND,
+                ..
+            })
+        ));
+    }
+}
@@ -1,31 +1,172 @@
+pub use address_filter::{AddressFilter, Cidr, InvalidCidr};
+pub use agent_version::MAX_AGENT_VERSION_SIZE;
+pub use bound_tcp::{BoundTcpStream, BoundTcpTransport, NextDialBinding};
+#[cfg(feature = "actix")]
+pub use bridge::ActixNode;
+pub use bridge::{serve_commands, ActorBridge, NodeCommand};
+#[cfg(feature = "capture")]
+pub use capture::{export_pcap, CaptureKind, CaptureRecord, CAPTURE_MAGIC};
+pub use codec::{FrameReader, LengthDelimited, DEFAULT_MAX_FRAME_SIZE};
+pub use compat::{Compat, TokioCompat};
+pub use extensions::Extensions;
+pub use fairness::FAIR_SCHEDULING_CHUNK_SIZE;
+pub use fan_out::DispatchStrategy;
+#[cfg(feature = "grpc")]
+pub use grpc::{grpc_channel, grpc_listener, GrpcListener, GrpcStream};
+pub use handshake_limit::DEFAULT_MAX_HANDSHAKE_SIZE;
+pub use health::{Health, HealthReport, HealthThresholds};
+pub use http_proxy::{HttpConnectTransport, HttpProxyStream};
 pub use libp2p_core as libp2p;
+pub use libp2p_stream::Error as SubstreamNegotiationError;
+pub use libp2p_stream::{
+    upgrade_connection, ConnectionDriver, ConnectionErrorKind, Control, DialErrorKind, LegacyNoise,
+    MAX_PENDING_INBOUND_SUBSTREAMS,
+};
+pub use memory::{MemoryHandle, MemoryUsage, SUBSTREAM_MEMORY_ESTIMATE};
 pub use multistream_select::NegotiationError;
+pub use negotiation_timeouts::{NegotiationTimeouts, DEFAULT_NEGOTIATION_TIMEOUT};
+pub use node_ext::NodeExt;
+pub use observer::NodeObserver;
+pub use open_substream_builder::OpenSubstreamBuilder;
+pub use outbox::{Enqueue, Outbox, OutboxFull, OverflowPolicy};
+pub use peer_filter::{PeerDescriptor, PeerFilter};
+pub use pool::{PooledSubstream, SubstreamPool};
+pub use quota::{Quota, QuotaKind};
+pub use record::{Record, Recorded, Replay};
+pub use resumption::ResumptionToken;
+pub use selection::SelectionPolicy;
+#[cfg(unix)]
+pub use socket_activation::systemd_listeners;
+pub use stats::{
+    ClosedSubstreams, ProtocolUsage, RejectedSubstreams, RejectionReason,
+    MAX_RETAINED_DISCONNECTED_PEERS,
+};
+pub use substream::{CloseReason, Substream, SubstreamReadHalf, SubstreamWriteHalf, WriteStalled};
+pub use supervisor::{ConnectionStatus, ConnectionSupervisor, NewOutboundSubstream};
+pub use tcp_options::TcpOptions;
+pub use timeline::ConnectionTimeline;
+pub use trace_header::{read_trace_header, write_trace_header, MAX_TRACE_ID_SIZE};
+#[cfg(unix)]
+pub use unix::{UnixStream, UnixTransport};
+pub use worker_pool::{WorkerPool, DEFAULT_MAX_QUEUED_SUBSTREAMS, DEFAULT_MAX_QUEUE_TIME};
 
+#[cfg(feature = "test-support")]
+pub mod churn;
+#[cfg(feature = "event-log")]
+pub mod event_log;
+pub mod heartbeat;
+#[cfg(feature = "json-rpc")]
+pub mod json_rpc;
+pub mod loopback;
+
+mod address_filter;
+mod address_history;
+mod agent_version;
+mod bound_tcp;
+mod bridge;
+#[cfg(feature = "capture")]
+mod capture;
+mod codec;
+mod compat;
+mod connection_intent;
+mod extensions;
+mod fairness;
+mod fan_out;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod handshake_limit;
+mod health;
+mod http_proxy;
+mod inbound_gate;
 mod libp2p_stream;
+mod memory;
 mod multiaddress_ext;
+mod negotiation_timeouts;
+mod node_ext;
+mod observer;
+mod open_substream_builder;
+mod outbox;
+mod peer_filter;
+mod pool;
+mod quota;
+mod record;
+mod resumption;
+mod selection;
+mod shared_config;
+#[cfg(unix)]
+mod socket_activation;
+mod startup;
+mod stats;
+mod substream;
+mod supervised;
+mod supervisor;
+mod task_set;
+mod tcp_options;
+mod timeline;
+mod trace_header;
+#[cfg(unix)]
+mod unix;
 mod verify_peer_id;
+mod worker_pool;
 
+use address_history::AddressHistory;
 use anyhow::bail;
-use anyhow::Context as _;
 use anyhow::Result;
+use async_trait::async_trait;
+use fairness::WriteScheduler;
+use fan_out::FanOut;
+use futures::channel::oneshot;
 use futures::future::BoxFuture;
 use futures::stream::BoxStream;
-use futures::TryStreamExt;
 use futures::{AsyncRead, AsyncWrite};
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use health::DialHistory;
+use inbound_gate::{HandshakePermit, InboundGate};
 use libp2p_core::identity::Keypair;
-use libp2p_core::{Multiaddr, Negotiated, PeerId, Transport};
-use libp2p_stream::Control;
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::{Endpoint, Multiaddr, PeerId, Transport};
+use memory::MemoryAccount;
 use multiaddress_ext::MultiaddrExt as _;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use selection::Candidate;
+use shared_config::SharedConfig;
+use startup::Startup;
+use stats::{Counted, Counters};
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
 use std::marker::PhantomData;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use substream::CloseTracker;
+use supervised::SupervisedHandler;
+use task_set::TaskSet;
 use thiserror::Error;
 use tokio_tasks::Tasks;
 use xtra::message_channel::StrongMessageChannel;
 use xtra::Context;
 use xtra_productivity::xtra_productivity;
 
-pub type Substream = Negotiated<yamux::Stream>;
+/// The `tracing` target under which security-relevant events are emitted.
+///
+/// This includes successful and failed handshakes (together with the remote address), peer ID mismatches and the registration of every connection, however it was established.
+/// Subscribers can filter on this target to feed these events into an audit or intrusion-detection pipeline.
+pub const AUDIT_TARGET: &str = "libp2p_xtra::audit";
+
+/// The default number of inbound connections that are upgraded concurrently on each listener.
+pub const DEFAULT_MAX_CONCURRENT_UPGRADES: usize = 16;
+
+/// How long a [`Broadcast`] waits for a single peer to take the message, unless configured otherwise.
+pub const DEFAULT_BROADCAST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of peers a [`Broadcast`] sends to at the same time, unless configured otherwise.
+pub const DEFAULT_MAX_CONCURRENT_BROADCASTS: usize = 32;
+
+/// How long a substream of a [`Broadcast`] is reused for subsequent broadcasts of the same protocol.
+const BROADCAST_SUBSTREAM_LIFETIME: Duration = Duration::from_secs(300);
 
 /// An actor for managing multiplexed connections over a given transport.
 ///
@@ -39,12 +180,56 @@ pub type Substream = Negotiated<yamux::Stream>;
 /// Opening a new substream can be achieved by sending the [`OpenSubstream`] message.
 pub struct Node {
     node: libp2p_stream::Node,
-    tasks: Tasks,
-    controls: HashMap<PeerId, (Control, Tasks)>,
+    make_node: Box<dyn Fn(Keypair, Vec<&'static str>) -> libp2p_stream::Node + Send>,
+    identity: Keypair,
+    supported_inbound_protocols: Vec<&'static str>,
+    connection_timeout: Duration,
+    tasks: TaskSet,
+    connections: HashMap<PeerId, HashMap<ConnectionId, Connection>>,
+    next_connection_id: Arc<AtomicU64>,
+    max_connections_per_peer: usize,
+    max_inbound_connections: Option<usize>,
+    inbound_gate: InboundGate,
+    reserved_inbound_slots: usize,
+    priority_peers: HashSet<PeerId>,
+    selection_policy: SelectionPolicy,
     inbound_substream_channels:
         HashMap<&'static str, Box<dyn StrongMessageChannel<NewInboundSubstream>>>,
-    listen_addresses: HashSet<Multiaddr>,
+    supervised_handlers: HashMap<&'static str, SupervisedHandler>,
+    write_weights: Option<Arc<HashMap<&'static str, u32>>>,
+    deprecated_protocols: HashSet<&'static str>,
+    address_filter: Arc<AddressFilter>,
+    advertisement_filter: Option<Arc<dyn Fn(Multiaddr) -> Option<Multiaddr> + Send + Sync>>,
+    client_mode: bool,
+    inbound_stall_threshold: Option<Duration>,
+    memory_budget: Option<usize>,
+    write_stall_timeout: Option<Duration>,
+    listen_addresses: HashMap<Multiaddr, Listener>,
+    socket_listeners: HashMap<Multiaddr, Tasks>,
     inflight_connections: HashSet<PeerId>,
+    counters: Counters,
+    config: SharedConfig,
+    counting_since: Instant,
+    subscribers: Vec<Box<dyn StrongMessageChannel<Event>>>,
+    /// Whether anyone subscribed to events, so substreams can skip reporting their end when nobody listens.
+    has_subscribers: Arc<AtomicBool>,
+    handler_grace_period: Option<Duration>,
+    upgrade_executor: Option<Arc<dyn Fn(BoxFuture<'static, ()>) + Send + Sync>>,
+    max_concurrent_upgrades: usize,
+    pending_prewarms: HashMap<PeerId, Vec<&'static str>>,
+    prewarmed: HashMap<(PeerId, &'static str), Substream>,
+    /// The substreams of earlier [`Broadcast`]s, per protocol.
+    broadcast_pools: HashMap<&'static str, Arc<std::sync::Mutex<SubstreamPool>>>,
+    draining: Arc<AtomicBool>,
+    resumption_ttl: Option<Duration>,
+    agent_version: Option<String>,
+    suspended_sessions: HashMap<PeerId, SuspendedSession>,
+    connection_waiters: HashMap<PeerId, Vec<oneshot::Sender<Result<(), Error>>>>,
+    startup: Startup,
+    health_thresholds: HealthThresholds,
+    dial_history: DialHistory,
+    failed_listeners: HashSet<Multiaddr>,
+    address_history: AddressHistory,
 }
 
 /// Open a substream to the provided peer.
@@ -89,30 +274,455 @@ impl OpenSubstream<Multiple> {
 /// Connect to the given [`Multiaddr`].
 ///
 /// The address must contain a `/p2p` suffix.
-/// Will fail if we are already connected to the peer.
+/// Will fail if we are already connected to the peer, unless multiple connections per peer are allowed (see [`Node::with_max_connections_per_peer`]).
 pub struct Connect(pub Multiaddr);
 
+/// Connect to the given [`Multiaddr`] like with [`Connect`], declaring the protocols this connection is for.
+///
+/// The protocols take the place of those declared through [`Node::with_connection_intent`] for this connection only. An empty list declares no intent.
+pub struct ConnectWithIntent {
+    pub address: Multiaddr,
+    pub protocols: Vec<&'static str>,
+}
+
+/// Establish a connection and negotiate substreams ahead of their first use.
+///
+/// The address must contain a `/p2p` suffix. If we are already connected to the peer, only the substreams are negotiated.
+/// For each of the given protocols, one substream is negotiated and kept until the next [`OpenSubstream`] to this peer that lists the protocol first.
+/// This way, the first real request pays neither for dialing and the handshake nor for protocol negotiation.
+pub struct Prewarm {
+    pub address: Multiaddr,
+    pub protocols: Vec<&'static str>,
+}
+
+/// Send a single message to every connected peer on a substream of the given protocol, e.g. to announce something.
+///
+/// The message is sent as a single frame of [`LengthDelimited`] on a [`SubstreamPool`] of the protocol, so subsequent broadcasts reuse the substream to a peer instead of negotiating a new one.
+/// Handlers of the protocol should therefore read frames until the substream ends.
+/// New substreams are opened like with [`OpenSubstream`], reusing substreams negotiated ahead of time through [`Prewarm`].
+/// Peers that do not support the protocol fail negotiation and are reported like any other failure, as are peers that do not take the message within the timeout.
+///
+/// Returns a receiver that resolves to the result per peer once the message was sent to all of them.
+pub struct Broadcast {
+    protocol: &'static str,
+    message: bytes::Bytes,
+    timeout: Duration,
+    max_concurrent: usize,
+}
+
+impl Broadcast {
+    pub fn new(protocol: &'static str, message: impl Into<bytes::Bytes>) -> Self {
+        Self {
+            protocol,
+            message: message.into(),
+            timeout: DEFAULT_BROADCAST_TIMEOUT,
+            max_concurrent: DEFAULT_MAX_CONCURRENT_BROADCASTS,
+        }
+    }
+
+    /// Give up on a peer that did not take the message within `timeout`, including opening the substream. Defaults to [`DEFAULT_BROADCAST_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+
+        self
+    }
+
+    /// Send to at most `max_concurrent` peers at the same time. Defaults to [`DEFAULT_MAX_CONCURRENT_BROADCASTS`].
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = max_concurrent;
+
+        self
+    }
+}
+
+/// Upgrade a connection that was established out-of-band and register it like any other connection.
+///
+/// This is useful for sockets obtained from outside of the transport, e.g. from an existing tunnel or through socket activation.
+/// The connection goes through the same upgrades and checks as any other connection, see [`upgrade_connection`]: the handshake size limit, the audit log, capture and, if `remote_address` is given, the address filter.
+/// If `expected_peer` is given, the connection is rejected if the remote authenticates with a different [`PeerId`].
+/// Unlike connections established through the transport, which replace the oldest connection to the peer, an injected connection is rejected with [`Error::AlreadyConnected`] if the peer is being dialed or already holds as many connections as allowed, see [`Node::with_max_connections_per_peer`].
+///
+/// Returns a receiver that resolves to the authenticated peer once the connection is registered, or to the reason it was not.
+pub struct InjectConnection {
+    pub io: Box<dyn AsyncReadWrite>,
+    pub role: Endpoint,
+    pub expected_peer: Option<PeerId>,
+    /// The address of the remote, if known. It is checked against the address filter and reported in [`ConnectionInfo::remote_address`].
+    pub remote_address: Option<Multiaddr>,
+}
+
+/// An I/O resource that can be passed to [`InjectConnection`].
+pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
 /// Disconnect from the given peer.
 pub struct Disconnect(pub PeerId);
 
+/// Close a single connection to the given peer, e.g. a relayed connection after a direct one has been established.
+///
+/// Other connections to the peer stay open. This is a no-op if the connection does not exist (anymore).
+pub struct DisconnectConnection {
+    pub peer: PeerId,
+    pub connection: ConnectionId,
+}
+
+/// Gracefully shut down the [`Node`], e.g. for a rolling restart.
+///
+/// All listeners are stopped immediately and no new connections or inbound substreams are accepted from here on.
+/// Existing connections stay open so substreams that are already in use can finish.
+/// Once the `deadline` is reached, all connections are closed.
+pub struct Drain {
+    pub deadline: Instant,
+}
+
+/// Stop the [`Node`] right away, closing all listeners and connections.
+///
+/// Unlike [`Drain`], this does not wait for substreams in use. Once handled, the actor is gone and further messages fail.
+pub struct Stop;
+
 /// Listen on the provided [`Multiaddr`].
 ///
 /// For this to work, the [`Node`] needs to be constructed with a compatible transport.
 /// In other words, you cannot listen on a `/memory` address if you haven't configured a `/memory` transport.
 pub struct ListenOn(pub Multiaddr);
 
+/// Accept connections from an already bound TCP listener.
+///
+/// This is meant for listeners that were not bound by the [`Node`] itself, e.g. sockets passed in through systemd socket activation (see [`systemd_listeners`]).
+/// Accepted connections are upgraded like any other connection, see [`InjectConnection`].
+///
+/// Returns the address the listener is bound to.
+pub struct ListenOnSocket(pub std::net::TcpListener);
+
+/// Rotate the identity of the [`Node`] to the given [`Keypair`].
+///
+/// All listeners are restarted under the new identity and new connections, both inbound and outbound, will use it.
+/// Existing connections are not affected and stay alive under the old identity until they are closed.
+///
+/// Returns the new [`PeerId`] of the [`Node`] so it can be re-advertised, after the listeners of the old identity released their addresses.
+/// Subscribers are notified through [`Event::IdentityRotated`].
+pub struct RotateIdentity(pub Keypair);
+
 /// Retrieve [`ConnectionStats`] from the [`Node`].
 pub struct GetConnectionStats;
 
+/// Retrieve the addresses other peers can reach the [`Node`] on, e.g. to publish them through a discovery mechanism.
+///
+/// These are the addresses the node listens on, passed through the filter set with [`Node::with_advertisement_filter`] and suffixed with our `/p2p` peer ID.
+/// If external addresses are configured through [`Node::with_external_addresses`], only those are advertised, and only while one of the listeners can serve them.
+/// Addresses that already end with a `/p2p` peer ID get ours in its place.
+pub struct GetAdvertisedAddresses;
+
 pub struct ConnectionStats {
     pub connected_peers: HashSet<PeerId>,
     pub listen_addresses: HashSet<Multiaddr>,
+    pub connections: HashMap<ConnectionId, ConnectionInfo>,
+}
+
+/// Identifies a single connection of the [`Node`].
+///
+/// Identifiers are assigned as soon as a connection is accepted or dialed, i.e. before the connection is upgraded.
+/// They are unique for the lifetime of a [`Node`] and never reused.
+/// All events, errors and stats that concern a particular connection carry its identifier, which allows correlating them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+    fn next(counter: &AtomicU64) -> Self {
+        Self(counter.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Details about a single connection, see [`ConnectionStats`].
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub peer: PeerId,
+    pub endpoint: Endpoint,
+    /// The address of the remote, if known. Connections injected through [`InjectConnection`] don't have one.
+    pub remote_address: Option<Multiaddr>,
+    /// The round-trip time, estimated from the duration of protocol negotiations on outbound substreams.
+    pub rtt: Option<Duration>,
+    /// Where the time establishing this connection was spent.
+    pub timeline: ConnectionTimeline,
+    /// The memory held by the substreams of this connection, see [`Node::with_memory_budget`].
+    pub memory: MemoryUsage,
+    /// The agent version the peer announced on this connection, if any, see [`Node::with_agent_version`].
+    pub agent_version: Option<String>,
+}
+
+/// Retrieve a [`StatsSnapshot`] of the traffic counters of the [`Node`].
+pub struct SnapshotStats;
+
+/// Retrieve a [`StatsSnapshot`] of the traffic counters of the [`Node`] and reset them to zero.
+///
+/// Sending this message at a fixed interval allows computing per-interval rates without keeping any external state.
+pub struct ResetStats;
+
+/// The settings of a [`Node`] that can be changed at runtime, see [`ApplyConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeConfig {
+    /// See [`Node::new`].
+    pub connection_timeout: Duration,
+    /// See [`Node::with_handler_grace_period`].
+    pub handler_grace_period: Option<Duration>,
+    /// See [`Node::with_max_concurrent_upgrades`].
+    pub max_concurrent_upgrades: usize,
+    /// See [`Node::with_max_connections_per_peer`].
+    pub max_connections_per_peer: usize,
+    /// See [`Node::with_max_inbound_connections`].
+    pub max_inbound_connections: Option<usize>,
+    /// See [`Node::with_reserved_inbound_slots`].
+    pub reserved_inbound_slots: usize,
+    /// See [`Node::with_selection_policy`].
+    pub selection_policy: SelectionPolicy,
+    /// See [`Node::with_session_resumption`].
+    pub session_resumption_ttl: Option<Duration>,
+    /// See [`Node::with_max_handshake_size`].
+    pub max_handshake_size: usize,
+}
+
+/// Apply a changed [`NodeConfig`] without dropping any connections, see [`Node::apply_config`].
+///
+/// Returns the names of the fields that only take effect after restarting the [`Node`] or its listeners.
+pub struct ApplyConfig(pub NodeConfig);
+
+/// Retrieve the [`NodeConfig`] the [`Node`] is currently running with.
+pub struct GetConfig;
+
+/// Periodically send a [`StatsSnapshot`] to the given actor.
+///
+/// The counters are not reset by this, i.e. every snapshot covers the time since the [`Node`] was constructed or [`ResetStats`] was last sent.
+/// Reporting stops once the receiving actor is gone.
+pub struct SubscribeStats {
+    pub interval: Duration,
+    pub receiver: Box<dyn StrongMessageChannel<StatsSnapshot>>,
+}
+
+/// Retrieve the number of inbound substreams that were dropped before reaching their handler, broken down by peer, protocol and [`RejectionReason`].
+///
+/// The numbers are accumulated since the [`Node`] was constructed and are not affected by [`ResetStats`].
+/// Only connected peers and the [`MAX_RETAINED_DISCONNECTED_PEERS`] most recently disconnected ones are included.
+pub struct GetRejectedSubstreams;
+
+/// Retrieve the number of substreams that ended, broken down by peer, protocol and [`CloseReason`].
+///
+/// The numbers are accumulated since the [`Node`] was constructed and are not affected by [`ResetStats`].
+/// Only connected peers and the [`MAX_RETAINED_DISCONNECTED_PEERS`] most recently disconnected ones are included.
+pub struct GetClosedSubstreams;
+
+/// Retrieve a [`PeerDescriptor`] of every connected peer that matches the filter, e.g. to select peers for a request.
+pub struct GetPeers {
+    pub filter: PeerFilter,
+}
+
+/// Retrieve up to `n` randomly chosen connected peers that match the filter, e.g. for gossip fan-out.
+///
+/// With a `seed`, the same set of connected peers always yields the same sample, which makes peer selection reproducible in tests.
+/// The sample is drawn with [`ChaCha8Rng`](rand_chacha::ChaCha8Rng), so it stays the same across platforms and versions of `rand`.
+pub struct SamplePeers {
+    pub n: usize,
+    pub filter: PeerFilter,
+    pub seed: Option<u64>,
+}
+
+/// Retrieve [`PeerInfo`] about the given peer from the [`Node`].
+pub struct GetPeerInfo(pub PeerId);
+
+pub struct PeerInfo {
+    /// The current connections to the peer, if any.
+    pub connections: Vec<ConnectionId>,
+    /// How the peer used each protocol, including on past connections.
+    ///
+    /// The numbers are accumulated since the [`Node`] was constructed and are not affected by [`ResetStats`].
+    pub protocols: HashMap<&'static str, ProtocolUsage>,
+}
+
+/// Traffic counters of the [`Node`], accumulated over all connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub bytes_inbound: u64,
+    pub bytes_outbound: u64,
+    pub substreams_inbound: u64,
+    pub substreams_outbound: u64,
+    /// Connections that were closed because the remote sent too much data during the handshake, see [`Node::with_max_handshake_size`].
+    pub oversized_handshakes: u64,
+    /// Inbound connections that were turned away because of the inbound connection limit, see [`Node::with_max_inbound_connections`].
+    pub rejected_inbound_connections: u64,
+    /// Inbound connections that were turned away because they were declared for protocols we do not support, see [`Node::with_connection_intent`].
+    pub rejected_connection_intents: u64,
+    /// The time over which the counters were accumulated.
+    pub elapsed: Duration,
+}
+
+/// Subscribe the given actor to all [`Event`]s emitted by the [`Node`].
+///
+/// The subscription ends once the actor is gone.
+pub struct Subscribe(pub Box<dyn StrongMessageChannel<Event>>);
+
+/// Events emitted by the [`Node`] to all actors registered through [`Subscribe`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
+    /// A connection to the given peer was established.
+    ///
+    /// This is enqueued before any [`NewInboundSubstream`] of the connection is dispatched, with the same limits as for [`Event::ConnectionClosed`].
+    ConnectionEstablished {
+        peer: PeerId,
+        connection: ConnectionId,
+        endpoint: Endpoint,
+    },
+    /// A connection to the given peer was closed.
+    ///
+    /// This is enqueued after the last [`NewInboundSubstream`] of the connection has been dispatched.
+    /// Actors that both subscribe to events and handle substreams therefore never see a substream of a closed connection.
+    ///
+    /// The guarantee only covers messages ending up in the mailbox of the same actor, i.e. one passed to [`Node::new`] that also subscribed.
+    /// Separate actors for events and substreams process their mailboxes independently, and substreams of supervised handlers, bridges or [`Node::with_handlers`] are delivered from tasks that are not ordered relative to events.
+    ConnectionClosed {
+        peer: PeerId,
+        connection: ConnectionId,
+    },
+    /// The last connection to the given peer was closed.
+    ///
+    /// This follows the [`Event::ConnectionClosed`] of that connection.
+    /// Protocol handlers that keep per-peer state alongside their substreams can subscribe to clean it up right away, instead of finding out through I/O errors on the substreams.
+    PeerDisconnected { peer: PeerId },
+    /// All listeners configured through [`Node::with_listen_addresses`] are bound and we are connected to all peers configured through [`Node::with_bootstrap_peers`].
+    ///
+    /// This is emitted at most once. Use [`AwaitReady`] to not miss it when subscribing late.
+    Ready,
+    /// An established connection failed and is about to be closed.
+    ///
+    /// This is emitted before [`Event::ConnectionClosed`]. Connections that are closed regularly, e.g. by the remote, do not emit this event.
+    ConnectionError {
+        peer: PeerId,
+        connection: ConnectionId,
+        kind: ConnectionErrorKind,
+        /// The error reported by the multiplexer.
+        error: String,
+    },
+    /// Establishing an outgoing connection to the given peer failed.
+    OutgoingConnectionError {
+        peer: PeerId,
+        connection: ConnectionId,
+        address: Multiaddr,
+        error_kind: DialErrorKind,
+    },
+    /// A substream that was handed out by the node ended.
+    SubstreamClosed {
+        peer: PeerId,
+        connection: ConnectionId,
+        protocol: &'static str,
+        reason: CloseReason,
+    },
+    /// The handler for the given protocol did not accept an inbound substream within the configured grace period.
+    ///
+    /// See [`Node::with_handler_grace_period`].
+    InboundSubstreamHandlerTimeout {
+        peer: PeerId,
+        connection: ConnectionId,
+        protocol: &'static str,
+    },
+    /// The given peer tried to open a substream but none of the protocols it proposed are supported.
+    ///
+    /// This usually indicates a version skew between deployments.
+    /// Unfortunately, multistream-select does not expose which protocols were proposed.
+    UnsupportedProtocolProposed {
+        peer: PeerId,
+        connection: ConnectionId,
+    },
+    /// The given peer negotiated an inbound protocol marked as deprecated, see [`Node::with_deprecated_protocols`].
+    ///
+    /// The substream is served as usual.
+    DeprecatedProtocolNegotiated {
+        peer: PeerId,
+        connection: ConnectionId,
+        protocol: &'static str,
+        /// The address of the remote, if known.
+        remote_address: Option<Multiaddr>,
+    },
+    /// A handler registered through [`Node::with_supervised_handler`] panicked while handling an inbound substream.
+    ///
+    /// The substream was reset, the connection is kept alive.
+    HandlerPanicked {
+        peer: PeerId,
+        connection: ConnectionId,
+        protocol: &'static str,
+        message: String,
+    },
+    /// The session with the given peer was resumed after a reconnect.
+    ///
+    /// The [`Extensions`] of the previous connection have been carried over to the new one.
+    /// See [`Node::with_session_resumption`].
+    SessionResumed {
+        peer: PeerId,
+        connection: ConnectionId,
+    },
+    /// The oldest inbound substream of the connection has been waiting for `waiting` to be dispatched, longer than the threshold of [`Node::with_inbound_stall_detection`].
+    ///
+    /// This is emitted once per stall: it is emitted again only after the backlog of the connection was cleared.
+    InboundSubstreamsStalled {
+        peer: PeerId,
+        connection: ConnectionId,
+        waiting: Duration,
+    },
+    /// The peer exceeded its [`Quota`] for the protocol, see [`Node::with_quota`].
+    QuotaExceeded {
+        peer: PeerId,
+        connection: ConnectionId,
+        protocol: &'static str,
+        kind: QuotaKind,
+    },
+    /// The peer reconnected from a different host than the one it last connected from, e.g. a different IP address.
+    ///
+    /// Only the hosts of the remote addresses are compared, so a new port on the same IP address does not count, nor does a different DNS protocol for the same name.
+    /// Only the first connection after the peer had none is compared, so concurrent connections from several hosts do not flap between them.
+    /// Connections without a host, like relayed ones or those without a known remote address, are ignored.
+    AddressChanged {
+        peer: PeerId,
+        old: Multiaddr,
+        new: Multiaddr,
+    },
+    /// The identity of the node was rotated through [`RotateIdentity`] and all listeners were restarted under the new one.
+    IdentityRotated { old: PeerId, new: PeerId },
+    /// The peer announced its agent version on the connection, see [`Node::with_agent_version`].
+    AgentVersionReceived {
+        peer: PeerId,
+        connection: ConnectionId,
+        agent_version: String,
+    },
 }
 
+/// Resolves once the node is ready, i.e. once [`Event::Ready`] has been emitted.
+///
+/// Fails if one of the configured listeners could not be bound or one of the bootstrap peers could not be dialed.
+/// A node without listeners and bootstrap peers configured is ready right away.
+/// See [`NodeExt::wait_until_ready`] for awaiting the returned receiver in one go.
+pub struct AwaitReady;
+
+/// Retrieve a [`HealthReport`] of the [`Node`], judged against its [`HealthThresholds`].
+pub struct GetHealth;
+
+/// Retrieve the [`Extensions`] of the connection to the given peer.
+///
+/// If there are multiple connections to the peer, the one picked by the [`SelectionPolicy`] is used.
+/// Returns `None` if we are not connected to the peer.
+pub struct GetExtensions(pub PeerId);
+
 /// Notifies an actor of a new, inbound substream from the given peer.
 pub struct NewInboundSubstream {
     pub peer: PeerId,
-    pub stream: libp2p_stream::Substream,
+    pub connection: ConnectionId,
+    pub stream: Substream,
+    /// The [`Extensions`] of the connection the substream was opened on.
+    pub extensions: Extensions,
 }
 
 #[derive(Debug, Error)]
@@ -123,12 +733,124 @@ pub enum Error {
     NegotiationTimeoutReached,
     #[error("Failed to negotiate protocol")]
     NegotiationFailed(#[from] NegotiationError), // TODO(public-api): Consider breaking this up.
+    #[error("Connection {1} to {0} is closed")]
+    ConnectionClosed(PeerId, ConnectionId),
     #[error("Bad connection")]
     BadConnection(#[from] yamux::ConnectionError), // TODO(public-api): Consider removing this.
     #[error("Address {0} does not end with a peer ID")]
     NoPeerIdInAddress(Multiaddr),
     #[error("Either currently connecting or already connected to peer {0}")]
     AlreadyConnected(PeerId),
+    #[error("Node is draining")]
+    Draining,
+    #[error("Failed to connect: {0}")]
+    ConnectFailed(DialErrorKind),
+    #[error("Failed to listen on {0}")]
+    ListenFailed(Multiaddr),
+    #[error("Node stopped")]
+    Stopped,
+    #[error("Node is in client mode and does not listen")]
+    ClientMode,
+    #[error("Memory budget of connection {1} to {0} is exhausted")]
+    MemoryBudgetExhausted(PeerId, ConnectionId),
+    #[error("External address {0} is not served by any listen address")]
+    UnservableExternalAddress(Multiaddr),
+    #[error("Failed to write trace header")]
+    TraceHeader(#[source] std::io::Error),
+    #[error("Address {0} is denied by the address filter")]
+    AddressFiltered(Multiaddr),
+    #[error("Inbound connection limit reached")]
+    InboundLimitReached,
+    #[error("Failed to send message")]
+    SendFailed(#[source] std::io::Error),
+    #[error("Timeout sending message")]
+    SendTimeoutReached,
+    /// Errors on an established connection come wrapped in this.
+    ///
+    /// Code that matched on the other variants for such errors, e.g. [`Error::NegotiationFailed`] from [`OpenSubstream`], has to match on [`Error::without_context`] instead.
+    #[error(transparent)]
+    Contextual(Box<ContextualError>),
+}
+
+/// An [`Error`] on an established connection, together with the peer, connection and protocols it concerns.
+///
+/// Every error raised while opening a substream on an authenticated connection is wrapped in this, so it can be logged without correlating it with other log lines.
+/// Use [`Error::without_context`] to match on the underlying error.
+#[derive(Debug)]
+pub struct ContextualError {
+    pub peer: PeerId,
+    pub connection: ConnectionId,
+    /// The address of the remote, if known, see [`ConnectionInfo::remote_address`].
+    pub remote_address: Option<Multiaddr>,
+    /// The protocols that were proposed.
+    pub protocols: Vec<&'static str>,
+    pub source: Error,
+}
+
+impl fmt::Display for ContextualError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed on connection {} to {}",
+            self.connection, self.peer
+        )?;
+
+        if let Some(remote_address) = &self.remote_address {
+            write!(f, " at {remote_address}")?;
+        }
+        if !self.protocols.is_empty() {
+            write!(f, " for {}", self.protocols.join(", "))?;
+        }
+
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl std::error::Error for ContextualError {
+    /// The source of the underlying error, as the underlying error itself is already part of the message.
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&self.source)
+    }
+}
+
+impl Error {
+    /// Whether retrying the failed operation might succeed.
+    ///
+    /// Transient failures like timeouts or a broken connection are retryable (possibly after reconnecting).
+    /// Permanent failures like the peer not supporting any of the requested protocols or an invalid address are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::NoConnection(_) => true,
+            Error::NegotiationTimeoutReached => true,
+            Error::NegotiationFailed(NegotiationError::Failed) => false,
+            Error::NegotiationFailed(NegotiationError::ProtocolError(_)) => true,
+            Error::ConnectionClosed(..) => true,
+            Error::BadConnection(_) => true,
+            Error::NoPeerIdInAddress(_) => false,
+            Error::AlreadyConnected(_) => false,
+            Error::Draining => false,
+            Error::ConnectFailed(kind) => kind.is_retryable(),
+            Error::ListenFailed(_) => false,
+            Error::Stopped => false,
+            Error::ClientMode => false,
+            Error::MemoryBudgetExhausted(..) => true,
+            Error::UnservableExternalAddress(_) => false,
+            Error::TraceHeader(_) => true,
+            Error::AddressFiltered(_) => false,
+            Error::InboundLimitReached => true,
+            Error::SendFailed(_) => true,
+            Error::SendTimeoutReached => true,
+            Error::Contextual(e) => e.source.is_retryable(),
+        }
+    }
+
+    /// Returns the underlying error if this is a [`ContextualError`], or this error otherwise.
+    pub fn without_context(&self) -> &Error {
+        match self {
+            Error::Contextual(e) => e.source.without_context(),
+            error => error,
+        }
+    }
 }
 
 impl Node {
@@ -138,9 +860,11 @@ impl Node {
     ///
     /// The `connection_timeout` is applied to:
     /// 1. Connection upgrades (i.e. noise handshake, yamux upgrade, etc)
-    /// 2. Protocol negotiations
+    /// 2. Protocol negotiations, capped at [`DEFAULT_NEGOTIATION_TIMEOUT`] unless configured separately through [`Node::with_negotiation_timeouts`]
     ///
     /// The provided substream handlers are actors that will be given the fully-negotiated substreams whenever a peer opens a new substream for the provided protocol.
+    /// The node does not supervise these actors: a panicking handler stops its actor, resetting the substream it was handling, and later substreams of its protocols are reset and counted as [`RejectionReason::HandlerGone`].
+    /// Use [`Node::with_supervised_handler`] for handlers that must survive a panic.
     pub fn new<T, const N: usize>(
         transport: T,
         identity: Keypair,
@@ -158,228 +882,2290 @@ impl Node {
         T::Dial: Send + 'static,
         T::ListenerUpgrade: Send + 'static,
     {
+        let supported_inbound_protocols = inbound_substream_handlers
+            .iter()
+            .map(|(proto, _)| *proto)
+            .collect::<Vec<_>>();
+        let counters = Counters::default();
+        let config = SharedConfig::default();
+        let make_node = {
+            let counters = counters.clone();
+            let config = config.clone();
+
+            move |identity, supported_inbound_protocols| {
+                libp2p_stream::Node::new(
+                    transport.clone(),
+                    identity,
+                    supported_inbound_protocols,
+                    connection_timeout,
+                    counters.clone(),
+                    config.clone(),
+                )
+            }
+        };
+
         Self {
-            node: libp2p_stream::Node::new(
-                transport,
-                identity,
-                inbound_substream_handlers
-                    .iter()
-                    .map(|(proto, _)| *proto)
-                    .collect(),
-                connection_timeout,
-            ),
-            tasks: Tasks::default(),
+            node: make_node(identity.clone(), supported_inbound_protocols.clone()),
+            make_node: Box::new(make_node),
+            identity,
+            supported_inbound_protocols,
+            connection_timeout,
+            tasks: TaskSet::default(),
             inbound_substream_channels: inbound_substream_handlers.into_iter().collect(),
-            controls: HashMap::default(),
-            listen_addresses: HashSet::default(),
+            supervised_handlers: HashMap::default(),
+            write_weights: None,
+            deprecated_protocols: HashSet::default(),
+            address_filter: Arc::default(),
+            advertisement_filter: None,
+            client_mode: false,
+            inbound_stall_threshold: None,
+            memory_budget: None,
+            write_stall_timeout: None,
+            connections: HashMap::default(),
+            next_connection_id: Arc::default(),
+            max_connections_per_peer: 1,
+            max_inbound_connections: None,
+            inbound_gate: InboundGate::default(),
+            reserved_inbound_slots: 0,
+            priority_peers: HashSet::default(),
+            selection_policy: SelectionPolicy::default(),
+            listen_addresses: HashMap::default(),
+            socket_listeners: HashMap::default(),
             inflight_connections: HashSet::default(),
+            counters,
+            config,
+            counting_since: Instant::now(),
+            subscribers: Vec::default(),
+            has_subscribers: Arc::default(),
+            handler_grace_period: None,
+            upgrade_executor: None,
+            max_concurrent_upgrades: DEFAULT_MAX_CONCURRENT_UPGRADES,
+            pending_prewarms: HashMap::default(),
+            prewarmed: HashMap::default(),
+            broadcast_pools: HashMap::default(),
+            draining: Arc::default(),
+            resumption_ttl: None,
+            agent_version: None,
+            suspended_sessions: HashMap::default(),
+            connection_waiters: HashMap::default(),
+            startup: Startup::default(),
+            health_thresholds: HealthThresholds::default(),
+            dial_history: DialHistory::default(),
+            failed_listeners: HashSet::default(),
+            address_history: AddressHistory::default(),
         }
     }
 
-    fn drop_connection(&mut self, peer: &PeerId) {
-        let (control, tasks) = match self.controls.remove(&peer) {
-            None => return,
-            Some(control) => control,
-        };
+    /// Expect substream handlers to accept an inbound substream within the given grace period.
+    ///
+    /// A handler accepts a substream by starting to use it, i.e. by reading from, writing to, closing or dropping the [`Substream`] it receives with [`NewInboundSubstream`].
+    /// If it fails to do so in time, the substream is reset, a warning is logged and [`Event::InboundSubstreamHandlerTimeout`] is emitted, which usually indicates a wedged handler.
+    /// The handler sees the reset as an [`io::ErrorKind::ConnectionReset`](std::io::ErrorKind::ConnectionReset) once it gets to the substream.
+    /// By default, handlers are not expected to accept substreams within any particular time.
+    pub fn with_handler_grace_period(mut self, grace_period: Duration) -> Self {
+        self.handler_grace_period = Some(grace_period);
 
-        // TODO: Evaluate whether dropping and closing has to be in a particular order.
-        self.tasks.add(async move {
-            control.close_connection().await;
-            drop(tasks);
-        });
+        self
     }
 
-    async fn open_substream(
-        &mut self,
-        peer: PeerId,
-        protocols: Vec<&'static str>,
-    ) -> Result<(&'static str, Substream), Error> {
-        let (control, _) = self
-            .controls
-            .get_mut(&peer)
-            .ok_or_else(|| Error::NoConnection(peer))?;
+    /// Spawn the upgrade (noise handshake, yamux upgrade, etc) of every inbound connection onto the given executor.
+    ///
+    /// By default, upgrades run concurrently within the listener task, see [`Node::with_max_concurrent_upgrades`].
+    /// The executor is responsible for bounding the number of concurrent upgrades.
+    pub fn with_upgrade_executor(
+        mut self,
+        executor: impl Fn(BoxFuture<'static, ()>) + Send + Sync + 'static,
+    ) -> Self {
+        self.upgrade_executor = Some(Arc::new(executor));
 
-        let (protocol, stream) = control
-            .open_substream(protocols)
-            .await?
-            .map_err(|e| match e {
-                libp2p_stream::Error::NegotiationFailed(e) => Error::NegotiationFailed(e),
-                libp2p_stream::Error::NegotiationTimeoutReached => Error::NegotiationTimeoutReached,
-            })?;
+        self
+    }
 
-        Ok((protocol, stream))
+    /// Limit how many inbound connections are upgraded concurrently on each listener.
+    ///
+    /// Defaults to [`DEFAULT_MAX_CONCURRENT_UPGRADES`]. Has no effect if an upgrade executor is configured.
+    pub fn with_max_concurrent_upgrades(mut self, max: usize) -> Self {
+        self.max_concurrent_upgrades = max;
+
+        self
     }
-}
 
-#[xtra_productivity]
-impl Node {
-    async fn handle(&mut self, msg: NewConnection, ctx: &mut Context<Self>) {
-        self.inflight_connections.remove(&msg.peer);
-        let this = ctx.address().expect("we are alive");
+    /// Allow up to `max` simultaneous connections to the same peer, e.g. a relayed and a direct one.
+    ///
+    /// [`Connect`] only fails with [`Error::AlreadyConnected`] once the limit is reached.
+    /// If a peer connects to us while the limit is reached, the oldest connection to this peer is closed.
+    /// Defaults to a single connection per peer.
+    pub fn with_max_connections_per_peer(mut self, max: usize) -> Self {
+        self.max_connections_per_peer = max.max(1);
 
-        let NewConnection {
-            peer,
-            control,
-            mut incoming_substreams,
-            worker,
-        } = msg;
+        self
+    }
 
-        let mut tasks = Tasks::default();
-        tasks.add(worker);
-        tasks.add_fallible(
-            {
-                let inbound_substream_channels = self
-                    .inbound_substream_channels
-                    .iter()
-                    .map(|(proto, channel)| {
-                        (
-                            proto.to_owned(),
-                            StrongMessageChannel::clone_channel(channel.as_ref()),
-                        )
-                    })
-                    .collect::<HashMap<_, _>>();
+    /// Accept at most `max` inbound connections in total.
+    ///
+    /// Once the limit is reached, further inbound connections are turned away before the handshake, except for one handshake at a time.
+    /// That one is needed to learn who the peer is: it is admitted if it replaces an inbound connection to the same peer (see [`Node::with_max_connections_per_peer`]) and closed otherwise.
+    /// Outbound connections are not limited. Rejected connections are counted in [`StatsSnapshot::rejected_inbound_connections`].
+    /// See [`Node::with_reserved_inbound_slots`] for keeping room for known peers.
+    pub fn with_max_inbound_connections(mut self, max: usize) -> Self {
+        self.max_inbound_connections = Some(max);
+        self.inbound_gate.set_max(Some(max));
 
-                async move {
-                    loop {
-                        let (stream, protocol) = match incoming_substreams.try_next().await {
-                            Ok(Some(Ok((stream, protocol)))) => (stream, protocol),
-                            Ok(Some(Err(libp2p_stream::Error::NegotiationTimeoutReached))) => {
-                                tracing::debug!("Hit timeout while negotiating substream");
-                                continue;
-                            }
-                            Ok(Some(Err(libp2p_stream::Error::NegotiationFailed(e)))) => {
-                                tracing::debug!("Failed to negotiate substream: {}", e);
-                                continue;
-                            }
-                            Ok(None) => bail!("Substream listener closed"),
-                            Err(e) => bail!(e),
-                        };
+        self
+    }
 
-                        let channel = inbound_substream_channels
-                            .get(&protocol)
-                            .expect("Cannot negotiate a protocol that we don't support");
+    /// Reserve the last `slots` of the inbound connection limit for known peers.
+    ///
+    /// Known peers are the given peers, the bootstrap peers (see [`Node::with_bootstrap_peers`]) and peers we are already connected to.
+    /// Once fewer than `slots` inbound connections are left, connections from other peers are rejected.
+    /// This keeps strangers from squeezing out reconnection attempts of established counterparties.
+    /// Has no effect without [`Node::with_max_inbound_connections`].
+    pub fn with_reserved_inbound_slots(
+        mut self,
+        slots: usize,
+        peers: impl IntoIterator<Item = PeerId>,
+    ) -> Self {
+        self.reserved_inbound_slots = slots;
+        self.priority_peers.extend(peers);
 
-                        let _ = channel.do_send(NewInboundSubstream { peer, stream });
-                    }
-                }
-            },
-            move |error| async move {
-                let _ = this.send(ConnectionFailed { peer, error }).await;
-            },
-        );
-        self.controls.insert(peer, (control, tasks));
+        self
     }
 
-    async fn handle(&mut self, msg: ListenerFailed) {
-        tracing::debug!("Listener failed: {:#}", msg.error);
+    /// Configure how one of several connections to the same peer is picked, see [`SelectionPolicy`].
+    pub fn with_selection_policy(mut self, policy: SelectionPolicy) -> Self {
+        self.selection_policy = policy;
 
-        self.listen_addresses.remove(&msg.address);
+        self
     }
 
-    async fn handle(&mut self, msg: FailedToConnect) {
-        tracing::debug!("Failed to connect: {:#}", msg.error);
-        let peer = msg.peer;
+    /// Allow resuming sessions with peers after an unexpected disconnect.
+    ///
+    /// Whenever we dial a peer, a [`ResumptionToken`] is agreed on for the session.
+    /// If the connection fails (as opposed to being closed through [`Disconnect`]), the session is kept for `ttl`.
+    /// When redialing the peer within that time, the token is presented and if the peer still knows the session, the [`Extensions`] of the old connection are carried over to the new one on both sides.
+    /// This allows re-associating application state and skipping expensive protocol setup.
+    ///
+    /// Both nodes need to enable session resumption. A resumed session is announced with [`Event::SessionResumed`].
+    pub fn with_session_resumption(mut self, ttl: Duration) -> Self {
+        self.resumption_ttl = Some(ttl);
+        self.support_session_resumption();
 
-        self.inflight_connections.remove(&peer);
-        self.drop_connection(&peer);
+        self
     }
 
-    async fn handle(&mut self, msg: ConnectionFailed) {
-        tracing::debug!("Connection failed: {:#}", msg.error);
-        let peer = msg.peer;
+    /// Announce the given agent version, e.g. `my-daemon/1.2.3`, to every peer we connect to.
+    ///
+    /// The version is sent on a dedicated substream right after a connection is established.
+    /// Agent versions received from peers are reported through [`Event::AgentVersionReceived`] and [`ConnectionInfo::agent_version`].
+    /// Both nodes need to enable this, a node without an agent version neither sends nor accepts one.
+    /// Versions longer than [`MAX_AGENT_VERSION_SIZE`] bytes are not sent.
+    pub fn with_agent_version(mut self, agent_version: impl Into<String>) -> Self {
+        self.agent_version = Some(agent_version.into());
+        self.support_agent_version();
 
-        self.drop_connection(&peer);
+        self
     }
 
-    async fn handle(&mut self, _: GetConnectionStats) -> ConnectionStats {
-        ConnectionStats {
-            connected_peers: self.controls.keys().copied().collect(),
-            listen_addresses: self.listen_addresses.clone(),
-        }
+    /// Limit how many bytes a remote may send before the handshake completes.
+    ///
+    /// This caps the size of protocol negotiation and handshake messages so an unauthenticated remote cannot force large allocations.
+    /// Connections are closed as soon as they exceed the limit, i.e. without waiting for an oversized handshake message to be received in full.
+    /// Such rejections are counted in [`StatsSnapshot::oversized_handshakes`]. Defaults to [`DEFAULT_MAX_HANDSHAKE_SIZE`].
+    pub fn with_max_handshake_size(self, bytes: usize) -> Self {
+        self.config.handshake_limit().set(bytes);
+
+        self
     }
 
-    async fn handle(&mut self, msg: Connect, ctx: &mut Context<Self>) -> Result<(), Error> {
-        let this = ctx.address().expect("we are alive");
+    /// Use separate timeouts for negotiating the protocol of substreams, per direction or per protocol.
+    ///
+    /// For example, an interactive protocol may warrant a short timeout while setting up a relay may take much longer.
+    /// By default, [`DEFAULT_NEGOTIATION_TIMEOUT`] applies to all negotiations, or the `connection_timeout` passed to [`Node::new`] if that is shorter.
+    pub fn with_negotiation_timeouts(self, timeouts: NegotiationTimeouts) -> Self {
+        self.config.negotiation_timeouts().set(timeouts);
 
-        let peer = msg
-            .0
-            .clone()
-            .extract_peer_id()
-            .ok_or_else(|| Error::NoPeerIdInAddress(msg.0.clone()))?;
+        self
+    }
 
-        if self.inflight_connections.contains(&peer) || self.controls.contains_key(&peer) {
-            return Err(Error::AlreadyConnected(peer));
-        }
+    /// Speak the noise handshake format of peers that predate the libp2p noise spec, e.g. to migrate an existing fleet.
+    ///
+    /// With [`LegacyNoise::Accept`], both formats are accepted from peers without any negotiation on the wire, so upgraded peers keep talking to old ones.
+    /// Once every peer accepts the spec format, this can be reset to the default of [`LegacyNoise::Reject`].
+    pub fn with_legacy_noise(self, legacy_noise: LegacyNoise) -> Self {
+        self.config.legacy_noise().set(legacy_noise);
 
-        self.inflight_connections.insert(peer);
+        self
+    }
+
+    /// Notify the given [`NodeObserver`] about lifecycle events of this [`Node`] and its connections.
+    pub fn with_observer(self, observer: Arc<dyn NodeObserver>) -> Self {
+        self.config.observer().set(observer);
+
+        self
+    }
+
+    /// Run the node as a client that only dials out.
+    ///
+    /// A client never listens: [`ListenOn`] is ignored with a warning, [`ListenOnSocket`] fails with [`Error::ClientMode`] and addresses passed to [`Node::with_listen_addresses`] fail the startup.
+    /// Peers can still open substreams on connections the client dialed.
+    pub fn with_client_mode(mut self) -> Self {
+        self.client_mode = true;
+
+        self
+    }
+
+    /// Report connections whose inbound substreams wait longer than `threshold` to be dispatched, see [`Event::InboundSubstreamsStalled`].
+    ///
+    /// Stalled inbound substreams show up as timeouts on the remote side, which are otherwise hard to trace back to this node.
+    /// Inbound substreams are negotiated one after another, so the threshold should be well above the negotiation timeout.
+    pub fn with_inbound_stall_detection(mut self, threshold: Duration) -> Self {
+        self.inbound_stall_threshold = Some(threshold);
+
+        self
+    }
+
+    /// Fail writes to substreams that wait for the peer to read for longer than `timeout` with [`WriteStalled`].
+    ///
+    /// This lets publishers of real-time data give up on a peer that does not keep up instead of blocking behind it.
+    /// A stalled write may leave a message half written, so the substream is reset along with the error and has to be discarded, see [`WriteStalled`].
+    /// The timeout can be changed per substream with [`Substream::set_write_stall_timeout`]. By default, writes wait for as long as it takes.
+    pub fn with_write_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.write_stall_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Limit how much every peer may use the given protocol, e.g. so a misbehaving peer cannot monopolize a shared node.
+    ///
+    /// Inbound substreams beyond the limit are rejected and substreams exceeding the limit on bytes are reset, see [`QuotaKind`].
+    /// Every violation is reported through [`Event::QuotaExceeded`]. Usage is tracked per peer across connections, see [`GetPeerInfo`].
+    pub fn with_quota(self, protocol: &'static str, quota: Quota) -> Self {
+        self.config.quotas().set(protocol, quota);
+
+        self
+    }
+
+    /// Declare the protocols outbound connections are going to be used for.
+    ///
+    /// The protocols are sent to the listener before the multiplexer is set up, which rejects the connection if it supports none of them.
+    /// Such connections then fail with [`DialErrorKind::NoCommonProtocol`] instead of taking up a connection slot on either side, and the listener counts them in [`StatsSnapshot::rejected_connection_intents`].
+    /// Listeners always accept declarations, but those that predate them are dialed without one.
+    /// An empty list declares no intent. Use [`ConnectWithIntent`] to declare the intent of a single connection.
+    pub fn with_connection_intent(self, protocols: impl IntoIterator<Item = &'static str>) -> Self {
+        self.config
+            .connection_intent()
+            .set(protocols.into_iter().collect());
+
+        self
+    }
+
+    /// Limit the memory the substreams of each connection may hold to `bytes`.
+    ///
+    /// Every open substream is accounted with [`SUBSTREAM_MEMORY_ESTIMATE`] for the buffers of the muxer, plus whatever codecs report through a [`MemoryHandle`].
+    /// Once the budget is exhausted, new substreams are refused: inbound ones are rejected with [`RejectionReason::MemoryBudget`] and opening outbound ones fails with [`Error::MemoryBudgetExhausted`].
+    /// If codec buffers grow beyond the budget, the most expensive substreams are reset until the connection is within budget again.
+    /// A reset substream is reset towards the peer as well, once its next read or write fails.
+    /// As the muxer part is an estimate, this is a best-effort limit rather than a hard one.
+    /// The accounting is part of [`ConnectionInfo::memory`]. By default, there is no budget.
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+
+        self
+    }
+
+    /// Only accept inbound connections from remotes that pass the given [`AddressFilter`].
+    ///
+    /// The filter is applied as soon as a connection is accepted, before the handshake, so unwanted networks can be dropped cheaply.
+    /// This applies to all listeners, including sockets passed in through [`ListenOnSocket`].
+    pub fn with_address_filter(mut self, filter: AddressFilter) -> Self {
+        self.address_filter = Arc::new(filter);
+
+        self
+    }
+
+    /// Filter or transform listen addresses before they are returned by [`GetAdvertisedAddresses`].
+    ///
+    /// Addresses for which the filter returns `None` are not advertised, e.g. private addresses that are unreachable for other peers.
+    /// Returning a different address substitutes it, e.g. the public address of a load balancer in front of the node.
+    pub fn with_advertisement_filter(
+        mut self,
+        filter: impl Fn(Multiaddr) -> Option<Multiaddr> + Send + Sync + 'static,
+    ) -> Self {
+        self.advertisement_filter = Some(Arc::new(filter));
+
+        self
+    }
+
+    /// Record the plaintext traffic of all connections into the given writer, typically a file.
+    ///
+    /// Inbound bytes are recorded after decryption and outbound bytes before encryption, together with a timestamp and the connection they belong to.
+    /// See the [`CaptureRecord`] for the format and how to read it back, or [`export_pcap`] to open it in Wireshark.
+    /// Records are written synchronously on the I/O path of the connections, wrap files in a [`std::io::BufWriter`] to reduce the number of writes.
+    ///
+    /// Captures contain all application data in the clear. This is a debugging aid and only available with the `capture` feature.
+    #[cfg(feature = "capture")]
+    pub fn with_capture(self, writer: impl std::io::Write + Send + 'static) -> Self {
+        self.config.capture().set(Box::new(writer));
+
+        self
+    }
+
+    /// Record the plaintext traffic of every connection to its own file, so it can be replayed with [`Replay`].
+    ///
+    /// Recordings contain all application data in the clear, keep them as protected as the data itself.
+    /// On Unix, recordings are created readable by the owner only, see [`Record::new`].
+    pub fn with_recording(self, record: Record) -> Self {
+        self.config.recording().set(record);
+
+        self
+    }
+
+    /// Listen on the given addresses as soon as the node is started.
+    ///
+    /// The node only becomes ready once all of them are bound, see [`AwaitReady`].
+    pub fn with_listen_addresses(mut self, addresses: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.startup.add_listen_addresses(addresses);
+
+        self
+    }
+
+    /// Advertise the given addresses instead of the addresses the node listens on, see [`GetAdvertisedAddresses`].
+    ///
+    /// This is meant for nodes behind a reverse proxy or load balancer, e.g. `/dns4/example.com/tcp/443`.
+    /// Every external address must use the same transport protocols as one of the addresses passed to [`Node::with_listen_addresses`], otherwise the startup fails with [`Error::UnservableExternalAddress`].
+    /// Listeners added later through [`ListenOn`] or [`ListenOnSocket`] count as well: external addresses are only advertised while a listener that can serve them is up.
+    pub fn with_external_addresses(
+        mut self,
+        addresses: impl IntoIterator<Item = Multiaddr>,
+    ) -> Self {
+        self.startup.add_external_addresses(addresses);
+
+        self
+    }
+
+    /// Dial the given addresses as soon as the node is started.
+    ///
+    /// The addresses must contain a `/p2p` suffix. The node only becomes ready once it is connected to all of these peers, see [`AwaitReady`].
+    /// Bootstrap peers are dialed once, use a [`ConnectionSupervisor`] to stay connected.
+    pub fn with_bootstrap_peers(mut self, addresses: impl IntoIterator<Item = Multiaddr>) -> Self {
+        self.startup.add_bootstrap_peers(addresses);
+
+        self
+    }
+
+    /// Handle inbound substreams of the given protocol by calling `handler` in a task of the connection.
+    ///
+    /// Unlike actors passed to [`Node::new`], a panicking handler is contained: the substream is reset, [`Event::HandlerPanicked`] is emitted and the connection as well as other substreams keep running.
+    /// Each substream is handled in its own task, which is forgotten once the handler returns. Handlers still running when the connection closes are stopped.
+    pub fn with_supervised_handler<F, Fut>(mut self, protocol: &'static str, handler: F) -> Self
+    where
+        F: Fn(NewInboundSubstream) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.supervised_handlers
+            .insert(protocol, Arc::new(move |message| handler(message).boxed()));
+
+        if !self.supported_inbound_protocols.contains(&protocol) {
+            self.supported_inbound_protocols.push(protocol);
+            self.node = (self.make_node)(
+                self.identity.clone(),
+                self.supported_inbound_protocols.clone(),
+            );
+        }
+
+        self
+    }
+
+    /// Handle inbound substreams of the given protocol with an actor of another framework, see [`ActorBridge`].
+    ///
+    /// Substreams are delivered from a supervised task, see [`Node::with_supervised_handler`].
+    pub fn with_bridge(self, protocol: &'static str, bridge: impl ActorBridge) -> Self {
+        self.with_supervised_handler(protocol, move |substream| bridge.deliver(substream))
+    }
+
+    /// Handle inbound substreams of the given protocol with several actors, e.g. a pool of workers sharing the load of a busy protocol.
+    ///
+    /// Each substream is delivered to one of the handlers, picked according to the [`DispatchStrategy`].
+    /// Substreams are delivered from a supervised task, see [`Node::with_supervised_handler`].
+    pub fn with_handlers(
+        self,
+        protocol: &'static str,
+        handlers: Vec<Box<dyn StrongMessageChannel<NewInboundSubstream>>>,
+        strategy: DispatchStrategy,
+    ) -> Self {
+        self.with_bridge(protocol, FanOut::new(handlers, strategy))
+    }
+
+    /// Share the write capacity of each connection between protocols according to the given weights.
+    ///
+    /// While several protocols write to the same connection, each gets a share proportional to its weight, protocols without a weight get a weight of 1.
+    /// Writes are split into chunks of at most [`FAIR_SCHEDULING_CHUNK_SIZE`] bytes so interactive protocols keep a low latency next to bulk transfers.
+    /// A protocol only holds back the others while it is actually writing: protocols that went quiet or whose writes wait for the peer to read do not count.
+    /// By default, writes are passed to the muxer as they come.
+    pub fn with_fair_scheduling(
+        mut self,
+        weights: impl IntoIterator<Item = (&'static str, u32)>,
+    ) -> Self {
+        self.write_weights = Some(Arc::new(weights.into_iter().collect()));
+
+        self
+    }
+
+    /// Mark the given inbound protocols as deprecated.
+    ///
+    /// Deprecated protocols are still served, but every time a peer negotiates one, a warning is logged and [`Event::DeprecatedProtocolNegotiated`] is emitted.
+    /// This tells operators which peers still have to upgrade before support for the protocol can be dropped.
+    pub fn with_deprecated_protocols(
+        mut self,
+        protocols: impl IntoIterator<Item = &'static str>,
+    ) -> Self {
+        self.deprecated_protocols.extend(protocols);
+
+        self
+    }
+
+    /// Judge the health of the node against the given thresholds, see [`GetHealth`].
+    pub fn with_health_thresholds(mut self, thresholds: HealthThresholds) -> Self {
+        self.health_thresholds = thresholds;
+
+        self
+    }
+
+    /// Returns the [`NodeConfig`] the node is currently running with.
+    pub fn config(&self) -> NodeConfig {
+        NodeConfig {
+            connection_timeout: self.connection_timeout,
+            handler_grace_period: self.handler_grace_period,
+            max_concurrent_upgrades: self.max_concurrent_upgrades,
+            max_connections_per_peer: self.max_connections_per_peer,
+            max_inbound_connections: self.max_inbound_connections,
+            reserved_inbound_slots: self.reserved_inbound_slots,
+            selection_policy: self.selection_policy,
+            session_resumption_ttl: self.resumption_ttl,
+            max_handshake_size: self.config.handshake_limit().get(),
+        }
+    }
+
+    /// Applies the given [`NodeConfig`] as far as possible without dropping any connections.
+    ///
+    /// Limits, policies and timeouts apply to new connections and substreams right away.
+    /// Returns the names of the fields that were changed but only take effect after a restart:
+    /// - `connection_timeout` is baked into the transport and is therefore left unchanged.
+    /// - `max_concurrent_upgrades` and enabling session resumption are applied to listeners started afterwards, i.e. existing listeners have to be restarted.
+    pub fn apply_config(&mut self, config: NodeConfig) -> Vec<&'static str> {
+        let is_listening = !self.listen_addresses.is_empty() || !self.socket_listeners.is_empty();
+        let mut requires_restart = Vec::new();
+
+        if config.connection_timeout != self.connection_timeout {
+            requires_restart.push("connection_timeout");
+        }
+
+        if config.max_concurrent_upgrades != self.max_concurrent_upgrades {
+            self.max_concurrent_upgrades = config.max_concurrent_upgrades;
+
+            if is_listening {
+                requires_restart.push("max_concurrent_upgrades");
+            }
+        }
+
+        if config.session_resumption_ttl != self.resumption_ttl {
+            self.resumption_ttl = config.session_resumption_ttl;
+
+            if config.session_resumption_ttl.is_some()
+                && self.support_session_resumption()
+                && is_listening
+            {
+                requires_restart.push("session_resumption_ttl");
+            }
+        }
+
+        self.handler_grace_period = config.handler_grace_period;
+        self.max_connections_per_peer = config.max_connections_per_peer.max(1);
+        self.max_inbound_connections = config.max_inbound_connections;
+        self.inbound_gate.set_max(config.max_inbound_connections);
+        self.reserved_inbound_slots = config.reserved_inbound_slots;
+        self.selection_policy = config.selection_policy;
+        self.config.handshake_limit().set(config.max_handshake_size);
+
+        requires_restart
+    }
+
+    /// Registers the agent version protocol.
+    fn support_agent_version(&mut self) {
+        if self
+            .supported_inbound_protocols
+            .contains(&agent_version::PROTOCOL)
+        {
+            return;
+        }
+
+        self.supported_inbound_protocols
+            .push(agent_version::PROTOCOL);
+        self.node = (self.make_node)(
+            self.identity.clone(),
+            self.supported_inbound_protocols.clone(),
+        );
+    }
+
+    /// Registers the session resumption protocol, returning whether it was not registered before.
+    fn support_session_resumption(&mut self) -> bool {
+        if self
+            .supported_inbound_protocols
+            .contains(&resumption::PROTOCOL)
+        {
+            return false;
+        }
+
+        self.supported_inbound_protocols.push(resumption::PROTOCOL);
+        self.node = (self.make_node)(
+            self.identity.clone(),
+            self.supported_inbound_protocols.clone(),
+        );
+
+        true
+    }
+
+    fn emit(&mut self, event: Event) {
+        self.subscribers
+            .retain(|subscriber| subscriber.do_send(event.clone()).is_ok());
+        self.has_subscribers
+            .store(!self.subscribers.is_empty(), Ordering::Relaxed);
+    }
+
+    fn stats_snapshot(&mut self, reset: bool) -> StatsSnapshot {
+        let (
+            bytes_inbound,
+            bytes_outbound,
+            substreams_inbound,
+            substreams_outbound,
+            oversized_handshakes,
+            rejected_inbound_connections,
+            rejected_connection_intents,
+        ) = self.counters.read(reset);
+        let elapsed = self.counting_since.elapsed();
+
+        if reset {
+            self.counting_since = Instant::now();
+        }
+
+        StatsSnapshot {
+            bytes_inbound,
+            bytes_outbound,
+            substreams_inbound,
+            substreams_outbound,
+            oversized_handshakes,
+            rejected_inbound_connections,
+            rejected_connection_intents,
+            elapsed,
+        }
+    }
+
+    fn listen_on(&mut self, listen_address: Multiaddr, ctx: &mut Context<Self>) {
+        if self.client_mode {
+            tracing::warn!(address = %listen_address, "Not listening because node is in client mode");
+            self.startup.listener_failed(&listen_address);
+            return;
+        }
+
+        let this = ctx.address().expect("we are alive");
+        self.failed_listeners.remove(&listen_address);
+
+        let (stopped_sender, stopped) = oneshot::channel();
+        let mut tasks = Tasks::default();
+        tasks.add_fallible(
+            {
+                let node = self.node.clone();
+                let this = this.clone();
+                let listen_address = listen_address.clone();
+
+                let upgrade_executor = self.upgrade_executor.clone();
+                let max_concurrent_upgrades = self.max_concurrent_upgrades;
+                let next_connection_id = self.next_connection_id.clone();
+                let address_filter = self.address_filter.clone();
+                let inbound_gate = self.inbound_gate.clone();
+                let counters = self.counters.clone();
+
+                async move {
+                    // Dropped together with the listener, see `Listener::stop`.
+                    let _stopped = stopped_sender;
+                    let listener = node.listen_on(listen_address.clone())?;
+                    let _ = this
+                        .send(ListenerBound {
+                            address: listen_address,
+                        })
+                        .await;
+
+                    let upgrades = listener
+                        .try_filter(move |(remote_address, _)| {
+                            futures::future::ready(accept_from(&address_filter, remote_address))
+                        })
+                        .try_filter_map(move |(remote_address, upgrade)| {
+                            let permit = match inbound_gate.try_admit() {
+                                Some(permit) => permit,
+                                None => {
+                                    tracing::debug!(%remote_address, "Dropping inbound connection before the handshake because the inbound connection limit is reached");
+                                    counters.inbound_connection_rejected();
+
+                                    return futures::future::ready(Ok(None));
+                                }
+                            };
+                            let id = ConnectionId::next(&next_connection_id);
+
+                            futures::future::ready(Ok(Some(
+                                register_inbound_connection(
+                                    this.clone(),
+                                    id,
+                                    remote_address,
+                                    upgrade,
+                                    permit,
+                                )
+                                .boxed(),
+                            )))
+                        });
+
+                    match upgrade_executor {
+                        Some(executor) => {
+                            upgrades
+                                .try_for_each(|upgrade| {
+                                    executor(upgrade);
+
+                                    futures::future::ready(Ok(()))
+                                })
+                                .await?
+                        }
+                        None => {
+                            upgrades
+                                .try_for_each_concurrent(max_concurrent_upgrades, |upgrade| {
+                                    upgrade.map(Ok)
+                                })
+                                .await?
+                        }
+                    }
+
+                    bail!("Listener closed")
+                }
+            },
+            {
+                let listen_address = listen_address.clone();
+
+                |error| async move {
+                    let _ = this
+                        .send(ListenerFailed {
+                            address: listen_address,
+                            error,
+                        })
+                        .await;
+                }
+            },
+        );
+        self.listen_addresses
+            .insert(listen_address, Listener { tasks, stopped }); // FIXME: This address could be a "catch-all" like "0.0.0.0" which actually results in listening on multiple interfaces.
+    }
+
+    /// Describes every connected peer that matches the filter.
+    fn peers(&self, filter: &PeerFilter) -> Vec<PeerDescriptor> {
+        self.connections
+            .iter()
+            .map(|(peer, connections)| {
+                let mut protocols = self
+                    .counters
+                    .peer_usage(peer)
+                    .into_iter()
+                    .filter(|(_, usage)| usage.substreams_outbound > 0)
+                    .map(|(protocol, _)| protocol)
+                    .collect::<Vec<_>>();
+                protocols.sort_unstable();
+
+                PeerDescriptor {
+                    peer: *peer,
+                    connected_since: connections
+                        .values()
+                        .filter_map(|connection| connection.timeline.muxer_ready)
+                        .min(),
+                    connections: connections
+                        .values()
+                        .map(|connection| connection.info(*peer))
+                        .collect(),
+                    protocols,
+                }
+            })
+            .filter(|peer| filter.matches(peer))
+            .collect()
+    }
+
+    fn connect(&mut self, address: Multiaddr, ctx: &mut Context<Self>) -> Result<(), Error> {
+        self.connect_with_intent(address, None, ctx)
+    }
+
+    /// Dials the address, declaring `intent` instead of the intent configured for all connections if given.
+    fn connect_with_intent(
+        &mut self,
+        address: Multiaddr,
+        intent: Option<Vec<&'static str>>,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), Error> {
+        if self.is_draining() {
+            return Err(Error::Draining);
+        }
+
+        let this = ctx.address().expect("we are alive");
+
+        let peer = address
+            .clone()
+            .extract_peer_id()
+            .ok_or_else(|| Error::NoPeerIdInAddress(address.clone()))?;
+
+        if self.inflight_connections.contains(&peer) || self.is_at_connection_limit(&peer) {
+            return Err(Error::AlreadyConnected(peer));
+        }
+
+        let id = ConnectionId::next(&self.next_connection_id);
+        let dial_started = Instant::now();
+        self.inflight_connections.insert(peer);
+        if let Some(intent) = intent {
+            self.config.connection_intent().set_for(peer, intent);
+        }
+        self.config.observer().get().dial_started(id, &address);
         self.tasks.add_fallible(
             {
                 let node = self.node.clone();
                 let this = this.clone();
+                let address = address.clone();
+                let intents = self.config.connection_intent().clone();
+
+                async move {
+                    let result = node.connect(address.clone()).await;
+                    intents.clear_for(&peer);
+                    let (peer, control, incoming_substreams, worker, timeline) = result?;
+
+                    let _ = this
+                        .do_send_async(NewConnection {
+                            id,
+                            peer,
+                            role: Endpoint::Dialer,
+                            remote_address: Some(address),
+                            control,
+                            incoming_substreams,
+                            worker,
+                            timeline: ConnectionTimeline {
+                                dial_started: Some(dial_started),
+                                ..timeline
+                            },
+                            registered: None,
+                        })
+                        .await;
+
+                    anyhow::Ok(())
+                }
+            },
+            move |error| async move {
+                let _ = this
+                    .send(FailedToConnect {
+                        peer,
+                        connection: id,
+                        address,
+                        error,
+                    })
+                    .await;
+            },
+        );
+
+        Ok(())
+    }
+
+    fn prewarm_substreams(
+        &mut self,
+        peer: PeerId,
+        protocols: Vec<&'static str>,
+        ctx: &mut Context<Self>,
+    ) {
+        let this = ctx.address().expect("we are alive");
+        let (id, connection) = match self.selected_connection(&peer) {
+            None => return,
+            Some(selected) => selected,
+        };
+
+        for protocol in protocols {
+            let mut control = connection.control.clone();
+            let this = this.clone();
+
+            connection.tasks.add(async move {
+                match control.open_substream(vec![protocol]).await {
+                    Ok(Ok((_, stream))) => {
+                        let _ = this
+                            .send(PrewarmedSubstream {
+                                peer,
+                                connection: id,
+                                protocol,
+                                stream,
+                            })
+                            .await;
+                    }
+                    Ok(Err(e)) => {
+                        tracing::debug!(%peer, %protocol, "Failed to prewarm substream: {:#}", e)
+                    }
+                    Err(e) => {
+                        tracing::debug!(%peer, %protocol, "Failed to prewarm substream: {:#}", e)
+                    }
+                }
+            });
+        }
+    }
+
+    fn notify_connection_waiters(&mut self, peer: &PeerId, result: impl Fn() -> Result<(), Error>) {
+        for waiter in self.connection_waiters.remove(peer).unwrap_or_default() {
+            let _ = waiter.send(result());
+        }
+    }
+
+    fn complete_startup_if_ready(&mut self) {
+        if self.startup.complete_if_ready() {
+            tracing::info!("Node is ready");
+            self.emit(Event::Ready);
+        }
+    }
+
+    fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    fn is_at_connection_limit(&self, peer: &PeerId) -> bool {
+        self.connections.get(peer).map_or(0, HashMap::len) >= self.max_connections_per_peer
+    }
+
+    /// Checks whether a connection injected through [`InjectConnection`] can be registered, returning the authenticated peer if so.
+    fn admit_injected_connection(&self, connection: &NewConnection) -> Result<PeerId, Error> {
+        let peer = connection.peer;
+
+        if self.is_draining() {
+            return Err(Error::Draining);
+        }
+
+        if self.inflight_connections.contains(&peer) || self.is_at_connection_limit(&peer) {
+            return Err(Error::AlreadyConnected(peer));
+        }
+
+        if connection.role == Endpoint::Listener && self.is_at_inbound_limit(&peer) {
+            self.counters.inbound_connection_rejected();
+            return Err(Error::InboundLimitReached);
+        }
+
+        Ok(peer)
+    }
+
+    /// Whether an inbound connection from the given peer exceeds the limit, see [`Node::with_reserved_inbound_slots`].
+    ///
+    /// A connection that replaces the oldest connection to the same peer because of [`Node::with_max_connections_per_peer`] does not count against the limit if the replaced connection is inbound as well.
+    fn is_at_inbound_limit(&self, peer: &PeerId) -> bool {
+        let max = match self.max_inbound_connections {
+            Some(max) => max,
+            None => return false,
+        };
+
+        if self.is_at_connection_limit(peer) {
+            let replaces_inbound = self
+                .connections
+                .get(peer)
+                .and_then(|connections| connections.iter().min_by_key(|(id, _)| **id))
+                .map_or(false, |(_, oldest)| oldest.endpoint == Endpoint::Listener);
+
+            if replaces_inbound {
+                return false;
+            }
+        }
+
+        let inbound = self.inbound_connections();
+        let is_known = self.priority_peers.contains(peer)
+            || self.connections.contains_key(peer)
+            || self.startup.is_bootstrap_peer(peer);
+
+        let limit = if is_known {
+            max
+        } else {
+            max.saturating_sub(self.reserved_inbound_slots)
+        };
+
+        inbound >= limit
+    }
+
+    fn inbound_connections(&self) -> usize {
+        self.connections
+            .values()
+            .flat_map(HashMap::values)
+            .filter(|connection| connection.endpoint == Endpoint::Listener)
+            .count()
+    }
+
+    /// Returns the connection to the given peer picked by the configured [`SelectionPolicy`].
+    fn selected_connection(&mut self, peer: &PeerId) -> Option<(ConnectionId, &mut Connection)> {
+        let connections = self.connections.get_mut(peer)?;
+
+        let id = self
+            .selection_policy
+            .select(connections.iter().map(|(id, connection)| Candidate {
+                id: *id,
+                relayed: connection.is_relayed(),
+                rtt: connection.rtt,
+            }))?;
+        let connection = connections.get_mut(&id)?;
+
+        Some((id, connection))
+    }
+
+    /// Keeps the session of the given connection around so it can be resumed after reconnecting.
+    fn suspend_session(&mut self, peer: &PeerId, id: ConnectionId) {
+        let ttl = match self.resumption_ttl {
+            None => return,
+            Some(ttl) => ttl,
+        };
+        let now = Instant::now();
+
+        self.suspended_sessions
+            .retain(|_, session| session.expires_at > now);
+
+        let connection = match self.connections.get(peer).and_then(|c| c.get(&id)) {
+            None => return,
+            Some(connection) => connection,
+        };
+        let token = match connection.session {
+            None => return,
+            Some(token) => token,
+        };
+
+        self.suspended_sessions.insert(
+            *peer,
+            SuspendedSession {
+                token,
+                extensions: connection.extensions.clone(),
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    /// Carries the state of the suspended session with the given token over to the given connection.
+    ///
+    /// Returns whether there was such a session.
+    fn resume_session(&mut self, peer: PeerId, id: ConnectionId, token: ResumptionToken) -> bool {
+        let connection = match self.connections.get_mut(&peer).and_then(|c| c.get_mut(&id)) {
+            None => return false,
+            Some(connection) => connection,
+        };
+        connection.session = Some(token);
+
+        let session = match self.suspended_sessions.remove(&peer) {
+            Some(session) if session.token == token && session.expires_at > Instant::now() => {
+                session
+            }
+            _ => return false,
+        };
+        connection.extensions.absorb(&session.extensions);
+
+        self.emit(Event::SessionResumed {
+            peer,
+            connection: id,
+        });
+
+        true
+    }
+
+    /// Closes all connections to the given peer.
+    fn drop_connection(&mut self, peer: &PeerId) {
+        let ids = match self.connections.get(peer) {
+            None => return,
+            Some(connections) => connections.keys().copied().collect::<Vec<_>>(),
+        };
+
+        for id in ids {
+            self.drop_single_connection(peer, id);
+        }
+    }
+
+    fn drop_single_connection(&mut self, peer: &PeerId, id: ConnectionId) {
+        let connections = match self.connections.get_mut(peer) {
+            None => return,
+            Some(connections) => connections,
+        };
+        let Connection {
+            control,
+            tasks,
+            substreams,
+            ..
+        } = match connections.remove(&id) {
+            None => return,
+            Some(connection) => connection,
+        };
+        let last_connection = connections.is_empty();
+        substreams.connection_closed();
+        self.emit(Event::ConnectionClosed {
+            peer: *peer,
+            connection: id,
+        });
+
+        // Prewarmed substreams are not tracked per connection, so they might belong to this one.
+        self.prewarmed
+            .retain(|(prewarmed_peer, _), _| prewarmed_peer != peer);
+        for pool in self.broadcast_pools.values() {
+            pool.lock().expect("not poisoned").clear(peer);
+        }
+
+        if last_connection {
+            self.connections.remove(peer);
+            self.counters.peer_disconnected(*peer);
+            self.address_history.disconnected(*peer);
+            self.emit(Event::PeerDisconnected { peer: *peer });
+        }
+        self.inbound_gate
+            .set_established(self.inbound_connections());
+        self.config.observer().get().connection_closed(peer, id);
+
+        // TODO: Evaluate whether dropping and closing has to be in a particular order.
+        self.tasks.add(async move {
+            control.close_connection().await;
+            drop(tasks);
+        });
+    }
+
+    async fn open_substream(
+        &mut self,
+        peer: PeerId,
+        protocols: Vec<&'static str>,
+    ) -> Result<(&'static str, Substream), Error> {
+        if let Some(protocol) = protocols.first().copied() {
+            if let Some(stream) = self.prewarmed.remove(&(peer, protocol)) {
+                return Ok((protocol, stream));
+            }
+        }
+
+        let (id, connection) = self
+            .selected_connection(&peer)
+            .ok_or_else(|| Error::NoConnection(peer))?;
+
+        let context = {
+            let remote_address = connection.remote_address.clone();
+            let protocols = protocols.clone();
+
+            move |source| {
+                Error::Contextual(Box::new(ContextualError {
+                    peer,
+                    connection: id,
+                    remote_address: remote_address.clone(),
+                    protocols: protocols.clone(),
+                    source,
+                }))
+            }
+        };
+
+        if !connection.substreams.memory().has_room_for_substream() {
+            return Err(context(Error::MemoryBudgetExhausted(peer, id)));
+        }
+
+        let started = Instant::now();
+        let result = connection.control.open_substream(protocols).await;
+
+        if let Ok(Ok(_)) = result {
+            let sample = started.elapsed();
+
+            connection.rtt = Some(match connection.rtt {
+                None => sample,
+                Some(rtt) => (rtt * 7 + sample) / 8,
+            });
+        }
+
+        let (protocol, stream) = match result {
+            Ok(result) => result,
+            Err(yamux::ConnectionError::Closed) => {
+                self.suspend_session(&peer, id);
+                self.drop_single_connection(&peer, id);
+                return Err(context(Error::ConnectionClosed(peer, id)));
+            }
+            Err(e) => return Err(context(Error::BadConnection(e))),
+        }
+        .map_err(|e| {
+            context(match e {
+                libp2p_stream::Error::NegotiationFailed(e) => Error::NegotiationFailed(e),
+                libp2p_stream::Error::NegotiationTimeoutReached => Error::NegotiationTimeoutReached,
+            })
+        })?;
+        let stream = connection
+            .substreams
+            .track(protocol, Endpoint::Dialer, stream);
+        self.counters.outbound_substream_opened();
+        self.config
+            .observer()
+            .get()
+            .substream_negotiated(&peer, id, protocol, Endpoint::Dialer);
+
+        Ok((protocol, stream))
+    }
+}
+
+#[xtra_productivity]
+impl Node {
+    async fn handle(&mut self, mut msg: NewConnection, ctx: &mut Context<Self>) {
+        match msg.registered.take() {
+            // Injected connections were not dialed by us, so they must neither end a dial nor replace an existing connection.
+            Some(registered) => {
+                let admitted = self.admit_injected_connection(&msg);
+                let rejected = admitted.is_err();
+                let _ = registered.send(admitted);
+
+                if rejected {
+                    tracing::debug!(peer = %msg.peer, connection = %msg.id, "Dropping injected connection");
+                    return;
+                }
+            }
+            None => {
+                self.inflight_connections.remove(&msg.peer);
+                if msg.role == Endpoint::Dialer {
+                    self.dial_history.record(true);
+                }
+            }
+        }
+        let this = ctx.address().expect("we are alive");
+
+        if self.is_draining() {
+            tracing::debug!(peer = %msg.peer, connection = %msg.id, "Dropping new connection because node is draining");
+            self.notify_connection_waiters(&msg.peer, || Err(Error::Draining));
+            return;
+        }
+
+        let NewConnection {
+            id,
+            peer,
+            role,
+            remote_address,
+            control,
+            mut incoming_substreams,
+            worker,
+            timeline,
+            ..
+        } = msg;
+
+        if role == Endpoint::Listener && self.is_at_inbound_limit(&peer) {
+            tracing::debug!(%peer, connection = %id, "Dropping inbound connection because the inbound connection limit is reached");
+            self.counters.inbound_connection_rejected();
+            return;
+        }
+
+        if self.is_at_connection_limit(&peer) {
+            let oldest = self
+                .connections
+                .get(&peer)
+                .and_then(|connections| connections.keys().min().copied());
+
+            if let Some(oldest) = oldest {
+                tracing::debug!(%peer, connection = %oldest, "Closing oldest connection to make room for new one");
+                self.drop_single_connection(&peer, oldest);
+            }
+        }
+
+        tracing::debug!(%peer, connection = %id, steps = ?timeline.steps(), "Connection established");
+        // Logged here rather than in the transport so connections upgraded through `InjectConnection`, `ListenOnSocket` or `upgrade_connection` are audited as well.
+        tracing::info!(
+            target: AUDIT_TARGET,
+            %peer,
+            connection = %id,
+            remote_address = %remote_address.as_ref().map_or_else(|| "unknown".to_owned(), ToString::to_string),
+            dialer = role == Endpoint::Dialer,
+            "Connection registered"
+        );
+
+        // Emitted before the inbound substreams are dispatched so subscribers see it first.
+        self.emit(Event::ConnectionEstablished {
+            peer,
+            connection: id,
+            endpoint: role,
+        });
+        let reconnected = self
+            .connections
+            .get(&peer)
+            .map_or(true, |connections| connections.is_empty());
+        if reconnected {
+            if let Some(new) = &remote_address {
+                if let Some(old) = self.address_history.connected(peer, new.clone()) {
+                    self.emit(Event::AddressChanged {
+                        peer,
+                        old,
+                        new: new.clone(),
+                    });
+                }
+            }
+        }
+
+        let extensions = Extensions::default();
+        let substreams = CloseTracker::new(
+            peer,
+            id,
+            self.counters.clone(),
+            self.config.clone(),
+            this.downgrade(),
+            self.has_subscribers.clone(),
+            self.write_weights.clone().map(WriteScheduler::new),
+            MemoryAccount::new(self.memory_budget),
+            self.write_stall_timeout,
+        );
+        let mut tasks = Tasks::default();
+        tasks.add(worker);
+        if let Some(threshold) = self.inbound_stall_threshold {
+            tasks.add(detect_inbound_stall(
+                control.clone(),
+                threshold,
+                peer,
+                id,
+                this.clone(),
+            ));
+        }
+        tasks.add_fallible(
+            {
+                let extensions = extensions.clone();
+                let substreams = substreams.clone();
+                let counters = self.counters.clone();
+                let config = self.config.clone();
+                let handler_grace_period = self.handler_grace_period;
+                let draining = self.draining.clone();
+                let this = this.clone();
+                let inbound_substream_channels = self
+                    .inbound_substream_channels
+                    .iter()
+                    .map(|(proto, channel)| {
+                        (
+                            proto.to_owned(),
+                            StrongMessageChannel::clone_channel(channel.as_ref()),
+                        )
+                    })
+                    .collect::<HashMap<_, _>>();
+                let supervised_handlers = self.supervised_handlers.clone();
+                let deprecated_protocols = self.deprecated_protocols.clone();
+
+                async move {
+                    let mut dispatches = TaskSet::default();
+
+                    loop {
+                        let (stream, protocol) = match incoming_substreams.try_next().await {
+                            Ok(Some(Ok((stream, protocol)))) => (stream, protocol),
+                            Ok(Some(Err(libp2p_stream::Error::NegotiationTimeoutReached))) => {
+                                tracing::debug!("Hit timeout while negotiating substream");
+                                counters.inbound_substream_rejected(
+                                    peer,
+                                    None,
+                                    RejectionReason::NegotiationTimeout,
+                                );
+                                continue;
+                            }
+                            Ok(Some(Err(libp2p_stream::Error::NegotiationFailed(e)))) => {
+                                tracing::debug!("Failed to negotiate substream: {}", e);
+
+                                let reason = match e {
+                                    NegotiationError::Failed => RejectionReason::UnsupportedProtocol,
+                                    NegotiationError::ProtocolError(_) => {
+                                        RejectionReason::MalformedNegotiation
+                                    }
+                                };
+                                counters.inbound_substream_rejected(peer, None, reason);
+
+                                if let NegotiationError::Failed = e {
+                                    let _ = this
+                                        .send(UnsupportedProtocolProposed {
+                                            peer,
+                                            connection: id,
+                                        })
+                                        .await;
+                                }
+                                continue;
+                            }
+                            Ok(None) => bail!("Substream listener closed"),
+                            Err(e) => bail!(e),
+                        };
+
+                        if draining.load(Ordering::Relaxed) {
+                            tracing::debug!(%peer, %protocol, "Dropping inbound substream because node is draining");
+                            counters.inbound_substream_rejected(
+                                peer,
+                                Some(protocol),
+                                RejectionReason::Draining,
+                            );
+                            continue;
+                        }
+
+                        if !substreams.memory().has_room_for_substream() {
+                            tracing::debug!(%peer, %protocol, "Dropping inbound substream because the memory budget of the connection is exhausted");
+                            counters.inbound_substream_rejected(
+                                peer,
+                                Some(protocol),
+                                RejectionReason::MemoryBudget,
+                            );
+                            continue;
+                        }
+
+                        if !counters
+                            .protocol_usage(peer, protocol, config.quotas().get(protocol))
+                            .admit_substream() {
+                            tracing::debug!(%peer, %protocol, "Dropping inbound substream because the peer exceeded its quota");
+                            counters.inbound_substream_rejected(
+                                peer,
+                                Some(protocol),
+                                RejectionReason::Quota,
+                            );
+                            let _ = this
+                                .send(QuotaExceeded {
+                                    peer,
+                                    connection: id,
+                                    protocol,
+                                    kind: QuotaKind::Substreams,
+                                })
+                                .await;
+                            continue;
+                        }
+
+                        counters.inbound_substream_opened();
+                        config.observer().get().substream_negotiated(
+                            &peer,
+                            id,
+                            protocol,
+                            Endpoint::Listener,
+                        );
+
+                        if deprecated_protocols.contains(protocol) {
+                            let _ = this
+                                .send(DeprecatedProtocolNegotiated {
+                                    peer,
+                                    connection: id,
+                                    protocol,
+                                })
+                                .await;
+                        }
+
+                        if protocol == agent_version::PROTOCOL {
+                            let this = this.clone();
+                            dispatches.add_fallible(
+                                async move {
+                                    let agent_version = agent_version::receive(stream).await?;
+                                    this.send(AgentVersionReceived {
+                                        peer,
+                                        connection: id,
+                                        agent_version,
+                                    })
+                                    .await?;
+
+                                    anyhow::Ok(())
+                                },
+                                move |e| async move {
+                                    tracing::debug!(%peer, "Failed to receive agent version: {:#}", e)
+                                },
+                            );
+                            continue;
+                        }
+
+                        if protocol == resumption::PROTOCOL {
+                            let this = this.clone();
+                            dispatches.add_fallible(
+                                resumption::answer(stream, move |token| async move {
+                                    let resumed = this
+                                        .send(ResumeSession {
+                                            peer,
+                                            connection: id,
+                                            token,
+                                        })
+                                        .await?;
+
+                                    anyhow::Ok(resumed)
+                                }),
+                                move |e| async move {
+                                    tracing::debug!(%peer, "Failed to answer session resumption: {:#}", e)
+                                },
+                            );
+                            continue;
+                        }
+
+                        let supervised_handler = supervised_handlers.get(&protocol);
+                        let grace_period =
+                            handler_grace_period.filter(|_| supervised_handler.is_none());
+                        let (stream, acceptance) = match grace_period {
+                            None => (substreams.track(protocol, Endpoint::Listener, stream), None),
+                            Some(_) => {
+                                let (stream, acceptance) = substreams.track_deferred(
+                                    protocol,
+                                    Endpoint::Listener,
+                                    stream,
+                                );
+
+                                (stream, Some(acceptance))
+                            }
+                        };
+                        let message = NewInboundSubstream {
+                            peer,
+                            connection: id,
+                            stream,
+                            extensions: extensions.clone(),
+                        };
+
+                        if let Some(handler) = supervised_handler {
+                            let run = supervised::run(handler.clone(), message, this.clone());
+                            substreams.if_open(|| dispatches.add(run));
+                            continue;
+                        }
+
+                        let channel = inbound_substream_channels
+                            .get(&protocol)
+                            .expect("Cannot negotiate a protocol that we don't support");
+
+                        // Dispatching while holding the tracker ensures the substream is enqueued before `Event::ConnectionClosed`.
+                        match substreams.if_open(|| channel.do_send(message)) {
+                            Some(Ok(())) => {}
+                            Some(Err(_)) => {
+                                counters.inbound_substream_rejected(
+                                    peer,
+                                    Some(protocol),
+                                    RejectionReason::HandlerGone,
+                                );
+                                continue;
+                            }
+                            None => {
+                                tracing::debug!(%peer, %protocol, "Dropping inbound substream because connection is closed");
+                                continue;
+                            }
+                        }
+
+                        let (grace_period, acceptance) = match (grace_period, acceptance) {
+                            (Some(grace_period), Some(acceptance)) => (grace_period, acceptance),
+                            _ => continue,
+                        };
+
+                        let counters = counters.clone();
+                        let this = this.clone();
+                        dispatches.add(async move {
+                            if acceptance.within(grace_period).await {
+                                return;
+                            }
+
+                            counters.inbound_substream_rejected(
+                                peer,
+                                Some(protocol),
+                                RejectionReason::HandlerTimeout,
+                            );
+                            tracing::warn!(
+                                %peer,
+                                %protocol,
+                                "Handler did not accept inbound substream within {:?}, resetting it",
+                                grace_period
+                            );
+                            let _ = this
+                                .send(InboundSubstreamHandlerTimedOut {
+                                    peer,
+                                    connection: id,
+                                    protocol,
+                                })
+                                .await;
+                        });
+                    }
+                }
+            },
+            move |error| async move {
+                let _ = this
+                    .send(ConnectionFailed {
+                        peer,
+                        connection: id,
+                        error,
+                    })
+                    .await;
+            },
+        );
+        if self.resumption_ttl.is_some() && role == Endpoint::Dialer {
+            let token = self
+                .suspended_sessions
+                .get(&peer)
+                .filter(|session| session.expires_at > Instant::now())
+                .map(|session| session.token)
+                .unwrap_or_else(ResumptionToken::random);
+            let mut control = control.clone();
+            let this = this.clone();
+
+            tasks.add_fallible(
+                async move {
+                    let (_, stream) = control.open_substream(vec![resumption::PROTOCOL]).await??;
+                    let resumed = resumption::propose(stream, token).await?;
+
+                    this.send(SessionEstablished {
+                        peer,
+                        connection: id,
+                        token,
+                        resumed,
+                    })
+                    .await?;
+
+                    anyhow::Ok(())
+                },
+                move |e| async move {
+                    tracing::debug!(%peer, "Failed to establish resumable session: {:#}", e)
+                },
+            );
+        }
+        if let Some(agent_version) = self.agent_version.clone() {
+            let mut control = control.clone();
+
+            tasks.add_fallible(
+                async move {
+                    let (_, stream) = control
+                        .open_substream(vec![agent_version::PROTOCOL])
+                        .await??;
+                    agent_version::send(stream, &agent_version).await?;
+
+                    anyhow::Ok(())
+                },
+                move |e| async move {
+                    tracing::debug!(%peer, "Failed to send agent version: {:#}", e)
+                },
+            );
+        }
+
+        self.counters.peer_connected(peer);
+        self.connections.entry(peer).or_default().insert(
+            id,
+            Connection {
+                control,
+                tasks,
+                extensions,
+                session: None,
+                endpoint: role,
+                remote_address,
+                rtt: None,
+                substreams,
+                timeline,
+                agent_version: None,
+            },
+        );
+        self.inbound_gate
+            .set_established(self.inbound_connections());
+
+        self.config
+            .observer()
+            .get()
+            .connection_established(&peer, id);
+
+        if let Some(protocols) = self.pending_prewarms.remove(&peer) {
+            self.prewarm_substreams(peer, protocols, ctx);
+        }
+
+        self.notify_connection_waiters(&peer, || Ok(()));
+        self.startup.peer_connected(&peer);
+        self.complete_startup_if_ready();
+    }
+
+    async fn handle(&mut self, msg: SessionEstablished) {
+        if !msg.resumed {
+            // The peer does not know the session (anymore), the proposed token starts a new one.
+            self.suspended_sessions.remove(&msg.peer);
+        }
+
+        self.resume_session(msg.peer, msg.connection, msg.token);
+    }
+
+    async fn handle(&mut self, msg: ResumeSession) -> bool {
+        self.resume_session(msg.peer, msg.connection, msg.token)
+    }
+
+    async fn handle(&mut self, msg: AgentVersionReceived) {
+        let connection = match self
+            .connections
+            .get_mut(&msg.peer)
+            .and_then(|connections| connections.get_mut(&msg.connection))
+        {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        tracing::debug!(peer = %msg.peer, agent_version = %msg.agent_version, "Peer announced its agent version");
+        connection.agent_version = Some(msg.agent_version.clone());
+
+        self.emit(Event::AgentVersionReceived {
+            peer: msg.peer,
+            connection: msg.connection,
+            agent_version: msg.agent_version,
+        });
+    }
+
+    async fn handle(&mut self, msg: InboundSubstreamsStalled) {
+        tracing::warn!(peer = %msg.peer, connection = %msg.connection, waiting = ?msg.waiting, "Inbound substreams are not dispatched, is the node overloaded?");
+
+        self.emit(Event::InboundSubstreamsStalled {
+            peer: msg.peer,
+            connection: msg.connection,
+            waiting: msg.waiting,
+        });
+    }
+
+    async fn handle(&mut self, msg: QuotaExceeded) {
+        tracing::info!(peer = %msg.peer, protocol = %msg.protocol, kind = ?msg.kind, "Peer exceeded its quota");
+
+        self.emit(Event::QuotaExceeded {
+            peer: msg.peer,
+            connection: msg.connection,
+            protocol: msg.protocol,
+            kind: msg.kind,
+        });
+    }
+
+    async fn handle(&mut self, msg: UnsupportedProtocolProposed) {
+        self.emit(Event::UnsupportedProtocolProposed {
+            peer: msg.peer,
+            connection: msg.connection,
+        });
+    }
+
+    async fn handle(&mut self, msg: DeprecatedProtocolNegotiated) {
+        let remote_address = self
+            .connections
+            .get(&msg.peer)
+            .and_then(|connections| connections.get(&msg.connection))
+            .and_then(|connection| connection.remote_address.clone());
+
+        tracing::warn!(
+            peer = %msg.peer,
+            connection = %msg.connection,
+            protocol = %msg.protocol,
+            ?remote_address,
+            "Peer negotiated deprecated protocol"
+        );
+        self.emit(Event::DeprecatedProtocolNegotiated {
+            peer: msg.peer,
+            connection: msg.connection,
+            protocol: msg.protocol,
+            remote_address,
+        });
+    }
+
+    async fn handle(&mut self, msg: HandlerPanicked) {
+        self.emit(Event::HandlerPanicked {
+            peer: msg.peer,
+            connection: msg.connection,
+            protocol: msg.protocol,
+            message: msg.message,
+        });
+    }
+
+    async fn handle(&mut self, msg: InboundSubstreamHandlerTimedOut) {
+        self.emit(Event::InboundSubstreamHandlerTimeout {
+            peer: msg.peer,
+            connection: msg.connection,
+            protocol: msg.protocol,
+        });
+    }
+
+    async fn handle(&mut self, msg: ListenerBound) {
+        tracing::debug!(address = %msg.address, "Listener bound");
+
+        self.startup.listener_bound(&msg.address);
+        self.complete_startup_if_ready();
+    }
+
+    async fn handle(&mut self, msg: ListenerFailed) {
+        tracing::debug!("Listener failed: {:#}", msg.error);
+
+        self.startup.listener_failed(&msg.address);
+        self.listen_addresses.remove(&msg.address);
+        self.socket_listeners.remove(&msg.address);
+        self.failed_listeners.insert(msg.address);
+    }
+
+    async fn handle(&mut self, _: GetHealth) -> HealthReport {
+        let dial_failure_rate = self
+            .dial_history
+            .failure_rate(self.health_thresholds.dial_window);
+
+        self.health_thresholds.evaluate(
+            self.listen_addresses.len() + self.socket_listeners.len(),
+            self.failed_listeners.len(),
+            self.connections.len(),
+            dial_failure_rate,
+        )
+    }
+
+    async fn handle(&mut self, _: AwaitReady) -> oneshot::Receiver<Result<(), Error>> {
+        self.startup.wait()
+    }
+
+    async fn handle(&mut self, msg: FailedToConnect) {
+        tracing::debug!(connection = %msg.connection, "Failed to connect: {:#}", msg.error);
+        let peer = msg.peer;
+
+        let error_kind = DialErrorKind::from_error(&msg.error);
+
+        self.inflight_connections.remove(&peer);
+        self.pending_prewarms.remove(&peer);
+        self.notify_connection_waiters(&peer, || Err(Error::ConnectFailed(error_kind)));
+        self.startup.peer_failed(&peer, error_kind);
+        self.dial_history.record(false);
+
+        self.emit(Event::OutgoingConnectionError {
+            peer,
+            connection: msg.connection,
+            address: msg.address,
+            error_kind,
+        });
+    }
+
+    async fn handle(&mut self, msg: GetExtensions) -> Option<Extensions> {
+        self.selected_connection(&msg.0)
+            .map(|(_, connection)| connection.extensions.clone())
+    }
+
+    async fn handle(&mut self, msg: Subscribe) {
+        self.subscribers.push(msg.0);
+        self.has_subscribers.store(true, Ordering::Relaxed);
+    }
+
+    async fn handle(&mut self, msg: ConnectionFailed) {
+        let remote_address = self
+            .connections
+            .get(&msg.peer)
+            .and_then(|connections| connections.get(&msg.connection))
+            .and_then(|connection| connection.remote_address.clone());
+        tracing::debug!(peer = %msg.peer, connection = %msg.connection, remote_address = ?remote_address, "Connection failed: {:#}", msg.error);
+        let peer = msg.peer;
+
+        if let Some(error) = msg.error.downcast_ref::<yamux::ConnectionError>() {
+            if let Some(kind) = ConnectionErrorKind::from_yamux_error(error) {
+                self.emit(Event::ConnectionError {
+                    peer,
+                    connection: msg.connection,
+                    kind,
+                    error: error.to_string(),
+                });
+            }
+        }
+
+        self.suspend_session(&peer, msg.connection);
+        self.drop_single_connection(&peer, msg.connection);
+    }
+
+    async fn handle(&mut self, _: GetAdvertisedAddresses) -> Vec<Multiaddr> {
+        let local_peer_id = self.identity.public().to_peer_id();
+        let mut advertised = Vec::new();
+
+        let listeners = self
+            .listen_addresses
+            .keys()
+            .chain(self.socket_listeners.keys())
+            .collect::<Vec<_>>();
+
+        if !self.startup.external_addresses().is_empty() {
+            return self
+                .startup
+                .external_addresses()
+                .iter()
+                .filter(|address| {
+                    let servable = listeners
+                        .iter()
+                        .any(|listener| startup::can_serve(listener, address));
+                    if !servable {
+                        tracing::debug!(%address, "Not advertising external address without a listener to serve it");
+                    }
+
+                    servable
+                })
+                .map(|address| with_peer_id(address.clone(), local_peer_id))
+                .collect();
+        }
+
+        for address in listeners.into_iter().cloned() {
+            let address = match &self.advertisement_filter {
+                Some(filter) => match filter(address) {
+                    Some(address) => address,
+                    None => continue,
+                },
+                None => address,
+            };
+            let address = with_peer_id(address, local_peer_id);
+
+            if !advertised.contains(&address) {
+                advertised.push(address);
+            }
+        }
+
+        advertised
+    }
+
+    async fn handle(&mut self, _: GetConnectionStats) -> ConnectionStats {
+        ConnectionStats {
+            connected_peers: self.connections.keys().copied().collect(),
+            connections: self
+                .connections
+                .iter()
+                .flat_map(|(peer, connections)| {
+                    connections
+                        .iter()
+                        .map(|(id, connection)| (*id, connection.info(*peer)))
+                })
+                .collect(),
+            listen_addresses: self
+                .listen_addresses
+                .keys()
+                .chain(self.socket_listeners.keys())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    async fn handle(&mut self, msg: GetPeers) -> Vec<PeerDescriptor> {
+        self.peers(&msg.filter)
+    }
+
+    async fn handle(&mut self, msg: SamplePeers) -> Vec<PeerDescriptor> {
+        let mut peers = self.peers(&msg.filter);
+        // Connections are kept in a `HashMap`, order the peers so the seed alone determines the sample.
+        peers.sort_by_key(|descriptor| descriptor.peer.to_bytes());
+
+        let sample = match msg.seed {
+            Some(seed) => peers.choose_multiple(&mut ChaCha8Rng::seed_from_u64(seed), msg.n),
+            None => peers.choose_multiple(&mut rand::thread_rng(), msg.n),
+        };
+
+        sample.cloned().collect()
+    }
+
+    async fn handle(&mut self, _: SnapshotStats) -> StatsSnapshot {
+        self.stats_snapshot(false)
+    }
+
+    async fn handle(&mut self, _: ResetStats) -> StatsSnapshot {
+        self.stats_snapshot(true)
+    }
+
+    async fn handle(&mut self, msg: ApplyConfig) -> Vec<&'static str> {
+        let requires_restart = self.apply_config(msg.0);
+
+        if !requires_restart.is_empty() {
+            tracing::info!(
+                "Configuration fields {:?} only take effect after a restart",
+                requires_restart
+            );
+        }
+
+        requires_restart
+    }
+
+    async fn handle(&mut self, _: GetConfig) -> NodeConfig {
+        self.config()
+    }
+
+    async fn handle(&mut self, _: GetRejectedSubstreams) -> Vec<RejectedSubstreams> {
+        self.counters.rejected_substreams()
+    }
+
+    async fn handle(&mut self, _: GetClosedSubstreams) -> Vec<ClosedSubstreams> {
+        self.counters.closed_substreams()
+    }
+
+    async fn handle(&mut self, msg: GetPeerInfo) -> PeerInfo {
+        PeerInfo {
+            connections: self
+                .connections
+                .get(&msg.0)
+                .map(|connections| connections.keys().copied().collect())
+                .unwrap_or_default(),
+            protocols: self.counters.peer_usage(&msg.0),
+        }
+    }
+
+    async fn handle(&mut self, msg: SubstreamClosed) {
+        self.emit(Event::SubstreamClosed {
+            peer: msg.peer,
+            connection: msg.connection,
+            protocol: msg.protocol,
+            reason: msg.reason,
+        });
+    }
 
-                async move {
-                    let (peer, control, incoming_substreams, worker) = node.connect(msg.0).await?;
+    async fn handle(
+        &mut self,
+        msg: Broadcast,
+        ctx: &mut Context<Self>,
+    ) -> oneshot::Receiver<HashMap<PeerId, Result<(), Error>>> {
+        let this = ctx.address().expect("we are alive");
+        let peers = self.connections.keys().copied().collect::<Vec<_>>();
+        let pool = self
+            .broadcast_pools
+            .entry(msg.protocol)
+            .or_insert_with(|| {
+                Arc::new(std::sync::Mutex::new(SubstreamPool::owned_by_node(
+                    this.downgrade(),
+                    msg.protocol,
+                    1,
+                    BROADCAST_SUBSTREAM_LIFETIME,
+                )))
+            })
+            .clone();
+        let (sender, receiver) = oneshot::channel();
+        let Broadcast {
+            message,
+            timeout,
+            max_concurrent,
+            ..
+        } = msg;
 
-                    let _ = this
-                        .do_send_async(NewConnection {
-                            peer,
-                            control,
-                            incoming_substreams,
-                            worker,
-                        })
-                        .await;
+        self.tasks.add(async move {
+            let results = futures::stream::iter(peers)
+                .map(|peer| {
+                    let send = send_broadcast(pool.clone(), peer, message.clone());
 
-                    anyhow::Ok(())
+                    async move {
+                        let result = tokio::time::timeout(timeout, send)
+                            .await
+                            .unwrap_or(Err(Error::SendTimeoutReached));
+
+                        (peer, result)
+                    }
+                })
+                .buffer_unordered(max_concurrent.max(1))
+                .collect::<HashMap<_, _>>()
+                .await;
+
+            let _ = sender.send(results);
+        });
+
+        receiver
+    }
+
+    async fn handle(&mut self, msg: SubscribeStats, ctx: &mut Context<Self>) {
+        let this = ctx.address().expect("we are alive");
+
+        self.tasks.add(async move {
+            loop {
+                tokio::time::sleep(msg.interval).await;
+
+                let snapshot = match this.send(SnapshotStats).await {
+                    Ok(snapshot) => snapshot,
+                    Err(_) => return,
+                };
+
+                if msg.receiver.do_send(snapshot).is_err() {
+                    return;
                 }
-            },
-            move |error| async move {
-                let _ = this.send(FailedToConnect { peer, error }).await;
-            },
+            }
+        });
+    }
+
+    async fn handle(&mut self, msg: Connect, ctx: &mut Context<Self>) -> Result<(), Error> {
+        self.connect(msg.0, ctx)
+    }
+
+    async fn handle(
+        &mut self,
+        msg: ConnectWithIntent,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), Error> {
+        self.connect_with_intent(msg.address, Some(msg.protocols), ctx)
+    }
+
+    async fn handle(
+        &mut self,
+        msg: AwaitConnection,
+        ctx: &mut Context<Self>,
+    ) -> Result<oneshot::Receiver<Result<(), Error>>, Error> {
+        let peer = msg
+            .0
+            .clone()
+            .extract_peer_id()
+            .ok_or_else(|| Error::NoPeerIdInAddress(msg.0.clone()))?;
+        let (sender, receiver) = oneshot::channel();
+
+        if self.connections.contains_key(&peer) {
+            let _ = sender.send(Ok(()));
+            return Ok(receiver);
+        }
+
+        if !self.inflight_connections.contains(&peer) {
+            self.connect(msg.0, ctx)?;
+        }
+
+        self.connection_waiters
+            .entry(peer)
+            .or_default()
+            .push(sender);
+
+        Ok(receiver)
+    }
+
+    async fn handle(&mut self, msg: Prewarm, ctx: &mut Context<Self>) -> Result<(), Error> {
+        let peer = msg
+            .address
+            .clone()
+            .extract_peer_id()
+            .ok_or_else(|| Error::NoPeerIdInAddress(msg.address.clone()))?;
+
+        if self.connections.contains_key(&peer) {
+            self.prewarm_substreams(peer, msg.protocols, ctx);
+            return Ok(());
+        }
+
+        self.pending_prewarms
+            .entry(peer)
+            .or_default()
+            .extend(msg.protocols);
+
+        if self.inflight_connections.contains(&peer) {
+            return Ok(());
+        }
+
+        self.connect(msg.address, ctx)
+    }
+
+    async fn handle(&mut self, msg: PrewarmedSubstream) {
+        let connection = match self
+            .connections
+            .get(&msg.peer)
+            .and_then(|connections| connections.get(&msg.connection))
+        {
+            None => return,
+            Some(connection) => connection,
+        };
+        let stream = connection
+            .substreams
+            .track(msg.protocol, Endpoint::Dialer, msg.stream);
+
+        self.counters.outbound_substream_opened();
+        self.config.observer().get().substream_negotiated(
+            &msg.peer,
+            msg.connection,
+            msg.protocol,
+            Endpoint::Dialer,
         );
+        self.prewarmed.insert((msg.peer, msg.protocol), stream);
+    }
 
-        Ok(())
+    async fn handle(
+        &mut self,
+        msg: InjectConnection,
+        ctx: &mut Context<Self>,
+    ) -> Result<oneshot::Receiver<Result<PeerId, Error>>, Error> {
+        if self.is_draining() {
+            return Err(Error::Draining);
+        }
+
+        if let Some(peer) = msg.expected_peer {
+            if self.inflight_connections.contains(&peer) || self.is_at_connection_limit(&peer) {
+                return Err(Error::AlreadyConnected(peer));
+            }
+        }
+
+        if let Some(remote_address) = &msg.remote_address {
+            if !accept_from(&self.address_filter, remote_address) {
+                return Err(Error::AddressFiltered(remote_address.clone()));
+            }
+        }
+
+        let this = ctx.address().expect("we are alive");
+        let id = ConnectionId::next(&self.next_connection_id);
+        let identity = self.identity.clone();
+        let supported_inbound_protocols = self.supported_inbound_protocols.clone();
+        let connection_timeout = self.connection_timeout;
+        let counters = self.counters.clone();
+        let config = self.config.clone();
+        let (sender, receiver) = oneshot::channel();
+
+        let permit = match msg.role {
+            Endpoint::Listener => match self.inbound_gate.try_admit() {
+                Some(permit) => Some(permit),
+                None => {
+                    self.counters.inbound_connection_rejected();
+                    return Err(Error::InboundLimitReached);
+                }
+            },
+            Endpoint::Dialer => None,
+        };
+
+        self.tasks.add(async move {
+            let InjectConnection {
+                io,
+                role,
+                expected_peer,
+                remote_address,
+            } = msg;
+            let upgrade = libp2p_stream::upgrade_connection_with_config(
+                io,
+                role,
+                remote_address.clone(),
+                &identity,
+                expected_peer,
+                supported_inbound_protocols,
+                connection_timeout,
+                counters,
+                config,
+            );
+            let upgrade = upgrade.await;
+            drop(permit);
+
+            let (peer, control, incoming_substreams, worker, timeline) = match upgrade {
+                Ok(connection) => connection,
+                Err(e) => {
+                    tracing::debug!(connection = %id, "Failed to upgrade injected connection: {:#}", e);
+                    let _ = sender.send(Err(Error::ConnectFailed(DialErrorKind::from_error(&e))));
+                    return;
+                }
+            };
+
+            let _ = this
+                .do_send_async(NewConnection {
+                    id,
+                    peer,
+                    role,
+                    remote_address,
+                    control,
+                    incoming_substreams,
+                    worker,
+                    timeline,
+                    registered: Some(sender),
+                })
+                .await;
+        });
+
+        Ok(receiver)
     }
 
     async fn handle(&mut self, msg: Disconnect) {
         self.drop_connection(&msg.0);
     }
 
+    async fn handle(&mut self, msg: DisconnectConnection) {
+        self.drop_single_connection(&msg.peer, msg.connection);
+    }
+
+    async fn handle(&mut self, msg: Drain, ctx: &mut Context<Self>) {
+        let this = ctx.address().expect("we are alive");
+
+        self.draining.store(true, Ordering::Relaxed);
+
+        // Dropping the tasks stops the listeners.
+        self.listen_addresses.clear();
+        self.socket_listeners.clear();
+        self.pending_prewarms.clear();
+        self.prewarmed.clear();
+
+        self.tasks.add(async move {
+            tokio::time::sleep_until(msg.deadline.into()).await;
+
+            let _ = this.send(DrainDeadlineReached).await;
+        });
+    }
+
+    async fn handle(&mut self, _: Stop, ctx: &mut Context<Self>) {
+        self.draining.store(true, Ordering::Relaxed);
+        self.listen_addresses.clear();
+        self.socket_listeners.clear();
+
+        let peers = self.connections.keys().copied().collect::<Vec<_>>();
+        for peer in peers {
+            self.drop_connection(&peer);
+        }
+
+        ctx.stop();
+    }
+
+    async fn handle(&mut self, _: DrainDeadlineReached) {
+        let peers = self.connections.keys().copied().collect::<Vec<_>>();
+
+        for peer in peers {
+            self.drop_connection(&peer);
+        }
+    }
+
     async fn handle(&mut self, msg: ListenOn, ctx: &mut Context<Self>) {
+        if self.is_draining() {
+            return;
+        }
+
+        self.listen_on(msg.0, ctx);
+    }
+
+    async fn handle(
+        &mut self,
+        msg: ListenOnSocket,
+        ctx: &mut Context<Self>,
+    ) -> std::io::Result<Multiaddr> {
+        if self.is_draining() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                Error::Draining,
+            ));
+        }
+
+        if self.client_mode {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                Error::ClientMode,
+            ));
+        }
+
         let this = ctx.address().expect("we are alive");
-        let listen_address = msg.0.clone();
 
-        self.listen_addresses.insert(listen_address.clone()); // FIXME: This address could be a "catch-all" like "0.0.0.0" which actually results in listening on multiple interfaces.
-        self.tasks.add_fallible(
+        let local_addr = msg.0.local_addr()?;
+        let address = Multiaddr::from(local_addr.ip()).with(Protocol::Tcp(local_addr.port()));
+
+        msg.0.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(msg.0)?;
+
+        let mut tasks = Tasks::default();
+        tasks.add_fallible(
             {
-                let node = self.node.clone();
                 let this = this.clone();
 
                 async move {
-                    let mut stream = node.listen_on(msg.0)?;
-
                     loop {
-                        let (peer, control, incoming_substreams, worker) =
-                            stream.try_next().await?.context("Listener closed")?;
+                        let (stream, remote) = listener.accept().await?;
 
-                        this.do_send_async(NewConnection {
-                            peer,
-                            control,
-                            incoming_substreams,
-                            worker,
-                        })
-                        .await?;
+                        let remote_address =
+                            Multiaddr::from(remote.ip()).with(Protocol::Tcp(remote.port()));
+
+                        // Injected connections always use the current identity of the node, even after a rotation.
+                        // The outcome of the upgrade is only logged, accepting the next connection must not wait for it.
+                        let _ = this
+                            .send(InjectConnection {
+                                io: Box::new(Compat::new(stream)),
+                                role: Endpoint::Listener,
+                                expected_peer: None,
+                                remote_address: Some(remote_address),
+                            })
+                            .await?;
                     }
                 }
             },
-            |error| async move {
-                let _ = this
-                    .send(ListenerFailed {
-                        address: listen_address,
-                        error,
-                    })
-                    .await;
+            {
+                let address = address.clone();
+
+                |error| async move {
+                    let _ = this.send(ListenerFailed { address, error }).await;
+                }
             },
         );
+        self.socket_listeners.insert(address.clone(), tasks);
+
+        Ok(address)
+    }
+
+    async fn handle(&mut self, msg: RotateIdentity, ctx: &mut Context<Self>) -> PeerId {
+        let old = self.identity.public().to_peer_id();
+        let new = msg.0.public().to_peer_id();
+        self.node = (self.make_node)(msg.0.clone(), self.supported_inbound_protocols.clone());
+        self.identity = msg.0;
+
+        let listeners = self.listen_addresses.drain().collect::<Vec<_>>();
+        let mut listen_addresses = Vec::with_capacity(listeners.len());
+        let mut stopped = Vec::with_capacity(listeners.len());
+        for (address, listener) in listeners {
+            listen_addresses.push(address);
+            stopped.push(listener.stop());
+        }
+        // Rebinding fails while the listeners running under the old identity still hold their addresses.
+        futures::future::join_all(stopped).await;
+
+        for address in listen_addresses {
+            self.listen_on(address, ctx);
+        }
+
+        tracing::info!(%old, %new, "Rotated identity");
+        self.emit(Event::IdentityRotated { old, new });
+
+        new
     }
 
     async fn handle(&mut self, msg: OpenSubstream<Single>) -> Result<Substream, Error> {
@@ -404,7 +3190,275 @@ impl Node {
     }
 }
 
-impl xtra::Actor for Node {}
+#[async_trait]
+impl xtra::Actor for Node {
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        let (listen_addresses, bootstrap_peers) = self.startup.start();
+
+        for address in listen_addresses {
+            self.listen_on(address, ctx);
+        }
+        for address in bootstrap_peers {
+            if let Err(e) = self.connect(address.clone(), ctx) {
+                tracing::debug!(%address, "Failed to dial bootstrap peer: {:#}", e);
+            }
+        }
+
+        self.complete_startup_if_ready();
+    }
+}
+
+/// Sends `message` to `peer` on an idle substream of `pool` or a new one, handing it back to the pool afterwards.
+async fn send_broadcast(
+    pool: Arc<std::sync::Mutex<SubstreamPool>>,
+    peer: PeerId,
+    message: bytes::Bytes,
+) -> Result<(), Error> {
+    let idle = pool.lock().expect("not poisoned").take_idle(&peer);
+    let mut stream = match idle {
+        Some(stream) => stream,
+        None => {
+            let open = pool.lock().expect("not poisoned").open(peer);
+            open.await?
+        }
+    };
+
+    LengthDelimited::new(&mut *stream)
+        .send_frame(message)
+        .await
+        .map_err(Error::SendFailed)?;
+    pool.lock().expect("not poisoned").release(stream);
+
+    Ok(())
+}
+
+/// Ends the address with the given peer ID, replacing any `/p2p` suffix it already has.
+fn with_peer_id(mut address: Multiaddr, peer: PeerId) -> Multiaddr {
+    if let Some(Protocol::P2p(_)) = address.iter().last() {
+        address.pop();
+    }
+
+    address.with(Protocol::P2p(peer.into()))
+}
+
+/// Checks an inbound connection against the [`AddressFilter`] before its upgrade is started.
+///
+/// Rejected connections are dropped right away, i.e. without spending any effort on a handshake.
+fn accept_from(address_filter: &AddressFilter, remote_address: &Multiaddr) -> bool {
+    if address_filter.is_allowed(remote_address) {
+        return true;
+    }
+
+    tracing::debug!(
+        target: AUDIT_TARGET,
+        %remote_address,
+        "Rejected connection from filtered address"
+    );
+
+    false
+}
+
+/// Reports to the [`Node`] whenever the inbound substreams of the connection wait longer than `threshold` to be dispatched.
+async fn detect_inbound_stall(
+    control: Control,
+    threshold: Duration,
+    peer: PeerId,
+    connection: ConnectionId,
+    this: xtra::Address<Node>,
+) {
+    let mut ticker = tokio::time::interval((threshold / 2).max(Duration::from_millis(1)));
+    let mut reported = false;
+
+    loop {
+        ticker.tick().await;
+
+        match control.inbound_backlog() {
+            Some(waiting) if waiting >= threshold => {
+                if !reported {
+                    reported = true;
+                    let _ = this
+                        .send(InboundSubstreamsStalled {
+                            peer,
+                            connection,
+                            waiting,
+                        })
+                        .await;
+                }
+            }
+            _ => reported = false,
+        }
+    }
+}
+
+/// Awaits the upgrade of an inbound connection and registers the connection with the [`Node`].
+async fn register_inbound_connection(
+    this: xtra::Address<Node>,
+    id: ConnectionId,
+    remote_address: Multiaddr,
+    upgrade: libp2p_stream::Upgrade,
+    permit: HandshakePermit,
+) {
+    let upgrade = upgrade.await;
+    drop(permit);
+
+    let (peer, control, incoming_substreams, worker, timeline) = match upgrade {
+        Ok(connection) => connection,
+        Err(e) => {
+            tracing::debug!(connection = %id, %remote_address, "Failed to upgrade inbound connection: {:#}", e);
+            return;
+        }
+    };
+
+    let _ = this
+        .do_send_async(NewConnection {
+            id,
+            peer,
+            role: Endpoint::Listener,
+            remote_address: Some(remote_address),
+            control,
+            incoming_substreams,
+            worker,
+            timeline,
+            registered: None,
+        })
+        .await;
+}
+
+/// A listener started through [`ListenOn`].
+struct Listener {
+    tasks: Tasks,
+    /// Resolves once the task of the listener is gone, i.e. its address is released.
+    stopped: oneshot::Receiver<()>,
+}
+
+impl Listener {
+    async fn stop(self) {
+        drop(self.tasks);
+        let _ = self.stopped.await;
+    }
+}
+
+struct Connection {
+    control: Control,
+    tasks: Tasks,
+    extensions: Extensions,
+    session: Option<ResumptionToken>,
+    endpoint: Endpoint,
+    remote_address: Option<Multiaddr>,
+    rtt: Option<Duration>,
+    substreams: CloseTracker,
+    timeline: ConnectionTimeline,
+    agent_version: Option<String>,
+}
+
+impl Connection {
+    fn info(&self, peer: PeerId) -> ConnectionInfo {
+        ConnectionInfo {
+            peer,
+            endpoint: self.endpoint,
+            remote_address: self.remote_address.clone(),
+            rtt: self.rtt,
+            timeline: ConnectionTimeline {
+                first_substream: self.substreams.first_substream(),
+                ..self.timeline
+            },
+            memory: self.substreams.memory().usage(),
+            agent_version: self.agent_version.clone(),
+        }
+    }
+
+    fn is_relayed(&self) -> bool {
+        self.remote_address
+            .iter()
+            .flat_map(|address| address.iter())
+            .any(|protocol| protocol == Protocol::P2pCircuit)
+    }
+}
+
+struct SuspendedSession {
+    token: ResumptionToken,
+    extensions: Extensions,
+    expires_at: Instant,
+}
+
+struct AgentVersionReceived {
+    peer: PeerId,
+    connection: ConnectionId,
+    agent_version: String,
+}
+
+struct SessionEstablished {
+    peer: PeerId,
+    connection: ConnectionId,
+    token: ResumptionToken,
+    resumed: bool,
+}
+
+struct ResumeSession {
+    peer: PeerId,
+    connection: ConnectionId,
+    token: ResumptionToken,
+}
+
+struct PrewarmedSubstream {
+    peer: PeerId,
+    connection: ConnectionId,
+    protocol: &'static str,
+    stream: libp2p_stream::Substream,
+}
+
+pub(crate) struct SubstreamClosed {
+    pub(crate) peer: PeerId,
+    pub(crate) connection: ConnectionId,
+    pub(crate) protocol: &'static str,
+    pub(crate) reason: CloseReason,
+}
+
+pub(crate) struct QuotaExceeded {
+    pub(crate) peer: PeerId,
+    pub(crate) connection: ConnectionId,
+    pub(crate) protocol: &'static str,
+    pub(crate) kind: QuotaKind,
+}
+
+struct DrainDeadlineReached;
+
+/// Resolves once we are connected to the peer of the given address, dialing it if necessary.
+struct AwaitConnection(Multiaddr);
+
+struct UnsupportedProtocolProposed {
+    peer: PeerId,
+    connection: ConnectionId,
+}
+
+struct InboundSubstreamsStalled {
+    peer: PeerId,
+    connection: ConnectionId,
+    waiting: Duration,
+}
+
+struct DeprecatedProtocolNegotiated {
+    peer: PeerId,
+    connection: ConnectionId,
+    protocol: &'static str,
+}
+
+struct InboundSubstreamHandlerTimedOut {
+    peer: PeerId,
+    connection: ConnectionId,
+    protocol: &'static str,
+}
+
+pub(crate) struct HandlerPanicked {
+    pub(crate) peer: PeerId,
+    pub(crate) connection: ConnectionId,
+    pub(crate) protocol: &'static str,
+    pub(crate) message: String,
+}
+
+struct ListenerBound {
+    address: Multiaddr,
+}
 
 struct ListenerFailed {
     address: Multiaddr,
@@ -413,16 +3467,22 @@ struct ListenerFailed {
 
 struct FailedToConnect {
     peer: PeerId,
+    connection: ConnectionId,
+    address: Multiaddr,
     error: anyhow::Error,
 }
 
 struct ConnectionFailed {
     peer: PeerId,
+    connection: ConnectionId,
     error: anyhow::Error,
 }
 
 struct NewConnection {
+    id: ConnectionId,
     peer: PeerId,
+    role: Endpoint,
+    remote_address: Option<Multiaddr>,
     control: Control,
     incoming_substreams: BoxStream<
         'static,
@@ -431,9 +3491,20 @@ struct NewConnection {
             yamux::ConnectionError,
         >,
     >,
-    worker: BoxFuture<'static, ()>,
+    worker: ConnectionDriver,
+    timeline: ConnectionTimeline,
+    /// Set for connections injected through [`InjectConnection`], which are told whether they were registered.
+    registered: Option<oneshot::Sender<Result<PeerId, Error>>>,
 }
 
 impl xtra::Message for NewInboundSubstream {
     type Result = ();
 }
+
+impl xtra::Message for Event {
+    type Result = ();
+}
+
+impl xtra::Message for StatsSnapshot {
+    type Result = ();
+}
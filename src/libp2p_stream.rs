@@ -1,34 +1,73 @@
+use crate::connection_intent::{IntentError, MultiplexUpgrade};
+use crate::handshake_limit::HandshakeLimited;
+use crate::negotiation_timeouts::NegotiationTimeouts;
+use crate::shared_config::SharedConfig;
+use crate::stats::{Counted, Counters};
+use crate::timeline::ConnectionTimeline;
+use crate::verify_peer_id;
 use crate::verify_peer_id::VerifyPeerId;
+use crate::AUDIT_TARGET;
 use anyhow::Result;
 use futures::channel::mpsc;
 use futures::future::BoxFuture;
 use futures::stream::BoxStream;
-use futures::{AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt, TryStreamExt};
+use futures::{AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt, TryFutureExt, TryStreamExt};
+use libp2p_core::either::EitherError;
 use libp2p_core::identity::Keypair;
-use libp2p_core::transport::timeout::TransportTimeout;
-use libp2p_core::transport::{Boxed, ListenerEvent};
-use libp2p_core::upgrade::Version;
+use libp2p_core::transport::timeout::{TransportTimeout, TransportTimeoutError};
+use libp2p_core::transport::{Boxed, ListenerEvent, TransportError};
+use libp2p_core::upgrade::{UpgradeError, Version};
 use libp2p_core::Multiaddr;
 use libp2p_core::PeerId;
 use libp2p_core::Transport;
 use libp2p_core::{upgrade, Endpoint, Negotiated};
 use libp2p_noise as noise;
 use multistream_select::NegotiationError;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
 use std::io;
-use std::time::Duration;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use void::Void;
 use yamux::Mode;
 
 pub type Substream = Negotiated<yamux::Stream>;
 
+/// The number of inbound substreams a [`ConnectionDriver`] buffers until they are taken from the stream of the [`Connection`].
+pub const MAX_PENDING_INBOUND_SUBSTREAMS: usize = 256;
+
 pub type Connection = (
     PeerId,
     Control,
     BoxStream<'static, Result<Result<(Substream, &'static str), Error>, yamux::ConnectionError>>,
-    BoxFuture<'static, ()>,
+    ConnectionDriver,
+    ConnectionTimeline,
 );
 
+/// Drives a [`Connection`]: reads and writes frames of the multiplexer and accepts inbound substreams.
+///
+/// The driver must be spawned for the connection to make any progress, including outbound substreams.
+/// Up to [`MAX_PENDING_INBOUND_SUBSTREAMS`] inbound substreams are buffered for the stream of the [`Connection`], so a consumer that ignores them does not stall the connection.
+/// Inbound substreams beyond that, or all of them once the stream is dropped, are reset right away instead of piling up.
+/// Completes once the connection is closed.
+#[must_use = "the connection makes no progress unless the driver is polled"]
+pub struct ConnectionDriver {
+    inner: BoxFuture<'static, ()>,
+}
+
+impl Future for ConnectionDriver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.poll_unpin(cx)
+    }
+}
+
+pub type Upgrade = BoxFuture<'static, io::Result<Connection>>;
+
 // TODO: Inline this abstraction.
 #[derive(Clone)]
 pub struct Node {
@@ -41,6 +80,8 @@ impl Node {
         identity: Keypair,
         supported_inbound_protocols: Vec<&'static str>,
         connection_timeout: Duration,
+        counters: Counters,
+        config: SharedConfig,
     ) -> Self
     where
         T: Transport + Clone + Send + Sync + 'static,
@@ -50,91 +91,139 @@ impl Node {
         T::Dial: Send + 'static,
         T::ListenerUpgrade: Send + 'static,
     {
-        let identity = noise::Keypair::<noise::X25519Spec>::new()
-            .into_authentic(&identity)
-            .expect("ed25519 signing does not fail");
+        let identity = noise_keys(&identity);
+        let observer = config.observer().clone();
+        let legacy_noise = config.legacy_noise().clone();
+        let connection_intent = config.connection_intent().clone();
+        let intent_counters = counters.clone();
+        let negotiation_timeouts = config.negotiation_timeouts().clone();
+        let recording = config.recording().clone();
+        #[cfg(feature = "capture")]
+        let capture = config.capture().clone();
 
-        let authenticated = transport.and_then(|conn, endpoint| {
-            upgrade::apply(
-                conn,
-                noise::NoiseConfig::xx(identity).into_authenticated(),
-                endpoint,
-                Version::V1,
+        let transport = transport.map(move |conn, _| {
+            HandshakeLimited::new(
+                Counted::new(conn, counters.clone(), config.observer().clone()),
+                config.handshake_limit().get(),
+                counters.clone(),
             )
         });
 
-        let peer_id_verified = VerifyPeerId::new(authenticated);
+        let authenticated = transport.and_then(move |conn, endpoint| {
+            let mut timeline = ConnectionTimeline {
+                transport_connected: Some(Instant::now()),
+                ..ConnectionTimeline::default()
+            };
+            let remote_address = endpoint.get_remote_address().clone();
+            let dialer = endpoint.is_dialer();
+            let observer = observer.get();
+            let lift_handle = conn.lift_handle();
+            let recording = recording.clone();
+            #[cfg(feature = "capture")]
+            let (capture, captured_address) = (capture.clone(), remote_address.clone());
 
-        let multiplexed = peer_id_verified.and_then(|(peer_id, conn), endpoint| {
             upgrade::apply(
                 conn,
-                upgrade::from_fn::<_, _, _, _, _, Void>(
-                    b"/yamux/1.0.0",
-                    move |conn, endpoint| async move {
-                        Ok(match endpoint {
-                            Endpoint::Dialer => (
-                                peer_id,
-                                yamux::Connection::new(
-                                    conn,
-                                    yamux::Config::default(),
-                                    Mode::Client,
-                                ),
-                            ),
-                            Endpoint::Listener => (
-                                peer_id,
-                                yamux::Connection::new(
-                                    conn,
-                                    yamux::Config::default(),
-                                    Mode::Server,
-                                ),
-                            ),
-                        })
-                    },
-                ),
+                noise_config(identity, legacy_noise.get()),
                 endpoint,
                 Version::V1,
             )
+            .inspect(move |result| match result {
+                Ok((peer, _)) => {
+                    lift_handle.lift();
+                    tracing::info!(
+                        target: AUDIT_TARGET,
+                        %peer,
+                        %remote_address,
+                        dialer,
+                        "Handshake succeeded"
+                    );
+                    observer.handshake_completed(peer, &remote_address);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        target: AUDIT_TARGET,
+                        %remote_address,
+                        dialer,
+                        error = %e,
+                        "Handshake failed"
+                    );
+                    observer.handshake_failed(&remote_address, e);
+                }
+            })
+            .map_ok(move |(peer, conn)| {
+                timeline.noise_completed = Some(Instant::now());
+                let role = if dialer {
+                    Endpoint::Dialer
+                } else {
+                    Endpoint::Listener
+                };
+                let conn = recording.open(conn, peer, role);
+                #[cfg(feature = "capture")]
+                let conn = capture.open(conn, peer, &captured_address, dialer);
+
+                (peer, (conn, timeline))
+            })
         });
 
-        let protocols_negotiated = multiplexed.map(move |(peer, mut connection), _| {
-            let control = Control {
-                inner: connection.control(),
-                connection_timeout,
-            };
+        let peer_id_verified = VerifyPeerId::new(authenticated);
 
-            let (mut sender, receiver) = mpsc::unbounded();
+        let multiplexed = peer_id_verified.and_then({
+            let supported_inbound_protocols = supported_inbound_protocols.clone();
 
-            let worker = async move {
-                while let Ok(Some(stream)) = connection.next_stream().await {
-                    let _ = sender.send(stream).await; // ignore error for now.
-                }
-            }
-            .boxed();
+            move |(peer_id, (conn, mut timeline)), endpoint| {
+                timeline.peer_verified = Some(Instant::now());
+                let upgrade = MultiplexUpgrade::new(
+                    endpoint.to_endpoint(),
+                    connection_intent.get(&peer_id),
+                    supported_inbound_protocols.clone(),
+                    intent_counters.clone(),
+                );
 
-            let incoming = receiver
-                .then(move |stream| {
-                    let supported_protocols = supported_inbound_protocols.clone();
-
-                    async move {
-                        let result = tokio::time::timeout(
-                            connection_timeout,
-                            multistream_select::listener_select_proto(stream, &supported_protocols),
-                        )
-                        .await;
-
-                        match result {
-                            Ok(Ok((protocol, stream))) => Ok(Ok((stream, *protocol))),
-                            Ok(Err(e)) => Ok(Err(Error::NegotiationFailed(e))),
-                            Err(_timeout) => Ok(Err(Error::NegotiationTimeoutReached)),
-                        }
-                    }
-                })
-                .boxed();
+                upgrade::apply(conn, upgrade, endpoint, Version::V1)
+                    .map_ok(move |connection| (peer_id, connection, timeline))
+            }
+        });
 
-            (peer, control, incoming, worker)
+        let protocols_negotiated = multiplexed.map(move |(peer, connection, timeline), _| {
+            into_connection(
+                peer,
+                connection,
+                ConnectionTimeline {
+                    muxer_ready: Some(Instant::now()),
+                    ..timeline
+                },
+                supported_inbound_protocols.clone(),
+                negotiation_timeouts.get_or(connection_timeout),
+            )
         });
 
-        let timeout_applied = TransportTimeout::new(protocols_negotiated, connection_timeout);
+        let timeout_applied = TransportTimeout::new(protocols_negotiated, connection_timeout)
+            .map_err(|e| {
+                let kind = match &e {
+                    TransportTimeoutError::Timeout => DialErrorKind::Timeout,
+                    TransportTimeoutError::Timer(_) => DialErrorKind::Other,
+                    TransportTimeoutError::Other(EitherError::A(e)) => match e {
+                        verify_peer_id::Error::PeerIdMismatch { .. } => {
+                            DialErrorKind::PeerIdMismatch
+                        }
+                        verify_peer_id::Error::NoPeerId => DialErrorKind::UnsupportedAddress,
+                        verify_peer_id::Error::Inner(EitherError::A(e)) => {
+                            DialErrorKind::from_transport_error(e)
+                        }
+                        verify_peer_id::Error::Inner(EitherError::B(_)) => DialErrorKind::Handshake,
+                    },
+                    TransportTimeoutError::Other(EitherError::B(UpgradeError::Apply(
+                        IntentError::Rejected,
+                    ))) => DialErrorKind::NoCommonProtocol,
+                    TransportTimeoutError::Other(EitherError::B(_)) => DialErrorKind::Handshake,
+                };
+
+                ClassifiedError {
+                    kind,
+                    source: Box::new(e),
+                }
+            });
 
         Self {
             inner: timeout_applied.boxed(),
@@ -142,22 +231,28 @@ impl Node {
     }
 
     // TODO: After inlining, create concept of `ListenerId` to properly track listeners?
+    /// Listens on the given address, yielding the remote address and the pending upgrade of every inbound connection.
+    ///
+    /// The upgrades are not driven by the returned stream, it is up to the caller to await them.
     pub fn listen_on(
         &self,
         address: Multiaddr,
-    ) -> Result<BoxStream<'static, io::Result<Connection>>> {
+    ) -> Result<BoxStream<'static, io::Result<(Multiaddr, Upgrade)>>> {
         let stream = self
             .inner
             .clone()
             .listen_on(address)?
             .map_ok(|e| match e {
                 ListenerEvent::NewAddress(_) => Ok(None), // TODO: Should we map these as well? How do we otherwise track our listeners?
-                ListenerEvent::Upgrade { upgrade, .. } => Ok(Some(upgrade)),
+                ListenerEvent::Upgrade {
+                    upgrade,
+                    remote_addr,
+                    ..
+                } => Ok(Some((remote_addr, upgrade))),
                 ListenerEvent::AddressExpired(_) => Ok(None),
                 ListenerEvent::Error(e) => Err(e),
             })
             .try_filter_map(|o| async move { o })
-            .and_then(|upgrade| upgrade)
             .boxed();
 
         Ok(stream)
@@ -172,9 +267,326 @@ impl Node {
     }
 }
 
+/// Upgrades a raw connection into a [`Connection`].
+///
+/// This runs the same pipeline as [`Node`] does for every connection established through its transport: a noise handshake, yamux and protocol negotiation for inbound substreams.
+/// It allows using connections that were not established through a `Transport`, like serial links or custom tunnels.
+///
+/// If `expected_peer` is given, the upgrade fails if the remote authenticates with a different [`PeerId`].
+/// The [`ConnectionDriver`] of the returned connection must be spawned for it to make progress.
+/// The `connection_timeout` is applied to the upgrade as well as to protocol negotiations on the connection, capped at [`DEFAULT_NEGOTIATION_TIMEOUT`](crate::DEFAULT_NEGOTIATION_TIMEOUT) for the latter.
+pub async fn upgrade_connection<C>(
+    io: C,
+    role: Endpoint,
+    identity: &Keypair,
+    expected_peer: Option<PeerId>,
+    supported_inbound_protocols: Vec<&'static str>,
+    connection_timeout: Duration,
+) -> Result<Connection>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    upgrade_connection_with_config(
+        io,
+        role,
+        None,
+        identity,
+        expected_peer,
+        supported_inbound_protocols,
+        connection_timeout,
+        Counters::default(),
+        SharedConfig::default(),
+    )
+    .await
+}
+
+/// Like [`upgrade_connection`], but subject to the configuration a [`Node`] shares through `config`, counting traffic in `counters`.
+///
+/// This applies everything the transport pipeline of the [`Node`] applies: the handshake size limit, legacy noise, the observer, recording, capture, the connection intent and negotiation timeouts.
+/// Failures carry a [`DialErrorKind`], see [`DialErrorKind::from_error`].
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn upgrade_connection_with_config<C>(
+    io: C,
+    role: Endpoint,
+    remote_address: Option<Multiaddr>,
+    identity: &Keypair,
+    expected_peer: Option<PeerId>,
+    supported_inbound_protocols: Vec<&'static str>,
+    connection_timeout: Duration,
+    counters: Counters,
+    config: SharedConfig,
+) -> Result<Connection>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut timeline = ConnectionTimeline::default();
+    let dialer = role == Endpoint::Dialer;
+    let observer = config.observer().get();
+    let remote_address = remote_address.unwrap_or_else(Multiaddr::empty);
+    let conn = HandshakeLimited::new(
+        Counted::new(io, counters.clone(), config.observer().clone()),
+        config.handshake_limit().get(),
+        counters.clone(),
+    );
+    let lift_handle = conn.lift_handle();
+
+    let upgrade = async {
+        let noise = noise_config(noise_keys(identity), config.legacy_noise().get());
+        let result = match role {
+            Endpoint::Dialer => upgrade::apply_outbound(conn, noise, Version::V1).await,
+            Endpoint::Listener => upgrade::apply_inbound(conn, noise).await,
+        };
+        let (peer, conn) = match result {
+            Ok((peer, conn)) => {
+                lift_handle.lift();
+                tracing::info!(
+                    target: AUDIT_TARGET,
+                    %peer,
+                    %remote_address,
+                    dialer,
+                    "Handshake succeeded"
+                );
+                observer.handshake_completed(&peer, &remote_address);
+                (peer, conn)
+            }
+            Err(e) => {
+                tracing::warn!(
+                    target: AUDIT_TARGET,
+                    %remote_address,
+                    dialer,
+                    error = %e,
+                    "Handshake failed"
+                );
+                observer.handshake_failed(&remote_address, &e);
+                return Err(ClassifiedError::new(DialErrorKind::Handshake, e));
+            }
+        };
+        timeline.noise_completed = Some(Instant::now());
+
+        if let Some(expected_peer) = expected_peer {
+            if expected_peer != peer {
+                tracing::warn!(
+                    target: AUDIT_TARGET,
+                    expected = %expected_peer,
+                    actual = %peer,
+                    "Peer ID mismatch"
+                );
+                return Err(ClassifiedError::new(
+                    DialErrorKind::PeerIdMismatch,
+                    anyhow::anyhow!("Peer ID mismatch, expected {expected_peer} but got {peer}"),
+                ));
+            }
+            timeline.peer_verified = Some(Instant::now());
+        }
+
+        let conn = config.recording().open(conn, peer, role);
+        #[cfg(feature = "capture")]
+        let conn = config.capture().open(conn, peer, &remote_address, dialer);
+
+        let multiplex_upgrade = MultiplexUpgrade::new(
+            role,
+            config.connection_intent().get(&peer),
+            supported_inbound_protocols.clone(),
+            counters,
+        );
+        let connection = match role {
+            Endpoint::Dialer => upgrade::apply_outbound(conn, multiplex_upgrade, Version::V1).await,
+            Endpoint::Listener => upgrade::apply_inbound(conn, multiplex_upgrade).await,
+        }
+        .map_err(|e| {
+            let kind = match &e {
+                UpgradeError::Apply(IntentError::Rejected) => DialErrorKind::NoCommonProtocol,
+                _ => DialErrorKind::Handshake,
+            };
+
+            ClassifiedError::new(kind, e)
+        })?;
+        timeline.muxer_ready = Some(Instant::now());
+
+        Ok(into_connection(
+            peer,
+            connection,
+            timeline,
+            supported_inbound_protocols,
+            config.negotiation_timeouts().get_or(connection_timeout),
+        ))
+    };
+
+    let connection = tokio::time::timeout(connection_timeout, upgrade)
+        .await
+        .map_err(|_| {
+            ClassifiedError::new(
+                DialErrorKind::Timeout,
+                anyhow::anyhow!("Timeout while upgrading connection"),
+            )
+        })??;
+
+    Ok(connection)
+}
+
+pub(crate) const YAMUX_PROTOCOL: &[u8] = b"/yamux/1.0.0";
+
+/// Whether to speak the noise handshake format used before the libp2p noise spec was finalised, see [`Node::with_legacy_noise`](crate::Node::with_legacy_noise).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyNoise {
+    /// Only speak the spec-compliant handshake.
+    Reject,
+    /// Send the spec-compliant handshake but also accept the legacy one from peers.
+    Accept,
+    /// Send the legacy handshake and accept both.
+    ///
+    /// Only useful while a majority of peers has not been upgraded yet: peers that do not accept the legacy format fail the handshake.
+    Prefer,
+}
+
+impl Default for LegacyNoise {
+    fn default() -> Self {
+        LegacyNoise::Reject
+    }
+}
+
+impl LegacyNoise {
+    fn config(self) -> noise::LegacyConfig {
+        noise::LegacyConfig {
+            send_legacy_handshake: self == LegacyNoise::Prefer,
+            recv_legacy_handshake: self != LegacyNoise::Reject,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct LegacyNoiseSlot {
+    inner: Arc<Mutex<LegacyNoise>>,
+}
+
+impl LegacyNoiseSlot {
+    pub fn set(&self, legacy_noise: LegacyNoise) {
+        *self.inner.lock().expect("not poisoned") = legacy_noise;
+    }
+
+    pub fn get(&self) -> LegacyNoise {
+        *self.inner.lock().expect("not poisoned")
+    }
+}
+
+pub(crate) fn noise_config(
+    identity: noise::AuthenticKeypair<noise::X25519Spec>,
+    legacy_noise: LegacyNoise,
+) -> noise::NoiseAuthenticated<noise::XX, noise::X25519Spec, ()> {
+    let mut config = noise::NoiseConfig::xx(identity);
+    config.set_legacy_config(legacy_noise.config());
+
+    config.into_authenticated()
+}
+
+pub(crate) fn noise_keys(identity: &Keypair) -> noise::AuthenticKeypair<noise::X25519Spec> {
+    noise::Keypair::<noise::X25519Spec>::new()
+        .into_authentic(identity)
+        .expect("ed25519 signing does not fail")
+}
+
+pub(crate) fn multiplex<C>(conn: C, endpoint: Endpoint) -> yamux::Connection<C>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let mode = match endpoint {
+        Endpoint::Dialer => Mode::Client,
+        Endpoint::Listener => Mode::Server,
+    };
+
+    yamux::Connection::new(conn, yamux::Config::default(), mode)
+}
+
+fn into_connection<C>(
+    peer: PeerId,
+    mut connection: yamux::Connection<C>,
+    timeline: ConnectionTimeline,
+    supported_inbound_protocols: Vec<&'static str>,
+    negotiation_timeouts: NegotiationTimeouts,
+) -> Connection
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let inbound_timeout = negotiation_timeouts.inbound();
+    let inbound_backlog = Arc::new(Mutex::new(VecDeque::new()));
+    let control = Control {
+        inner: connection.control(),
+        negotiation_timeouts: Arc::new(negotiation_timeouts),
+        inbound_backlog: inbound_backlog.clone(),
+    };
+
+    let (mut sender, receiver) = mpsc::channel(MAX_PENDING_INBOUND_SUBSTREAMS);
+
+    let worker = ConnectionDriver {
+        inner: {
+            let inbound_backlog = inbound_backlog.clone();
+
+            async move {
+                loop {
+                    let stream = match connection.next_stream().await {
+                        Ok(Some(stream)) => stream,
+                        Ok(None) | Err(yamux::ConnectionError::Closed) => return,
+                        Err(e) => {
+                            // The error ends the connection, so it is forwarded even if the backlog is full.
+                            let _ = sender.send(Err(e)).await;
+                            return;
+                        }
+                    };
+
+                    // Held while sending, so the backlog entry is in place before the substream can be taken.
+                    let mut backlog = inbound_backlog.lock().expect("not poisoned");
+
+                    // Dropping a substream we could not hand over makes yamux reset it.
+                    match sender.try_send(Ok(stream)) {
+                        Ok(()) => backlog.push_back(Instant::now()),
+                        Err(e) if e.is_full() => {
+                            tracing::debug!(
+                                "Resetting inbound substream because {} inbound substreams are pending",
+                                MAX_PENDING_INBOUND_SUBSTREAMS
+                            );
+                        }
+                        Err(_) => {}
+                    }
+                }
+            }
+            .boxed()
+        },
+    };
+
+    let incoming = receiver
+        .then(move |stream| {
+            let supported_protocols = supported_inbound_protocols.clone();
+            if stream.is_ok() {
+                inbound_backlog.lock().expect("not poisoned").pop_front();
+            }
+
+            async move {
+                let stream = stream?;
+                let result = tokio::time::timeout(
+                    inbound_timeout,
+                    multistream_select::listener_select_proto(stream, &supported_protocols),
+                )
+                .await;
+
+                match result {
+                    Ok(Ok((protocol, stream))) => Ok(Ok((stream, *protocol))),
+                    Ok(Err(e)) => Ok(Err(Error::NegotiationFailed(e))),
+                    Err(_timeout) => Ok(Err(Error::NegotiationTimeoutReached)),
+                }
+            }
+        })
+        .boxed();
+
+    (peer, control, incoming, worker, timeline)
+}
+
+/// A handle for opening substreams on and closing a connection.
+#[derive(Clone)]
 pub struct Control {
     inner: yamux::Control,
-    connection_timeout: Duration,
+    negotiation_timeouts: Arc<NegotiationTimeouts>,
+    /// When each of the inbound substreams that were not taken from the stream of the connection yet arrived.
+    inbound_backlog: Arc<Mutex<VecDeque<Instant>>>,
 }
 
 impl Control {
@@ -184,8 +596,9 @@ impl Control {
     ) -> Result<Result<(&'static str, Negotiated<yamux::Stream>), Error>, yamux::ConnectionError>
     {
         let stream = self.inner.open_stream().await?;
+        let timeout = self.negotiation_timeouts.outbound(&protocols);
 
-        let result = tokio::time::timeout(self.connection_timeout, async {
+        let result = tokio::time::timeout(timeout, async {
             let (protocol, stream) =
                 multistream_select::dialer_select_proto(stream, protocols, Version::V1).await?;
 
@@ -202,11 +615,187 @@ impl Control {
         Ok(Ok((protocol, stream)))
     }
 
+    /// How long the oldest inbound substream has been waiting to be taken from the stream of inbound substreams, if any is waiting.
+    ///
+    /// Inbound substreams are negotiated one after another as the stream is polled, so this includes the time spent negotiating earlier substreams.
+    pub fn inbound_backlog(&self) -> Option<Duration> {
+        self.inbound_backlog
+            .lock()
+            .expect("not poisoned")
+            .front()
+            .map(Instant::elapsed)
+    }
+
     pub async fn close_connection(mut self) {
         let _ = self.inner.close().await;
     }
 }
 
+/// Why establishing a connection failed.
+///
+/// This taxonomy is stable and meant for alerting, i.e. to tell whether a remote peer is likely offline or whether the local node has a problem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DialErrorKind {
+    /// The address cannot be dialed with the configured transport or lacks a peer ID.
+    UnsupportedAddress,
+    /// The remote could not be reached, e.g. because the connection was refused or reset. The peer is likely offline.
+    Unreachable,
+    /// The local network is not usable, e.g. because the local address is unavailable. This indicates a problem on our side.
+    LocalNetwork,
+    /// The connection was not established within the configured timeout.
+    Timeout,
+    /// The noise handshake or the multiplexer negotiation failed.
+    Handshake,
+    /// The remote authenticated with a different peer ID than the one we dialed.
+    PeerIdMismatch,
+    /// The remote supports none of the protocols the connection was declared for, see [`Node::with_connection_intent`](crate::Node::with_connection_intent).
+    NoCommonProtocol,
+    /// Any other failure.
+    Other,
+}
+
+impl DialErrorKind {
+    /// Whether dialing the same address again might succeed.
+    ///
+    /// A mismatching peer ID, an unsupported address or a remote without any of the intended protocols will fail again, everything else may be transient.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DialErrorKind::UnsupportedAddress
+            | DialErrorKind::PeerIdMismatch
+            | DialErrorKind::NoCommonProtocol => false,
+            DialErrorKind::Unreachable
+            | DialErrorKind::LocalNetwork
+            | DialErrorKind::Timeout
+            | DialErrorKind::Handshake
+            | DialErrorKind::Other => true,
+        }
+    }
+
+    /// Classifies an error returned from [`Node::connect`].
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        if let Some(classified) = error.downcast_ref::<ClassifiedError>() {
+            return classified.kind;
+        }
+
+        let io_error = match error.downcast_ref::<TransportError<io::Error>>() {
+            Some(TransportError::MultiaddrNotSupported(_)) => {
+                return DialErrorKind::UnsupportedAddress
+            }
+            Some(TransportError::Other(e)) => e,
+            None => match error.downcast_ref::<io::Error>() {
+                Some(e) => e,
+                None => return DialErrorKind::Other,
+            },
+        };
+
+        // The boxed transport wraps our `ClassifiedError` into one or more `io::Error`s.
+        let mut io_error = io_error;
+        while let Some(inner) = io_error.get_ref() {
+            if let Some(classified) = inner.downcast_ref::<ClassifiedError>() {
+                return classified.kind;
+            }
+
+            io_error = match inner.downcast_ref::<io::Error>() {
+                Some(e) => e,
+                None => break,
+            };
+        }
+
+        DialErrorKind::from_io_error(io_error)
+    }
+
+    fn from_transport_error(error: &(dyn std::error::Error + 'static)) -> Self {
+        match error.downcast_ref::<io::Error>() {
+            Some(e) => DialErrorKind::from_io_error(e),
+            None => DialErrorKind::Unreachable,
+        }
+    }
+
+    fn from_io_error(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe => DialErrorKind::Unreachable,
+            io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::AddrInUse
+            | io::ErrorKind::PermissionDenied => DialErrorKind::LocalNetwork,
+            io::ErrorKind::TimedOut => DialErrorKind::Timeout,
+            _ => DialErrorKind::Other,
+        }
+    }
+}
+
+/// The message of each kind is as stable as the kind itself.
+impl fmt::Display for DialErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            DialErrorKind::UnsupportedAddress => "unsupported address",
+            DialErrorKind::Unreachable => "remote unreachable",
+            DialErrorKind::LocalNetwork => "local network unusable",
+            DialErrorKind::Timeout => "timeout",
+            DialErrorKind::Handshake => "handshake failed",
+            DialErrorKind::PeerIdMismatch => "peer ID mismatch",
+            DialErrorKind::NoCommonProtocol => "no common protocol",
+            DialErrorKind::Other => "other failure",
+        };
+
+        f.write_str(message)
+    }
+}
+
+/// Why an established connection failed, see [`Event::ConnectionError`](crate::Event::ConnectionError).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionErrorKind {
+    /// The remote sent frames that violate the yamux protocol.
+    ProtocolViolation,
+    /// Reading from or writing to the underlying connection failed.
+    Io,
+    /// The remote opened more substreams than allowed or we ran out of substream IDs.
+    TooManyStreams,
+    /// Any other failure.
+    Other,
+}
+
+impl ConnectionErrorKind {
+    /// Classifies an error of the yamux connection, returns `None` if the connection was closed regularly.
+    pub fn from_yamux_error(error: &yamux::ConnectionError) -> Option<Self> {
+        let kind = match error {
+            yamux::ConnectionError::Closed => return None,
+            yamux::ConnectionError::Decode(_) => ConnectionErrorKind::ProtocolViolation,
+            yamux::ConnectionError::Io(_) => ConnectionErrorKind::Io,
+            yamux::ConnectionError::TooManyStreams | yamux::ConnectionError::NoMoreStreamIds => {
+                ConnectionErrorKind::TooManyStreams
+            }
+            #[allow(unreachable_patterns)]
+            _ => ConnectionErrorKind::Other,
+        };
+
+        Some(kind)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Failed to establish connection: {kind}")]
+struct ClassifiedError {
+    kind: DialErrorKind,
+    #[source]
+    source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+impl ClassifiedError {
+    fn new(
+        kind: DialErrorKind,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Self {
+            kind,
+            source: source.into(),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Timeout in protocol negotiation")]
@@ -214,3 +803,8 @@ pub enum Error {
     #[error("Failed to negotiate protocol")]
     NegotiationFailed(#[from] NegotiationError),
 }
+
+#[cfg(test)]
+mod fuzz;
+#[cfg(all(test, feature = "interop"))]
+mod interop;
@@ -0,0 +1,167 @@
+//! Property-based tests feeding malformed input into the inbound pipeline.
+//!
+//! The input is replayed by a [`ScriptedIo`] that ignores everything written to it.
+//! Regardless of the input, the pipeline must neither panic nor hang but fail with an error.
+
+use super::*;
+use proptest::prelude::*;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+proptest! {
+    #[test]
+    fn malformed_handshake_fails_with_error(input in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let result = block_on(async {
+            upgrade_connection(
+                ScriptedIo::new(input),
+                Endpoint::Listener,
+                &Keypair::generate_ed25519(),
+                None,
+                vec!["/foo/1.0.0"],
+                Duration::from_secs(1),
+            )
+            .await
+        });
+
+        prop_assert!(result.is_err());
+    }
+
+    #[test]
+    fn malformed_yamux_frames_close_connection(input in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let substreams = block_on(drain_incoming_substreams(input));
+
+        prop_assert!(substreams.iter().all(|substream| substream.is_err()));
+    }
+
+    #[test]
+    fn malformed_protocol_negotiation_fails_with_typed_error(payload in proptest::collection::vec(any::<u8>(), 1..256)) {
+        let substreams = block_on(drain_incoming_substreams(yamux_syn_frame(1, &payload)));
+
+        prop_assert!(substreams.iter().all(|substream| matches!(
+            substream,
+            Err(Error::NegotiationFailed(_) | Error::NegotiationTimeoutReached)
+        )));
+    }
+}
+
+#[test]
+fn unknown_yamux_version_is_classified_as_protocol_violation() {
+    let mut frame = yamux_syn_frame(1, b"");
+    frame[0] = 1;
+
+    let error = block_on(async {
+        let connection = multiplex(ScriptedIo::new(frame), Endpoint::Listener);
+        let (_, _control, mut incoming, worker, _) = into_connection(
+            PeerId::random(),
+            connection,
+            ConnectionTimeline::default(),
+            vec!["/foo/1.0.0"],
+            NegotiationTimeouts::new(Duration::from_secs(1)),
+        );
+        tokio::spawn(worker);
+
+        loop {
+            match incoming.next().await {
+                Some(Err(e)) => break e,
+                Some(Ok(_)) => continue,
+                None => panic!("connection closed without error"),
+            }
+        }
+    });
+
+    assert_eq!(
+        ConnectionErrorKind::from_yamux_error(&error),
+        Some(ConnectionErrorKind::ProtocolViolation)
+    );
+}
+
+/// Runs a yamux connection over the given input and collects the results of all inbound substream negotiations.
+async fn drain_incoming_substreams(input: Vec<u8>) -> Vec<Result<&'static str, Error>> {
+    let connection = multiplex(ScriptedIo::new(input), Endpoint::Listener);
+    let (_, _control, incoming, worker, _) = into_connection(
+        PeerId::random(),
+        connection,
+        ConnectionTimeline::default(),
+        vec!["/foo/1.0.0"],
+        NegotiationTimeouts::new(Duration::from_secs(1)),
+    );
+    tokio::spawn(worker);
+
+    incoming
+        .take_while(|result| futures::future::ready(result.is_ok()))
+        .filter_map(|result| async move { result.ok() })
+        .map(|result| result.map(|(_, protocol)| protocol))
+        .collect()
+        .await
+}
+
+/// Encodes a yamux data frame that opens the given stream.
+fn yamux_syn_frame(stream_id: u32, payload: &[u8]) -> Vec<u8> {
+    const VERSION: u8 = 0;
+    const TYPE_DATA: u8 = 0;
+    const FLAG_SYN: u16 = 1;
+
+    let mut frame = vec![VERSION, TYPE_DATA];
+    frame.extend_from_slice(&FLAG_SYN.to_be_bytes());
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    frame
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(async {
+            tokio::time::timeout(TIMEOUT, future)
+                .await
+                .expect("pipeline to not hang on malformed input")
+        })
+}
+
+/// Replays a fixed input and discards everything written to it.
+struct ScriptedIo {
+    input: futures::io::Cursor<Vec<u8>>,
+}
+
+impl ScriptedIo {
+    fn new(input: Vec<u8>) -> Self {
+        Self {
+            input: futures::io::Cursor::new(input),
+        }
+    }
+}
+
+impl AsyncRead for ScriptedIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.input).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ScriptedIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,381 @@
+//! Interop tests replaying transcripts of go-libp2p peers against the connection pipeline.
+//!
+//! The transcripts are not captured from go peers but written by hand, following how go-yamux and go-multistream frame their messages:
+//! e.g. go-yamux opens substreams with a window update instead of a data frame and go-multistream proposes lazily (V1Lazy) with early data.
+//! They can only be as accurate as that reading of the go implementations. Replacing them with captures needs a go toolchain and a network to run go-libp2p peers, which these tests must not depend on.
+//!
+//! The transcripts start after the noise handshake: its ephemeral keys make a recorded handshake impossible to replay, so only the plaintext yamux and multistream-select layers are covered.
+//! There is no separate compliance mode of the node, the node's regular pipeline is what is tested.
+//!
+//! Run with `cargo test --features interop`.
+
+use super::*;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const TYPE_DATA: u8 = 0;
+const TYPE_WINDOW_UPDATE: u8 = 1;
+const TYPE_PING: u8 = 2;
+const TYPE_GO_AWAY: u8 = 3;
+
+const FLAG_NONE: u16 = 0;
+const FLAG_SYN: u16 = 1;
+const FLAG_ACK: u16 = 2;
+const FLAG_RST: u16 = 8;
+
+#[test]
+fn substream_opened_with_window_update_is_negotiated() {
+    let mut transcript = frame(TYPE_WINDOW_UPDATE, FLAG_SYN, 1, 0, b"");
+    transcript.extend(frame(
+        TYPE_DATA,
+        FLAG_NONE,
+        1,
+        0,
+        &[multistream_header(), proposal("/foo/1.0.0")].concat(),
+    ));
+
+    let result = block_on(first_inbound_substream(TranscriptIo::new(vec![transcript])));
+
+    assert!(matches!(result, Some(Ok("/foo/1.0.0"))));
+}
+
+#[test]
+fn lazy_proposal_with_early_data_is_negotiated() {
+    let payload = [
+        multistream_header(),
+        proposal("/foo/1.0.0"),
+        b"hello".to_vec(),
+    ]
+    .concat();
+    let transcript = frame(TYPE_DATA, FLAG_SYN, 1, 0, &payload);
+
+    let received = block_on(async {
+        let (_control, mut incoming) =
+            connect(TranscriptIo::new(vec![transcript]), Endpoint::Listener);
+
+        let (mut stream, protocol) = incoming
+            .next()
+            .await
+            .expect("substream")
+            .expect("no connection error")
+            .expect("negotiated");
+        assert_eq!(protocol, "/foo/1.0.0");
+
+        let mut buf = [0u8; 5];
+        futures::AsyncReadExt::read_exact(&mut stream, &mut buf)
+            .await
+            .unwrap();
+
+        buf
+    });
+
+    assert_eq!(&received, b"hello");
+}
+
+#[test]
+fn lazy_proposal_of_unsupported_protocol_is_declined() {
+    let payload = [
+        multistream_header(),
+        proposal("/bar/1.0.0"),
+        b"hello".to_vec(),
+    ]
+    .concat();
+    let transcript = frame(TYPE_DATA, FLAG_SYN, 1, 0, &payload);
+
+    let (io, written) = TranscriptIo::recording(vec![transcript]);
+    let result = block_on(first_inbound_substream(io));
+
+    // We decline and wait for another proposal that never comes.
+    assert!(matches!(
+        result,
+        Some(Err(Error::NegotiationTimeoutReached))
+    ));
+    assert!(contains(&written.lock().unwrap(), &proposal("na")));
+}
+
+#[test]
+fn reset_during_negotiation_fails_with_typed_error() {
+    let mut transcript = frame(TYPE_DATA, FLAG_SYN, 1, 0, &multistream_header());
+    transcript.extend(frame(TYPE_DATA, FLAG_RST, 1, 0, b""));
+
+    let result = block_on(first_inbound_substream(TranscriptIo::new(vec![transcript])));
+
+    assert!(matches!(result, Some(Err(Error::NegotiationFailed(_)))));
+}
+
+#[test]
+fn ping_is_answered_with_same_opaque_value() {
+    let transcript = frame(TYPE_PING, FLAG_SYN, 0, 0x2a2a, b"");
+
+    let (io, written) = TranscriptIo::recording(vec![transcript]);
+    block_on(async {
+        let (_control, _incoming) = connect(io, Endpoint::Listener);
+
+        let pong = frame(TYPE_PING, FLAG_ACK, 0, 0x2a2a, b"");
+        wait_until(|| contains(&written.lock().unwrap(), &pong)).await;
+    });
+}
+
+#[test]
+fn go_away_ends_inbound_substreams() {
+    let transcript = frame(TYPE_GO_AWAY, FLAG_NONE, 0, 0, b"");
+
+    let result = block_on(first_inbound_substream(TranscriptIo::new(vec![transcript])));
+
+    assert!(result.is_none());
+}
+
+#[test]
+fn outbound_substream_is_negotiated_with_go_listener() {
+    // go-multistream echoes the header and the accepted protocol in a single write.
+    let response = frame(
+        TYPE_DATA,
+        FLAG_ACK,
+        1,
+        0,
+        &[multistream_header(), proposal("/foo/1.0.0")].concat(),
+    );
+
+    let protocol = block_on(async {
+        let (mut control, _incoming) =
+            connect(TranscriptIo::new(vec![vec![], response]), Endpoint::Dialer);
+
+        let (protocol, _stream) = control
+            .open_substream(vec!["/foo/1.0.0"])
+            .await
+            .expect("no connection error")
+            .expect("negotiated");
+
+        protocol
+    });
+
+    assert_eq!(protocol, "/foo/1.0.0");
+}
+
+#[test]
+fn lazy_outbound_proposal_sends_early_data() {
+    let (io, written) = TranscriptIo::recording(vec![]);
+
+    block_on(async {
+        let (control, _incoming) = connect(io, Endpoint::Dialer);
+        let stream = control.inner.clone().open_stream().await.unwrap();
+
+        let (protocol, mut stream) =
+            multistream_select::dialer_select_proto(stream, vec!["/foo/1.0.0"], Version::V1Lazy)
+                .await
+                .unwrap();
+        assert_eq!(protocol, "/foo/1.0.0");
+
+        futures::AsyncWriteExt::write_all(&mut stream, b"hello")
+            .await
+            .unwrap();
+        futures::AsyncWriteExt::flush(&mut stream).await.unwrap();
+
+        wait_until(|| {
+            let written = written.lock().unwrap();
+
+            contains(&written, &proposal("/foo/1.0.0")) && contains(&written, b"hello")
+        })
+        .await;
+    });
+}
+
+#[test]
+fn inbound_substreams_not_taken_are_reported_as_backlog() {
+    let transcript = frame(
+        TYPE_DATA,
+        FLAG_SYN,
+        1,
+        0,
+        &[multistream_header(), proposal("/foo/1.0.0")].concat(),
+    );
+
+    block_on(async {
+        let (control, mut incoming) =
+            connect(TranscriptIo::new(vec![transcript]), Endpoint::Listener);
+
+        wait_until(|| control.inbound_backlog().is_some()).await;
+
+        incoming.next().await.unwrap().unwrap().unwrap();
+        assert!(control.inbound_backlog().is_none());
+    });
+}
+
+/// Runs a yamux connection over the given transcript and returns the result of the first inbound substream negotiation, if any.
+async fn first_inbound_substream(io: TranscriptIo) -> Option<Result<&'static str, Error>> {
+    let (_control, mut incoming) = connect(io, Endpoint::Listener);
+
+    match incoming.next().await? {
+        Ok(result) => Some(result.map(|(_, protocol)| protocol)),
+        Err(_) => None,
+    }
+}
+
+fn connect(
+    io: TranscriptIo,
+    endpoint: Endpoint,
+) -> (
+    Control,
+    BoxStream<'static, Result<Result<(Substream, &'static str), Error>, yamux::ConnectionError>>,
+) {
+    let (_, control, incoming, worker, _) = into_connection(
+        PeerId::random(),
+        multiplex(io, endpoint),
+        ConnectionTimeline::default(),
+        vec!["/foo/1.0.0"],
+        NegotiationTimeouts::new(Duration::from_secs(1)),
+    );
+    tokio::spawn(worker);
+
+    (control, incoming)
+}
+
+/// Encodes a yamux frame, `length` is only used for frames without payload, e.g. as window delta or ping value.
+fn frame(ty: u8, flags: u16, stream_id: u32, length: u32, payload: &[u8]) -> Vec<u8> {
+    const VERSION: u8 = 0;
+
+    let length = if payload.is_empty() {
+        length
+    } else {
+        payload.len() as u32
+    };
+
+    let mut frame = vec![VERSION, ty];
+    frame.extend_from_slice(&flags.to_be_bytes());
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    frame
+}
+
+fn multistream_header() -> Vec<u8> {
+    proposal("/multistream/1.0.0")
+}
+
+/// Encodes a multistream-select message, i.e. a varint length prefix and the newline terminated message.
+fn proposal(message: &str) -> Vec<u8> {
+    let len = message.len() + 1;
+    assert!(len < 128, "single byte varint");
+
+    let mut encoded = vec![len as u8];
+    encoded.extend_from_slice(message.as_bytes());
+    encoded.push(b'\n');
+
+    encoded
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+async fn wait_until(condition: impl Fn() -> bool) {
+    while !condition() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(async {
+            tokio::time::timeout(TIMEOUT, future)
+                .await
+                .expect("transcript to be processed in time")
+        })
+}
+
+/// Replays a transcript in chunks, releasing the next chunk only after something was written.
+///
+/// This lets a transcript answer what we send, e.g. a go listener accepting our proposal.
+/// Once the transcript is exhausted, reads stay pending instead of closing the connection.
+struct TranscriptIo {
+    chunks: std::collections::VecDeque<Vec<u8>>,
+    current: io::Cursor<Vec<u8>>,
+    released: bool,
+    written: Arc<Mutex<Vec<u8>>>,
+    waker: Option<Waker>,
+}
+
+impl TranscriptIo {
+    fn new(chunks: Vec<Vec<u8>>) -> Self {
+        Self::recording(chunks).0
+    }
+
+    /// Like [`TranscriptIo::new`], additionally returning everything written to it.
+    fn recording(chunks: Vec<Vec<u8>>) -> (Self, Arc<Mutex<Vec<u8>>>) {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let io = Self {
+            chunks: chunks.into(),
+            current: io::Cursor::new(Vec::new()),
+            released: true,
+            written: written.clone(),
+            waker: None,
+        };
+
+        (io, written)
+    }
+}
+
+impl AsyncRead for TranscriptIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let n = io::Read::read(&mut self.current, buf)?;
+            if n > 0 {
+                return Poll::Ready(Ok(n));
+            }
+
+            if !self.released {
+                self.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            match self.chunks.pop_front() {
+                Some(chunk) => {
+                    self.current = io::Cursor::new(chunk);
+                    self.released = false;
+                }
+                None => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for TranscriptIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        self.released = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
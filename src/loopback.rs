@@ -0,0 +1,161 @@
+use crate::task_set::TaskSet;
+use crate::{Error, NewInboundSubstream, Node, OpenSubstream};
+use anyhow::{bail, Context as _, Result};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p_core::PeerId;
+use std::io;
+use std::time::{Duration, Instant};
+use xtra::Address;
+use xtra_productivity::xtra_productivity;
+
+pub const PROTOCOL: &str = "/loopback/1.0.0";
+
+/// The maximum size of a single loopback message in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Echoes every message received on inbound `/loopback/1.0.0` substreams back to the sender.
+///
+/// Register the actor as the handler of [`PROTOCOL`] to let peers verify connectivity with [`probe`].
+#[derive(Default)]
+pub struct Loopback {
+    tasks: TaskSet,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl Loopback {
+    async fn handle(&mut self, msg: NewInboundSubstream) {
+        let peer = msg.peer;
+
+        self.tasks
+            .add_fallible(echo(msg.stream), move |e| async move {
+                tracing::debug!(%peer, "Loopback substream failed: {:#}", e);
+            });
+    }
+}
+
+impl xtra::Actor for Loopback {}
+
+/// Runs the listening side of the loopback protocol on the given substream.
+///
+/// Every message is a 4 byte big-endian length followed by as many bytes, and is sent back unchanged.
+/// Returns once the other side closes the substream.
+pub async fn echo<S>(mut stream: S) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let mut len = [0u8; 4];
+        match stream.read_exact(&mut len).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            bail!("Loopback message of {len} bytes exceeds maximum of {MAX_MESSAGE_SIZE} bytes");
+        }
+
+        let mut message = vec![0u8; len];
+        stream.read_exact(&mut message).await?;
+
+        write_message(&mut stream, &message).await?;
+    }
+}
+
+/// Sends the payload over a loopback substream and waits for it to be echoed back, returning the round-trip time.
+///
+/// Fails if the echoed payload differs from what was sent.
+pub async fn round_trip<S>(stream: &mut S, payload: &[u8]) -> Result<Duration>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if payload.len() > MAX_MESSAGE_SIZE {
+        bail!(
+            "Loopback message of {} bytes exceeds maximum of {MAX_MESSAGE_SIZE} bytes",
+            payload.len()
+        );
+    }
+
+    let started = Instant::now();
+    write_message(stream, payload).await?;
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len != payload.len() {
+        bail!(
+            "Echoed {len} bytes instead of the {} bytes sent",
+            payload.len()
+        );
+    }
+
+    let mut echoed = vec![0u8; len];
+    stream.read_exact(&mut echoed).await?;
+    if echoed != payload {
+        bail!("Echoed payload differs from the payload sent");
+    }
+
+    Ok(started.elapsed())
+}
+
+/// Opens a loopback substream to the peer and measures the round-trip time of a message of each of the given sizes.
+///
+/// The peer must have registered a [`Loopback`] handler.
+/// Sizes around the MTU of the path, e.g. 1200 or 1500 bytes, help to verify that messages spanning several packets arrive intact.
+pub async fn probe(
+    node: &Address<Node>,
+    peer: PeerId,
+    sizes: impl IntoIterator<Item = usize>,
+) -> Result<Vec<Duration>> {
+    let mut stream = node
+        .send(OpenSubstream::single_protocol(peer, PROTOCOL))
+        .await
+        .map_err(|_| Error::Stopped)??;
+
+    let mut round_trips = Vec::new();
+    for size in sizes {
+        let payload = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+        let rtt = round_trip(&mut stream, &payload)
+            .await
+            .with_context(|| format!("Loopback of {size} bytes failed"))?;
+
+        round_trips.push(rtt);
+    }
+
+    stream.close().await?;
+
+    Ok(round_trips)
+}
+
+async fn write_message<S>(stream: &mut S, message: &[u8]) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream
+        .write_all(&(message.len() as u32).to_be_bytes())
+        .await?;
+    stream.write_all(message).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+
+    #[tokio::test]
+    async fn oversized_message_is_not_echoed() {
+        let mut input = ((MAX_MESSAGE_SIZE + 1) as u32).to_be_bytes().to_vec();
+        input.extend(vec![0u8; MAX_MESSAGE_SIZE + 1]);
+        let mut stream = Cursor::new(input.clone());
+
+        let error = echo(&mut stream).await.unwrap_err();
+
+        assert!(error.to_string().contains("exceeds maximum"));
+        assert_eq!(stream.position(), 4, "only the length prefix is read");
+        assert_eq!(stream.into_inner(), input, "nothing is written back");
+    }
+}
@@ -0,0 +1,302 @@
+//! Accounting of the memory held by the substreams of a connection, see [`Node::with_memory_budget`](crate::Node::with_memory_budget).
+//!
+//! The muxer does not expose how much it buffers, so every open substream is accounted with an estimate, see [`SUBSTREAM_MEMORY_ESTIMATE`].
+//! Codecs report their buffers through a [`MemoryHandle`].
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Waker};
+
+/// The memory accounted for every open substream.
+///
+/// This is an estimate, not a measurement, because yamux does not report its buffers.
+/// 256 KiB is the default yamux receive window, i.e. how much the peer may send before the data is read.
+/// A substream that is read promptly holds much less.
+/// Unless yamux only grants more window once data is read, a substream that is not read from can also hold more, so the budget does not strictly bound the memory of the muxer.
+pub const SUBSTREAM_MEMORY_ESTIMATE: usize = 256 * 1024;
+
+/// The memory held by a connection as far as it is accounted for, see [`ConnectionInfo::memory`](crate::ConnectionInfo::memory).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Buffers of the muxer, [`SUBSTREAM_MEMORY_ESTIMATE`] per open substream.
+    ///
+    /// This includes inbound substreams still waiting in the mailbox of their handler, but not substreams that were reset to stay within the budget.
+    pub muxer: usize,
+    /// Buffers of codecs reporting through a [`MemoryHandle`], except for those of substreams that were reset to stay within the budget.
+    pub codecs: usize,
+    /// See [`Node::with_memory_budget`](crate::Node::with_memory_budget).
+    pub budget: Option<usize>,
+    /// The number of substreams that were reset to stay within the budget.
+    pub resets: u64,
+}
+
+impl MemoryUsage {
+    /// The memory that counts against the budget.
+    pub fn total(&self) -> usize {
+        self.muxer + self.codecs
+    }
+}
+
+/// The memory account of a single connection.
+#[derive(Clone)]
+pub(crate) struct MemoryAccount {
+    budget: Option<usize>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    next_substream: u64,
+    substreams: HashMap<u64, Entry>,
+    resets: u64,
+}
+
+#[derive(Default)]
+struct Entry {
+    codec: usize,
+    reset: bool,
+    /// The read and write half of a substream can be polled from different tasks, e.g. after [`AsyncReadExt::split`](futures::AsyncReadExt::split).
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+/// The half of a substream that is polled, see [`MemoryAccount::poll_reset`].
+#[derive(Clone, Copy)]
+pub(crate) enum Half {
+    Read,
+    Write,
+}
+
+impl Entry {
+    fn cost(&self) -> usize {
+        SUBSTREAM_MEMORY_ESTIMATE + self.codec
+    }
+}
+
+impl State {
+    fn live(&self) -> impl Iterator<Item = &Entry> {
+        self.substreams.values().filter(|entry| !entry.reset)
+    }
+
+    /// The memory of all substreams that were not reset yet.
+    fn total(&self) -> usize {
+        self.live().map(Entry::cost).sum()
+    }
+}
+
+impl MemoryAccount {
+    pub(crate) fn new(budget: Option<usize>) -> Self {
+        Self {
+            budget,
+            state: Arc::default(),
+        }
+    }
+
+    /// Whether another substream fits into the budget.
+    pub(crate) fn has_room_for_substream(&self) -> bool {
+        match self.budget {
+            Some(budget) => {
+                self.state.lock().expect("not poisoned").total() + SUBSTREAM_MEMORY_ESTIMATE
+                    <= budget
+            }
+            None => true,
+        }
+    }
+
+    /// Starts accounting for a new substream, returning its identifier within this account.
+    pub(crate) fn open(&self) -> u64 {
+        let mut state = self.state.lock().expect("not poisoned");
+        let id = state.next_substream;
+        state.next_substream += 1;
+        state.substreams.insert(id, Entry::default());
+
+        id
+    }
+
+    pub(crate) fn close(&self, substream: u64) {
+        self.state
+            .lock()
+            .expect("not poisoned")
+            .substreams
+            .remove(&substream);
+    }
+
+    /// Whether the substream was reset to stay within the budget.
+    ///
+    /// If not, the task polling the given half is woken up once it is.
+    pub(crate) fn poll_reset(&self, substream: u64, half: Half, cx: &mut Context<'_>) -> bool {
+        if self.budget.is_none() {
+            return false;
+        }
+
+        let mut state = self.state.lock().expect("not poisoned");
+        match state.substreams.get_mut(&substream) {
+            Some(entry) if entry.reset => true,
+            Some(entry) => {
+                let waker = match half {
+                    Half::Read => &mut entry.read_waker,
+                    Half::Write => &mut entry.write_waker,
+                };
+                *waker = Some(cx.waker().clone());
+
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn set_codec(&self, substream: u64, bytes: usize) {
+        let mut state = self.state.lock().expect("not poisoned");
+        if let Some(entry) = state.substreams.get_mut(&substream) {
+            entry.codec = bytes;
+        }
+
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+
+        while state.total() > budget {
+            let most_expensive = state
+                .substreams
+                .iter_mut()
+                .filter(|(_, entry)| !entry.reset)
+                .max_by_key(|(_, entry)| entry.cost())
+                .map(|(id, entry)| (*id, entry));
+
+            let (id, entry) = match most_expensive {
+                Some(most_expensive) => most_expensive,
+                None => break,
+            };
+
+            tracing::debug!(
+                substream = id,
+                cost = entry.cost(),
+                budget,
+                "Resetting substream to stay within memory budget"
+            );
+            entry.reset = true;
+            for waker in [entry.read_waker.take(), entry.write_waker.take()]
+                .into_iter()
+                .flatten()
+            {
+                waker.wake();
+            }
+            state.resets += 1;
+        }
+    }
+
+    pub(crate) fn usage(&self) -> MemoryUsage {
+        let state = self.state.lock().expect("not poisoned");
+
+        MemoryUsage {
+            muxer: state.live().count() * SUBSTREAM_MEMORY_ESTIMATE,
+            codecs: state.live().map(|entry| entry.codec).sum(),
+            budget: self.budget,
+            resets: state.resets,
+        }
+    }
+}
+
+/// Reports the buffers of a codec on top of a [`Substream`](crate::Substream) to the memory account of its connection.
+///
+/// See [`Substream::memory_handle`](crate::Substream::memory_handle) and [`LengthDelimited::with_memory_handle`](crate::LengthDelimited::with_memory_handle).
+#[derive(Clone)]
+pub struct MemoryHandle {
+    account: MemoryAccount,
+    substream: u64,
+}
+
+impl MemoryHandle {
+    pub(crate) fn new(account: MemoryAccount, substream: u64) -> Self {
+        Self { account, substream }
+    }
+
+    /// Sets the number of bytes the codec currently holds.
+    ///
+    /// If this exceeds the budget of the connection, the most expensive substreams are reset, which might be this one.
+    /// A reset substream fails its next read or write and resets the stream towards the peer, its codec should then be dropped.
+    pub fn set_buffered(&self, bytes: usize) {
+        self.account.set_codec(self.substream, bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn exceeding_budget_resets_most_expensive_substream() {
+        let account = MemoryAccount::new(Some(3 * SUBSTREAM_MEMORY_ESTIMATE));
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let small = account.open();
+        let large = account.open();
+
+        MemoryHandle::new(account.clone(), small).set_buffered(1024);
+        assert!(!account.has_room_for_substream());
+
+        MemoryHandle::new(account.clone(), large).set_buffered(SUBSTREAM_MEMORY_ESTIMATE * 2);
+
+        assert!(account.poll_reset(large, Half::Read, &mut cx));
+        assert!(!account.poll_reset(small, Half::Read, &mut cx));
+        assert_eq!(account.usage().resets, 1);
+        assert_eq!(
+            account.usage().total(),
+            SUBSTREAM_MEMORY_ESTIMATE + 1024,
+            "reset substreams no longer count"
+        );
+
+        account.close(large);
+        assert!(account.has_room_for_substream());
+    }
+
+    #[test]
+    fn without_budget_nothing_is_reset() {
+        let account = MemoryAccount::new(None);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let substream = account.open();
+
+        MemoryHandle::new(account.clone(), substream).set_buffered(usize::MAX / 2);
+
+        assert!(!account.poll_reset(substream, Half::Write, &mut cx));
+        assert!(account.has_room_for_substream());
+        assert_eq!(
+            account.usage().total(),
+            SUBSTREAM_MEMORY_ESTIMATE + usize::MAX / 2
+        );
+    }
+
+    #[test]
+    fn reset_wakes_both_halves() {
+        let account = MemoryAccount::new(Some(2 * SUBSTREAM_MEMORY_ESTIMATE));
+        let substream = account.open();
+        let reader = Arc::new(CountingWaker::default());
+        let writer = Arc::new(CountingWaker::default());
+
+        assert!(!account.poll_reset(
+            substream,
+            Half::Read,
+            &mut Context::from_waker(&Waker::from(reader.clone()))
+        ));
+        assert!(!account.poll_reset(
+            substream,
+            Half::Write,
+            &mut Context::from_waker(&Waker::from(writer.clone()))
+        ));
+        MemoryHandle::new(account.clone(), substream).set_buffered(2 * SUBSTREAM_MEMORY_ESTIMATE);
+
+        assert_eq!(reader.0.load(Ordering::Relaxed), 1);
+        assert_eq!(writer.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl std::task::Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
@@ -1,8 +1,23 @@
 use libp2p_core::multiaddr::Protocol;
 use libp2p_core::{Multiaddr, PeerId};
+use std::net::IpAddr;
 
 pub trait MultiaddrExt {
     fn extract_peer_id(self) -> Option<PeerId>;
+
+    /// The host the address points to, e.g. `1.2.3.4` of `/ip4/1.2.3.4/tcp/4001`.
+    ///
+    /// Relayed addresses have no host of their own, their first component is the host of the relay.
+    /// Neither do addresses of transports without hosts, like the memory transport.
+    fn host(&self) -> Option<Host>;
+}
+
+/// Where an address points to, regardless of port and transport, see [`MultiaddrExt::host`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Ip(IpAddr),
+    /// A domain name, the same whether it is resolved through `/dns`, `/dns4`, `/dns6` or `/dnsaddr`.
+    Dns(String),
 }
 
 impl MultiaddrExt for Multiaddr {
@@ -14,4 +29,73 @@ impl MultiaddrExt for Multiaddr {
 
         Some(peer_id)
     }
+
+    fn host(&self) -> Option<Host> {
+        if self
+            .iter()
+            .any(|protocol| matches!(protocol, Protocol::P2pCircuit))
+        {
+            return None;
+        }
+
+        match self.iter().next()? {
+            Protocol::Ip4(ip) => Some(Host::Ip(IpAddr::V4(ip))),
+            Protocol::Ip6(ip) => Some(Host::Ip(IpAddr::V6(ip))),
+            Protocol::Dns(name)
+            | Protocol::Dns4(name)
+            | Protocol::Dns6(name)
+            | Protocol::Dnsaddr(name) => Some(Host::Dns(name.to_ascii_lowercase())),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(address: &str) -> Option<Host> {
+        address.parse::<Multiaddr>().unwrap().host()
+    }
+
+    #[test]
+    fn host_ignores_port_and_transport() {
+        assert_eq!(
+            host("/ip4/1.2.3.4/tcp/4001"),
+            Some(Host::Ip("1.2.3.4".parse().unwrap()))
+        );
+        assert_eq!(host("/ip4/1.2.3.4/tcp/4001"), host("/ip4/1.2.3.4/udp/4002"));
+        assert_eq!(
+            host("/ip6/::1/tcp/4001"),
+            Some(Host::Ip("::1".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn dns_names_are_the_same_host_regardless_of_protocol() {
+        assert_eq!(
+            host("/dns4/Example.com/tcp/4001"),
+            Some(Host::Dns("example.com".to_owned()))
+        );
+        assert_eq!(
+            host("/dns4/example.com/tcp/4001"),
+            host("/dns6/example.com/tcp/4001")
+        );
+        assert_eq!(
+            host("/dns/example.com/tcp/4001"),
+            host("/dnsaddr/example.com")
+        );
+    }
+
+    #[test]
+    fn relayed_and_memory_addresses_have_no_host() {
+        let relayed = format!(
+            "/ip4/1.2.3.4/tcp/4001/p2p/{}/p2p-circuit/p2p/{}",
+            PeerId::random(),
+            PeerId::random()
+        );
+
+        assert_eq!(host(&relayed), None);
+        assert_eq!(host("/memory/1234"), None);
+    }
 }
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// The timeout for negotiating the protocol of a substream if none is configured, see [`NegotiationTimeouts::default`].
+pub const DEFAULT_NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Timeouts for negotiating the protocol of a substream, see [`Node::with_negotiation_timeouts`](crate::Node::with_negotiation_timeouts).
+///
+/// Outbound, timeouts of individual protocols take precedence over the outbound timeout and the longest timeout of the proposed protocols applies.
+/// Inbound, the protocol is only known once negotiation completes, so only the inbound timeout applies.
+/// This keeps a peer from holding an inbound substream for as long as the slowest protocol we support may take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiationTimeouts {
+    inbound: Duration,
+    outbound: Duration,
+    protocols: HashMap<&'static str, Duration>,
+}
+
+impl Default for NegotiationTimeouts {
+    /// Uses [`DEFAULT_NEGOTIATION_TIMEOUT`] in both directions and for all protocols.
+    fn default() -> Self {
+        Self::new(DEFAULT_NEGOTIATION_TIMEOUT)
+    }
+}
+
+impl NegotiationTimeouts {
+    /// Uses the given timeout in both directions and for all protocols.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            inbound: timeout,
+            outbound: timeout,
+            protocols: HashMap::default(),
+        }
+    }
+
+    pub fn with_inbound(mut self, timeout: Duration) -> Self {
+        self.inbound = timeout;
+
+        self
+    }
+
+    pub fn with_outbound(mut self, timeout: Duration) -> Self {
+        self.outbound = timeout;
+
+        self
+    }
+
+    /// Uses the given timeout for outbound substreams proposing the protocol.
+    ///
+    /// Inbound substreams of the protocol are negotiated with the inbound timeout, see [`NegotiationTimeouts`].
+    pub fn with_protocol(mut self, protocol: &'static str, timeout: Duration) -> Self {
+        self.protocols.insert(protocol, timeout);
+
+        self
+    }
+
+    /// The timeout for negotiating an inbound substream.
+    pub fn inbound(&self) -> Duration {
+        self.inbound
+    }
+
+    /// The timeout for negotiating an outbound substream proposing the given protocols.
+    pub fn outbound(&self, protocols: &[&'static str]) -> Duration {
+        protocols
+            .iter()
+            .map(|protocol| {
+                self.protocols
+                    .get(protocol)
+                    .copied()
+                    .unwrap_or(self.outbound)
+            })
+            .max()
+            .unwrap_or(self.outbound)
+    }
+}
+
+/// The [`NegotiationTimeouts`] configured on a [`Node`](crate::Node), shared with its transport.
+///
+/// Unless set, [`DEFAULT_NEGOTIATION_TIMEOUT`] applies to all negotiations, or the connection timeout of the node if that is shorter.
+#[derive(Clone, Default)]
+pub struct NegotiationTimeoutsSlot {
+    inner: Arc<RwLock<Option<NegotiationTimeouts>>>,
+}
+
+impl NegotiationTimeoutsSlot {
+    pub fn set(&self, timeouts: NegotiationTimeouts) {
+        *self.inner.write().expect("not poisoned") = Some(timeouts);
+    }
+
+    pub fn get_or(&self, connection_timeout: Duration) -> NegotiationTimeouts {
+        self.inner
+            .read()
+            .expect("not poisoned")
+            .clone()
+            .unwrap_or_else(|| {
+                NegotiationTimeouts::new(connection_timeout.min(DEFAULT_NEGOTIATION_TIMEOUT))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocol_timeouts_take_precedence_over_outbound_timeout() {
+        let timeouts = NegotiationTimeouts::new(Duration::from_secs(10))
+            .with_inbound(Duration::from_secs(5))
+            .with_protocol("/interactive/1.0.0", Duration::from_secs(2))
+            .with_protocol("/relay/1.0.0", Duration::from_secs(30));
+
+        assert_eq!(
+            timeouts.outbound(&["/interactive/1.0.0"]),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            timeouts.outbound(&["/interactive/1.0.0", "/other/1.0.0"]),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            timeouts.outbound(&["/relay/1.0.0", "/interactive/1.0.0"]),
+            Duration::from_secs(30)
+        );
+        assert_eq!(timeouts.inbound(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn unset_timeouts_are_capped_by_connection_timeout() {
+        let slot = NegotiationTimeoutsSlot::default();
+
+        assert_eq!(
+            slot.get_or(Duration::from_secs(60)),
+            NegotiationTimeouts::default()
+        );
+        assert_eq!(
+            slot.get_or(Duration::from_secs(2)).inbound(),
+            Duration::from_secs(2)
+        );
+    }
+}
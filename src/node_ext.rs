@@ -0,0 +1,51 @@
+use crate::multiaddress_ext::MultiaddrExt as _;
+use crate::{AwaitConnection, AwaitReady, Error, Node, OpenSubstream, Substream};
+use async_trait::async_trait;
+use libp2p_core::Multiaddr;
+use xtra::Address;
+
+/// Convenience methods on the [`Address`] of a [`Node`].
+#[async_trait]
+pub trait NodeExt {
+    /// Connects to the given address (or reuses an existing connection to the peer) and opens a substream for the given protocol.
+    ///
+    /// The address must contain a `/p2p` suffix.
+    async fn connect_and_open(
+        &self,
+        address: Multiaddr,
+        protocol: &'static str,
+    ) -> Result<Substream, Error>;
+
+    /// Waits until the node is ready, see [`AwaitReady`].
+    async fn wait_until_ready(&self) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl NodeExt for Address<Node> {
+    async fn connect_and_open(
+        &self,
+        address: Multiaddr,
+        protocol: &'static str,
+    ) -> Result<Substream, Error> {
+        let peer = address
+            .clone()
+            .extract_peer_id()
+            .ok_or_else(|| Error::NoPeerIdInAddress(address.clone()))?;
+
+        let connected = self
+            .send(AwaitConnection(address))
+            .await
+            .map_err(|_| Error::NoConnection(peer))??;
+        connected.await.map_err(|_| Error::NoConnection(peer))??;
+
+        self.send(OpenSubstream::single_protocol(peer, protocol))
+            .await
+            .map_err(|_| Error::NoConnection(peer))?
+    }
+
+    async fn wait_until_ready(&self) -> Result<(), Error> {
+        let ready = self.send(AwaitReady).await.map_err(|_| Error::Stopped)?;
+
+        ready.await.map_err(|_| Error::Stopped)?
+    }
+}
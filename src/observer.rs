@@ -0,0 +1,70 @@
+use crate::{CloseReason, ConnectionId};
+use libp2p_core::{Endpoint, Multiaddr, PeerId};
+use std::sync::{Arc, RwLock};
+
+/// Receives callbacks for lifecycle events of a [`Node`](crate::Node).
+///
+/// This allows bridging into arbitrary telemetry systems.
+/// All methods default to doing nothing, so implementors only need to override the ones they are interested in.
+/// The callbacks are invoked synchronously from within the [`Node`](crate::Node) and its connections and should therefore return quickly.
+pub trait NodeObserver: Send + Sync {
+    fn dial_started(&self, _connection: ConnectionId, _address: &Multiaddr) {}
+
+    fn handshake_completed(&self, _peer: &PeerId, _remote_address: &Multiaddr) {}
+
+    fn handshake_failed(&self, _remote_address: &Multiaddr, _error: &dyn std::error::Error) {}
+
+    fn connection_established(&self, _peer: &PeerId, _connection: ConnectionId) {}
+
+    fn connection_closed(&self, _peer: &PeerId, _connection: ConnectionId) {}
+
+    fn substream_negotiated(
+        &self,
+        _peer: &PeerId,
+        _connection: ConnectionId,
+        _protocol: &'static str,
+        _endpoint: Endpoint,
+    ) {
+    }
+
+    fn substream_closed(
+        &self,
+        _peer: &PeerId,
+        _connection: ConnectionId,
+        _protocol: &'static str,
+        _reason: CloseReason,
+    ) {
+    }
+
+    fn bytes_received(&self, _bytes: usize) {}
+
+    fn bytes_sent(&self, _bytes: usize) {}
+}
+
+struct NoopObserver;
+
+impl NodeObserver for NoopObserver {}
+
+/// Holds the [`NodeObserver`] of a [`Node`](crate::Node), shared with all of its connections.
+#[derive(Clone)]
+pub struct ObserverSlot {
+    inner: Arc<RwLock<Arc<dyn NodeObserver>>>,
+}
+
+impl ObserverSlot {
+    pub fn set(&self, observer: Arc<dyn NodeObserver>) {
+        *self.inner.write().expect("not poisoned") = observer;
+    }
+
+    pub fn get(&self) -> Arc<dyn NodeObserver> {
+        self.inner.read().expect("not poisoned").clone()
+    }
+}
+
+impl Default for ObserverSlot {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Arc::new(NoopObserver))),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{write_trace_header, AwaitConnection, Error, Node, OpenSubstream, Substream};
+use libp2p_core::{Multiaddr, PeerId};
+use std::time::Duration;
+use xtra::Address;
+
+/// Opens a substream with retries, timeouts and redialing.
+///
+/// This wraps [`OpenSubstream`] in the retry loop most applications need anyway.
+/// Only errors that are [retryable](Error::is_retryable) are retried, waiting `backoff`, `2 * backoff`, `4 * backoff`, etc. in between attempts.
+pub struct OpenSubstreamBuilder {
+    peer: PeerId,
+    protocols: Vec<&'static str>,
+    timeout: Option<Duration>,
+    retries: u32,
+    backoff: Duration,
+    redial: Option<Multiaddr>,
+    trace_header: Option<Option<String>>,
+}
+
+impl OpenSubstreamBuilder {
+    pub fn new(peer: PeerId, protocol: &'static str) -> Self {
+        Self {
+            peer,
+            protocols: vec![protocol],
+            timeout: None,
+            retries: 0,
+            backoff: Duration::ZERO,
+            redial: None,
+            trace_header: None,
+        }
+    }
+
+    /// Also try the given protocol if the peer does not support the previous ones, see [`OpenSubstream::multiple_protocols`].
+    pub fn fallback(mut self, protocol: &'static str) -> Self {
+        self.protocols.push(protocol);
+
+        self
+    }
+
+    /// Fail an attempt if it does not complete within the given time, including a possible redial.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    /// Retry up to `retries` times, starting with a backoff of `backoff` that doubles on every attempt.
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+
+        self
+    }
+
+    /// Dial the given address before an attempt if we are not connected to the peer.
+    ///
+    /// The address must contain a `/p2p` suffix.
+    pub fn redial(mut self, address: Multiaddr) -> Self {
+        self.redial = Some(address);
+
+        self
+    }
+
+    /// Write a trace header carrying the given trace ID, if any, once the substream is open, see [`write_trace_header`].
+    ///
+    /// Only use this with protocols whose listeners read the header with [`read_trace_header`](crate::read_trace_header).
+    pub fn trace_header(mut self, trace_id: Option<String>) -> Self {
+        self.trace_header = Some(trace_id);
+
+        self
+    }
+
+    /// Opens the substream, returning the negotiated protocol alongside it.
+    pub async fn open(self, node: &Address<Node>) -> Result<(&'static str, Substream), Error> {
+        let mut attempt = 0;
+
+        loop {
+            let result = match self.timeout {
+                None => self.try_open(node).await,
+                Some(timeout) => tokio::time::timeout(timeout, self.try_open(node))
+                    .await
+                    .unwrap_or(Err(Error::NegotiationTimeoutReached)),
+            };
+
+            match result {
+                Ok(substream) => return Ok(substream),
+                Err(e) if e.is_retryable() && attempt < self.retries => {
+                    tracing::debug!(peer = %self.peer, attempt, "Failed to open substream, retrying: {:#}", e);
+
+                    tokio::time::sleep(self.backoff.saturating_mul(2u32.saturating_pow(attempt)))
+                        .await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn try_open(&self, node: &Address<Node>) -> Result<(&'static str, Substream), Error> {
+        let peer = self.peer;
+
+        if let Some(address) = self.redial.clone() {
+            let connected = node
+                .send(AwaitConnection(address))
+                .await
+                .map_err(|_| Error::NoConnection(peer))??;
+            connected.await.map_err(|_| Error::NoConnection(peer))??;
+        }
+
+        let (protocol, mut substream) = node
+            .send(OpenSubstream::multiple_protocols(
+                peer,
+                self.protocols.clone(),
+            ))
+            .await
+            .map_err(|_| Error::NoConnection(peer))??;
+
+        if let Some(trace_id) = &self.trace_header {
+            write_trace_header(&mut substream, trace_id.as_deref())
+                .await
+                .map_err(Error::TraceHeader)?;
+        }
+
+        Ok((protocol, substream))
+    }
+}
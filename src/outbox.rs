@@ -0,0 +1,174 @@
+use crate::{ConnectionStatus, LengthDelimited, NewOutboundSubstream, Substream};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use xtra_productivity::xtra_productivity;
+
+/// Buffers the messages of a fire-and-forget protocol while the substream to the peer is being re-established.
+///
+/// Register the outbox as the handler of the protocol and as a status subscriber of a [`ConnectionSupervisor`](crate::ConnectionSupervisor).
+/// Messages sent through [`Enqueue`] are written as frames of [`LengthDelimited`] as long as the substream is up.
+/// While it is not, up to `capacity` messages are buffered and flushed in order once the supervisor hands out a new substream.
+/// Messages are fire-and-forget: a message that was written before the substream broke is not sent again.
+pub struct Outbox {
+    stream: Option<LengthDelimited<Substream>>,
+    buffer: Buffer,
+}
+
+/// What an [`Outbox`] does with a message once its buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest buffered message to make room for the new one.
+    DropOldest,
+    /// Reject the new message with [`OutboxFull`].
+    DropNewest,
+}
+
+/// Queue a message for the peer of an [`Outbox`].
+pub struct Enqueue(pub Bytes);
+
+/// The [`Outbox`] is full and its [`OverflowPolicy`] is [`OverflowPolicy::DropNewest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Outbox is full")]
+pub struct OutboxFull;
+
+impl Outbox {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            stream: None,
+            buffer: Buffer {
+                messages: VecDeque::default(),
+                capacity,
+                policy,
+            },
+        }
+    }
+
+    /// Writes buffered messages until the buffer is empty or the substream fails.
+    async fn flush(&mut self) {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        while let Some(message) = self.buffer.messages.pop_front() {
+            if let Err(e) = stream.send_frame(message.clone()).await {
+                tracing::debug!(
+                    "Failed to send buffered message, waiting for new substream: {}",
+                    e
+                );
+
+                self.buffer.messages.push_front(message);
+                self.stream = None;
+                return;
+            }
+        }
+    }
+}
+
+#[xtra_productivity(message_impl = false)]
+impl Outbox {
+    async fn handle(&mut self, msg: NewOutboundSubstream) {
+        self.stream = Some(LengthDelimited::new(msg.stream));
+        self.flush().await;
+    }
+
+    async fn handle(&mut self, msg: ConnectionStatus) {
+        if let ConnectionStatus::Disconnected(_) = msg {
+            self.stream = None;
+        }
+    }
+}
+
+#[xtra_productivity]
+impl Outbox {
+    async fn handle(&mut self, msg: Enqueue) -> Result<(), OutboxFull> {
+        if let (Some(stream), true) = (self.stream.as_mut(), self.buffer.messages.is_empty()) {
+            match stream.send_frame(msg.0.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    tracing::debug!("Failed to send message, waiting for new substream: {}", e);
+                    self.stream = None;
+                }
+            }
+        }
+
+        self.buffer.push(msg.0)?;
+        self.flush().await;
+
+        Ok(())
+    }
+}
+
+impl xtra::Actor for Outbox {}
+
+/// The messages waiting to be sent, bounded according to the [`OverflowPolicy`].
+struct Buffer {
+    messages: VecDeque<Bytes>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl Buffer {
+    fn push(&mut self, message: Bytes) -> Result<(), OutboxFull> {
+        if self.messages.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropNewest => return Err(OutboxFull),
+                OverflowPolicy::DropOldest => {
+                    tracing::debug!("Outbox is full, dropping oldest message");
+
+                    if self.messages.pop_front().is_none() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        self.messages.push_back(message);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(capacity: usize, policy: OverflowPolicy) -> Buffer {
+        Buffer {
+            messages: VecDeque::default(),
+            capacity,
+            policy,
+        }
+    }
+
+    #[test]
+    fn drop_oldest_keeps_most_recent_messages() {
+        let mut buffer = buffer(2, OverflowPolicy::DropOldest);
+
+        for message in ["a", "b", "c"] {
+            buffer.push(Bytes::from(message)).unwrap();
+        }
+
+        assert_eq!(buffer.messages, [Bytes::from("b"), Bytes::from("c")]);
+    }
+
+    #[test]
+    fn drop_newest_rejects_once_full() {
+        let mut buffer = buffer(2, OverflowPolicy::DropNewest);
+
+        buffer.push(Bytes::from("a")).unwrap();
+        buffer.push(Bytes::from("b")).unwrap();
+
+        assert_eq!(buffer.push(Bytes::from("c")), Err(OutboxFull));
+        assert_eq!(buffer.messages, [Bytes::from("a"), Bytes::from("b")]);
+    }
+
+    #[test]
+    fn zero_capacity_with_drop_oldest_discards_everything() {
+        let mut buffer = buffer(0, OverflowPolicy::DropOldest);
+
+        buffer.push(Bytes::from("a")).unwrap();
+
+        assert!(buffer.messages.is_empty());
+    }
+}
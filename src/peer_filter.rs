@@ -0,0 +1,80 @@
+use crate::ConnectionInfo;
+use libp2p_core::{Endpoint, PeerId};
+use std::time::{Duration, Instant};
+
+/// Selects connected peers, see [`GetPeers`](crate::GetPeers).
+///
+/// All conditions must hold for a peer to match. Without any condition, every connected peer matches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerFilter {
+    protocol: Option<&'static str>,
+    connected_longer_than: Option<Duration>,
+    endpoint: Option<Endpoint>,
+}
+
+/// A connected peer, see [`GetPeers`](crate::GetPeers).
+#[derive(Debug, Clone)]
+pub struct PeerDescriptor {
+    pub peer: PeerId,
+    /// When the oldest of the current connections to the peer was established.
+    pub connected_since: Option<Instant>,
+    pub connections: Vec<ConnectionInfo>,
+    /// The protocols we successfully negotiated an outbound substream for with the peer so far, i.e. the protocols the peer is known to support.
+    ///
+    /// Inbound substreams do not count: a peer that opens substreams of a protocol does not necessarily accept them.
+    pub protocols: Vec<&'static str>,
+}
+
+impl PeerFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only peers known to support the given protocol, i.e. we successfully negotiated an outbound substream of the protocol with them before.
+    pub fn with_protocol(mut self, protocol: &'static str) -> Self {
+        self.protocol = Some(protocol);
+
+        self
+    }
+
+    /// Only peers that have been connected for longer than the given duration.
+    pub fn connected_longer_than(mut self, duration: Duration) -> Self {
+        self.connected_longer_than = Some(duration);
+
+        self
+    }
+
+    /// Only peers with a connection in the given direction, i.e. [`Endpoint::Listener`] for inbound connections.
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoint = Some(endpoint);
+
+        self
+    }
+
+    pub fn matches(&self, peer: &PeerDescriptor) -> bool {
+        if let Some(protocol) = self.protocol {
+            if !peer.protocols.contains(&protocol) {
+                return false;
+            }
+        }
+
+        if let Some(duration) = self.connected_longer_than {
+            match peer.connected_since {
+                Some(since) if since.elapsed() > duration => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(endpoint) = self.endpoint {
+            if !peer
+                .connections
+                .iter()
+                .any(|connection| connection.endpoint == endpoint)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{Error, Node, OpenSubstream, Substream};
+use futures::Future;
+use libp2p_core::PeerId;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+use xtra::{Address, WeakAddress};
+
+/// A pool of idle substreams for a single protocol.
+///
+/// Opening a substream requires a round-trip for protocol negotiation.
+/// For request-heavy protocols that support multiple requests per substream, this can be avoided by reusing substreams that are no longer in use.
+///
+/// Substreams are taken out of the pool with [`SubstreamPool::get`] and handed back with [`SubstreamPool::release`].
+/// At most `max_idle` substreams are kept per peer and substreams older than `max_lifetime` are never handed out again.
+pub struct SubstreamPool {
+    node: WeakAddress<Node>,
+    /// Keeps the node alive for as long as the pool, unless the node owns the pool itself.
+    _node: Option<Address<Node>>,
+    protocol: &'static str,
+    max_idle: usize,
+    max_lifetime: Duration,
+    idle: HashMap<PeerId, Vec<PooledSubstream>>,
+}
+
+/// A substream that was handed out by a [`SubstreamPool`].
+pub struct PooledSubstream {
+    peer: PeerId,
+    stream: Substream,
+    opened_at: Instant,
+}
+
+impl SubstreamPool {
+    pub fn new(
+        node: Address<Node>,
+        protocol: &'static str,
+        max_idle: usize,
+        max_lifetime: Duration,
+    ) -> Self {
+        Self {
+            node: node.downgrade(),
+            _node: Some(node),
+            protocol,
+            max_idle,
+            max_lifetime,
+            idle: HashMap::default(),
+        }
+    }
+
+    /// Constructs a pool that is owned by the node itself and therefore must not keep it alive.
+    pub(crate) fn owned_by_node(
+        node: WeakAddress<Node>,
+        protocol: &'static str,
+        max_idle: usize,
+        max_lifetime: Duration,
+    ) -> Self {
+        Self {
+            node,
+            _node: None,
+            protocol,
+            max_idle,
+            max_lifetime,
+            idle: HashMap::default(),
+        }
+    }
+
+    /// Returns an idle substream to the given peer or opens a new one if there is none.
+    pub async fn get(&mut self, peer: PeerId) -> Result<PooledSubstream, Error> {
+        if let Some(stream) = self.take_idle(&peer) {
+            return Ok(stream);
+        }
+
+        self.open(peer).await
+    }
+
+    /// Returns an idle substream to the given peer, if there is one.
+    pub(crate) fn take_idle(&mut self, peer: &PeerId) -> Option<PooledSubstream> {
+        let idle = self.idle.get_mut(peer)?;
+
+        while let Some(stream) = idle.pop() {
+            if stream.opened_at.elapsed() < self.max_lifetime {
+                return Some(stream);
+            }
+        }
+
+        None
+    }
+
+    /// Opens a new substream to the given peer.
+    ///
+    /// The returned future does not borrow the pool, so a pool shared between tasks can open several substreams at once.
+    pub(crate) fn open(
+        &self,
+        peer: PeerId,
+    ) -> impl Future<Output = Result<PooledSubstream, Error>> + Send + 'static {
+        let node = self.node.clone();
+        let protocol = self.protocol;
+
+        async move {
+            let stream = node
+                .send(OpenSubstream::single_protocol(peer, protocol))
+                .await
+                .map_err(|_| Error::NoConnection(peer))??;
+
+            Ok(PooledSubstream {
+                peer,
+                stream,
+                opened_at: Instant::now(),
+            })
+        }
+    }
+
+    /// Hands a substream back to the pool.
+    ///
+    /// The substream must be in a state where the next request can be sent on it, otherwise it should be dropped instead.
+    pub fn release(&mut self, stream: PooledSubstream) {
+        if stream.opened_at.elapsed() >= self.max_lifetime {
+            return;
+        }
+
+        let idle = self.idle.entry(stream.peer).or_default();
+
+        if idle.len() < self.max_idle {
+            idle.push(stream);
+        }
+    }
+
+    /// Drops all idle substreams to the given peer.
+    pub fn clear(&mut self, peer: &PeerId) {
+        self.idle.remove(peer);
+    }
+}
+
+impl PooledSubstream {
+    pub fn peer(&self) -> PeerId {
+        self.peer
+    }
+
+    pub fn into_inner(self) -> Substream {
+        self.stream
+    }
+}
+
+impl Deref for PooledSubstream {
+    type Target = Substream;
+
+    fn deref(&self) -> &Self::Target {
+        &self.stream
+    }
+}
+
+impl DerefMut for PooledSubstream {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.stream
+    }
+}
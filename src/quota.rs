@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Hard limits on how much a single peer may use a protocol, see [`Node::with_quota`](crate::Node::with_quota).
+///
+/// Limits apply per peer over fixed windows, e.g. 100 substreams per hour, starting with the first use of the protocol by the peer.
+/// Both limits only cover what the peer sends: the substream limit covers inbound substreams, the byte limit covers the bytes we read from substreams of either direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    substreams: Option<Limit>,
+    bytes: Option<Limit>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Limit {
+    max: u64,
+    period: Duration,
+}
+
+/// Which limit of a [`Quota`] a peer exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum QuotaKind {
+    /// Inbound substreams beyond the limit are rejected with [`RejectionReason::Quota`](crate::RejectionReason::Quota).
+    Substreams,
+    /// Substreams of the protocol are reset towards the peer and close with [`CloseReason::Quota`](crate::CloseReason::Quota) until the window ends.
+    Bytes,
+}
+
+impl Quota {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow at most `max` inbound substreams per `period`.
+    pub fn with_substreams(mut self, max: u64, period: Duration) -> Self {
+        self.substreams = Some(Limit { max, period });
+
+        self
+    }
+
+    /// Allow the peer to send at most `max` bytes per `period`.
+    pub fn with_bytes(mut self, max: u64, period: Duration) -> Self {
+        self.bytes = Some(Limit { max, period });
+
+        self
+    }
+}
+
+/// The use of a [`Quota`] by a single peer.
+pub(crate) struct QuotaState {
+    quota: Quota,
+    substreams: Window,
+    bytes: Window,
+}
+
+struct Window {
+    start: Instant,
+    used: u64,
+}
+
+impl Window {
+    fn new(now: Instant) -> Self {
+        Self {
+            start: now,
+            used: 0,
+        }
+    }
+
+    /// Returns the usage within the current window, starting a new window once `period` is over.
+    fn current(&mut self, period: Duration, now: Instant) -> &mut u64 {
+        if now.duration_since(self.start) >= period {
+            *self = Window::new(now);
+        }
+
+        &mut self.used
+    }
+}
+
+impl QuotaState {
+    pub(crate) fn new(quota: Quota, now: Instant) -> Self {
+        Self {
+            quota,
+            substreams: Window::new(now),
+            bytes: Window::new(now),
+        }
+    }
+
+    /// Counts an inbound substream, returns `false` if it exceeds the limit.
+    pub(crate) fn admit_substream(&mut self, now: Instant) -> bool {
+        let limit = match self.quota.substreams {
+            None => return true,
+            Some(limit) => limit,
+        };
+
+        let used = self.substreams.current(limit.period, now);
+        if *used >= limit.max {
+            return false;
+        }
+        *used += 1;
+
+        true
+    }
+
+    pub(crate) fn transferred(&mut self, bytes: usize, now: Instant) {
+        if let Some(limit) = self.quota.bytes {
+            *self.bytes.current(limit.period, now) += bytes as u64;
+        }
+    }
+
+    pub(crate) fn bytes_exhausted(&mut self, now: Instant) -> bool {
+        match self.quota.bytes {
+            None => false,
+            Some(limit) => *self.bytes.current(limit.period, now) >= limit.max,
+        }
+    }
+}
+
+/// The [`Quota`]s configured on a [`Node`](crate::Node) per protocol, shared with its substreams.
+#[derive(Clone, Default)]
+pub struct QuotaSlot {
+    inner: Arc<RwLock<HashMap<&'static str, Quota>>>,
+}
+
+impl QuotaSlot {
+    pub fn set(&self, protocol: &'static str, quota: Quota) {
+        self.inner
+            .write()
+            .expect("not poisoned")
+            .insert(protocol, quota);
+    }
+
+    pub fn get(&self, protocol: &'static str) -> Option<Quota> {
+        self.inner
+            .read()
+            .expect("not poisoned")
+            .get(protocol)
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_reset_once_the_window_is_over() {
+        let start = Instant::now();
+        let mut state = QuotaState::new(
+            Quota::new()
+                .with_substreams(2, Duration::from_secs(60))
+                .with_bytes(100, Duration::from_secs(60)),
+            start,
+        );
+
+        assert!(state.admit_substream(start));
+        assert!(state.admit_substream(start));
+        assert!(!state.admit_substream(start + Duration::from_secs(30)));
+        assert!(state.admit_substream(start + Duration::from_secs(60)));
+
+        state.transferred(60, start);
+        assert!(!state.bytes_exhausted(start));
+        state.transferred(60, start);
+        assert!(state.bytes_exhausted(start + Duration::from_secs(59)));
+        assert!(!state.bytes_exhausted(start + Duration::from_secs(60)));
+    }
+}
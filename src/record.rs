@@ -0,0 +1,507 @@
+use crate::libp2p_stream::{noise_config, noise_keys, LegacyNoise};
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{stream, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, StreamExt};
+use libp2p_core::identity::Keypair;
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::transport::memory::Channel;
+use libp2p_core::transport::{ListenerEvent, MemoryTransport, TransportError};
+use libp2p_core::upgrade::{self, Version};
+use libp2p_core::{Endpoint, Multiaddr, PeerId, Transport};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const INBOUND: u8 = 0;
+const OUTBOUND: u8 = 1;
+
+/// How many frames may be waiting for the writer before recording a connection is given up.
+const RECORDING_BUFFER: usize = 1024;
+
+/// Records the plaintext traffic of every connection of a [`Node`](crate::Node) to disk, see [`Node::with_recording`](crate::Node::with_recording).
+///
+/// Each connection is written to its own file within the configured directory, named after the time it was established, the role of the node and the remote peer.
+/// A recording is a sequence of frames, each consisting of:
+///
+/// 1. The direction (`0` for inbound, `1` for outbound) as a single byte.
+/// 2. The time since the connection was established in microseconds as a big-endian `u64`.
+/// 3. The length of the payload as a big-endian `u32`.
+/// 4. The payload.
+///
+/// Inbound bytes are recorded after decryption and outbound bytes before encryption, i.e. a recording contains multistream-select and yamux framing but no noise.
+/// Frames are handed to a dedicated writer thread through a bounded buffer, the connections never wait for the disk.
+/// If the writer falls behind or writing fails, recording of the affected connection stops with a warning; the connection itself is never failed.
+#[derive(Clone)]
+pub struct Record {
+    directory: PathBuf,
+    sender: mpsc::Sender<Command>,
+    next_connection: Arc<AtomicU64>,
+}
+
+enum Command {
+    Open { connection: u64, path: PathBuf },
+    Frame { connection: u64, frame: Vec<u8> },
+    Close { connection: u64 },
+}
+
+impl Record {
+    /// Records into the given directory, which must exist.
+    ///
+    /// Recordings contain the plaintext of all traffic. On Unix, their files are created readable and writable by the owner only; the directory should be protected as well.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let (sender, receiver) = mpsc::channel(RECORDING_BUFFER);
+
+        std::thread::Builder::new()
+            .name("recording".to_owned())
+            .spawn(move || write_recordings(receiver))
+            .expect("failed to spawn recording thread");
+
+        Self {
+            directory: directory.into(),
+            sender,
+            next_connection: Arc::default(),
+        }
+    }
+
+    fn open<C>(&self, inner: C, peer: PeerId, endpoint: Endpoint) -> Recorded<C> {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let role = match endpoint {
+            Endpoint::Dialer => "dialer",
+            Endpoint::Listener => "listener",
+        };
+        let connection = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .directory
+            .join(format!("{since_epoch}-{role}-{peer}-{connection}.rec"));
+
+        let mut sender = self.sender.clone();
+        let sender = match sender.try_send(Command::Open { connection, path }) {
+            Ok(()) => Some(sender),
+            Err(_) => {
+                tracing::warn!("Recording fell behind, not recording connection to {peer}");
+                None
+            }
+        };
+
+        Recorded {
+            inner,
+            sender,
+            connection,
+            established: Instant::now(),
+        }
+    }
+}
+
+/// Writes the frames of all connections of a [`Record`], flushing whenever there is nothing left to write.
+fn write_recordings(mut commands: mpsc::Receiver<Command>) {
+    let mut files = HashMap::<u64, BufWriter<File>>::new();
+
+    loop {
+        let command = match commands.try_next() {
+            Ok(Some(command)) => command,
+            Ok(None) => break,
+            Err(_empty) => {
+                for file in files.values_mut() {
+                    if let Err(e) = file.flush() {
+                        tracing::warn!("Failed to flush recording: {}", e);
+                    }
+                }
+
+                match block_on(commands.next()) {
+                    Some(command) => command,
+                    None => break,
+                }
+            }
+        };
+
+        match command {
+            Command::Open { connection, path } => match create_recording(&path) {
+                Ok(file) => {
+                    files.insert(connection, BufWriter::new(file));
+                }
+                Err(e) => tracing::warn!("Failed to create recording {}: {}", path.display(), e),
+            },
+            Command::Frame { connection, frame } => {
+                if let Some(file) = files.get_mut(&connection) {
+                    if let Err(e) = file.write_all(&frame) {
+                        tracing::warn!("Failed to write recording, stopping: {}", e);
+                        files.remove(&connection);
+                    }
+                }
+            }
+            Command::Close { connection } => {
+                if let Some(mut file) = files.remove(&connection) {
+                    if let Err(e) = file.flush() {
+                        tracing::warn!("Failed to flush recording: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Creates the file of a new recording, accessible by the owner only as it holds plaintext traffic.
+///
+/// Fails instead of writing to a file that already exists, which may have been created with looser permissions.
+fn create_recording(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+}
+
+/// The [`Record`] configured on a [`Node`](crate::Node), shared with all of its connections.
+#[derive(Clone, Default)]
+pub struct RecordSlot {
+    inner: Arc<RwLock<Option<Record>>>,
+}
+
+impl RecordSlot {
+    pub fn set(&self, record: Record) {
+        *self.inner.write().expect("not poisoned") = Some(record);
+    }
+
+    /// Starts recording the given authenticated connection if a recording is configured.
+    pub fn open<C>(&self, conn: C, peer: PeerId, endpoint: Endpoint) -> Recorded<C> {
+        match self.inner.read().expect("not poisoned").as_ref() {
+            Some(record) => record.open(conn, peer, endpoint),
+            None => Recorded {
+                inner: conn,
+                sender: None,
+                connection: 0,
+                established: Instant::now(),
+            },
+        }
+    }
+}
+
+/// A connection whose plaintext traffic is being recorded by [`Record`].
+pub struct Recorded<C> {
+    inner: C,
+    sender: Option<mpsc::Sender<Command>>,
+    connection: u64,
+    established: Instant,
+}
+
+impl<C> Recorded<C> {
+    fn record(&mut self, direction: u8, bytes: &[u8]) {
+        let sender = match self.sender.as_mut() {
+            None => return,
+            Some(sender) => sender,
+        };
+        if bytes.is_empty() {
+            return;
+        }
+
+        let elapsed = self.established.elapsed().as_micros() as u64;
+
+        let mut frame = Vec::with_capacity(13 + bytes.len());
+        frame.push(direction);
+        frame.extend_from_slice(&elapsed.to_be_bytes());
+        frame.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        frame.extend_from_slice(bytes);
+
+        let command = Command::Frame {
+            connection: self.connection,
+            frame,
+        };
+        if sender.try_send(command).is_err() {
+            tracing::warn!("Recording fell behind, stopping");
+            self.close();
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(mut sender) = self.sender.take() {
+            let _ = sender.try_send(Command::Close {
+                connection: self.connection,
+            });
+        }
+    }
+}
+
+impl<C> Drop for Recorded<C> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<C> AsyncRead for Recorded<C>
+where
+    C: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.record(INBOUND, &buf[..n]);
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<C> AsyncWrite for Recorded<C>
+where
+    C: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.record(OUTBOUND, &buf[..n]);
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// A transport that replays the inbound side of a recording made by [`Record`] to a [`Node`](crate::Node).
+///
+/// Pass it to [`Node::new`](crate::Node::new) in place of the production transport to reproduce what a node went through with a peer:
+///
+/// - For a recording made as listener, send [`ListenOn`](crate::ListenOn) with any address. The listener yields a single inbound connection from the replayed peer.
+/// - For a recording made as dialer, send [`Connect`](crate::Connect) to any address ending in `/p2p/` and [`Replay::peer_id`].
+///
+/// The replayed peer runs a fresh noise handshake with the node and then sends the recorded inbound bytes in order, discarding everything the node sends.
+/// Timestamps are not honoured, the bytes are sent as fast as the node reads them.
+/// The connection stays open until the node closes it.
+#[derive(Clone)]
+pub struct Replay {
+    inbound: Arc<Vec<u8>>,
+    identity: Keypair,
+}
+
+impl Replay {
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = io::BufReader::new(File::open(path)?);
+        let mut inbound = Vec::new();
+
+        loop {
+            let mut header = [0u8; 13];
+            match file.read_exact(&mut header[..1]) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            file.read_exact(&mut header[1..])?;
+
+            let direction = header[0];
+            let len = u32::from_be_bytes([header[9], header[10], header[11], header[12]]);
+
+            let mut payload = vec![0u8; len as usize];
+            file.read_exact(&mut payload)?;
+
+            if direction == INBOUND {
+                inbound.extend_from_slice(&payload);
+            }
+        }
+
+        Ok(Self {
+            inbound: Arc::new(inbound),
+            identity: Keypair::generate_ed25519(),
+        })
+    }
+
+    /// The [`PeerId`] of the replayed peer, generated for every [`Replay`].
+    pub fn peer_id(&self) -> PeerId {
+        self.identity.public().to_peer_id()
+    }
+
+    /// Connects to the node and spawns the replayed peer, which plays the given role.
+    async fn replay(self, remote_role: Endpoint) -> io::Result<Channel<Vec<u8>>> {
+        let (local, remote) = pipe().await?;
+
+        tokio::spawn(async move {
+            if let Err(e) = play(remote, remote_role, self.identity, self.inbound).await {
+                tracing::debug!("Replay ended: {:#}", e);
+            }
+        });
+
+        Ok(local)
+    }
+}
+
+impl Transport for Replay {
+    type Output = Channel<Vec<u8>>;
+    type Error = io::Error;
+    type Listener =
+        BoxStream<'static, Result<ListenerEvent<Self::ListenerUpgrade, Self::Error>, Self::Error>>;
+    type ListenerUpgrade = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        let events = [
+            ListenerEvent::NewAddress(addr.clone()),
+            ListenerEvent::Upgrade {
+                upgrade: self.replay(Endpoint::Dialer).boxed(),
+                local_addr: addr.clone(),
+                remote_addr: addr,
+            },
+        ];
+
+        Ok(stream::iter(events)
+            .map(Ok)
+            .chain(stream::pending())
+            .boxed())
+    }
+
+    fn dial(self, _: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        Ok(self.replay(Endpoint::Listener).boxed())
+    }
+
+    fn dial_as_listener(self, _: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        Ok(self.replay(Endpoint::Dialer).boxed())
+    }
+
+    fn address_translation(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+/// Creates a pair of connected in-memory connections.
+async fn pipe() -> io::Result<(Channel<Vec<u8>>, Channel<Vec<u8>>)> {
+    let other = |e| io::Error::new(io::ErrorKind::Other, e);
+
+    let mut listener = MemoryTransport
+        .listen_on(Protocol::Memory(0).into())
+        .map_err(|e| other(e.to_string()))?;
+    let address = match listener.next().await {
+        Some(Ok(ListenerEvent::NewAddress(address))) => address,
+        _ => {
+            return Err(other(
+                "Memory listener did not report its address".to_owned(),
+            ))
+        }
+    };
+
+    let dial = MemoryTransport
+        .dial(address)
+        .map_err(|e| other(e.to_string()))?;
+    let accept = async {
+        match listener.next().await {
+            Some(Ok(ListenerEvent::Upgrade { upgrade, .. })) => {
+                upgrade.await.map_err(|e| other(e.to_string()))
+            }
+            _ => Err(other("Memory listener closed".to_owned())),
+        }
+    };
+
+    futures::try_join!(
+        dial.map(|result| result.map_err(|e| other(e.to_string()))),
+        accept
+    )
+}
+
+/// Plays the replayed peer: authenticates, sends the recorded bytes and discards everything it receives.
+async fn play(
+    io: Channel<Vec<u8>>,
+    role: Endpoint,
+    identity: Keypair,
+    inbound: Arc<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let noise = noise_config(noise_keys(&identity), LegacyNoise::default());
+    let (_, conn) = match role {
+        Endpoint::Dialer => upgrade::apply_outbound(io, noise, Version::V1).await?,
+        Endpoint::Listener => upgrade::apply_inbound(io, noise).await?,
+    };
+    let (reader, mut writer) = conn.split();
+
+    let send = async {
+        writer.write_all(&inbound).await?;
+        writer.flush().await?;
+
+        io::Result::Ok(writer)
+    };
+    let discard = futures::io::copy(reader, &mut futures::io::sink());
+
+    let (writer, _) = futures::try_join!(send, discard)?;
+    drop(writer);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn records_inbound_bytes_for_replay() {
+        let directory = std::env::temp_dir().join(format!("record-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let record = Record::new(&directory);
+
+        let mut recorded = record.open(
+            Cursor::new(b"inbound".to_vec()),
+            PeerId::random(),
+            Endpoint::Listener,
+        );
+        let mut buf = [0u8; 7];
+        recorded.read_exact(&mut buf).await.unwrap();
+        recorded.write_all(b"outbound").await.unwrap();
+        drop(recorded);
+
+        // The recording is written in the background.
+        let replay = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(recording) = std::fs::read_dir(&directory).unwrap().next() {
+                    let replay = Replay::from_file(recording.unwrap().path()).unwrap();
+                    if !replay.inbound.is_empty() {
+                        return replay;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(replay.inbound.as_slice(), b"inbound");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let recording = std::fs::read_dir(&directory).unwrap().next().unwrap();
+            let mode = recording.unwrap().metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
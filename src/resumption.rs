@@ -0,0 +1,86 @@
+use anyhow::{Context as _, Result};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Future};
+use std::fmt;
+
+pub const PROTOCOL: &str = "/libp2p-xtra/resume/1.0.0";
+
+/// Identifies a session between two nodes across reconnects.
+///
+/// See [`Node::with_session_resumption`](crate::Node::with_session_resumption).
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResumptionToken([u8; 32]);
+
+impl ResumptionToken {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl fmt::Debug for ResumptionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only print a prefix, the token is a secret shared by the two nodes.
+        write!(f, "ResumptionToken({:02x}{:02x}..)", self.0[0], self.0[1])
+    }
+}
+
+/// Proposes the given token to the other node, returning whether it resumed the session.
+pub(crate) async fn propose<S>(mut stream: S, token: ResumptionToken) -> Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(&token.0).await?;
+    stream.flush().await?;
+
+    let mut resumed = [0u8; 1];
+    stream
+        .read_exact(&mut resumed)
+        .await
+        .context("Failed to read answer to proposed token")?;
+
+    Ok(resumed[0] == 1)
+}
+
+/// Reads the token proposed by the other node and answers with the decision of `resume`.
+pub(crate) async fn answer<S, F, Fut>(mut stream: S, resume: F) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnOnce(ResumptionToken) -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    let mut token = [0u8; 32];
+    stream
+        .read_exact(&mut token)
+        .await
+        .context("Failed to read proposed token")?;
+
+    let resumed = resume(ResumptionToken(token)).await?;
+
+    stream.write_all(&[resumed as u8]).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+
+    #[tokio::test]
+    async fn proposed_token_is_answered() {
+        let token = ResumptionToken::random();
+
+        let mut proposal = Cursor::new(Vec::new());
+        proposal.write_all(&token.0).await.unwrap();
+        proposal.set_position(0);
+
+        answer(&mut proposal, |proposed| async move {
+            assert_eq!(proposed, token);
+            Ok(true)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(&proposal.get_ref()[32..], &[1]);
+    }
+}
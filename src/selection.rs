@@ -0,0 +1,104 @@
+use crate::ConnectionId;
+use std::cmp::Reverse;
+use std::time::Duration;
+
+/// How the [`Node`](crate::Node) picks one of several connections to the same peer, e.g. for [`OpenSubstream`](crate::OpenSubstream).
+///
+/// Connections for which no round-trip time has been measured yet are considered slowest.
+/// Ties are broken in favour of the most recently established connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionPolicy {
+    /// Prefer direct over relayed connections and then the lowest round-trip time.
+    PreferDirect,
+    /// Prefer the lowest round-trip time, regardless of whether a connection is relayed.
+    LowestRtt,
+}
+
+impl Default for SelectionPolicy {
+    fn default() -> Self {
+        SelectionPolicy::PreferDirect
+    }
+}
+
+pub(crate) struct Candidate {
+    pub id: ConnectionId,
+    pub relayed: bool,
+    pub rtt: Option<Duration>,
+}
+
+impl SelectionPolicy {
+    pub(crate) fn select(
+        self,
+        candidates: impl IntoIterator<Item = Candidate>,
+    ) -> Option<ConnectionId> {
+        let rtt = |candidate: &Candidate| candidate.rtt.unwrap_or(Duration::MAX);
+
+        let selected = match self {
+            SelectionPolicy::PreferDirect => candidates
+                .into_iter()
+                .min_by_key(|c| (c.relayed, rtt(c), Reverse(c.id))),
+            SelectionPolicy::LowestRtt => candidates
+                .into_iter()
+                .min_by_key(|c| (rtt(c), Reverse(c.id))),
+        };
+
+        selected.map(|candidate| candidate.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<Candidate> {
+        vec![
+            Candidate {
+                id: ConnectionId(0),
+                relayed: true,
+                rtt: Some(Duration::from_millis(10)),
+            },
+            Candidate {
+                id: ConnectionId(1),
+                relayed: false,
+                rtt: Some(Duration::from_millis(50)),
+            },
+            Candidate {
+                id: ConnectionId(2),
+                relayed: false,
+                rtt: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn prefer_direct_picks_fastest_direct_connection() {
+        let selected = SelectionPolicy::PreferDirect.select(candidates());
+
+        assert_eq!(selected, Some(ConnectionId(1)));
+    }
+
+    #[test]
+    fn lowest_rtt_ignores_relaying() {
+        let selected = SelectionPolicy::LowestRtt.select(candidates());
+
+        assert_eq!(selected, Some(ConnectionId(0)));
+    }
+
+    #[test]
+    fn ties_are_broken_by_most_recent_connection() {
+        let selected = SelectionPolicy::LowestRtt.select(vec![
+            Candidate {
+                id: ConnectionId(3),
+                relayed: false,
+                rtt: None,
+            },
+            Candidate {
+                id: ConnectionId(4),
+                relayed: false,
+                rtt: None,
+            },
+        ]);
+
+        assert_eq!(selected, Some(ConnectionId(4)));
+    }
+}
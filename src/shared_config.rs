@@ -0,0 +1,61 @@
+#[cfg(feature = "capture")]
+use crate::capture::CaptureSlot;
+use crate::connection_intent::ConnectionIntentSlot;
+use crate::handshake_limit::HandshakeLimitSlot;
+use crate::libp2p_stream::LegacyNoiseSlot;
+use crate::negotiation_timeouts::NegotiationTimeoutsSlot;
+use crate::observer::ObserverSlot;
+use crate::quota::QuotaSlot;
+use crate::record::RecordSlot;
+
+/// Configuration shared between the [`Node`](crate::Node) and all of its connections.
+///
+/// The `with_*` builders of the [`Node`](crate::Node) write to these slots, connections read them while they are being set up and used.
+/// Statistics flow the other way and are collected in [`Counters`](crate::stats::Counters).
+#[derive(Clone, Default)]
+pub struct SharedConfig {
+    observer: ObserverSlot,
+    handshake_limit: HandshakeLimitSlot,
+    legacy_noise: LegacyNoiseSlot,
+    negotiation_timeouts: NegotiationTimeoutsSlot,
+    quotas: QuotaSlot,
+    connection_intent: ConnectionIntentSlot,
+    recording: RecordSlot,
+    #[cfg(feature = "capture")]
+    capture: CaptureSlot,
+}
+
+impl SharedConfig {
+    pub fn observer(&self) -> &ObserverSlot {
+        &self.observer
+    }
+
+    pub fn handshake_limit(&self) -> &HandshakeLimitSlot {
+        &self.handshake_limit
+    }
+
+    pub fn legacy_noise(&self) -> &LegacyNoiseSlot {
+        &self.legacy_noise
+    }
+
+    pub fn negotiation_timeouts(&self) -> &NegotiationTimeoutsSlot {
+        &self.negotiation_timeouts
+    }
+
+    pub fn quotas(&self) -> &QuotaSlot {
+        &self.quotas
+    }
+
+    pub fn connection_intent(&self) -> &ConnectionIntentSlot {
+        &self.connection_intent
+    }
+
+    pub fn recording(&self) -> &RecordSlot {
+        &self.recording
+    }
+
+    #[cfg(feature = "capture")]
+    pub fn capture(&self) -> &CaptureSlot {
+        &self.capture
+    }
+}
@@ -0,0 +1,96 @@
+use socket2::{SockRef, Type};
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+/// The first file descriptor passed by systemd, see `sd_listen_fds(3)`.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the listening sockets passed to this process through systemd socket activation.
+///
+/// The returned listeners can be handed to the [`Node`](crate::Node) with [`ListenOnSocket`](crate::ListenOnSocket).
+/// This allows binding privileged ports without running the daemon as root and starting the daemon on demand.
+///
+/// Returns an empty list if the process was not socket-activated, including when `LISTEN_PID` names a different process, e.g. a parent that inherited the environment.
+/// Fails if any of the passed file descriptors is not a listening TCP socket, in which case none of them is taken.
+/// The environment variables are removed so the sockets cannot be taken twice.
+pub fn systemd_listeners() -> io::Result<Vec<TcpListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(Vec::new()),
+    };
+
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+
+    let fds = fds
+        .parse::<RawFd>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let fds = SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds;
+    // Validate all descriptors before claiming any, so nothing is closed if one of them is unexpected.
+    for fd in fds.clone() {
+        check_tcp_listener(fd)?;
+    }
+
+    let listeners = fds
+        // SAFETY: `LISTEN_PID` names this process, so the service manager passed these descriptors to it and we checked that each is a listening TCP socket.
+        // Removing the environment variables above ensures this function hands them out only once.
+        // Nothing prevents other code in the process from using the same descriptors through their numbers, which is the caller's responsibility.
+        .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+        .collect();
+
+    Ok(listeners)
+}
+
+/// Fails unless `fd` is a listening IPv4 or IPv6 stream socket.
+fn check_tcp_listener(fd: RawFd) -> io::Result<()> {
+    let invalid = |reason: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("File descriptor {fd} passed by systemd {reason}"),
+        )
+    };
+    let socket = SockRef::from(&fd);
+
+    if socket.r#type()? != Type::STREAM {
+        return Err(invalid("is not a stream socket"));
+    }
+
+    if socket.local_addr()?.as_socket().is_none() {
+        return Err(invalid("is not an IPv4 or IPv6 socket"));
+    }
+
+    #[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+    if !socket.is_listener()? {
+        return Err(invalid("is not listening"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn accepts_only_listening_tcp_sockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (unix, _) = std::os::unix::net::UnixStream::pair().unwrap();
+
+        assert!(check_tcp_listener(listener.as_raw_fd()).is_ok());
+        assert!(check_tcp_listener(udp.as_raw_fd()).is_err());
+        assert!(check_tcp_listener(unix.as_raw_fd()).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::multiaddress_ext::MultiaddrExt as _;
+use crate::{DialErrorKind, Error};
+use futures::channel::oneshot;
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::{Multiaddr, PeerId};
+use std::collections::HashSet;
+use std::mem;
+
+/// Tracks the listeners and bootstrap connections a [`Node`](crate::Node) waits for before it is ready.
+///
+/// See [`Node::with_listen_addresses`](crate::Node::with_listen_addresses) and [`Node::with_bootstrap_peers`](crate::Node::with_bootstrap_peers).
+/// Also validates the addresses configured through [`Node::with_external_addresses`](crate::Node::with_external_addresses).
+#[derive(Default)]
+pub(crate) struct Startup {
+    listen_addresses: Vec<Multiaddr>,
+    bootstrap_peers: Vec<Multiaddr>,
+    external_addresses: Vec<Multiaddr>,
+    started: bool,
+    pending_listeners: HashSet<Multiaddr>,
+    pending_peers: HashSet<PeerId>,
+    outcome: Option<Result<(), Failure>>,
+    waiters: Vec<oneshot::Sender<Result<(), Error>>>,
+}
+
+#[derive(Debug, Clone)]
+enum Failure {
+    Listen(Multiaddr),
+    Connect(DialErrorKind),
+    NoPeerId(Multiaddr),
+    UnservableExternalAddress(Multiaddr),
+}
+
+impl Failure {
+    fn to_error(&self) -> Error {
+        match self {
+            Failure::Listen(address) => Error::ListenFailed(address.clone()),
+            Failure::Connect(kind) => Error::ConnectFailed(*kind),
+            Failure::NoPeerId(address) => Error::NoPeerIdInAddress(address.clone()),
+            Failure::UnservableExternalAddress(address) => {
+                Error::UnservableExternalAddress(address.clone())
+            }
+        }
+    }
+}
+
+impl Startup {
+    pub(crate) fn add_listen_addresses(&mut self, addresses: impl IntoIterator<Item = Multiaddr>) {
+        self.listen_addresses.extend(addresses);
+    }
+
+    pub(crate) fn add_bootstrap_peers(&mut self, addresses: impl IntoIterator<Item = Multiaddr>) {
+        self.bootstrap_peers.extend(addresses);
+    }
+
+    pub(crate) fn add_external_addresses(
+        &mut self,
+        addresses: impl IntoIterator<Item = Multiaddr>,
+    ) {
+        self.external_addresses.extend(addresses);
+    }
+
+    pub(crate) fn external_addresses(&self) -> &[Multiaddr] {
+        &self.external_addresses
+    }
+
+    /// Returns the addresses to listen on and the bootstrap peers to dial.
+    ///
+    /// Until these are bound and connected respectively, the node is not ready.
+    pub(crate) fn start(&mut self) -> (Vec<Multiaddr>, Vec<Multiaddr>) {
+        self.started = true;
+        self.pending_listeners = self.listen_addresses.iter().cloned().collect();
+
+        let mut bootstrap_peers = Vec::new();
+        for address in self.bootstrap_peers.clone() {
+            match address.clone().extract_peer_id() {
+                Some(peer) => {
+                    self.pending_peers.insert(peer);
+                    bootstrap_peers.push(address);
+                }
+                None => self.fail(Failure::NoPeerId(address)),
+            }
+        }
+
+        // Without configured listen addresses, listeners come up later through `ListenOn` or `ListenOnSocket` and are only checked when advertising.
+        if !self.listen_addresses.is_empty() {
+            for address in self.external_addresses.clone() {
+                let servable = self
+                    .listen_addresses
+                    .iter()
+                    .any(|listen_address| can_serve(listen_address, &address));
+
+                if !servable {
+                    self.fail(Failure::UnservableExternalAddress(address));
+                }
+            }
+        }
+
+        (self.listen_addresses.clone(), bootstrap_peers)
+    }
+
+    pub(crate) fn is_bootstrap_peer(&self, peer: &PeerId) -> bool {
+        self.bootstrap_peers
+            .iter()
+            .any(|address| address.clone().extract_peer_id() == Some(*peer))
+    }
+
+    pub(crate) fn listener_bound(&mut self, address: &Multiaddr) {
+        self.pending_listeners.remove(address);
+    }
+
+    pub(crate) fn listener_failed(&mut self, address: &Multiaddr) {
+        if self.pending_listeners.contains(address) {
+            self.fail(Failure::Listen(address.clone()));
+        }
+    }
+
+    pub(crate) fn peer_connected(&mut self, peer: &PeerId) {
+        self.pending_peers.remove(peer);
+    }
+
+    pub(crate) fn peer_failed(&mut self, peer: &PeerId, kind: DialErrorKind) {
+        if self.pending_peers.contains(peer) {
+            self.fail(Failure::Connect(kind));
+        }
+    }
+
+    /// Marks the node as ready if nothing is pending anymore.
+    ///
+    /// Returns `true` only for the call that made the node ready.
+    pub(crate) fn complete_if_ready(&mut self) -> bool {
+        if !self.started
+            || self.outcome.is_some()
+            || !self.pending_listeners.is_empty()
+            || !self.pending_peers.is_empty()
+        {
+            return false;
+        }
+
+        self.outcome = Some(Ok(()));
+        for waiter in self.waiters.drain(..) {
+            let _ = waiter.send(Ok(()));
+        }
+
+        true
+    }
+
+    /// Resolves once the node is ready or failed to start.
+    pub(crate) fn wait(&mut self) -> oneshot::Receiver<Result<(), Error>> {
+        let (sender, receiver) = oneshot::channel();
+
+        match &self.outcome {
+            None => self.waiters.push(sender),
+            Some(outcome) => {
+                let _ = sender.send(outcome.as_ref().map_err(Failure::to_error).copied());
+            }
+        }
+
+        receiver
+    }
+
+    fn fail(&mut self, failure: Failure) {
+        if self.outcome.is_some() {
+            return;
+        }
+
+        for waiter in self.waiters.drain(..) {
+            let _ = waiter.send(Err(failure.to_error()));
+        }
+        self.outcome = Some(Err(failure));
+    }
+}
+
+/// Whether a listener on `listen_address` can serve connections to the external address, i.e. whether both use the same transport protocols.
+pub(crate) fn can_serve(listen_address: &Multiaddr, external_address: &Multiaddr) -> bool {
+    transport_stack(listen_address) == transport_stack(external_address)
+}
+
+/// The protocols of an address that a transport has to speak, i.e. everything but the host and the `/p2p` suffix.
+///
+/// `/dns4/example.com/tcp/443` and `/ip4/0.0.0.0/tcp/4001` are served by the same transport, `/ip4/0.0.0.0/tcp/4001/ws` is not.
+fn transport_stack(address: &Multiaddr) -> Vec<mem::Discriminant<Protocol<'static>>> {
+    address
+        .iter()
+        .filter(|protocol| {
+            !matches!(
+                protocol,
+                Protocol::Ip4(_)
+                    | Protocol::Ip6(_)
+                    | Protocol::Dns(_)
+                    | Protocol::Dns4(_)
+                    | Protocol::Dns6(_)
+                    | Protocol::P2p(_)
+            )
+        })
+        .map(|protocol| mem::discriminant(&protocol.acquire()))
+        .collect()
+}
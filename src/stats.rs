@@ -0,0 +1,479 @@
+use crate::observer::ObserverSlot;
+use crate::quota::{Quota, QuotaState};
+use crate::substream::CloseReason;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p_core::{Endpoint, PeerId};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Counters shared between the [`Node`](crate::Node) and all of its connections.
+#[derive(Clone, Default)]
+pub struct Counters {
+    bytes_inbound: Arc<AtomicU64>,
+    bytes_outbound: Arc<AtomicU64>,
+    substreams_inbound: Arc<AtomicU64>,
+    substreams_outbound: Arc<AtomicU64>,
+    oversized_handshakes: Arc<AtomicU64>,
+    rejected_inbound_connections: Arc<AtomicU64>,
+    rejected_connection_intents: Arc<AtomicU64>,
+    peers: Arc<Mutex<PeerStats>>,
+}
+
+/// The number of disconnected peers whose statistics are retained, i.e. their rejected and closed substreams and their protocol usage.
+///
+/// Statistics of connected peers are always kept. Once more peers than this disconnected, the statistics of the peer that disconnected first are dropped.
+pub const MAX_RETAINED_DISCONNECTED_PEERS: usize = 1024;
+
+/// The statistics of all peers, bounded to connected peers and the [`MAX_RETAINED_DISCONNECTED_PEERS`] most recently disconnected ones.
+#[derive(Default)]
+struct PeerStats {
+    peers: HashMap<PeerId, PeerCounters>,
+    /// Peers without a connection, the one that disconnected first at the front.
+    disconnected: VecDeque<PeerId>,
+}
+
+#[derive(Default)]
+struct PeerCounters {
+    connected: bool,
+    rejected: HashMap<(Option<&'static str>, RejectionReason), u64>,
+    closed: HashMap<(&'static str, CloseReason), u64>,
+    usage: HashMap<&'static str, Arc<UsageCounters>>,
+}
+
+impl PeerStats {
+    fn get_mut(&mut self, peer: PeerId) -> &mut PeerCounters {
+        // A peer we have not seen connecting counts as disconnected, e.g. one whose statistics were dropped before its last substream ended.
+        if !self.peers.contains_key(&peer) {
+            self.retire(peer);
+        }
+
+        self.peers.entry(peer).or_default()
+    }
+
+    fn connected(&mut self, peer: PeerId) {
+        self.disconnected
+            .retain(|disconnected| *disconnected != peer);
+        self.peers.entry(peer).or_default().connected = true;
+    }
+
+    fn disconnected(&mut self, peer: PeerId) {
+        match self.peers.get_mut(&peer) {
+            Some(counters) => counters.connected = false,
+            None => return,
+        }
+
+        self.retire(peer);
+    }
+
+    fn retire(&mut self, peer: PeerId) {
+        self.disconnected
+            .retain(|disconnected| *disconnected != peer);
+        self.disconnected.push_back(peer);
+
+        while self.disconnected.len() > MAX_RETAINED_DISCONNECTED_PEERS {
+            if let Some(oldest) = self.disconnected.pop_front() {
+                self.peers.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Why an inbound substream was dropped before reaching its handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RejectionReason {
+    /// The node was draining, see [`Drain`](crate::Drain).
+    Draining,
+    /// None of the protocols proposed by the peer are supported.
+    UnsupportedProtocol,
+    /// Protocol negotiation did not complete in time.
+    NegotiationTimeout,
+    /// The peer sent malformed protocol negotiation messages.
+    MalformedNegotiation,
+    /// The handler did not accept the substream within the grace period, see [`Node::with_handler_grace_period`](crate::Node::with_handler_grace_period).
+    HandlerTimeout,
+    /// The handler of the protocol is no longer running.
+    HandlerGone,
+    /// The memory budget of the connection was exhausted, see [`Node::with_memory_budget`](crate::Node::with_memory_budget).
+    MemoryBudget,
+    /// The peer exceeded its quota of substreams for the protocol, see [`Node::with_quota`](crate::Node::with_quota).
+    Quota,
+}
+
+/// The number of inbound substreams rejected for a particular reason, see [`GetRejectedSubstreams`](crate::GetRejectedSubstreams).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RejectedSubstreams {
+    pub peer: PeerId,
+    /// The negotiated protocol. Not known if the substream was rejected during negotiation.
+    pub protocol: Option<&'static str>,
+    pub reason: RejectionReason,
+    pub count: u64,
+}
+
+/// The number of substreams that ended for a particular reason, see [`GetClosedSubstreams`](crate::GetClosedSubstreams).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClosedSubstreams {
+    pub peer: PeerId,
+    pub protocol: &'static str,
+    pub reason: CloseReason,
+    pub count: u64,
+}
+
+/// How a peer used a protocol, see [`GetPeerInfo`](crate::GetPeerInfo).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProtocolUsage {
+    pub substreams_inbound: u64,
+    pub substreams_outbound: u64,
+    /// Bytes read from substreams of the protocol.
+    pub bytes_inbound: u64,
+    /// Bytes written to substreams of the protocol.
+    pub bytes_outbound: u64,
+    /// Substreams on which reading or writing failed, e.g. because they were reset.
+    pub errors: u64,
+}
+
+/// The usage of a protocol by a peer, updated by all substreams of the protocol to the peer.
+///
+/// Also enforces the [`Quota`](crate::Quota) of the protocol, if any.
+#[derive(Default)]
+pub struct UsageCounters {
+    substreams_inbound: AtomicU64,
+    substreams_outbound: AtomicU64,
+    bytes_inbound: AtomicU64,
+    bytes_outbound: AtomicU64,
+    errors: AtomicU64,
+    quota: Option<Mutex<QuotaState>>,
+}
+
+impl UsageCounters {
+    /// Counts an inbound substream against the quota, returns `false` if the peer exceeded it.
+    pub fn admit_substream(&self) -> bool {
+        match &self.quota {
+            None => true,
+            Some(quota) => quota
+                .lock()
+                .expect("not poisoned")
+                .admit_substream(Instant::now()),
+        }
+    }
+
+    /// Whether the peer used up its quota of bytes for the current window.
+    pub fn bytes_exhausted(&self) -> bool {
+        match &self.quota {
+            None => false,
+            Some(quota) => quota
+                .lock()
+                .expect("not poisoned")
+                .bytes_exhausted(Instant::now()),
+        }
+    }
+
+    fn transferred(&self, bytes: usize) {
+        if let Some(quota) = &self.quota {
+            quota
+                .lock()
+                .expect("not poisoned")
+                .transferred(bytes, Instant::now());
+        }
+    }
+
+    pub fn substream_opened(&self, endpoint: Endpoint) {
+        match endpoint {
+            Endpoint::Listener => &self.substreams_inbound,
+            Endpoint::Dialer => &self.substreams_outbound,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read(&self, bytes: usize) {
+        self.bytes_inbound
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.transferred(bytes);
+    }
+
+    /// Bytes we write are up to us, so unlike bytes read they do not count against the quota.
+    pub fn written(&self, bytes: usize) {
+        self.bytes_outbound
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> ProtocolUsage {
+        ProtocolUsage {
+            substreams_inbound: self.substreams_inbound.load(Ordering::Relaxed),
+            substreams_outbound: self.substreams_outbound.load(Ordering::Relaxed),
+            bytes_inbound: self.bytes_inbound.load(Ordering::Relaxed),
+            bytes_outbound: self.bytes_outbound.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Counters {
+    pub fn inbound_substream_opened(&self) {
+        self.substreams_inbound.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn outbound_substream_opened(&self) {
+        self.substreams_outbound.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn oversized_handshake_rejected(&self) {
+        self.oversized_handshakes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inbound_connection_rejected(&self) {
+        self.rejected_inbound_connections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_intent_rejected(&self) {
+        self.rejected_connection_intents
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks the peer as connected, retaining its statistics until it disconnects.
+    pub fn peer_connected(&self, peer: PeerId) {
+        self.peers.lock().expect("not poisoned").connected(peer);
+    }
+
+    /// Marks the peer as disconnected, see [`MAX_RETAINED_DISCONNECTED_PEERS`].
+    pub fn peer_disconnected(&self, peer: PeerId) {
+        self.peers.lock().expect("not poisoned").disconnected(peer);
+    }
+
+    pub fn inbound_substream_rejected(
+        &self,
+        peer: PeerId,
+        protocol: Option<&'static str>,
+        reason: RejectionReason,
+    ) {
+        *self
+            .peers
+            .lock()
+            .expect("not poisoned")
+            .get_mut(peer)
+            .rejected
+            .entry((protocol, reason))
+            .or_default() += 1;
+    }
+
+    pub fn rejected_substreams(&self) -> Vec<RejectedSubstreams> {
+        self.peers
+            .lock()
+            .expect("not poisoned")
+            .peers
+            .iter()
+            .flat_map(|(peer, counters)| {
+                counters
+                    .rejected
+                    .iter()
+                    .map(|((protocol, reason), count)| RejectedSubstreams {
+                        peer: *peer,
+                        protocol: *protocol,
+                        reason: *reason,
+                        count: *count,
+                    })
+            })
+            .collect()
+    }
+
+    pub fn substream_closed(&self, peer: PeerId, protocol: &'static str, reason: CloseReason) {
+        *self
+            .peers
+            .lock()
+            .expect("not poisoned")
+            .get_mut(peer)
+            .closed
+            .entry((protocol, reason))
+            .or_default() += 1;
+    }
+
+    pub fn closed_substreams(&self) -> Vec<ClosedSubstreams> {
+        self.peers
+            .lock()
+            .expect("not poisoned")
+            .peers
+            .iter()
+            .flat_map(|(peer, counters)| {
+                counters
+                    .closed
+                    .iter()
+                    .map(|((protocol, reason), count)| ClosedSubstreams {
+                        peer: *peer,
+                        protocol: *protocol,
+                        reason: *reason,
+                        count: *count,
+                    })
+            })
+            .collect()
+    }
+
+    /// Returns the counters for the usage of the protocol by the peer, for a substream to update.
+    ///
+    /// The `quota` only applies if the peer did not use the protocol before.
+    pub fn protocol_usage(
+        &self,
+        peer: PeerId,
+        protocol: &'static str,
+        quota: Option<Quota>,
+    ) -> Arc<UsageCounters> {
+        self.peers
+            .lock()
+            .expect("not poisoned")
+            .get_mut(peer)
+            .usage
+            .entry(protocol)
+            .or_insert_with(|| {
+                Arc::new(UsageCounters {
+                    quota: quota.map(|quota| Mutex::new(QuotaState::new(quota, Instant::now()))),
+                    ..UsageCounters::default()
+                })
+            })
+            .clone()
+    }
+
+    /// Returns how the peer used each protocol.
+    pub fn peer_usage(&self, peer: &PeerId) -> HashMap<&'static str, ProtocolUsage> {
+        self.peers
+            .lock()
+            .expect("not poisoned")
+            .peers
+            .get(peer)
+            .map(|counters| {
+                counters
+                    .usage
+                    .iter()
+                    .map(|(protocol, usage)| (*protocol, usage.get()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the current values as `(bytes_inbound, bytes_outbound, substreams_inbound, substreams_outbound, oversized_handshakes, rejected_inbound_connections, rejected_connection_intents)`.
+    ///
+    /// If `reset` is true, all counters are set back to zero.
+    pub fn read(&self, reset: bool) -> (u64, u64, u64, u64, u64, u64, u64) {
+        let read = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+
+        (
+            read(&self.bytes_inbound),
+            read(&self.bytes_outbound),
+            read(&self.substreams_inbound),
+            read(&self.substreams_outbound),
+            read(&self.oversized_handshakes),
+            read(&self.rejected_inbound_connections),
+            read(&self.rejected_connection_intents),
+        )
+    }
+}
+
+/// A connection that counts all bytes read and written into the given [`Counters`] and reports them to the observer.
+pub struct Counted<C> {
+    inner: C,
+    counters: Counters,
+    observer: ObserverSlot,
+}
+
+impl<C> Counted<C> {
+    pub fn new(inner: C, counters: Counters, observer: ObserverSlot) -> Self {
+        Self {
+            inner,
+            counters,
+            observer,
+        }
+    }
+}
+
+impl<C> AsyncRead for Counted<C>
+where
+    C: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.counters
+            .bytes_inbound
+            .fetch_add(n as u64, Ordering::Relaxed);
+        self.observer.get().bytes_received(n);
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<C> AsyncWrite for Counted<C>
+where
+    C: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.counters
+            .bytes_outbound
+            .fetch_add(n as u64, Ordering::Relaxed);
+        self.observer.get().bytes_sent(n);
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_recently_disconnected_peers_are_retained() {
+        let counters = Counters::default();
+        let connected = PeerId::random();
+        counters.peer_connected(connected);
+        counters.inbound_substream_rejected(connected, None, RejectionReason::UnsupportedProtocol);
+
+        let first = PeerId::random();
+        counters.peer_connected(first);
+        counters.inbound_substream_rejected(first, None, RejectionReason::UnsupportedProtocol);
+        counters.peer_disconnected(first);
+        for _ in 0..MAX_RETAINED_DISCONNECTED_PEERS {
+            let peer = PeerId::random();
+            counters.peer_connected(peer);
+            counters.inbound_substream_rejected(peer, None, RejectionReason::UnsupportedProtocol);
+            counters.peer_disconnected(peer);
+        }
+
+        let peers = counters
+            .rejected_substreams()
+            .into_iter()
+            .map(|rejected| rejected.peer)
+            .collect::<Vec<_>>();
+        assert_eq!(peers.len(), MAX_RETAINED_DISCONNECTED_PEERS + 1);
+        assert!(peers.contains(&connected));
+        assert!(!peers.contains(&first));
+    }
+}
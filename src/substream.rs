@@ -0,0 +1,815 @@
+use crate::compat::TokioCompat;
+use crate::fairness::{WriteScheduler, WriteTicket};
+use crate::libp2p_stream;
+use crate::memory::{Half, MemoryAccount, MemoryHandle};
+use crate::shared_config::SharedConfig;
+use crate::stats::{Counters, UsageCounters};
+use crate::{ConnectionId, Node, QuotaExceeded, QuotaKind, SubstreamClosed};
+use futures::channel::oneshot;
+use futures::io::ReuniteError;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite};
+use libp2p_core::{Endpoint, PeerId};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Why a [`Substream`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CloseReason {
+    /// We closed our half of the substream before dropping it.
+    Graceful,
+    /// The peer reset the substream.
+    ResetByPeer,
+    /// The substream was dropped without being closed first.
+    LocalReset,
+    /// The connection the substream was running on was closed.
+    ConnectionClosed,
+    /// Reading from or writing to the substream failed with [`io::ErrorKind::TimedOut`].
+    Timeout,
+    /// The substream was reset to keep its connection within the memory budget, see [`Node::with_memory_budget`](crate::Node::with_memory_budget).
+    MemoryBudget,
+    /// The substream was reset because the peer exceeded its quota of bytes for the protocol, see [`Node::with_quota`](crate::Node::with_quota).
+    Quota,
+    /// The handler did not start using the substream within the grace period, so the node reset it, see [`Node::with_handler_grace_period`](crate::Node::with_handler_grace_period).
+    HandlerTimeout,
+    /// Reading from or writing to the substream failed for a reason not covered above, e.g. a write after we closed it.
+    Failed,
+}
+
+/// A substream to a peer on which a protocol has been negotiated.
+///
+/// Once dropped, the substream reports why it ended through [`Event::SubstreamClosed`](crate::Event::SubstreamClosed) and [`GetClosedSubstreams`](crate::GetClosedSubstreams).
+///
+/// This used to be an alias of `Negotiated<yamux::Stream>`. Code that named that type has to switch to this one, which implements the same I/O traits.
+pub struct Substream {
+    inner: Inner,
+    protocol: &'static str,
+    tracker: CloseTracker,
+    closed_locally: bool,
+    closed_remotely: bool,
+    error: Option<io::ErrorKind>,
+    usage: Arc<UsageCounters>,
+    /// The state of the current write, as far as fair scheduling is concerned.
+    write_ticket: WriteTicket,
+    /// Identifies the substream within the memory account of the connection.
+    memory: u64,
+    reset_for_memory: bool,
+    reset_for_quota: bool,
+    write_stall: WriteStall,
+    stall_timeout: StallTimeout,
+}
+
+/// The error of a write that waited for the peer to read for longer than the write stall timeout, see [`Node::with_write_stall_timeout`](crate::Node::with_write_stall_timeout).
+///
+/// It is wrapped in an [`io::Error`] of kind [`io::ErrorKind::TimedOut`], use [`WriteStalled::from_io_error`] to tell it apart from other timeouts.
+/// The failed write did not write anything, but earlier writes of e.g. a `write_all` may have, leaving a message half written.
+/// The substream is therefore reset along with the error: later reads and writes fail and it closes with [`CloseReason::Timeout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Peer did not read from the substream for {blocked_for:?}")]
+pub struct WriteStalled {
+    pub blocked_for: Duration,
+}
+
+impl WriteStalled {
+    pub fn from_io_error(error: &io::Error) -> Option<&WriteStalled> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+/// The stream underlying a [`Substream`].
+enum Inner {
+    Ready(libp2p_stream::Substream),
+    /// The node withholds the stream until the handler starts using the substream, see [`CloseTracker::track_deferred`].
+    Deferred(Handoff),
+    /// The handler did not start using the substream within the grace period and the node reset it.
+    Expired,
+    /// We reset the substream, see [`Inner::reset`].
+    Reset,
+}
+
+impl Inner {
+    /// Returns the stream, taking it over from the node if it was deferred.
+    fn get(&mut self) -> io::Result<Pin<&mut libp2p_stream::Substream>> {
+        if let Inner::Deferred(handoff) = self {
+            *self = match handoff.accept() {
+                Some(stream) => Inner::Ready(stream),
+                None => Inner::Expired,
+            };
+        }
+
+        match self {
+            Inner::Ready(stream) => Ok(Pin::new(stream)),
+            Inner::Deferred(_) | Inner::Expired => Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "Handler did not start using the substream within the grace period",
+            )),
+            Inner::Reset => Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "Substream was reset",
+            )),
+        }
+    }
+
+    /// Drops the stream without closing it, which makes yamux reset it towards the peer.
+    fn reset(&mut self) {
+        if let Inner::Deferred(handoff) = self {
+            drop(handoff.accept());
+        }
+
+        *self = Inner::Reset;
+    }
+
+    /// Resets the stream after a write failed with [`WriteStalled`].
+    fn reset_if_stalled<T>(&mut self, result: &io::Result<T>) {
+        if let Err(e) = result {
+            if WriteStalled::from_io_error(e).is_some() {
+                self.reset();
+            }
+        }
+    }
+}
+
+/// Hands the stream of an inbound substream to its handler, unless the node reset it first.
+#[derive(Clone)]
+struct Handoff {
+    inner: Arc<Mutex<HandoffState>>,
+}
+
+struct HandoffState {
+    stream: Option<libp2p_stream::Substream>,
+    accepted: Option<oneshot::Sender<()>>,
+}
+
+impl Handoff {
+    fn accept(&self) -> Option<libp2p_stream::Substream> {
+        let mut state = self.inner.lock().expect("not poisoned");
+        if let Some(accepted) = state.accepted.take() {
+            let _ = accepted.send(());
+        }
+
+        state.stream.take()
+    }
+}
+
+/// Resolves once the handler of a deferred substream starts using it, see [`CloseTracker::track_deferred`].
+pub(crate) struct Acceptance {
+    handoff: Handoff,
+    accepted: oneshot::Receiver<()>,
+}
+
+impl Acceptance {
+    /// Waits for the handler to start using the substream for up to `grace_period`, resetting the substream if it does not.
+    ///
+    /// Returns whether the handler accepted the substream in time.
+    pub(crate) async fn within(self, grace_period: Duration) -> bool {
+        if tokio::time::timeout(grace_period, self.accepted)
+            .await
+            .is_ok()
+        {
+            return true;
+        }
+
+        // yamux resets a substream that is dropped before being closed.
+        let stream = self
+            .handoff
+            .inner
+            .lock()
+            .expect("not poisoned")
+            .stream
+            .take();
+
+        stream.is_none()
+    }
+}
+
+/// Tracks how long writes to a substream wait for the peer to make room in the flow control window.
+#[derive(Clone, Default)]
+struct WriteStall {
+    inner: Arc<Mutex<StallState>>,
+}
+
+#[derive(Default)]
+struct StallState {
+    since: Option<Instant>,
+    total: Duration,
+}
+
+impl WriteStall {
+    fn record<T>(&self, poll: &Poll<T>) {
+        let mut state = self.inner.lock().expect("not poisoned");
+
+        match (poll.is_pending(), state.since) {
+            (true, None) => state.since = Some(Instant::now()),
+            (false, Some(since)) => {
+                state.since = None;
+                state.total += since.elapsed();
+            }
+            _ => {}
+        }
+    }
+
+    /// Ends the current wait without a write having completed, e.g. because the write was dropped.
+    fn abandon(&self) {
+        let mut state = self.inner.lock().expect("not poisoned");
+
+        if let Some(since) = state.since.take() {
+            state.total += since.elapsed();
+        }
+    }
+
+    fn blocked_for(&self) -> Option<Duration> {
+        self.inner
+            .lock()
+            .expect("not poisoned")
+            .since
+            .map(|since| since.elapsed())
+    }
+
+    fn total(&self) -> Duration {
+        let state = self.inner.lock().expect("not poisoned");
+
+        state.total + state.since.map(|since| since.elapsed()).unwrap_or_default()
+    }
+}
+
+/// Fails writes that wait for the peer to read for longer than `timeout`.
+struct StallTimeout {
+    timeout: Option<Duration>,
+    timer: Option<Pin<Box<tokio::time::Sleep>>>,
+    /// The buffer of the pending write, telling a write that is polled again apart from a new one.
+    pending: Option<(usize, usize)>,
+}
+
+impl StallTimeout {
+    fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            timer: None,
+            pending: None,
+        }
+    }
+
+    /// Abandons the pending write unless `buf` is the buffer it was polled with.
+    ///
+    /// A pending write whose future was dropped is never polled again, so the next write, flush or close is the first we learn of it.
+    fn start_write(&mut self, buf: &[u8], stall: &WriteStall) {
+        if self.pending.is_some() && self.pending != Some(Self::id(buf)) {
+            self.abandon(stall);
+        }
+    }
+
+    fn abandon(&mut self, stall: &WriteStall) {
+        if self.pending.take().is_some() {
+            self.timer = None;
+            stall.abandon();
+        }
+    }
+
+    fn id(buf: &[u8]) -> (usize, usize) {
+        (buf.as_ptr() as usize, buf.len())
+    }
+
+    /// Records the outcome of a write of `buf` in `stall`, turning a write that is pending for too long into a [`WriteStalled`] error.
+    fn poll_write<T>(
+        &mut self,
+        buf: &[u8],
+        poll: Poll<io::Result<T>>,
+        stall: &WriteStall,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<T>> {
+        stall.record(&poll);
+
+        if poll.is_ready() {
+            self.timer = None;
+            self.pending = None;
+            return poll;
+        }
+        self.pending = Some(Self::id(buf));
+
+        let timeout = match self.timeout {
+            None => return Poll::Pending,
+            Some(timeout) => timeout,
+        };
+        let blocked_for = stall.blocked_for().unwrap_or_default();
+        let timer = self.timer.get_or_insert_with(|| {
+            Box::pin(tokio::time::sleep(timeout.saturating_sub(blocked_for)))
+        });
+        futures::ready!(timer.as_mut().poll(cx));
+        self.timer = None;
+
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            WriteStalled {
+                blocked_for: stall.blocked_for().unwrap_or_default(),
+            },
+        )))
+    }
+}
+
+/// Tracks the substreams of a single connection, handing out [`Substream`]s that report back once they end.
+#[derive(Clone)]
+pub(crate) struct CloseTracker {
+    peer: PeerId,
+    connection: ConnectionId,
+    connection_alive: Arc<Mutex<bool>>,
+    first_substream: Arc<Mutex<Option<Instant>>>,
+    counters: Counters,
+    config: SharedConfig,
+    node: xtra::WeakAddress<Node>,
+    has_subscribers: Arc<AtomicBool>,
+    scheduler: Option<WriteScheduler>,
+    memory: MemoryAccount,
+    write_stall_timeout: Option<Duration>,
+}
+
+impl CloseTracker {
+    pub(crate) fn new(
+        peer: PeerId,
+        connection: ConnectionId,
+        counters: Counters,
+        config: SharedConfig,
+        node: xtra::WeakAddress<Node>,
+        has_subscribers: Arc<AtomicBool>,
+        scheduler: Option<WriteScheduler>,
+        memory: MemoryAccount,
+        write_stall_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            peer,
+            connection,
+            connection_alive: Arc::new(Mutex::new(true)),
+            first_substream: Arc::default(),
+            counters,
+            config,
+            node,
+            has_subscribers,
+            scheduler,
+            memory,
+            write_stall_timeout,
+        }
+    }
+
+    pub(crate) fn track(
+        &self,
+        protocol: &'static str,
+        endpoint: Endpoint,
+        stream: libp2p_stream::Substream,
+    ) -> Substream {
+        self.wrap(protocol, endpoint, Inner::Ready(stream))
+    }
+
+    /// Like [`CloseTracker::track`], but withholds the stream from the [`Substream`] until it is first used or dropped.
+    ///
+    /// This lets the node tell when a handler starts processing a substream, as opposed to when the handler receives the message or finishes processing it.
+    pub(crate) fn track_deferred(
+        &self,
+        protocol: &'static str,
+        endpoint: Endpoint,
+        stream: libp2p_stream::Substream,
+    ) -> (Substream, Acceptance) {
+        let (sender, receiver) = oneshot::channel();
+        let handoff = Handoff {
+            inner: Arc::new(Mutex::new(HandoffState {
+                stream: Some(stream),
+                accepted: Some(sender),
+            })),
+        };
+
+        (
+            self.wrap(protocol, endpoint, Inner::Deferred(handoff.clone())),
+            Acceptance {
+                handoff,
+                accepted: receiver,
+            },
+        )
+    }
+
+    fn wrap(&self, protocol: &'static str, endpoint: Endpoint, inner: Inner) -> Substream {
+        self.first_substream
+            .lock()
+            .expect("not poisoned")
+            .get_or_insert_with(Instant::now);
+
+        let usage =
+            self.counters
+                .protocol_usage(self.peer, protocol, self.config.quotas().get(protocol));
+        usage.substream_opened(endpoint);
+
+        Substream {
+            inner,
+            protocol,
+            tracker: self.clone(),
+            closed_locally: false,
+            closed_remotely: false,
+            error: None,
+            usage,
+            write_ticket: WriteTicket::default(),
+            memory: self.memory.open(),
+            reset_for_memory: false,
+            reset_for_quota: false,
+            write_stall: WriteStall::default(),
+            stall_timeout: StallTimeout::new(self.write_stall_timeout),
+        }
+    }
+
+    pub(crate) fn memory(&self) -> &MemoryAccount {
+        &self.memory
+    }
+
+    /// Returns when the first substream on this connection was handed out.
+    pub(crate) fn first_substream(&self) -> Option<Instant> {
+        *self.first_substream.lock().expect("not poisoned")
+    }
+
+    /// Marks the connection as closed, attributing the end of all its remaining substreams to that.
+    ///
+    /// Waits for an ongoing [`CloseTracker::if_open`] to finish, so no dispatch can happen after this returns.
+    pub(crate) fn connection_closed(&self) {
+        *self.connection_alive.lock().expect("not poisoned") = false;
+    }
+
+    /// Runs `dispatch` unless the connection has been closed, preventing it from being closed concurrently.
+    ///
+    /// This orders everything `dispatch` enqueues before whatever is enqueued after [`CloseTracker::connection_closed`].
+    pub(crate) fn if_open<T>(&self, dispatch: impl FnOnce() -> T) -> Option<T> {
+        let alive = self.connection_alive.lock().expect("not poisoned");
+
+        (*alive).then(dispatch)
+    }
+
+    fn is_connection_alive(&self) -> bool {
+        *self.connection_alive.lock().expect("not poisoned")
+    }
+}
+
+impl Substream {
+    pub fn peer(&self) -> PeerId {
+        self.tracker.peer
+    }
+
+    pub fn connection(&self) -> ConnectionId {
+        self.tracker.connection
+    }
+
+    pub fn protocol(&self) -> &'static str {
+        self.protocol
+    }
+
+    /// How long the current write has been waiting for the peer to read, if it is waiting.
+    ///
+    /// A write that is dropped while waiting, e.g. by a timeout around it, counts as waiting until the next write, flush or close.
+    /// A write waits once the flow control window of the substream is exhausted, i.e. the peer reads slower than we write.
+    /// Handlers publishing real-time data can check this before writing and drop updates instead of queueing up behind a slow consumer.
+    pub fn write_blocked_for(&self) -> Option<Duration> {
+        self.write_stall.blocked_for()
+    }
+
+    /// The total time writes to this substream waited for the peer to read.
+    ///
+    /// A total that grows about as fast as time passes indicates a peer that cannot keep up with what we send.
+    pub fn write_blocked_total(&self) -> Duration {
+        self.write_stall.total()
+    }
+
+    /// Fail writes that wait for the peer to read for longer than `timeout` with [`WriteStalled`], overriding [`Node::with_write_stall_timeout`](crate::Node::with_write_stall_timeout).
+    ///
+    /// `None` lets writes wait for as long as it takes.
+    pub fn set_write_stall_timeout(&mut self, timeout: Option<Duration>) {
+        self.stall_timeout.timeout = timeout;
+        self.stall_timeout.timer = None;
+    }
+
+    /// Wraps the substream in an adapter implementing tokio's `AsyncRead` and `AsyncWrite`.
+    ///
+    /// This allows using the substream with tokio-native libraries like `tokio-util` codecs.
+    pub fn compat(self) -> TokioCompat<Self> {
+        TokioCompat::new(self)
+    }
+
+    /// Returns a handle for reporting the buffers of a codec on top of this substream, see [`LengthDelimited::with_memory_handle`](crate::LengthDelimited::with_memory_handle).
+    pub fn memory_handle(&self) -> MemoryHandle {
+        MemoryHandle::new(self.tracker.memory.clone(), self.memory)
+    }
+
+    /// Splits the substream into owned halves that can be moved into separate tasks, e.g. one reading and one writing.
+    ///
+    /// The substream ends once both halves are dropped. Closing the [`SubstreamWriteHalf`] closes our half of the substream.
+    pub fn split(self) -> (SubstreamReadHalf, SubstreamWriteHalf) {
+        let protocol = self.protocol;
+        let write_stall = self.write_stall.clone();
+        let (reader, writer) = AsyncReadExt::split(self);
+
+        (
+            SubstreamReadHalf {
+                inner: reader,
+                protocol,
+            },
+            SubstreamWriteHalf {
+                inner: writer,
+                protocol,
+                write_stall,
+            },
+        )
+    }
+
+    fn close_reason(&self) -> CloseReason {
+        if let Inner::Expired = self.inner {
+            return CloseReason::HandlerTimeout;
+        }
+
+        if self.reset_for_memory {
+            return CloseReason::MemoryBudget;
+        }
+
+        if self.reset_for_quota {
+            return CloseReason::Quota;
+        }
+
+        if self.closed_locally && self.closed_remotely {
+            return CloseReason::Graceful;
+        }
+
+        match self.error {
+            Some(io::ErrorKind::TimedOut) => return CloseReason::Timeout,
+            Some(io::ErrorKind::ConnectionReset) => return CloseReason::ResetByPeer,
+            _ => {}
+        }
+
+        if !self.tracker.is_connection_alive() {
+            return CloseReason::ConnectionClosed;
+        }
+
+        match self.error {
+            None if self.closed_locally => CloseReason::Graceful,
+            None => CloseReason::LocalReset,
+            // yamux refuses writes once the peer reset the substream, but also once we closed our half.
+            Some(io::ErrorKind::WriteZero | io::ErrorKind::BrokenPipe) if !self.closed_locally => {
+                CloseReason::ResetByPeer
+            }
+            Some(_) => CloseReason::Failed,
+        }
+    }
+
+    fn record<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(e) = &result {
+            if self.error.is_none() {
+                self.usage.failed();
+            }
+            self.error.get_or_insert(e.kind());
+        }
+
+        result
+    }
+
+    /// Fails once the substream was reset to stay within the memory budget of the connection.
+    fn check_memory_budget(&mut self, half: Half, cx: &mut Context<'_>) -> io::Result<()> {
+        if !self.reset_for_memory && self.tracker.memory.poll_reset(self.memory, half, cx) {
+            self.reset_for_memory = true;
+            self.inner.reset();
+        }
+
+        if self.reset_for_memory {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "Substream was reset to stay within the memory budget",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Fails once the peer used up its quota of bytes for the protocol, reporting that to the node once.
+    fn check_quota(&mut self) -> io::Result<()> {
+        if !self.reset_for_quota && self.usage.bytes_exhausted() {
+            self.reset_for_quota = true;
+            self.inner.reset();
+
+            let _ = self.tracker.node.do_send(QuotaExceeded {
+                peer: self.tracker.peer,
+                connection: self.tracker.connection,
+                protocol: self.protocol,
+                kind: QuotaKind::Bytes,
+            });
+        }
+
+        if self.reset_for_quota {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "Substream was reset because the peer exceeded its quota",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl AsyncRead for Substream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.check_memory_budget(Half::Read, cx)?;
+        self.check_quota()?;
+
+        let result = futures::ready!(self.inner.get()?.poll_read(cx, buf));
+        let n = self.record(result)?;
+        self.usage.read(n);
+
+        if n == 0 && !buf.is_empty() {
+            self.closed_remotely = true;
+        }
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for Substream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        this.check_memory_budget(Half::Write, cx)?;
+        this.check_quota()?;
+        this.stall_timeout.start_write(buf, &this.write_stall);
+
+        let scheduler = match &this.tracker.scheduler {
+            None => {
+                let poll = this.inner.get()?.poll_write(cx, buf);
+                let result = futures::ready!(this.stall_timeout.poll_write(
+                    buf,
+                    poll,
+                    &this.write_stall,
+                    cx
+                ));
+                this.inner.reset_if_stalled(&result);
+                let n = this.record(result)?;
+                this.usage.written(n);
+
+                return Poll::Ready(Ok(n));
+            }
+            Some(scheduler) => scheduler,
+        };
+
+        let len = futures::ready!(scheduler.poll_acquire(
+            this.protocol,
+            &mut this.write_ticket,
+            cx,
+            buf.len()
+        ));
+        let poll = match this.inner.get() {
+            Ok(stream) => stream.poll_write(cx, &buf[..len]),
+            Err(e) => Poll::Ready(Err(e)),
+        };
+        let result =
+            futures::ready!(this
+                .stall_timeout
+                .poll_write(buf, poll, &this.write_stall, cx));
+        this.inner.reset_if_stalled(&result);
+        scheduler.complete(
+            this.protocol,
+            &mut this.write_ticket,
+            *result.as_ref().unwrap_or(&0),
+        );
+        let n = this.record(result)?;
+        this.usage.written(n);
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        this.stall_timeout.abandon(&this.write_stall);
+
+        let result = futures::ready!(self.inner.get()?.poll_flush(cx));
+
+        Poll::Ready(self.record(result))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        this.stall_timeout.abandon(&this.write_stall);
+
+        let result = futures::ready!(self.inner.get()?.poll_close(cx));
+        self.record(result)?;
+        self.closed_locally = true;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The reading half of a [`Substream`], see [`Substream::split`].
+pub struct SubstreamReadHalf {
+    inner: futures::io::ReadHalf<Substream>,
+    protocol: &'static str,
+}
+
+/// The writing half of a [`Substream`], see [`Substream::split`].
+pub struct SubstreamWriteHalf {
+    inner: futures::io::WriteHalf<Substream>,
+    protocol: &'static str,
+    write_stall: WriteStall,
+}
+
+impl SubstreamReadHalf {
+    pub fn protocol(&self) -> &'static str {
+        self.protocol
+    }
+
+    /// Puts the [`Substream`] back together, failing if the halves belong to different substreams.
+    pub fn reunite(self, other: SubstreamWriteHalf) -> Result<Substream, ReuniteError<Substream>> {
+        self.inner.reunite(other.inner)
+    }
+}
+
+impl SubstreamWriteHalf {
+    pub fn protocol(&self) -> &'static str {
+        self.protocol
+    }
+
+    /// See [`Substream::write_blocked_for`].
+    pub fn write_blocked_for(&self) -> Option<Duration> {
+        self.write_stall.blocked_for()
+    }
+
+    /// See [`Substream::write_blocked_total`].
+    pub fn write_blocked_total(&self) -> Duration {
+        self.write_stall.total()
+    }
+}
+
+impl AsyncRead for SubstreamReadHalf {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SubstreamWriteHalf {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl Drop for Substream {
+    fn drop(&mut self) {
+        // Dropping a deferred substream counts as accepting it, dropping the stream along with us.
+        let _ = self.inner.get();
+
+        // Release the scheduler in case the substream is dropped in the middle of a write.
+        if let (Some(scheduler), true) = (&self.tracker.scheduler, self.write_ticket.is_granted()) {
+            scheduler.complete(self.protocol, &mut self.write_ticket, 0);
+        }
+
+        let reason = self.close_reason();
+        let CloseTracker {
+            peer,
+            connection,
+            counters,
+            config,
+            node,
+            has_subscribers,
+            memory,
+            ..
+        } = &self.tracker;
+
+        memory.close(self.memory);
+
+        counters.substream_closed(*peer, self.protocol, reason);
+        config
+            .observer()
+            .get()
+            .substream_closed(peer, *connection, self.protocol, reason);
+        // Substreams come and go at a high rate on busy nodes, only bother the node if someone listens.
+        if has_subscribers.load(Ordering::Relaxed) {
+            let _ = node.do_send(SubstreamClosed {
+                peer: *peer,
+                connection: *connection,
+                protocol: self.protocol,
+                reason,
+            });
+        }
+    }
+}
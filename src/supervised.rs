@@ -0,0 +1,60 @@
+use crate::{HandlerPanicked, NewInboundSubstream, Node};
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use xtra::Address;
+
+/// A handler registered through [`Node::with_supervised_handler`](crate::Node::with_supervised_handler).
+pub(crate) type SupervisedHandler =
+    Arc<dyn Fn(NewInboundSubstream) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// Runs the handler on the given substream, reporting a panic to the [`Node`] instead of propagating it.
+///
+/// The substream is dropped while unwinding, which resets it.
+pub(crate) async fn run(
+    handler: SupervisedHandler,
+    message: NewInboundSubstream,
+    node: Address<Node>,
+) {
+    let peer = message.peer;
+    let connection = message.connection;
+    let protocol = message.stream.protocol();
+
+    match AssertUnwindSafe(async move { handler(message).await })
+        .catch_unwind()
+        .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            tracing::debug!(%peer, %protocol, "Handler failed: {:#}", e);
+        }
+        Err(payload) => {
+            let message = panic_message(payload.as_ref());
+            tracing::error!(%peer, %protocol, "Handler panicked: {}", message);
+
+            let _ = node
+                .send(HandlerPanicked {
+                    peer,
+                    connection,
+                    protocol,
+                    message,
+                })
+                .await;
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return (*message).to_owned();
+    }
+
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+
+    "Box<dyn Any>".to_owned()
+}
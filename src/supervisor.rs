@@ -0,0 +1,207 @@
+use crate::{Connect, GetConnectionStats, Node, OpenSubstream, Substream};
+use async_trait::async_trait;
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::{Multiaddr, PeerId};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio_tasks::Tasks;
+use xtra::message_channel::StrongMessageChannel;
+use xtra::{Address, Context};
+use xtra_productivity::xtra_productivity;
+
+/// An actor for maintaining a connection to a single peer.
+///
+/// Every `check_interval`, the supervisor checks whether the [`Node`] is still connected to the peer.
+/// If not, it dials the next of the configured addresses, cycling through them on every attempt.
+///
+/// Once (re-)connected, the supervisor opens a substream for each of the configured protocols and hands it to the respective handler as a [`NewOutboundSubstream`].
+/// This allows long-lived substreams to be re-established transparently after a reconnect, the handler can tell them apart through [`NewOutboundSubstream::resumed`].
+/// Substreams that fail to open are retried on every check for as long as the connection is up.
+///
+/// Changes of the connection status are broadcast as [`ConnectionStatus`] to all status subscribers.
+pub struct ConnectionSupervisor {
+    node: Address<Node>,
+    peer: PeerId,
+    addresses: Vec<Multiaddr>,
+    next_address: usize,
+    substream_handlers: Vec<(
+        &'static str,
+        Box<dyn StrongMessageChannel<NewOutboundSubstream>>,
+    )>,
+    status_subscribers: Vec<Box<dyn StrongMessageChannel<ConnectionStatus>>>,
+    check_interval: Duration,
+    connected: bool,
+    /// Protocols for which a substream has been handed out before.
+    opened: HashSet<&'static str>,
+    /// Protocols for which a substream still has to be opened on the current connection.
+    pending: HashSet<&'static str>,
+    tasks: Tasks,
+}
+
+/// Notifies an actor of a new substream opened by the [`ConnectionSupervisor`].
+pub struct NewOutboundSubstream {
+    pub peer: PeerId,
+    pub protocol: &'static str,
+    pub stream: Substream,
+    /// Whether this substream replaces one that was handed out before, i.e. on a previous connection.
+    pub resumed: bool,
+}
+
+/// The status of the connection maintained by a [`ConnectionSupervisor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connected(PeerId),
+    Disconnected(PeerId),
+}
+
+impl ConnectionSupervisor {
+    /// Construct a new [`ConnectionSupervisor`].
+    ///
+    /// The `addresses` may or may not end with a `/p2p` suffix, it is added if missing.
+    pub fn new(
+        node: Address<Node>,
+        peer: PeerId,
+        addresses: Vec<Multiaddr>,
+        check_interval: Duration,
+        substream_handlers: Vec<(
+            &'static str,
+            Box<dyn StrongMessageChannel<NewOutboundSubstream>>,
+        )>,
+        status_subscribers: Vec<Box<dyn StrongMessageChannel<ConnectionStatus>>>,
+    ) -> Self {
+        let addresses = addresses
+            .into_iter()
+            .map(|address| {
+                if matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                    address
+                } else {
+                    address.with(Protocol::P2p(peer.into()))
+                }
+            })
+            .collect();
+
+        Self {
+            node,
+            peer,
+            addresses,
+            next_address: 0,
+            substream_handlers,
+            status_subscribers,
+            check_interval,
+            connected: false,
+            opened: HashSet::default(),
+            pending: HashSet::default(),
+            tasks: Tasks::default(),
+        }
+    }
+
+    fn broadcast(&mut self, status: ConnectionStatus) {
+        self.status_subscribers
+            .retain(|subscriber| subscriber.do_send(status).is_ok());
+    }
+
+    async fn open_substreams(&mut self) {
+        for (protocol, handler) in &self.substream_handlers {
+            let protocol = *protocol;
+            if !self.pending.contains(protocol) {
+                continue;
+            }
+
+            let result = self
+                .node
+                .send(OpenSubstream::single_protocol(self.peer, protocol))
+                .await;
+
+            match result {
+                Ok(Ok(stream)) => {
+                    self.pending.remove(protocol);
+                    let resumed = !self.opened.insert(protocol);
+
+                    let _ = handler.do_send(NewOutboundSubstream {
+                        peer: self.peer,
+                        protocol,
+                        stream,
+                        resumed,
+                    });
+                }
+                Ok(Err(e)) => {
+                    tracing::debug!(peer = %self.peer, %protocol, "Failed to open substream: {:#}", e)
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+#[xtra_productivity]
+impl ConnectionSupervisor {
+    async fn handle(&mut self, _: Check) {
+        let stats = match self.node.send(GetConnectionStats).await {
+            Ok(stats) => stats,
+            Err(_) => return,
+        };
+        let connected = stats.connected_peers.contains(&self.peer);
+
+        match (self.connected, connected) {
+            (false, true) => {
+                self.connected = true;
+                self.broadcast(ConnectionStatus::Connected(self.peer));
+                self.pending = self
+                    .substream_handlers
+                    .iter()
+                    .map(|(protocol, _)| *protocol)
+                    .collect();
+                self.open_substreams().await;
+            }
+            (true, true) if !self.pending.is_empty() => {
+                self.open_substreams().await;
+            }
+            (true, false) => {
+                self.connected = false;
+                self.broadcast(ConnectionStatus::Disconnected(self.peer));
+            }
+            _ => {}
+        }
+
+        if connected || self.addresses.is_empty() {
+            return;
+        }
+
+        let address = self.addresses[self.next_address % self.addresses.len()].clone();
+        self.next_address = self.next_address.wrapping_add(1);
+
+        if let Ok(Err(e)) = self.node.send(Connect(address.clone())).await {
+            tracing::debug!(peer = %self.peer, %address, "Failed to dial: {:#}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl xtra::Actor for ConnectionSupervisor {
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        let this = ctx.address().expect("we are alive").downgrade();
+        let check_interval = self.check_interval;
+
+        self.tasks.add(async move {
+            let mut ticker = tokio::time::interval(check_interval);
+
+            loop {
+                ticker.tick().await;
+
+                if this.send(Check).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+struct Check;
+
+impl xtra::Message for NewOutboundSubstream {
+    type Result = ();
+}
+
+impl xtra::Message for ConnectionStatus {
+    type Result = ();
+}
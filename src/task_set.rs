@@ -0,0 +1,85 @@
+use futures::stream::FuturesUnordered;
+use futures::{Future, FutureExt, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::task::JoinHandle;
+
+/// Spawns tasks like [`tokio_tasks::Tasks`], but forgets them once they finish.
+///
+/// Owners that spawn a task per substream or per peer would otherwise accumulate the handles of finished tasks for as long as they live.
+/// Tasks that are still running are aborted once the set is dropped.
+#[derive(Default)]
+pub(crate) struct TaskSet {
+    running: FuturesUnordered<AbortOnDrop>,
+}
+
+impl TaskSet {
+    pub(crate) fn add(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        self.prune();
+        self.running.push(AbortOnDrop(tokio::spawn(task)));
+    }
+
+    pub(crate) fn add_fallible<E, EF>(
+        &mut self,
+        task: impl Future<Output = Result<(), E>> + Send + 'static,
+        on_error: impl FnOnce(E) -> EF + Send + 'static,
+    ) where
+        E: Send + 'static,
+        EF: Future<Output = ()> + Send + 'static,
+    {
+        self.add(async move {
+            if let Err(e) = task.await {
+                on_error(e).await;
+            }
+        });
+    }
+
+    /// The number of tasks that are still running.
+    pub(crate) fn len(&mut self) -> usize {
+        self.prune();
+
+        self.running.len()
+    }
+
+    /// Drops the handles of all tasks that finished since the last call.
+    fn prune(&mut self) {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+
+        while let Poll::Ready(Some(())) = self.running.poll_next_unpin(&mut cx) {}
+    }
+}
+
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Future for AbortOnDrop {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.poll_unpin(cx).map(|_| ())
+    }
+}
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn finished_tasks_are_pruned() {
+        let mut tasks = TaskSet::default();
+        for _ in 0..10 {
+            tasks.add(async {});
+        }
+        tasks.add(futures::future::pending());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(tasks.len(), 1);
+    }
+}
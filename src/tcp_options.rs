@@ -0,0 +1,164 @@
+use socket2::{Domain, SockRef, Socket, TcpKeepalive, Type};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::time::Duration;
+
+/// Socket options for the TCP connections of a [`BoundTcpTransport`](crate::BoundTcpTransport).
+///
+/// Options that are not set keep the defaults of the operating system.
+/// Latency-sensitive protocols typically enable [`TcpOptions::with_nodelay`], bulk transfers benefit from larger buffers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<Duration>,
+    linger: Option<Duration>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    tos: Option<u8>,
+    port_reuse: bool,
+}
+
+impl TcpOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `TCP_NODELAY`, disabling Nagle's algorithm if `true`.
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+
+        self
+    }
+
+    /// Enables `SO_KEEPALIVE`, sending keepalive probes once the connection was idle for the given duration and then every duration.
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+
+        self
+    }
+
+    /// Sets `SO_LINGER`, blocking close for up to the given duration while unsent data remains.
+    pub fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = Some(linger);
+
+        self
+    }
+
+    /// Sets `SO_SNDBUF`. The operating system may round or double the value.
+    pub fn with_send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+
+        self
+    }
+
+    /// Sets `SO_RCVBUF`. The operating system may round or double the value.
+    pub fn with_recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+
+        self
+    }
+
+    /// Marks outgoing packets with the given type-of-service byte, of which the upper six bits are the DSCP.
+    ///
+    /// Only applied to IPv4 sockets, the traffic class of IPv6 sockets is left untouched.
+    pub fn with_tos(mut self, tos: u8) -> Self {
+        self.tos = Some(tos);
+
+        self
+    }
+
+    /// Sets `SO_REUSEADDR` and, on Unix, `SO_REUSEPORT` so that dials and listeners can share a local port.
+    ///
+    /// Together with [`BoundTcpTransport::with_source_port`](crate::BoundTcpTransport::with_source_port) this makes outbound connections originate from the listen port, which is what NAT hole punching relies on.
+    /// The listener has to be bound with port reuse as well, see [`TcpOptions::bind_listener`].
+    pub fn with_port_reuse(mut self, port_reuse: bool) -> Self {
+        self.port_reuse = port_reuse;
+
+        self
+    }
+
+    /// Binds a listening socket to the given address with these options applied.
+    ///
+    /// Hand the listener to the [`Node`](crate::Node) with [`ListenOnSocket`](crate::ListenOnSocket).
+    pub fn bind_listener(&self, address: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+        self.apply(SockRef::from(&socket), address.is_ipv4())?;
+        if address.is_ipv6() {
+            // Operating systems disagree on the default, binding both stacks relies on it being set.
+            socket.set_only_v6(true)?;
+        }
+        socket.bind(&address.into())?;
+        socket.listen(1024)?;
+
+        Ok(socket.into())
+    }
+
+    /// Binds listening sockets on all IPv4 and all IPv6 interfaces to the same port with these options applied.
+    ///
+    /// The IPv6 socket is bound with `IPV6_V6ONLY` so the two listeners do not compete for IPv4 connections, whatever the default of the operating system.
+    /// With port `0`, the IPv4 socket picks a free port and the IPv6 socket binds to the same one.
+    /// Hand both listeners to the [`Node`](crate::Node) with [`ListenOnSocket`](crate::ListenOnSocket), which reports their addresses.
+    pub fn bind_dual_stack(&self, port: u16) -> io::Result<(TcpListener, TcpListener)> {
+        let ipv4 = self.bind_listener(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))?;
+        let port = ipv4.local_addr()?.port();
+        let ipv6 = self.bind_listener(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port))?;
+
+        Ok((ipv4, ipv6))
+    }
+
+    pub(crate) fn apply(&self, socket: SockRef<'_>, ipv4: bool) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if let Some(interval) = self.keepalive {
+            socket.set_tcp_keepalive(&keepalive(interval))?;
+        }
+        if let Some(linger) = self.linger {
+            socket.set_linger(Some(linger))?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let (Some(tos), true) = (self.tos, ipv4) {
+            socket.set_tos(tos as u32)?;
+        }
+        if self.port_reuse {
+            socket.set_reuse_address(true)?;
+            #[cfg(unix)]
+            socket.set_reuse_port(true)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+fn keepalive(interval: Duration) -> TcpKeepalive {
+    TcpKeepalive::new()
+        .with_time(interval)
+        .with_interval(interval)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn keepalive(interval: Duration) -> TcpKeepalive {
+    TcpKeepalive::new().with_time(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_both_stacks_to_the_same_port() {
+        let (ipv4, ipv6) = TcpOptions::new().bind_dual_stack(0).unwrap();
+
+        let ipv4 = ipv4.local_addr().unwrap();
+        let ipv6 = ipv6.local_addr().unwrap();
+        assert!(ipv4.is_ipv4());
+        assert!(ipv6.is_ipv6());
+        assert_eq!(ipv4.port(), ipv6.port());
+    }
+}
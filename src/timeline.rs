@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+/// When each step of establishing a connection completed, see [`ConnectionInfo::timeline`](crate::ConnectionInfo::timeline).
+///
+/// Steps that did not happen (yet) are `None`.
+/// Inbound connections have no dial start and connections injected through [`InjectConnection`](crate::InjectConnection) have neither a dial start nor a transport connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionTimeline {
+    pub dial_started: Option<Instant>,
+    /// The transport (e.g. TCP) connection was established.
+    pub transport_connected: Option<Instant>,
+    pub noise_completed: Option<Instant>,
+    /// The [`PeerId`](libp2p_core::PeerId) of the remote matched the dialed address.
+    pub peer_verified: Option<Instant>,
+    pub muxer_ready: Option<Instant>,
+    /// The first substream on this connection was negotiated, in either direction.
+    pub first_substream: Option<Instant>,
+}
+
+impl ConnectionTimeline {
+    /// Returns how long each step took, i.e. the time since the previous step that happened.
+    ///
+    /// This is meant for exporting the timeline as individual metrics or spans.
+    pub fn steps(&self) -> Vec<(&'static str, Duration)> {
+        let steps = [
+            ("dial_started", self.dial_started),
+            ("transport_connected", self.transport_connected),
+            ("noise_completed", self.noise_completed),
+            ("peer_verified", self.peer_verified),
+            ("muxer_ready", self.muxer_ready),
+            ("first_substream", self.first_substream),
+        ];
+
+        let mut previous = None;
+        let mut durations = Vec::new();
+
+        for (name, completed) in steps {
+            let completed = match completed {
+                None => continue,
+                Some(completed) => completed,
+            };
+
+            if let Some(previous) = previous {
+                durations.push((name, completed.saturating_duration_since(previous)));
+            }
+            previous = Some(completed);
+        }
+
+        durations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_skip_missing_instants() {
+        let start = Instant::now();
+        let timeline = ConnectionTimeline {
+            dial_started: None,
+            transport_connected: Some(start),
+            noise_completed: Some(start + Duration::from_millis(10)),
+            peer_verified: None,
+            muxer_ready: Some(start + Duration::from_millis(15)),
+            first_substream: Some(start + Duration::from_millis(45)),
+        };
+
+        assert_eq!(
+            timeline.steps(),
+            vec![
+                ("noise_completed", Duration::from_millis(10)),
+                ("muxer_ready", Duration::from_millis(5)),
+                ("first_substream", Duration::from_millis(30)),
+            ]
+        );
+    }
+}
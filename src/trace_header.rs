@@ -0,0 +1,133 @@
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io;
+
+/// The maximum size of a trace ID in a trace header in bytes.
+pub const MAX_TRACE_ID_SIZE: usize = 256;
+
+/// Writes a trace header carrying the given trace ID, e.g. a W3C `traceparent`, to a freshly opened substream.
+///
+/// The header is an unsigned varint length followed by as many bytes of UTF-8 and must be the first thing written to the substream.
+/// Without a trace ID, an empty header is written, so the listener of a protocol with trace headers can always read one.
+/// See [`OpenSubstreamBuilder::trace_header`](crate::OpenSubstreamBuilder::trace_header) for writing it as part of opening the substream.
+pub async fn write_trace_header<S>(stream: &mut S, trace_id: Option<&str>) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let trace_id = trace_id.unwrap_or_default();
+    if trace_id.len() > MAX_TRACE_ID_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Trace ID of {} bytes exceeds maximum of {MAX_TRACE_ID_SIZE} bytes",
+                trace_id.len()
+            ),
+        ));
+    }
+
+    let mut header = encode_varint(trace_id.len());
+    header.extend_from_slice(trace_id.as_bytes());
+    stream.write_all(&header).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// Reads the trace header written by [`write_trace_header`] from an inbound substream.
+///
+/// Returns the trace ID of the dialer, if it sent one, to be recorded on the span of the handler.
+pub async fn read_trace_header<S>(stream: &mut S) -> io::Result<Option<String>>
+where
+    S: AsyncRead + Unpin,
+{
+    let len = read_varint(stream).await?;
+    if len > MAX_TRACE_ID_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Trace ID of {len} bytes exceeds maximum of {MAX_TRACE_ID_SIZE} bytes"),
+        ));
+    }
+    if len == 0 {
+        return Ok(None);
+    }
+
+    let mut trace_id = vec![0u8; len];
+    stream.read_exact(&mut trace_id).await?;
+
+    String::from_utf8(trace_id)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn encode_varint(mut value: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+async fn read_varint<S>(stream: &mut S) -> io::Result<usize>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut value = 0usize;
+
+    // A length of at most `MAX_TRACE_ID_SIZE` fits into two bytes, anything longer is rejected anyway.
+    for shift in [0, 7, 14] {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await?;
+
+        value |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Trace header length is too long",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    #[test]
+    fn trace_header_round_trips() {
+        block_on(async {
+            let trace_id = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+            let mut buffer = Cursor::new(Vec::new());
+            write_trace_header(&mut buffer, Some(trace_id))
+                .await
+                .unwrap();
+            write_trace_header(&mut buffer, None).await.unwrap();
+            buffer.write_all(b"payload").await.unwrap();
+
+            buffer.set_position(0);
+            assert_eq!(
+                read_trace_header(&mut buffer).await.unwrap().as_deref(),
+                Some(trace_id)
+            );
+            assert_eq!(read_trace_header(&mut buffer).await.unwrap(), None);
+
+            let mut payload = Vec::new();
+            buffer.read_to_end(&mut payload).await.unwrap();
+            assert_eq!(payload, b"payload");
+        });
+    }
+
+    #[test]
+    fn long_trace_ids_use_multi_byte_length() {
+        assert_eq!(encode_varint(200), [0xc8, 0x01]);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::compat::Compat;
+use futures::future::{BoxFuture, Ready};
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::transport::{ListenerEvent, TransportError};
+use libp2p_core::{Multiaddr, Transport};
+use std::fs::{DirBuilder, Permissions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// A transport for `/unix` addresses, i.e. Unix domain sockets.
+///
+/// This is useful for connecting co-located processes without exposing a port on the network.
+/// Like any other transport, it is meant to be passed to [`Node::new`](crate::Node::new) which applies the usual upgrades on top.
+#[derive(Clone, Default)]
+pub struct UnixTransport {
+    permissions: Option<u32>,
+}
+
+impl UnixTransport {
+    /// Sets the file permissions (e.g. `0o600`) of the socket files created when listening.
+    ///
+    /// This allows restricting which local users can connect to the socket.
+    /// The socket is bound in a private directory and only linked to its address once the permissions are set, so it is never reachable with looser permissions.
+    pub fn with_permissions(mode: u32) -> Self {
+        Self {
+            permissions: Some(mode),
+        }
+    }
+}
+
+impl Transport for UnixTransport {
+    type Output = UnixStream;
+    type Error = io::Error;
+    type Listener =
+        BoxStream<'static, Result<ListenerEvent<Self::ListenerUpgrade, Self::Error>, Self::Error>>;
+    type ListenerUpgrade = Ready<Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        let path = match socket_path(&addr) {
+            Some(path) => path,
+            None => return Err(TransportError::MultiaddrNotSupported(addr)),
+        };
+
+        let listener = bind(&path, self.permissions).map_err(TransportError::Other)?;
+
+        let new_address = futures::stream::once({
+            let addr = addr.clone();
+
+            async move { Ok::<_, io::Error>(ListenerEvent::NewAddress(addr)) }
+        });
+        let upgrades = futures::stream::unfold(listener, move |listener| {
+            let addr = addr.clone();
+
+            async move {
+                let event = match listener.accept().await {
+                    Ok((stream, _)) => ListenerEvent::Upgrade {
+                        upgrade: futures::future::ready(Ok(UnixStream::new(stream))),
+                        local_addr: addr.clone(),
+                        remote_addr: addr,
+                    },
+                    Err(e) => ListenerEvent::Error(e),
+                };
+
+                Some((Ok(event), listener))
+            }
+        });
+
+        Ok(new_address.chain(upgrades).boxed())
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        let path = match socket_path(&addr) {
+            Some(path) => path,
+            None => return Err(TransportError::MultiaddrNotSupported(addr)),
+        };
+
+        Ok(async move {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+
+            Ok(UnixStream::new(stream))
+        }
+        .boxed())
+    }
+
+    fn dial_as_listener(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        self.dial(addr)
+    }
+
+    fn address_translation(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+/// Extracts the socket path from an address of the form `/unix/<path>`, optionally followed by `/p2p/<peer-id>`.
+fn socket_path(addr: &Multiaddr) -> Option<PathBuf> {
+    let mut protocols = addr.iter();
+
+    let path = match protocols.next()? {
+        Protocol::Unix(path) => PathBuf::from(path.into_owned()),
+        _ => return None,
+    };
+
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => Some(path),
+        Some(_) => None,
+    }
+}
+
+/// Binds a listener to `path`, replacing a stale socket left behind by a previous listener.
+///
+/// With `mode`, the socket is bound in a private directory next to `path`, restricted and then hard-linked to `path`.
+/// Unlike a rename, the link fails instead of replacing whatever appeared at `path` in the meantime.
+fn bind(path: &Path, mode: Option<u32>) -> io::Result<tokio::net::UnixListener> {
+    remove_stale_socket(path)?;
+
+    let mode = match mode {
+        None => return tokio::net::UnixListener::bind(path),
+        Some(mode) => mode,
+    };
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let staging = parent.join(format!(".{:08x}", rand::random::<u32>()));
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("s");
+
+    let listener = tokio::net::UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, Permissions::from_mode(mode))?;
+        std::fs::hard_link(&staged, path)?;
+
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+
+    listener
+}
+
+/// Removes the socket at `path` if no listener accepts connections on it anymore.
+///
+/// Anything at `path` that is not a socket is left alone and fails the bind.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another listener", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// A connection established by [`UnixTransport`].
+pub type UnixStream = Compat<tokio::net::UnixStream>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt, TryStreamExt};
+
+    #[test]
+    fn rejects_non_unix_address() {
+        let result = UnixTransport::default().dial("/memory/10000".parse().unwrap());
+
+        assert!(matches!(
+            result,
+            Err(TransportError::MultiaddrNotSupported(_))
+        ))
+    }
+
+    #[tokio::test]
+    async fn dialer_can_talk_to_listener() {
+        let path = std::env::temp_dir().join(format!("unix-{:016x}.sock", rand::random::<u64>()));
+        let addr =
+            Multiaddr::empty().with(Protocol::Unix(path.to_string_lossy().into_owned().into()));
+
+        let mut listener = UnixTransport::with_permissions(0o600)
+            .listen_on(addr.clone())
+            .unwrap();
+        let mut dialer = UnixTransport::default().dial(addr).unwrap().await.unwrap();
+
+        let mut listener_conn = loop {
+            if let ListenerEvent::Upgrade { upgrade, .. } =
+                listener.try_next().await.unwrap().unwrap()
+            {
+                break upgrade.await.unwrap();
+            }
+        };
+
+        dialer.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        listener_conn.read_exact(&mut buf).await.unwrap();
+
+        assert_eq!(&buf, b"hello");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
+    #[tokio::test]
+    async fn replaces_stale_socket_but_not_other_files() {
+        let path = std::env::temp_dir().join(format!("unix-{:016x}.sock", rand::random::<u64>()));
+        let addr =
+            Multiaddr::empty().with(Protocol::Unix(path.to_string_lossy().into_owned().into()));
+
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(UnixTransport::with_permissions(0o600)
+            .listen_on(addr.clone())
+            .is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, b"not a socket").unwrap();
+        assert!(UnixTransport::default().listen_on(addr).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a socket");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use crate::multiaddress_ext::MultiaddrExt;
+use crate::AUDIT_TARGET;
 use futures::future::BoxFuture;
 use futures::stream::BoxStream;
 use futures::FutureExt;
@@ -100,6 +101,13 @@ where
     let (actual_peer_id, conn) = dial.await.map_err(Error::Inner)?;
 
     if expected_peer_id != actual_peer_id {
+        tracing::warn!(
+            target: AUDIT_TARGET,
+            expected = %expected_peer_id,
+            actual = %actual_peer_id,
+            "Peer ID mismatch"
+        );
+
         return Err(Error::PeerIdMismatch {
             actual: actual_peer_id,
             expected: expected_peer_id,
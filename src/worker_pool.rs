@@ -0,0 +1,154 @@
+use crate::fan_out::{DispatchStrategy, FanOut};
+use crate::task_set::TaskSet;
+use crate::NewInboundSubstream;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use xtra::message_channel::StrongMessageChannel;
+use xtra::Context;
+use xtra_productivity::xtra_productivity;
+
+/// The number of substreams a [`WorkerPool`] queues while all workers are busy, unless configured otherwise.
+pub const DEFAULT_MAX_QUEUED_SUBSTREAMS: usize = 1024;
+
+/// How long a substream waits for a worker of a [`WorkerPool`] before it is reset, unless configured otherwise.
+pub const DEFAULT_MAX_QUEUE_TIME: Duration = Duration::from_secs(30);
+
+/// An actor that owns a set of worker actors and hands each inbound substream to the least busy of them.
+///
+/// Register the pool as the handler of a protocol in place of a single actor.
+/// A worker is busy with a substream until its handler returns, and is given at most `max_concurrent_per_worker` substreams at a time.
+/// Substreams arriving while all workers are at their limit are queued and dispatched as workers become idle.
+/// Without a limit per worker, [`Node::with_handlers`](crate::Node::with_handlers) with [`DispatchStrategy::LeastBusy`] does the same without an actor in between.
+pub struct WorkerPool {
+    workers: FanOut,
+    queue: VecDeque<Queued>,
+    max_queued: usize,
+    max_queue_time: Duration,
+    expiry_scheduled: bool,
+    tasks: TaskSet,
+}
+
+struct Queued {
+    since: Instant,
+    substream: NewInboundSubstream,
+}
+
+impl WorkerPool {
+    pub fn new(
+        workers: Vec<Box<dyn StrongMessageChannel<NewInboundSubstream>>>,
+        max_concurrent_per_worker: usize,
+    ) -> Self {
+        Self {
+            workers: FanOut::new(workers, DispatchStrategy::LeastBusy)
+                .with_max_in_flight(max_concurrent_per_worker),
+            queue: VecDeque::new(),
+            max_queued: DEFAULT_MAX_QUEUED_SUBSTREAMS,
+            max_queue_time: DEFAULT_MAX_QUEUE_TIME,
+            expiry_scheduled: false,
+            tasks: TaskSet::default(),
+        }
+    }
+
+    /// Drop substreams that arrive while `max_queued` substreams are already waiting for a worker.
+    ///
+    /// Dropping a substream resets it. Defaults to [`DEFAULT_MAX_QUEUED_SUBSTREAMS`].
+    pub fn with_max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = max_queued;
+
+        self
+    }
+
+    /// Drop substreams that waited for a worker for longer than `max_queue_time`.
+    ///
+    /// The peer has usually given up on them by then. Defaults to [`DEFAULT_MAX_QUEUE_TIME`].
+    pub fn with_max_queue_time(mut self, max_queue_time: Duration) -> Self {
+        self.max_queue_time = max_queue_time;
+
+        self
+    }
+
+    /// Hands queued substreams to idle workers for as long as there are both.
+    fn dispatch(&mut self, ctx: &mut Context<Self>) {
+        let this = ctx.address().expect("we are alive");
+
+        while let Some(Queued { since, substream }) = self.queue.pop_front() {
+            let delivery = match self.workers.try_deliver(substream) {
+                Ok(delivery) => delivery,
+                Err(substream) => {
+                    self.queue.push_front(Queued { since, substream });
+                    return;
+                }
+            };
+            let this = this.clone();
+
+            self.tasks.add(async move {
+                if let Err(e) = delivery.await {
+                    tracing::debug!("Dropping substream: {:#}", e);
+                }
+
+                let _ = this.send(WorkerIdle).await;
+            });
+        }
+    }
+
+    /// Drops the substreams that waited for too long and schedules a check for the next one to expire.
+    fn expire(&mut self, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        while let Some(queued) = self.queue.front() {
+            if now.duration_since(queued.since) < self.max_queue_time {
+                break;
+            }
+
+            let queued = self.queue.pop_front().expect("queue is not empty");
+            tracing::debug!(peer = %queued.substream.peer, "Dropping inbound substream because it waited too long for a worker");
+        }
+
+        let next_expiry = match self.queue.front() {
+            Some(queued) if !self.expiry_scheduled => queued.since + self.max_queue_time,
+            _ => return,
+        };
+
+        let this = ctx.address().expect("we are alive");
+        self.expiry_scheduled = true;
+        self.tasks.add(async move {
+            tokio::time::sleep_until(next_expiry.into()).await;
+
+            let _ = this.send(ExpireQueued).await;
+        });
+    }
+}
+
+#[xtra_productivity(message_impl = false)]
+impl WorkerPool {
+    async fn handle(&mut self, msg: NewInboundSubstream, ctx: &mut Context<Self>) {
+        if self.queue.len() >= self.max_queued {
+            tracing::debug!(peer = %msg.peer, "Dropping inbound substream because all workers are busy");
+            return;
+        }
+
+        self.queue.push_back(Queued {
+            since: Instant::now(),
+            substream: msg,
+        });
+        self.dispatch(ctx);
+        self.expire(ctx);
+    }
+}
+
+#[xtra_productivity]
+impl WorkerPool {
+    async fn handle(&mut self, _: WorkerIdle, ctx: &mut Context<Self>) {
+        self.dispatch(ctx);
+    }
+
+    async fn handle(&mut self, _: ExpireQueued, ctx: &mut Context<Self>) {
+        self.expiry_scheduled = false;
+        self.expire(ctx);
+    }
+}
+
+impl xtra::Actor for WorkerPool {}
+
+struct WorkerIdle;
+
+struct ExpireQueued;
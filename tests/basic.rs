@@ -1,17 +1,33 @@
 use anyhow::Context as _;
 use anyhow::Result;
 use asynchronous_codec::Bytes;
-use futures::{SinkExt, StreamExt};
+use futures::channel::{mpsc, oneshot};
+use futures::{AsyncWriteExt, SinkExt, StreamExt};
 use libp2p_core::multiaddr::Protocol;
-use libp2p_core::Multiaddr;
+use libp2p_core::{Endpoint, Multiaddr};
+use libp2p_xtra::heartbeat;
 use libp2p_xtra::libp2p::identity::Keypair;
-use libp2p_xtra::libp2p::transport::MemoryTransport;
+use libp2p_xtra::libp2p::transport::memory::Channel;
+use libp2p_xtra::libp2p::transport::{ListenerEvent, MemoryTransport};
 use libp2p_xtra::libp2p::PeerId;
+use libp2p_xtra::libp2p::Transport;
+use libp2p_xtra::loopback;
 use libp2p_xtra::{
-    Connect, Disconnect, GetConnectionStats, ListenOn, NewInboundSubstream, Node, OpenSubstream,
+    serve_commands, AddressFilter, ApplyConfig, Broadcast, CloseReason, ClosedSubstreams, Compat,
+    Connect, ConnectWithIntent, ConnectionSupervisor, DialErrorKind, Disconnect,
+    DisconnectConnection, DispatchStrategy, Drain, Enqueue, Event, GetAdvertisedAddresses,
+    GetClosedSubstreams, GetConfig, GetConnectionStats, GetHealth, GetPeerInfo, GetPeers,
+    GetRejectedSubstreams, Health, HealthThresholds, InjectConnection, LegacyNoise,
+    LengthDelimited, ListenOn, ListenOnSocket, NewInboundSubstream, NewOutboundSubstream, Node,
+    NodeCommand, NodeExt, OpenSubstream, OpenSubstreamBuilder, Outbox, OverflowPolicy, PeerFilter,
+    Quota, QuotaKind, RejectedSubstreams, RejectionReason, ResetStats, RotateIdentity, SamplePeers,
+    SnapshotStats, Subscribe, SubstreamPool, WorkerPool, WriteStalled, SUBSTREAM_MEMORY_ESTIMATE,
 };
 use std::collections::HashSet;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::sync::{Notify, Semaphore};
 use tokio_tasks::Tasks;
 use xtra::message_channel::StrongMessageChannel;
 use xtra::spawn::TokioGlobalSpawnExt;
@@ -44,6 +60,228 @@ async fn hello_world() {
     assert_eq!(string, "Hello Bob!");
 }
 
+#[tokio::test]
+async fn length_delimited_frames_are_compatible_with_length_codec() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let bob_to_alice = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let mut framed = LengthDelimited::new(bob_to_alice);
+
+    framed.send_frame(Bytes::from_static(b"Bob")).await.unwrap();
+    let reply = framed.next_frame().await.unwrap().unwrap();
+
+    assert_eq!(reply, "Hello Bob!");
+}
+
+#[tokio::test]
+async fn split_substream_halves_can_be_used_from_separate_tasks() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let bob_to_alice = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let (reader, writer) = bob_to_alice.split();
+
+    let reading = tokio::spawn(async move {
+        LengthDelimited::new(reader)
+            .next_frame()
+            .await
+            .unwrap()
+            .unwrap()
+    });
+    tokio::spawn(async move {
+        LengthDelimited::new(writer)
+            .send_frame(Bytes::from_static(b"Bob"))
+            .await
+            .unwrap()
+    })
+    .await
+    .unwrap();
+    let reply = reading.await.unwrap();
+
+    assert_eq!(reply, "Hello Bob!");
+}
+
+#[tokio::test]
+async fn substream_can_be_used_through_tokio_io_traits() {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let mut bob_to_alice = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .compat();
+
+    bob_to_alice.write_u64(3).await.unwrap();
+    bob_to_alice.write_all(b"Bob").await.unwrap();
+    bob_to_alice.flush().await.unwrap();
+    let len = bob_to_alice.read_u64().await.unwrap();
+    let mut reply = vec![0u8; len as usize];
+    bob_to_alice.read_exact(&mut reply).await.unwrap();
+
+    assert_eq!(reply, b"Hello Bob!");
+}
+
+#[tokio::test]
+async fn opened_substreams_are_counted_until_reset() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let bob_to_alice = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    hello_world_dialer(bob_to_alice, "Bob").await.unwrap();
+
+    let stats = bob.send(ResetStats).await.unwrap();
+    assert_eq!(stats.substreams_outbound, 1);
+    assert!(stats.bytes_outbound > 0);
+
+    let stats = bob.send(SnapshotStats).await.unwrap();
+    assert_eq!(stats.substreams_outbound, 0);
+}
+
+#[tokio::test]
+async fn pool_reuses_released_substreams() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+    let mut pool = SubstreamPool::new(
+        bob.clone(),
+        "/hello-world/1.0.0",
+        1,
+        Duration::from_secs(60),
+    );
+
+    let stream = pool.get(alice_peer_id).await.unwrap();
+    pool.release(stream);
+    let _stream = pool.get(alice_peer_id).await.unwrap();
+
+    let stats = bob.send(SnapshotStats).await.unwrap();
+    assert_eq!(stats.substreams_outbound, 1);
+}
+
+#[tokio::test]
+async fn connect_and_open_dials_and_negotiates_substream() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let (_, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+
+    let stream = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/hello-world/1.0.0",
+        )
+        .await
+        .unwrap();
+    let string = hello_world_dialer(stream, "Bob").await.unwrap();
+
+    assert_eq!(string, "Hello Bob!");
+}
+
+#[tokio::test]
+async fn open_substream_builder_redials_and_falls_back() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let (_, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+
+    let (protocol, stream) = OpenSubstreamBuilder::new(alice_peer_id, "/hello-world/2.0.0")
+        .fallback("/hello-world/1.0.0")
+        .timeout(Duration::from_secs(5))
+        .retries(3, Duration::from_millis(10))
+        .redial(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+        )
+        .open(&bob)
+        .await
+        .unwrap();
+    let string = hello_world_dialer(stream, "Bob").await.unwrap();
+
+    assert_eq!(protocol, "/hello-world/1.0.0");
+    assert_eq!(string, "Hello Bob!");
+}
+
 #[tokio::test]
 async fn after_connect_see_each_other_as_connected() {
     let (alice_peer_id, bob_peer_id, alice, bob, _) = alice_and_bob([], []).await;
@@ -55,108 +293,2674 @@ async fn after_connect_see_each_other_as_connected() {
     assert_eq!(bob_stats.connected_peers, HashSet::from([alice_peer_id]));
 }
 
+#[tokio::test]
+async fn connection_timeline_covers_all_steps_of_dialer() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let stream = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+    let connection = bob_stats.connections.get(&stream.connection()).unwrap();
+
+    let steps = connection
+        .timeline
+        .steps()
+        .into_iter()
+        .map(|(step, _)| step)
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        steps,
+        vec![
+            "transport_connected",
+            "noise_completed",
+            "peer_verified",
+            "muxer_ready",
+            "first_substream"
+        ]
+    );
+}
+
 #[tokio::test]
 async fn disconnect_is_reflected_in_stats() {
     let (_, bob_peer_id, alice, bob, _) = alice_and_bob([], []).await;
 
     alice.send(Disconnect(bob_peer_id)).await.unwrap();
 
-    let alice_stats = alice.send(GetConnectionStats).await.unwrap();
-    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+    let alice_stats = alice.send(GetConnectionStats).await.unwrap();
+    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+
+    assert_eq!(alice_stats.connected_peers, HashSet::from([]));
+    assert_eq!(bob_stats.connected_peers, HashSet::from([]));
+}
+
+#[tokio::test]
+async fn peer_disconnected_follows_the_last_connection_closed() {
+    let (_, bob_peer_id, alice, _bob, _) = alice_and_bob([], []).await;
+    let (sender, receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    alice
+        .send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+
+    alice.send(Disconnect(bob_peer_id)).await.unwrap();
+
+    let events = receiver
+        .filter(|event| {
+            futures::future::ready(matches!(
+                event,
+                Event::ConnectionClosed { .. } | Event::PeerDisconnected { .. }
+            ))
+        })
+        .take(2)
+        .collect::<Vec<_>>()
+        .await;
+
+    assert!(matches!(events[0], Event::ConnectionClosed { peer, .. } if peer == bob_peer_id));
+    assert!(matches!(events[1], Event::PeerDisconnected { peer } if peer == bob_peer_id));
+}
+
+#[tokio::test]
+async fn applying_config_keeps_connections_and_reports_restart_fields() {
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob([], []).await;
+    let config = bob.send(GetConfig).await.unwrap();
+
+    let requires_restart = bob
+        .send(ApplyConfig(libp2p_xtra::NodeConfig {
+            connection_timeout: config.connection_timeout * 2,
+            max_connections_per_peer: 2,
+            ..config
+        }))
+        .await
+        .unwrap();
+
+    let applied = bob.send(GetConfig).await.unwrap();
+    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+
+    assert_eq!(requires_restart, vec!["connection_timeout"]);
+    assert_eq!(applied.connection_timeout, config.connection_timeout);
+    assert_eq!(applied.max_connections_per_peer, 2);
+    assert_eq!(bob_stats.connected_peers, HashSet::from([alice_peer_id]));
+}
+
+#[tokio::test]
+async fn drain_stops_listening_and_closes_connections_at_deadline() {
+    let (alice_peer_id, _, alice, bob, _) = alice_and_bob([], []).await;
+
+    alice
+        .send(Drain {
+            deadline: Instant::now() + Duration::from_millis(200),
+        })
+        .await
+        .unwrap();
+
+    let alice_stats = alice.send(GetConnectionStats).await.unwrap();
+    assert_eq!(alice_stats.listen_addresses, HashSet::from([]));
+    assert_eq!(alice_stats.connected_peers.len(), 1);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let alice_stats = alice.send(GetConnectionStats).await.unwrap();
+    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+    assert_eq!(alice_stats.connected_peers, HashSet::from([]));
+    assert!(!bob_stats.connected_peers.contains(&alice_peer_id));
+}
+
+#[tokio::test]
+async fn multiple_connections_to_same_peer_are_listed_in_stats() {
+    let (alice_peer_id, alice, bob) = alice_and_bob_with_two_connections().await;
+
+    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+    let alice_stats = alice.send(GetConnectionStats).await.unwrap();
+
+    assert_eq!(bob_stats.connected_peers, HashSet::from([alice_peer_id]));
+    assert_eq!(bob_stats.connections.len(), 2);
+    assert_eq!(alice_stats.connections.len(), 2);
+}
+
+#[tokio::test]
+async fn can_disconnect_single_connection() {
+    let (alice_peer_id, _alice, bob) = alice_and_bob_with_two_connections().await;
+
+    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+    let connection = *bob_stats.connections.keys().min().unwrap();
+
+    bob.send(DisconnectConnection {
+        peer: alice_peer_id,
+        connection,
+    })
+    .await
+    .unwrap();
+
+    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+    assert_eq!(bob_stats.connected_peers, HashSet::from([alice_peer_id]));
+    assert_eq!(bob_stats.connections.len(), 1);
+    assert!(!bob_stats.connections.contains_key(&connection));
+}
+
+#[tokio::test]
+async fn cannot_open_substream_for_unhandled_protocol() {
+    let (_, bob_peer_id, alice, _bob, _) = alice_and_bob([], []).await;
+
+    let error = alice
+        .send(OpenSubstream::single_protocol(
+            bob_peer_id,
+            "/foo/bar/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    assert!(matches!(
+        error.without_context(),
+        libp2p_xtra::Error::NegotiationFailed(libp2p_xtra::NegotiationError::Failed)
+    ));
+    assert!(!error.is_retryable());
+    assert!(matches!(
+        &error,
+        libp2p_xtra::Error::Contextual(context)
+            if context.peer == bob_peer_id && context.protocols == ["/foo/bar/1.0.0"]
+    ));
+    assert!(error
+        .to_string()
+        .ends_with(": Failed to negotiate protocol"));
+}
+
+#[tokio::test]
+async fn unsupported_inbound_substreams_are_counted_as_rejected() {
+    let (alice_peer_id, bob_peer_id, alice, bob, _) = alice_and_bob([], []).await;
+
+    let _ = alice
+        .send(OpenSubstream::single_protocol(
+            bob_peer_id,
+            "/foo/bar/1.0.0",
+        ))
+        .await
+        .unwrap();
+
+    // Bob counts the rejection once negotiation failed on his side, which may happen after Alice saw it fail.
+    let rejected = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let rejected = bob.send(GetRejectedSubstreams).await.unwrap();
+            if !rejected.is_empty() {
+                return rejected;
+            }
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .expect("rejection to be counted");
+
+    assert_eq!(
+        rejected,
+        vec![RejectedSubstreams {
+            peer: alice_peer_id,
+            protocol: None,
+            reason: RejectionReason::UnsupportedProtocol,
+            count: 1
+        }]
+    );
+}
+
+#[tokio::test]
+async fn cannot_connect_twice() {
+    let (alice_peer_id, _bob_peer_id, _alice, bob, alice_listen) = alice_and_bob([], []).await;
+
+    let error = bob
+        .send(Connect(
+            alice_listen.with(Protocol::P2p(alice_peer_id.into())),
+        ))
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        libp2p_xtra::Error::AlreadyConnected(twin) if twin == alice_peer_id
+    ))
+}
+
+#[tokio::test]
+async fn chooses_first_protocol_in_list_of_multiple() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let (actual_protocol, _) = bob
+        .send(OpenSubstream::multiple_protocols(
+            alice_peer_id,
+            vec![
+                "/hello-world/1.0.0",
+                "/foo-bar/1.0.0", // This is unsupported by Alice.
+            ],
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(actual_protocol, "/hello-world/1.0.0");
+}
+
+#[tokio::test]
+async fn falls_back_to_next_protocol_if_unsupported() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let (actual_protocol, _) = bob
+        .send(OpenSubstream::multiple_protocols(
+            alice_peer_id,
+            vec![
+                "/foo-bar/1.0.0", // This is unsupported by Alice.
+                "/hello-world/1.0.0",
+            ],
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(actual_protocol, "/hello-world/1.0.0");
+}
+
+#[tokio::test]
+async fn heartbeats_exchange_application_payloads() {
+    let alice_heartbeat_handler = Heartbeat::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(heartbeat::PROTOCOL, alice_heartbeat_handler.clone_channel())],
+        [],
+    )
+    .await;
+
+    let bob_to_alice = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            heartbeat::PROTOCOL,
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let (sender, mut receiver) = mpsc::unbounded();
+    tokio::spawn(heartbeat::run(
+        bob_to_alice,
+        Duration::from_millis(100),
+        3,
+        || b"bob".to_vec(),
+        move |payload| {
+            let _ = sender.unbounded_send(payload);
+        },
+    ));
+
+    let payload = receiver.next().await.unwrap();
+
+    assert_eq!(payload, b"alice");
+}
+
+#[tokio::test]
+async fn close_reasons_of_substreams_are_counted() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let mut graceful = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let orphaned = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+
+    graceful.close().await.unwrap();
+    drop(graceful);
+    bob.send(Disconnect(alice_peer_id)).await.unwrap();
+    drop(orphaned);
+
+    let mut closed = bob.send(GetClosedSubstreams).await.unwrap();
+    closed.sort_by_key(|closed| closed.reason != CloseReason::Graceful);
+
+    assert_eq!(
+        closed,
+        vec![
+            ClosedSubstreams {
+                peer: alice_peer_id,
+                protocol: "/hello-world/1.0.0",
+                reason: CloseReason::Graceful,
+                count: 1,
+            },
+            ClosedSubstreams {
+                peer: alice_peer_id,
+                protocol: "/hello-world/1.0.0",
+                reason: CloseReason::ConnectionClosed,
+                count: 1,
+            }
+        ]
+    );
+}
+
+#[tokio::test]
+async fn connection_events_are_ordered_around_inbound_substreams() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    let recorder = OrderRecorder { sender }.create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([("/hello-world/1.0.0", recorder.clone_channel())]);
+    let (_, bob) = make_node([]);
+    alice
+        .send(Subscribe(recorder.clone_channel()))
+        .await
+        .unwrap();
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    bob.send(Connect(
+        format!("/memory/{port}/p2p/{alice_peer_id}")
+            .parse()
+            .unwrap(),
+    ))
+    .await
+    .unwrap()
+    .unwrap();
+    let _substream = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    bob.send(Disconnect(alice_peer_id)).await.unwrap();
+
+    let order = tokio::time::timeout(
+        Duration::from_secs(10),
+        receiver.by_ref().take(3).collect::<Vec<_>>(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(order, vec!["established", "substream", "closed"]);
+}
+
+#[tokio::test]
+async fn node_is_ready_once_listening_and_connected_to_bootstrap_peers() {
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let port = rand::random::<u16>();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_listen_addresses([format!("/memory/{port}").parse().unwrap()])
+    .create(None)
+    .spawn_global();
+    alice.wait_until_ready().await.unwrap();
+
+    let bob = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_bootstrap_peers([format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse()
+        .unwrap()])
+    .create(None)
+    .spawn_global();
+    bob.wait_until_ready().await.unwrap();
+
+    let stats = bob.send(GetConnectionStats).await.unwrap();
+    assert!(stats.connected_peers.contains(&alice_peer_id));
+}
+
+#[tokio::test]
+async fn unreachable_bootstrap_peer_fails_startup() {
+    let port = rand::random::<u16>();
+    let node = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_bootstrap_peers([format!("/memory/{port}/p2p/{}", PeerId::random())
+        .parse()
+        .unwrap()])
+    .create(None)
+    .spawn_global();
+
+    let error = node.wait_until_ready().await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        libp2p_xtra::Error::ConnectFailed(DialErrorKind::Unreachable)
+    ));
+    assert_eq!(error.to_string(), "Failed to connect: remote unreachable");
+}
+
+#[tokio::test]
+async fn health_reflects_connected_peers_and_failed_dials() {
+    let thresholds = HealthThresholds {
+        target_peers: 1,
+        max_dial_failure_rate: 0.2,
+        ..HealthThresholds::default()
+    };
+    let node = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_health_thresholds(thresholds)
+    .create(None)
+    .spawn_global();
+
+    let report = node.send(GetHealth).await.unwrap();
+    assert_eq!(report.health, Health::Unhealthy);
+
+    let (alice_peer_id, alice) = make_node([]);
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    node.connect_and_open(
+        format!("/memory/{port}/p2p/{alice_peer_id}")
+            .parse()
+            .unwrap(),
+        "/unsupported/1.0.0",
+    )
+    .await
+    .unwrap_err();
+
+    let report = node.send(GetHealth).await.unwrap();
+    assert_eq!(report.health, Health::Healthy);
+    assert_eq!(report.connected_peers, 1);
+
+    let unreachable = rand::random::<u16>();
+    node.connect_and_open(
+        format!("/memory/{unreachable}/p2p/{}", PeerId::random())
+            .parse()
+            .unwrap(),
+        "/unsupported/1.0.0",
+    )
+    .await
+    .unwrap_err();
+
+    let report = node.send(GetHealth).await.unwrap();
+    assert_eq!(report.health, Health::Degraded);
+    assert_eq!(report.dial_failure_rate, 0.5);
+}
+
+#[tokio::test]
+async fn panicking_supervised_handler_keeps_connection_alive() {
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+    )
+    .with_supervised_handler("/panic/1.0.0", panicking_handler)
+    .create(None)
+    .spawn_global();
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    alice
+        .send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+    let (_, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let mut panicking = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/panic/1.0.0",
+        )
+        .await
+        .unwrap();
+
+    let mut buf = [0u8; 1];
+    let read = futures::AsyncReadExt::read(&mut panicking, &mut buf).await;
+    assert!(!matches!(read, Ok(1)));
+
+    let panicked = loop {
+        match receiver.next().await.unwrap() {
+            Event::HandlerPanicked { message, .. } => break message,
+            _ => continue,
+        }
+    };
+    assert_eq!(panicked, "boom");
+
+    let hello = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let string = hello_world_dialer(hello, "Bob").await.unwrap();
+    assert_eq!(string, "Hello Bob!");
+}
+
+#[tokio::test]
+async fn supervisor_marks_substreams_reopened_after_reconnect_as_resumed() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, alice_listen) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = OutboundSubstreamCollector { sender }
+        .create(None)
+        .spawn_global();
+    let _supervisor = ConnectionSupervisor::new(
+        bob.clone(),
+        alice_peer_id,
+        vec![alice_listen],
+        Duration::from_millis(50),
+        vec![("/hello-world/1.0.0", collector.clone_channel())],
+        vec![],
+    )
+    .create(None)
+    .spawn_global();
+
+    let first = receiver.next().await.unwrap();
+    assert!(!first.resumed);
+    bob.send(Disconnect(alice_peer_id)).await.unwrap();
+
+    let second = receiver.next().await.unwrap();
+    assert!(second.resumed);
+    let string = hello_world_dialer(second.stream, "Bob").await.unwrap();
+    assert_eq!(string, "Hello Bob!");
+}
+
+#[tokio::test]
+async fn outbox_flushes_buffered_messages_once_substream_is_up() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    let alice_frame_collector = FrameCollector {
+        sender,
+        tasks: Tasks::default(),
+    }
+    .create(None)
+    .spawn_global();
+    let (alice_peer_id, alice) =
+        make_node([("/outbox/1.0.0", alice_frame_collector.clone_channel())]);
+    let (_, bob) = make_node([]);
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+
+    let outbox = Outbox::new(2, OverflowPolicy::DropOldest)
+        .create(None)
+        .spawn_global();
+    for message in ["dropped", "first", "second"] {
+        outbox
+            .send(Enqueue(Bytes::from(message)))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    let _supervisor = ConnectionSupervisor::new(
+        bob,
+        alice_peer_id,
+        vec![format!("/memory/{port}").parse().unwrap()],
+        Duration::from_millis(50),
+        vec![("/outbox/1.0.0", outbox.clone_channel())],
+        vec![outbox.clone_channel()],
+    )
+    .create(None)
+    .spawn_global();
+
+    assert_eq!(receiver.next().await.unwrap(), Bytes::from("first"));
+    assert_eq!(receiver.next().await.unwrap(), Bytes::from("second"));
+
+    outbox
+        .send(Enqueue(Bytes::from("third")))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(receiver.next().await.unwrap(), Bytes::from("third"));
+}
+
+#[tokio::test]
+async fn deprecated_protocol_is_served_with_warning_event() {
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+    )
+    .with_deprecated_protocols(["/hello-world/1.0.0"])
+    .create(None)
+    .spawn_global();
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    alice
+        .send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+    let (bob_peer_id, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let stream = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/hello-world/1.0.0",
+        )
+        .await
+        .unwrap();
+
+    let string = hello_world_dialer(stream, "Bob").await.unwrap();
+    assert_eq!(string, "Hello Bob!");
+
+    let (peer, protocol) = loop {
+        if let Event::DeprecatedProtocolNegotiated { peer, protocol, .. } =
+            receiver.next().await.unwrap()
+        {
+            break (peer, protocol);
+        }
+    };
+    assert_eq!(peer, bob_peer_id);
+    assert_eq!(protocol, "/hello-world/1.0.0");
+}
+
+#[tokio::test]
+async fn failed_dial_emits_event_with_error_kind() {
+    let (_, node) = make_node([]);
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    node.send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+
+    let peer = PeerId::random();
+    let port = rand::random::<u16>();
+    node.send(Connect(
+        format!("/memory/{port}/p2p/{peer}").parse().unwrap(),
+    ))
+    .await
+    .unwrap()
+    .unwrap();
+
+    let event = receiver.next().await.unwrap();
+
+    assert!(matches!(
+        event,
+        Event::OutgoingConnectionError {
+            peer: failed,
+            error_kind: DialErrorKind::Unreachable,
+            ..
+        } if failed == peer
+    ))
+}
+
+#[cfg(feature = "capture")]
+#[tokio::test]
+async fn capture_records_plaintext_traffic_of_connections() {
+    use libp2p_xtra::{CaptureKind, CaptureRecord};
+
+    let capture = SharedBuffer::default();
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let bob = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_capture(capture.clone())
+    .create(None)
+    .spawn_global();
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let alice_address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+    let stream = bob
+        .connect_and_open(alice_address.clone(), "/hello-world/1.0.0")
+        .await
+        .unwrap();
+    let string = hello_world_dialer(stream, "Bob").await.unwrap();
+    assert_eq!(string, "Hello Bob!");
+
+    let capture = capture.0.lock().unwrap().clone();
+    let mut reader = capture.as_slice();
+    CaptureRecord::read_magic(&mut reader).unwrap();
+    let mut records = Vec::new();
+    while let Some(record) = CaptureRecord::read_from(&mut reader).unwrap() {
+        records.push(record);
+    }
+
+    assert_eq!(records[0].kind, CaptureKind::Opened { dialer: true });
+    assert_eq!(
+        Multiaddr::try_from(records[0].payload.clone()).unwrap(),
+        alice_address
+    );
+    let payload = |kind| {
+        records
+            .iter()
+            .filter(|record| record.kind == kind)
+            .flat_map(|record| record.payload.clone())
+            .collect::<Vec<_>>()
+    };
+    let contains = |haystack: &[u8], needle: &[u8]| {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    };
+    assert!(contains(
+        &payload(CaptureKind::Outbound),
+        b"/hello-world/1.0.0"
+    ));
+    assert!(contains(&payload(CaptureKind::Outbound), b"Bob"));
+    assert!(contains(&payload(CaptureKind::Inbound), b"Hello Bob!"));
+}
+
+#[tokio::test]
+async fn filtered_remote_is_dropped_before_handshake() {
+    let alice = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_address_filter(AddressFilter::default().deny("127.0.0.0/8".parse().unwrap()))
+    .create(None)
+    .spawn_global();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    alice.send(ListenOnSocket(listener)).await.unwrap().unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+
+    // An accepted connection would wait for our handshake, a filtered one is closed right away.
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .expect("connection to be closed");
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
+
+#[tokio::test]
+async fn reserved_inbound_slots_are_kept_for_priority_peers() {
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let carol_id = Keypair::generate_ed25519();
+    let carol_peer_id = carol_id.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_max_inbound_connections(1)
+    .with_reserved_inbound_slots(1, [carol_peer_id])
+    .create(None)
+    .spawn_global();
+    let (_, bob) = make_node([]);
+    let carol = Node::new(
+        MemoryTransport::default(),
+        carol_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .create(None)
+    .spawn_global();
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let alice_address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+
+    bob.send(Connect(alice_address.clone()))
+        .await
+        .unwrap()
+        .unwrap();
+    carol.send(Connect(alice_address)).await.unwrap().unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let alice_stats = alice.send(GetConnectionStats).await.unwrap();
+    assert_eq!(alice_stats.connected_peers, HashSet::from([carol_peer_id]));
+}
+
+#[tokio::test]
+async fn inbound_connections_beyond_the_limit_are_counted() {
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_max_inbound_connections(1)
+    .create(None)
+    .spawn_global();
+    let (bob_peer_id, bob) = make_node([]);
+    let (_, carol) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let alice_address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+
+    bob.send(Connect(alice_address.clone()))
+        .await
+        .unwrap()
+        .unwrap();
+    let _ = carol.send(Connect(alice_address)).await.unwrap();
+
+    let stats = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let stats = alice.send(SnapshotStats).await.unwrap();
+            if stats.rejected_inbound_connections > 0 {
+                return stats;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(stats.rejected_inbound_connections, 1);
+
+    let alice_stats = alice.send(GetConnectionStats).await.unwrap();
+    assert_eq!(alice_stats.connected_peers, HashSet::from([bob_peer_id]));
+}
+
+#[tokio::test]
+async fn substreams_are_delivered_through_mpsc_bridge() {
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let (sender, mut receiver) = mpsc::channel(1);
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_bridge("/hello-world/1.0.0", sender)
+    .create(None)
+    .spawn_global();
+    let (bob_peer_id, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let stream = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/hello-world/1.0.0",
+        )
+        .await
+        .unwrap();
+
+    let dialer = tokio::spawn(hello_world_dialer(stream, "Bob"));
+    let substream: NewInboundSubstream = receiver.next().await.unwrap();
+    assert_eq!(substream.peer, bob_peer_id);
+    hello_world_listener(substream.stream).await.unwrap();
+
+    assert_eq!(dialer.await.unwrap().unwrap(), "Hello Bob!");
+}
+
+#[tokio::test]
+async fn commands_are_served_from_a_channel() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let (_, bob) = make_node([]);
+    let (mut commands, receiver) = mpsc::channel(1);
+    tokio::spawn(serve_commands(bob, receiver));
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let (reply, stream) = oneshot::channel();
+    commands
+        .send(NodeCommand::ConnectAndOpen {
+            address: format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            protocol: "/hello-world/1.0.0",
+            reply,
+        })
+        .await
+        .unwrap();
+    let stream = stream.await.unwrap().unwrap();
+
+    let greeting = hello_world_dialer(stream, "Bob").await.unwrap();
+    assert_eq!(greeting, "Hello Bob!");
+}
+
+#[tokio::test]
+async fn client_mode_dials_but_does_not_listen() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let client = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_client_mode()
+    .create(None)
+    .spawn_global();
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let stream = client
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/hello-world/1.0.0",
+        )
+        .await
+        .unwrap();
+    let string = hello_world_dialer(stream, "Client").await.unwrap();
+    assert_eq!(string, "Hello Client!");
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let error = client
+        .send(ListenOnSocket(listener))
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert!(matches!(
+        error.into_inner().unwrap().downcast::<libp2p_xtra::Error>(),
+        Ok(error) if matches!(*error, libp2p_xtra::Error::ClientMode)
+    ));
+}
+
+#[tokio::test]
+async fn opening_substreams_beyond_memory_budget_fails() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let bob = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_memory_budget(SUBSTREAM_MEMORY_ESTIMATE)
+    .create(None)
+    .spawn_global();
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let _stream = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/hello-world/1.0.0",
+        )
+        .await
+        .unwrap();
+
+    let error = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert!(matches!(
+        error.without_context(),
+        libp2p_xtra::Error::MemoryBudgetExhausted(peer, _) if *peer == alice_peer_id
+    ));
+
+    let bob_stats = bob.send(GetConnectionStats).await.unwrap();
+    let memory = bob_stats.connections.values().next().unwrap().memory;
+    assert_eq!(memory.muxer, SUBSTREAM_MEMORY_ESTIMATE);
+    assert_eq!(memory.budget, Some(SUBSTREAM_MEMORY_ESTIMATE));
+}
+
+#[tokio::test]
+async fn legacy_noise_handshake_is_only_spoken_with_peers_accepting_it() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let alice_identity = Keypair::generate_ed25519();
+    let alice_peer_id = alice_identity.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_identity,
+        Duration::from_secs(20),
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+    )
+    .with_legacy_noise(LegacyNoise::Prefer)
+    .create(None)
+    .spawn_global();
+    let make_dialer = |legacy_noise| {
+        Node::new(
+            MemoryTransport::default(),
+            Keypair::generate_ed25519(),
+            Duration::from_secs(20),
+            [],
+        )
+        .with_legacy_noise(legacy_noise)
+        .create(None)
+        .spawn_global()
+    };
+    let upgraded = make_dialer(LegacyNoise::Reject);
+    let migrating = make_dialer(LegacyNoise::Accept);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+
+    let result = upgraded
+        .connect_and_open(address.clone(), "/hello-world/1.0.0")
+        .await;
+    assert!(result.is_err());
+
+    let stream = migrating
+        .connect_and_open(address, "/hello-world/1.0.0")
+        .await
+        .unwrap();
+    let string = hello_world_dialer(stream, "Migrating").await.unwrap();
+    assert_eq!(string, "Hello Migrating!");
+}
+
+#[tokio::test]
+async fn loopback_echoes_messages_up_to_maximum_size() {
+    let alice_loopback_handler = loopback::Loopback::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
+        [(loopback::PROTOCOL, alice_loopback_handler.clone_channel())],
+        [],
+    )
+    .await;
+
+    let round_trips = loopback::probe(
+        &bob,
+        alice_peer_id,
+        [0, 1, 1500, loopback::MAX_MESSAGE_SIZE],
+    )
+    .await
+    .unwrap();
+    assert_eq!(round_trips.len(), 4);
+
+    let error = loopback::probe(&bob, alice_peer_id, [loopback::MAX_MESSAGE_SIZE + 1])
+        .await
+        .unwrap_err();
+    assert!(format!("{error:#}").contains("exceeds maximum"));
+}
+
+#[tokio::test]
+async fn churn_does_not_leak_connections() {
+    use libp2p_xtra::churn::ChurnScenario;
+
+    let hello_world_handler = HelloWorld::default().create(None).spawn_global();
+
+    ChurnScenario::new(7)
+        .with_nodes(3)
+        .with_steps(20)
+        .with_settle_time(Duration::from_millis(50))
+        .run(
+            |identity| {
+                Node::new(
+                    MemoryTransport::default(),
+                    identity,
+                    Duration::from_secs(20),
+                    [("/hello-world/1.0.0", hello_world_handler.clone_channel())],
+                )
+            },
+            |nodes| {
+                let nodes = nodes.to_vec();
+
+                async move {
+                    for node in &nodes {
+                        let stats = node.node.send(GetConnectionStats).await?;
+
+                        for peer in stats.connected_peers {
+                            let stream = node
+                                .node
+                                .send(OpenSubstream::single_protocol(peer, "/hello-world/1.0.0"))
+                                .await??;
+                            let string = hello_world_dialer(stream, "Churn").await?;
+                            anyhow::ensure!(string == "Hello Churn!");
+                        }
+                    }
+
+                    Ok(())
+                }
+            },
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn substreams_are_spread_over_handlers_of_a_protocol() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    let workers = ["first", "second"]
+        .into_iter()
+        .map(|name| {
+            Tally {
+                name,
+                sender: sender.clone(),
+            }
+            .create(None)
+            .spawn_global()
+            .clone_channel()
+        })
+        .collect::<Vec<_>>();
+    let alice_identity = Keypair::generate_ed25519();
+    let alice_peer_id = alice_identity.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_identity,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_handlers("/tally/1.0.0", workers, DispatchStrategy::RoundRobin)
+    .create(None)
+    .spawn_global();
+    let (_, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+
+    let mut streams = Vec::new();
+    for _ in 0..4 {
+        streams.push(
+            bob.connect_and_open(address.clone(), "/tally/1.0.0")
+                .await
+                .unwrap(),
+        );
+    }
+
+    let mut names = Vec::new();
+    for _ in 0..4 {
+        names.push(receiver.next().await.unwrap());
+    }
+    names.sort_unstable();
+    assert_eq!(names, ["first", "first", "second", "second"]);
+}
+
+#[tokio::test]
+async fn worker_pool_queues_substreams_while_workers_are_at_their_limit() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    let release = Arc::new(Notify::new());
+    let workers = ["first", "second"]
+        .into_iter()
+        .map(|name| {
+            Gate {
+                name,
+                sender: sender.clone(),
+                release: release.clone(),
+            }
+            .create(None)
+            .spawn_global()
+            .clone_channel()
+        })
+        .collect::<Vec<_>>();
+    let pool = WorkerPool::new(workers, 1).create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) =
+        alice_and_bob([("/gate/1.0.0", pool.clone_channel())], []).await;
+
+    let mut streams = Vec::new();
+    for _ in 0..3 {
+        streams.push(
+            bob.send(OpenSubstream::single_protocol(alice_peer_id, "/gate/1.0.0"))
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+    }
+
+    let mut names = vec![
+        receiver.next().await.unwrap(),
+        receiver.next().await.unwrap(),
+    ];
+    names.sort_unstable();
+    assert_eq!(names, ["first", "second"]);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), receiver.next())
+            .await
+            .is_err(),
+        "third substream must wait for a worker"
+    );
+
+    release.notify_waiters();
+    assert!(receiver.next().await.is_some());
+}
+
+#[tokio::test]
+async fn worker_pool_resets_substreams_that_wait_too_long() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    let worker = Gate {
+        name: "only",
+        sender,
+        release: Arc::new(Notify::new()),
+    }
+    .create(None)
+    .spawn_global();
+    let pool = WorkerPool::new(vec![worker.clone_channel()], 1)
+        .with_max_queue_time(Duration::from_millis(100))
+        .create(None)
+        .spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) =
+        alice_and_bob([("/gate/1.0.0", pool.clone_channel())], []).await;
+
+    let _busy = bob
+        .send(OpenSubstream::single_protocol(alice_peer_id, "/gate/1.0.0"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(receiver.next().await.unwrap(), "only");
+    let mut queued = bob
+        .send(OpenSubstream::single_protocol(alice_peer_id, "/gate/1.0.0"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(
+        Duration::from_secs(5),
+        futures::AsyncReadExt::read(&mut queued, &mut buf),
+    )
+    .await
+    .expect("queued substream must be reset");
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
+
+#[tokio::test]
+async fn advertised_addresses_pass_through_filter() {
+    let identity = Keypair::generate_ed25519();
+    let peer_id = identity.public().to_peer_id();
+    let private_port = rand::random::<u64>();
+    let public_port = private_port.wrapping_add(1);
+    let node = Node::new(
+        MemoryTransport::default(),
+        identity,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_advertisement_filter(move |address| match address.iter().next() {
+        Some(Protocol::Memory(port)) if port == private_port => None,
+        _ => Some("/dns4/example.com/tcp/443".parse().unwrap()),
+    })
+    .create(None)
+    .spawn_global();
+
+    for port in [private_port, public_port] {
+        node.send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+            .await
+            .unwrap();
+    }
+
+    let advertised = node.send(GetAdvertisedAddresses).await.unwrap();
+
+    assert_eq!(
+        advertised,
+        [format!("/dns4/example.com/tcp/443/p2p/{peer_id}")
+            .parse::<Multiaddr>()
+            .unwrap()]
+    );
+}
+
+#[tokio::test]
+async fn external_addresses_are_advertised_if_a_listener_can_serve_them() {
+    let identity = Keypair::generate_ed25519();
+    let peer_id = identity.public().to_peer_id();
+    let port = rand::random::<u64>();
+    let node = Node::new(
+        MemoryTransport::default(),
+        identity,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_listen_addresses([format!("/memory/{port}").parse().unwrap()])
+    .with_external_addresses(["/memory/443".parse().unwrap()])
+    .create(None)
+    .spawn_global();
+    node.wait_until_ready().await.unwrap();
+
+    let advertised = node.send(GetAdvertisedAddresses).await.unwrap();
+    assert_eq!(
+        advertised,
+        [format!("/memory/443/p2p/{peer_id}")
+            .parse::<Multiaddr>()
+            .unwrap()]
+    );
+
+    let unservable = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_listen_addresses([format!("/memory/{}", port.wrapping_add(1)).parse().unwrap()])
+    .with_external_addresses(["/dns4/example.com/tcp/443".parse().unwrap()])
+    .create(None)
+    .spawn_global();
+
+    let error = unservable.wait_until_ready().await.unwrap_err();
+    assert!(matches!(
+        error,
+        libp2p_xtra::Error::UnservableExternalAddress(_)
+    ));
+}
+
+#[tokio::test]
+async fn external_addresses_are_advertised_once_a_listener_comes_up() {
+    let identity = Keypair::generate_ed25519();
+    let peer_id = identity.public().to_peer_id();
+    let external = format!("/memory/443/p2p/{peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+    let node = Node::new(
+        MemoryTransport::default(),
+        identity,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_external_addresses([external.clone()])
+    .create(None)
+    .spawn_global();
+    node.wait_until_ready().await.unwrap();
+
+    assert!(node.send(GetAdvertisedAddresses).await.unwrap().is_empty());
+
+    node.send(ListenOn(
+        format!("/memory/{}", rand::random::<u64>())
+            .parse()
+            .unwrap(),
+    ))
+    .await
+    .unwrap();
+
+    let advertised = node.send(GetAdvertisedAddresses).await.unwrap();
+    assert_eq!(advertised, [external], "no second /p2p suffix");
+}
+
+#[tokio::test]
+async fn peer_info_reports_protocol_usage_per_direction() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, bob_peer_id, alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+
+    let bob_to_alice = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    hello_world_dialer(bob_to_alice, "Bob").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let bob_info = bob.send(GetPeerInfo(alice_peer_id)).await.unwrap();
+    let alice_info = alice.send(GetPeerInfo(bob_peer_id)).await.unwrap();
+
+    assert_eq!(bob_info.connections.len(), 1);
+    let bob_usage = bob_info.protocols["/hello-world/1.0.0"];
+    let alice_usage = alice_info.protocols["/hello-world/1.0.0"];
+    assert_eq!(bob_usage.substreams_outbound, 1);
+    assert_eq!(bob_usage.substreams_inbound, 0);
+    assert_eq!(alice_usage.substreams_inbound, 1);
+    assert_eq!(bob_usage.bytes_outbound, alice_usage.bytes_inbound);
+    assert_eq!(bob_usage.bytes_inbound, alice_usage.bytes_outbound);
+    assert!(bob_usage.bytes_inbound > 0);
+}
+
+#[tokio::test]
+async fn substreams_beyond_quota_are_rejected() {
+    let (tally_sender, mut tally_receiver) = mpsc::unbounded();
+    let tally = Tally {
+        name: "tally",
+        sender: tally_sender,
+    }
+    .create(None)
+    .spawn_global();
+    let alice_identity = Keypair::generate_ed25519();
+    let alice_peer_id = alice_identity.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_identity,
+        Duration::from_secs(20),
+        [("/tally/1.0.0", tally.clone_channel())],
+    )
+    .with_quota(
+        "/tally/1.0.0",
+        Quota::new().with_substreams(1, Duration::from_secs(3600)),
+    )
+    .create(None)
+    .spawn_global();
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    alice
+        .send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+    let (bob_peer_id, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+    let _first = bob
+        .connect_and_open(address.clone(), "/tally/1.0.0")
+        .await
+        .unwrap();
+    let _second = bob.connect_and_open(address, "/tally/1.0.0").await.unwrap();
+
+    let (peer, kind) = loop {
+        if let Event::QuotaExceeded { peer, kind, .. } = receiver.next().await.unwrap() {
+            break (peer, kind);
+        }
+    };
+    assert_eq!(peer, bob_peer_id);
+    assert_eq!(kind, QuotaKind::Substreams);
+    assert_eq!(tally_receiver.next().await, Some("tally"));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), tally_receiver.next())
+            .await
+            .is_err(),
+        "second substream must not reach the handler"
+    );
+
+    let rejected = alice.send(GetRejectedSubstreams).await.unwrap();
+    assert!(rejected
+        .iter()
+        .any(|rejected| rejected.reason == RejectionReason::Quota && rejected.count == 1));
+}
+
+#[tokio::test]
+async fn substreams_exceeding_the_byte_quota_are_reset() {
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let (substreams, mut received) = mpsc::channel(1);
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_bridge("/upload/1.0.0", substreams)
+    .with_quota(
+        "/upload/1.0.0",
+        Quota::new().with_bytes(1024, Duration::from_secs(3600)),
+    )
+    .create(None)
+    .spawn_global();
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    alice
+        .send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+    let (bob_peer_id, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let mut upload = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/upload/1.0.0",
+        )
+        .await
+        .unwrap();
+    upload.write_all(&[0u8; 4096]).await.unwrap();
+    upload.flush().await.unwrap();
+
+    let mut substream = received.next().await.unwrap().stream;
+    let mut read = Vec::new();
+    let result = futures::AsyncReadExt::read_to_end(&mut substream, &mut read).await;
+    assert!(result.is_err());
+    assert!(read.len() < 4096);
+
+    let (peer, kind) = loop {
+        if let Event::QuotaExceeded { peer, kind, .. } = receiver.next().await.unwrap() {
+            break (peer, kind);
+        }
+    };
+    assert_eq!(peer, bob_peer_id);
+    assert_eq!(kind, QuotaKind::Bytes);
+
+    // Alice still holds the substream, so only a reset on the wire ends it for Bob.
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(
+        Duration::from_secs(5),
+        futures::AsyncReadExt::read(&mut upload, &mut buf),
+    )
+    .await
+    .expect("substream must be reset");
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
+
+#[tokio::test]
+async fn writes_to_peer_that_does_not_read_are_reported_as_blocked() {
+    let idle = Idle::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) =
+        alice_and_bob([("/idle/1.0.0", idle.clone_channel())], []).await;
+
+    let mut stream = bob
+        .send(OpenSubstream::single_protocol(alice_peer_id, "/idle/1.0.0"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stream.write_blocked_for(), None);
+
+    let update = vec![0u8; 1024 * 1024];
+    let result = tokio::time::timeout(Duration::from_millis(500), stream.write_all(&update)).await;
+
+    assert!(result.is_err(), "write must block on flow control");
+    assert!(stream.write_blocked_for().is_some());
+    assert!(stream.write_blocked_total() > Duration::ZERO);
+}
+
+#[tokio::test]
+async fn dropped_write_no_longer_counts_as_blocked() {
+    let idle = Idle::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) =
+        alice_and_bob([("/idle/1.0.0", idle.clone_channel())], []).await;
+
+    let mut stream = bob
+        .send(OpenSubstream::single_protocol(alice_peer_id, "/idle/1.0.0"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let update = vec![0u8; 1024 * 1024];
+    let result = tokio::time::timeout(Duration::from_millis(500), stream.write_all(&update)).await;
+    assert!(result.is_err(), "write must block on flow control");
+    assert!(stream.write_blocked_for().is_some());
+
+    tokio::time::timeout(Duration::from_secs(5), stream.flush())
+        .await
+        .expect("flush must not wait for the peer to read")
+        .unwrap();
+
+    assert_eq!(stream.write_blocked_for(), None);
+    let total = stream.write_blocked_total();
+    assert!(total >= Duration::from_millis(400));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(stream.write_blocked_total(), total);
+}
+
+#[tokio::test]
+async fn writes_to_peer_that_does_not_read_fail_after_stall_timeout() {
+    let idle = Idle::default().create(None).spawn_global();
+    let (alice_peer_id, _, _alice, bob, _) =
+        alice_and_bob([("/idle/1.0.0", idle.clone_channel())], []).await;
+
+    let mut stream = bob
+        .send(OpenSubstream::single_protocol(alice_peer_id, "/idle/1.0.0"))
+        .await
+        .unwrap()
+        .unwrap();
+    stream.set_write_stall_timeout(Some(Duration::from_millis(200)));
+
+    let update = vec![0u8; 1024 * 1024];
+    let error = tokio::time::timeout(Duration::from_secs(5), stream.write_all(&update))
+        .await
+        .expect("write must fail before the test times out")
+        .unwrap_err();
+
+    let stalled = WriteStalled::from_io_error(&error).expect("dedicated error");
+    assert!(stalled.blocked_for >= Duration::from_millis(200));
+
+    let error = stream.write_all(b"more").await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+}
+
+#[tokio::test]
+async fn broadcast_reaches_connected_peers_supporting_the_protocol() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    let alice_frame_collector = FrameCollector {
+        sender,
+        tasks: Tasks::default(),
+    }
+    .create(None)
+    .spawn_global();
+    let (alice_peer_id, _, alice, bob, _) = alice_and_bob(
+        [("/announce/1.0.0", alice_frame_collector.clone_channel())],
+        [],
+    )
+    .await;
+    let (carol_peer_id, carol) = make_node([]);
+    let port = rand::random::<u16>();
+    carol
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let carol_address = format!("/memory/{port}/p2p/{carol_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+    bob.connect_and_open(carol_address, "/announce/1.0.0")
+        .await
+        .expect_err("carol does not support the protocol");
+
+    let results = bob
+        .send(Broadcast::new(
+            "/announce/1.0.0",
+            Bytes::from_static(b"news"),
+        ))
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 2);
+    assert!(results[&alice_peer_id].is_ok());
+    assert!(results[&carol_peer_id].is_err());
+    assert_eq!(receiver.next().await.unwrap(), Bytes::from_static(b"news"));
+
+    let results = bob
+        .send(Broadcast::new("/announce/1.0.0", Bytes::from_static(b"more")).with_max_concurrent(1))
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+
+    assert!(results[&alice_peer_id].is_ok());
+    assert_eq!(receiver.next().await.unwrap(), Bytes::from_static(b"more"));
+    let stats = alice.send(SnapshotStats).await.unwrap();
+    assert_eq!(
+        stats.substreams_inbound, 1,
+        "second broadcast reuses the substream"
+    );
+}
+
+#[tokio::test]
+async fn peers_can_be_filtered_by_protocol_duration_and_direction() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, bob_peer_id, alice, bob, _) = alice_and_bob(
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+        [],
+    )
+    .await;
+    let bob_to_alice = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    hello_world_dialer(bob_to_alice, "Bob").await.unwrap();
+
+    let peers = |node: &Address<Node>, filter| {
+        let node = node.clone();
+
+        async move {
+            node.send(GetPeers { filter })
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|descriptor| descriptor.peer)
+                .collect::<Vec<_>>()
+        }
+    };
+
+    assert_eq!(
+        peers(&bob, PeerFilter::new().with_protocol("/hello-world/1.0.0")).await,
+        [alice_peer_id]
+    );
+    assert!(
+        peers(
+            &alice,
+            PeerFilter::new().with_protocol("/hello-world/1.0.0")
+        )
+        .await
+        .is_empty(),
+        "inbound substreams do not show that bob supports the protocol"
+    );
+    assert_eq!(
+        peers(&alice, PeerFilter::new().with_endpoint(Endpoint::Listener)).await,
+        [bob_peer_id]
+    );
+    assert!(
+        peers(&alice, PeerFilter::new().with_endpoint(Endpoint::Dialer))
+            .await
+            .is_empty()
+    );
+    assert!(peers(&bob, PeerFilter::new().with_protocol("/other/1.0.0"))
+        .await
+        .is_empty());
+    assert!(peers(
+        &alice,
+        PeerFilter::new().connected_longer_than(Duration::from_secs(3600))
+    )
+    .await
+    .is_empty());
+}
+
+#[tokio::test]
+async fn peer_samples_are_reproducible_with_seed() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+
+    let mut dialers = Vec::new();
+    for _ in 0..4 {
+        let (_, dialer) = make_node([]);
+        dialer
+            .connect_and_open(address.clone(), "/hello-world/1.0.0")
+            .await
+            .unwrap();
+        dialers.push(dialer);
+    }
+
+    let sample = |n, seed| {
+        let alice = alice.clone();
+
+        async move {
+            alice
+                .send(SamplePeers {
+                    n,
+                    filter: PeerFilter::new(),
+                    seed,
+                })
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|descriptor| descriptor.peer)
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let first = sample(2, Some(7)).await;
+    assert_eq!(first.len(), 2);
+    assert_eq!(sample(2, Some(7)).await, first);
+    assert_eq!(sample(10, None).await.len(), 4);
+}
+
+#[tokio::test]
+async fn listener_rejects_connections_without_common_protocol() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+
+    let dialer_with_intent = |protocols: Vec<&'static str>| {
+        Node::new(
+            MemoryTransport::default(),
+            Keypair::generate_ed25519(),
+            Duration::from_secs(20),
+            [],
+        )
+        .with_connection_intent(protocols)
+        .create(None)
+        .spawn_global()
+    };
+
+    let bob = dialer_with_intent(vec!["/other/1.0.0"]);
+    let error = bob
+        .connect_and_open(address.clone(), "/hello-world/1.0.0")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        libp2p_xtra::Error::ConnectFailed(DialErrorKind::NoCommonProtocol)
+    ));
+    let stats = alice.send(GetConnectionStats).await.unwrap();
+    assert!(stats.connected_peers.is_empty());
+    let snapshot = alice.send(SnapshotStats).await.unwrap();
+    assert_eq!(snapshot.rejected_connection_intents, 1);
+
+    bob.send(ConnectWithIntent {
+        address: address.clone(),
+        protocols: vec!["/hello-world/1.0.0"],
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    bob.connect_and_open(address.clone(), "/hello-world/1.0.0")
+        .await
+        .unwrap();
+
+    let carol = dialer_with_intent(vec!["/other/1.0.0", "/hello-world/1.0.0"]);
+    carol
+        .connect_and_open(address.clone(), "/hello-world/1.0.0")
+        .await
+        .unwrap();
+
+    let dave = dialer_with_intent(vec![]);
+    dave.connect_and_open(address, "/hello-world/1.0.0")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn reconnecting_from_a_different_host_emits_address_changed() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+    )
+    .with_max_connections_per_peer(2)
+    .create(None)
+    .spawn_global();
+    let bob = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_max_connections_per_peer(2)
+    .create(None)
+    .spawn_global();
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    bob.send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let local_addr = listener.local_addr().unwrap();
+    alice.send(ListenOnSocket(listener)).await.unwrap().unwrap();
+
+    // The remote address of an injected connection is taken as given, which lets us pretend that Alice moves between hosts.
+    let connect_from = |remote_address: &str| {
+        let bob = bob.clone();
+        let remote_address = remote_address.parse::<Multiaddr>().unwrap();
+
+        async move {
+            let stream = tokio::net::TcpStream::connect(local_addr).await.unwrap();
+            let registered = bob
+                .send(InjectConnection {
+                    io: Box::new(Compat::new(stream)),
+                    role: Endpoint::Dialer,
+                    expected_peer: Some(alice_peer_id),
+                    remote_address: Some(remote_address),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            registered.await.unwrap().unwrap();
+        }
+    };
+
+    connect_from("/ip4/127.0.0.1/tcp/4001").await;
+    bob.send(Disconnect::peer(alice_peer_id)).await.unwrap();
+
+    // A new port alone does not count, nor does a concurrent connection from another host.
+    connect_from("/ip4/127.0.0.1/tcp/4002").await;
+    connect_from("/ip4/10.0.0.2/tcp/4001").await;
+    bob.send(Disconnect::peer(alice_peer_id)).await.unwrap();
+
+    connect_from("/ip4/10.0.0.1/tcp/4001").await;
+
+    let (peer, old, new) = loop {
+        if let Event::AddressChanged { peer, old, new } = receiver.next().await.unwrap() {
+            break (peer, old, new);
+        }
+    };
+    assert_eq!(peer, alice_peer_id);
+    assert_eq!(old, "/ip4/127.0.0.1/tcp/4002".parse::<Multiaddr>().unwrap());
+    assert_eq!(new, "/ip4/10.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap());
+}
+
+#[tokio::test]
+async fn recorded_connection_replays_through_node() {
+    use libp2p_xtra::{Record, Replay};
+
+    let directory = std::env::temp_dir().join(format!("replay-{:016x}", rand::random::<u64>()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [(
+            "/hello-world/1.0.0",
+            alice_hello_world_handler.clone_channel(),
+        )],
+    )
+    .with_recording(Record::new(&directory))
+    .create(None)
+    .spawn_global();
+    let (_, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let stream = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/hello-world/1.0.0",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        hello_world_dialer(stream, "Bob").await.unwrap(),
+        "Hello Bob!"
+    );
+
+    // The recording is written in the background.
+    let recording = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(entry) = std::fs::read_dir(&directory).unwrap().next() {
+                let path = entry.unwrap().path();
+                let bytes = std::fs::read(&path).unwrap();
+                if bytes.windows(3).any(|window| window == b"Bob") {
+                    return path;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    let (sender, mut receiver) = mpsc::channel(1);
+    let carol = Node::new(
+        Replay::from_file(recording).unwrap(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_bridge("/hello-world/1.0.0", sender)
+    .create(None)
+    .spawn_global();
+    carol
+        .send(ListenOn("/memory/0".parse().unwrap()))
+        .await
+        .unwrap();
+
+    let substream: NewInboundSubstream = receiver.next().await.unwrap();
+    let mut stream =
+        asynchronous_codec::Framed::new(substream.stream, asynchronous_codec::LengthCodec);
+    let name = stream.next().await.unwrap().unwrap();
+    assert_eq!(name, Bytes::from("Bob"));
+}
+
+#[tokio::test]
+async fn agent_versions_are_exchanged_on_connect() {
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_agent_version("alice/1.0.0")
+    .create(None)
+    .spawn_global();
+    let bob = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_agent_version("bob/2.0.0")
+    .create(None)
+    .spawn_global();
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    bob.send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    bob.send(Connect(
+        format!("/memory/{port}/p2p/{alice_peer_id}")
+            .parse()
+            .unwrap(),
+    ))
+    .await
+    .unwrap()
+    .unwrap();
+
+    let (peer, connection, agent_version) = loop {
+        if let Event::AgentVersionReceived {
+            peer,
+            connection,
+            agent_version,
+        } = receiver.next().await.unwrap()
+        {
+            break (peer, connection, agent_version);
+        }
+    };
+    assert_eq!(peer, alice_peer_id);
+    assert_eq!(agent_version, "alice/1.0.0");
+
+    let stats = bob.send(GetConnectionStats).await.unwrap();
+    assert_eq!(
+        stats.connections[&connection].agent_version.as_deref(),
+        Some("alice/1.0.0")
+    );
+}
+
+#[tokio::test]
+async fn rotated_identity_is_used_for_new_connections() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let (_, bob) = make_node([]);
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    alice
+        .send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+
+    let new_identity = Keypair::generate_ed25519();
+    let new_peer_id = alice
+        .send(RotateIdentity(new_identity.clone()))
+        .await
+        .unwrap();
+    assert_eq!(new_peer_id, new_identity.public().to_peer_id());
+
+    let (old, new) = loop {
+        if let Event::IdentityRotated { old, new } = receiver.next().await.unwrap() {
+            break (old, new);
+        }
+    };
+    assert_eq!(old, alice_peer_id);
+    assert_eq!(new, new_peer_id);
+
+    // The listener is restarted on the same address under the new identity.
+    let stream = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{new_peer_id}").parse().unwrap(),
+            "/hello-world/1.0.0",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        hello_world_dialer(stream, "Bob").await.unwrap(),
+        "Hello Bob!"
+    );
+}
+
+#[tokio::test]
+async fn substreams_not_used_within_grace_period_are_reset() {
+    let idle = Idle::default().create(None).spawn_global();
+    let alice_identity = Keypair::generate_ed25519();
+    let alice_peer_id = alice_identity.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_identity,
+        Duration::from_secs(20),
+        [("/idle/1.0.0", idle.clone_channel())],
+    )
+    .with_handler_grace_period(Duration::from_millis(200))
+    .create(None)
+    .spawn_global();
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    alice
+        .send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+    let (_, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let mut stream = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/idle/1.0.0",
+        )
+        .await
+        .unwrap();
+
+    // The handler receives the substream right away but never uses it.
+    let protocol = loop {
+        if let Event::InboundSubstreamHandlerTimeout { protocol, .. } =
+            receiver.next().await.unwrap()
+        {
+            break protocol;
+        }
+    };
+    assert_eq!(protocol, "/idle/1.0.0");
+
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(
+        Duration::from_secs(5),
+        futures::AsyncReadExt::read(&mut stream, &mut buf),
+    )
+    .await
+    .expect("substream must be reset");
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
+
+#[tokio::test]
+async fn substreams_for_panicked_actor_are_rejected_as_handler_gone() {
+    let panicking = Panicking.create(None).spawn_global();
+    let (alice_peer_id, _, alice, bob, _) =
+        alice_and_bob([("/panic/1.0.0", panicking.clone_channel())], []).await;
+
+    for _ in 0..2 {
+        let mut stream = bob
+            .send(OpenSubstream::single_protocol(
+                alice_peer_id,
+                "/panic/1.0.0",
+            ))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut buf = [0u8; 1];
+        let read = futures::AsyncReadExt::read(&mut stream, &mut buf).await;
+        assert!(!matches!(read, Ok(1)));
+    }
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let rejected = alice.send(GetRejectedSubstreams).await.unwrap();
+            if rejected
+                .iter()
+                .any(|rejected| rejected.reason == RejectionReason::HandlerGone)
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("second substream must be rejected");
+}
+
+#[tokio::test]
+async fn injected_connection_reports_registration_and_rejects_duplicates() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let (bob_peer_id, bob) = make_node([]);
+
+    let (alice_io, bob_io) = memory_pipe().await;
+    let alice_registered = inject(&alice, alice_io, Endpoint::Listener).await;
+    let bob_registered = inject(&bob, bob_io, Endpoint::Dialer).await;
+    assert_eq!(alice_registered.await.unwrap().unwrap(), bob_peer_id);
+    assert_eq!(bob_registered.await.unwrap().unwrap(), alice_peer_id);
+
+    let (alice_io, bob_io) = memory_pipe().await;
+    let alice_registered = inject(&alice, alice_io, Endpoint::Listener).await;
+    let _bob_registered = inject(&bob, bob_io, Endpoint::Dialer).await;
+    assert!(matches!(
+        alice_registered.await.unwrap(),
+        Err(libp2p_xtra::Error::AlreadyConnected(peer)) if peer == bob_peer_id
+    ));
+
+    let stream = bob
+        .send(OpenSubstream::single_protocol(
+            alice_peer_id,
+            "/hello-world/1.0.0",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let string = hello_world_dialer(stream, "Bob").await.unwrap();
+    assert_eq!(string, "Hello Bob!");
+}
+
+#[tokio::test]
+async fn substreams_of_a_dropped_inbound_stream_are_reset() {
+    let alice_id = Keypair::generate_ed25519();
+    let bob_id = Keypair::generate_ed25519();
+    let (alice_io, bob_io) = memory_pipe().await;
+
+    let (alice, bob) = futures::future::join(
+        libp2p_xtra::upgrade_connection(
+            alice_io,
+            Endpoint::Listener,
+            &alice_id,
+            None,
+            vec!["/hello-world/1.0.0"],
+            Duration::from_secs(20),
+        ),
+        libp2p_xtra::upgrade_connection(
+            bob_io,
+            Endpoint::Dialer,
+            &bob_id,
+            None,
+            vec![],
+            Duration::from_secs(20),
+        ),
+    )
+    .await;
+    let (_, _alice_control, alice_incoming, alice_driver, _) = alice.unwrap();
+    let (_, mut bob_control, _bob_incoming, bob_driver, _) = bob.unwrap();
+    tokio::spawn(alice_driver);
+    tokio::spawn(bob_driver);
+    drop(alice_incoming);
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        bob_control.open_substream(vec!["/hello-world/1.0.0"]),
+    )
+    .await
+    .expect("substream to be reset rather than left pending");
 
-    assert_eq!(alice_stats.connected_peers, HashSet::from([]));
-    assert_eq!(bob_stats.connected_peers, HashSet::from([]));
+    assert!(matches!(
+        result,
+        Ok(Err(
+            libp2p_xtra::SubstreamNegotiationError::NegotiationFailed(_)
+        )) | Err(_)
+    ));
 }
 
 #[tokio::test]
-async fn cannot_open_substream_for_unhandled_protocol() {
-    let (_, bob_peer_id, alice, _bob, _) = alice_and_bob([], []).await;
+async fn accepts_connections_on_passed_in_socket() {
+    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
+    let (alice_peer_id, alice) = make_node([(
+        "/hello-world/1.0.0",
+        alice_hello_world_handler.clone_channel(),
+    )]);
+    let (_, bob) = make_node([]);
 
-    let error = alice
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let local_addr = listener.local_addr().unwrap();
+    let address = alice.send(ListenOnSocket(listener)).await.unwrap().unwrap();
+    assert_eq!(
+        address,
+        format!("/ip4/127.0.0.1/tcp/{}", local_addr.port())
+            .parse::<Multiaddr>()
+            .unwrap()
+    );
+
+    let stream = tokio::net::TcpStream::connect(local_addr).await.unwrap();
+    let registered = bob
+        .send(InjectConnection {
+            io: Box::new(Compat::new(stream)),
+            role: Endpoint::Dialer,
+            expected_peer: Some(alice_peer_id),
+            remote_address: Some(address),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(registered.await.unwrap().unwrap(), alice_peer_id);
+
+    let stream = bob
         .send(OpenSubstream::single_protocol(
-            bob_peer_id,
-            "/foo/bar/1.0.0",
+            alice_peer_id,
+            "/hello-world/1.0.0",
         ))
         .await
         .unwrap()
-        .unwrap_err();
-
-    assert!(matches!(
-        error,
-        libp2p_xtra::Error::NegotiationFailed(libp2p_xtra::NegotiationError::Failed)
-    ))
+        .unwrap();
+    let string = hello_world_dialer(stream, "Bob").await.unwrap();
+    assert_eq!(string, "Hello Bob!");
 }
 
+#[cfg(feature = "json-rpc")]
 #[tokio::test]
-async fn cannot_connect_twice() {
-    let (alice_peer_id, _bob_peer_id, _alice, bob, alice_listen) = alice_and_bob([], []).await;
+async fn json_rpc_calls_are_answered_over_a_substream() {
+    use libp2p_xtra::json_rpc;
 
-    let error = bob
-        .send(Connect(
-            alice_listen.with(Protocol::P2p(alice_peer_id.into())),
-        ))
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl json_rpc::Handler for Echo {
+        async fn call(
+            &self,
+            _: String,
+            params: serde_json::Value,
+        ) -> Result<serde_json::Value, json_rpc::RpcError> {
+            Ok(params)
+        }
+    }
+
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_supervised_handler("/json-rpc/1.0.0", |substream| {
+        json_rpc::serve(substream.stream, Echo)
+    })
+    .create(None)
+    .spawn_global();
+    let (_, bob) = make_node([]);
+
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
         .await
-        .unwrap()
-        .unwrap_err();
+        .unwrap();
+    let stream = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/json-rpc/1.0.0",
+        )
+        .await
+        .unwrap();
+    let client = json_rpc::Client::new(stream);
 
-    assert!(matches!(
-        error,
-        libp2p_xtra::Error::AlreadyConnected(twin) if twin == alice_peer_id
-    ))
+    let calls = (0..json_rpc::MAX_CONCURRENT_REQUESTS * 2)
+        .map(|i| client.call::<_, usize>("echo", i))
+        .collect::<Vec<_>>();
+    let results = futures::future::join_all(calls).await;
+
+    for (i, result) in results.into_iter().enumerate() {
+        assert_eq!(result.unwrap(), i);
+    }
 }
 
 #[tokio::test]
-async fn chooses_first_protocol_in_list_of_multiple() {
-    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
-    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
-        [(
-            "/hello-world/1.0.0",
-            alice_hello_world_handler.clone_channel(),
-        )],
+async fn fair_scheduling_is_not_blocked_by_substream_the_peer_does_not_read() {
+    let idle = Idle::default().create(None).spawn_global();
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [("/idle/1.0.0", idle.clone_channel())],
+    )
+    .with_supervised_handler("/sink/1.0.0", |substream| async move {
+        futures::io::copy(substream.stream, &mut futures::io::sink()).await?;
+
+        Ok(())
+    })
+    .create(None)
+    .spawn_global();
+    let bob = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
         [],
     )
-    .await;
+    .with_fair_scheduling([])
+    .create(None)
+    .spawn_global();
 
-    let (actual_protocol, _) = bob
-        .send(OpenSubstream::multiple_protocols(
-            alice_peer_id,
-            vec![
-                "/hello-world/1.0.0",
-                "/foo-bar/1.0.0", // This is unsupported by Alice.
-            ],
-        ))
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let mut stuck = bob
+        .connect_and_open(
+            format!("/memory/{port}/p2p/{alice_peer_id}")
+                .parse()
+                .unwrap(),
+            "/idle/1.0.0",
+        )
+        .await
+        .unwrap();
+    let _stuck = tokio::spawn(async move {
+        // Far more than the receive window of the substream, so the write gets stuck in the muxer.
+        let _ = stuck.write_all(&vec![0u8; 4 * 1024 * 1024]).await;
+    });
+    let mut active = bob
+        .send(OpenSubstream::single_protocol(alice_peer_id, "/sink/1.0.0"))
         .await
         .unwrap()
         .unwrap();
 
-    assert_eq!(actual_protocol, "/hello-world/1.0.0");
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        active.write_all(&vec![1u8; 1024 * 1024]),
+    )
+    .await
+    .expect("active substream not to be held back by the stuck one")
+    .unwrap();
 }
 
-#[tokio::test]
-async fn falls_back_to_next_protocol_if_unsupported() {
-    let alice_hello_world_handler = HelloWorld::default().create(None).spawn_global();
-    let (alice_peer_id, _, _alice, bob, _) = alice_and_bob(
-        [(
-            "/hello-world/1.0.0",
-            alice_hello_world_handler.clone_channel(),
-        )],
-        [],
+#[tokio::test(flavor = "multi_thread")]
+async fn substreams_held_up_by_a_handler_that_never_accepts_are_reported_as_stalled() {
+    let release = Arc::new(Semaphore::new(0));
+    // With a mailbox of one, dispatching blocks once the handler holds one substream and another one waits in its mailbox.
+    let stuck = Stuck {
+        release: release.clone(),
+    }
+    .create(Some(1))
+    .spawn_global();
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [("/stuck/1.0.0", stuck.clone_channel())],
     )
-    .await;
+    .with_inbound_stall_detection(Duration::from_millis(200))
+    .create(None)
+    .spawn_global();
+    let (sender, mut receiver) = mpsc::unbounded();
+    let collector = EventCollector { sender }.create(None).spawn_global();
+    alice
+        .send(Subscribe(collector.clone_channel()))
+        .await
+        .unwrap();
+    let (bob_peer_id, bob) = make_node([]);
 
-    let (actual_protocol, _) = bob
-        .send(OpenSubstream::multiple_protocols(
-            alice_peer_id,
-            vec![
-                "/foo-bar/1.0.0", // This is unsupported by Alice.
-                "/hello-world/1.0.0",
-            ],
-        ))
+    let port = rand::random::<u16>();
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
         .await
-        .unwrap()
         .unwrap();
+    bob.send(Connect(
+        format!("/memory/{port}/p2p/{alice_peer_id}")
+            .parse()
+            .unwrap(),
+    ))
+    .await
+    .unwrap()
+    .unwrap();
+    for _ in 0..5 {
+        let bob = bob.clone();
+        tokio::spawn(async move {
+            let _ = bob
+                .send(OpenSubstream::single_protocol(
+                    alice_peer_id,
+                    "/stuck/1.0.0",
+                ))
+                .await;
+        });
+    }
 
-    assert_eq!(actual_protocol, "/hello-world/1.0.0");
+    let stalled = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match receiver.next().await.unwrap() {
+                Event::InboundSubstreamsStalled { peer, waiting, .. } => return (peer, waiting),
+                _ => continue,
+            }
+        }
+    })
+    .await;
+    // Unblocks the dispatch of the connection, so the runtime can shut down.
+    release.close();
+
+    let (peer, waiting) = stalled.expect("stall to be reported");
+    assert_eq!(peer, bob_peer_id);
+    assert!(waiting >= Duration::from_millis(200));
 }
 
 async fn alice_and_bob<const AN: usize, const BN: usize>(
@@ -190,6 +2994,49 @@ async fn alice_and_bob<const AN: usize, const BN: usize>(
     (alice_peer_id, bob_peer_id, alice, bob, alice_listen)
 }
 
+async fn alice_and_bob_with_two_connections() -> (PeerId, Address<Node>, Address<Node>) {
+    let port = rand::random::<u16>();
+    let alice_id = Keypair::generate_ed25519();
+    let alice_peer_id = alice_id.public().to_peer_id();
+
+    let alice = Node::new(
+        MemoryTransport::default(),
+        alice_id,
+        Duration::from_secs(20),
+        [],
+    )
+    .with_max_connections_per_peer(2)
+    .create(None)
+    .spawn_global();
+    let bob = Node::new(
+        MemoryTransport::default(),
+        Keypair::generate_ed25519(),
+        Duration::from_secs(20),
+        [],
+    )
+    .with_max_connections_per_peer(2)
+    .create(None)
+    .spawn_global();
+
+    alice
+        .send(ListenOn(format!("/memory/{port}").parse().unwrap()))
+        .await
+        .unwrap();
+    let alice_address = format!("/memory/{port}/p2p/{alice_peer_id}")
+        .parse::<Multiaddr>()
+        .unwrap();
+
+    for _ in 0..2 {
+        bob.send(Connect(alice_address.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    (alice_peer_id, alice, bob)
+}
+
 fn make_node<const N: usize>(
     substream_handlers: [(
         &'static str,
@@ -230,6 +3077,219 @@ impl HelloWorld {
 
 impl xtra::Actor for HelloWorld {}
 
+struct Tally {
+    name: &'static str,
+    sender: mpsc::UnboundedSender<&'static str>,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl Tally {
+    async fn handle(&mut self, _: NewInboundSubstream) {
+        let _ = self.sender.unbounded_send(self.name);
+    }
+}
+
+impl xtra::Actor for Tally {}
+
+struct Gate {
+    name: &'static str,
+    sender: mpsc::UnboundedSender<&'static str>,
+    release: Arc<Notify>,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl Gate {
+    async fn handle(&mut self, _: NewInboundSubstream) {
+        let released = self.release.notified();
+        let _ = self.sender.unbounded_send(self.name);
+        released.await;
+    }
+}
+
+impl xtra::Actor for Gate {}
+
+/// Keeps inbound substreams open without ever reading from them.
+#[derive(Default)]
+struct Idle {
+    substreams: Vec<NewInboundSubstream>,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl Idle {
+    async fn handle(&mut self, msg: NewInboundSubstream) {
+        self.substreams.push(msg);
+    }
+}
+
+impl xtra::Actor for Idle {}
+
+/// Holds on to every inbound substream until released.
+struct Stuck {
+    release: Arc<Semaphore>,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl Stuck {
+    async fn handle(&mut self, _: NewInboundSubstream) {
+        let _ = self.release.acquire().await;
+    }
+}
+
+impl xtra::Actor for Stuck {}
+
+#[derive(Default)]
+struct Heartbeat {
+    tasks: Tasks,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl Heartbeat {
+    async fn handle(&mut self, msg: NewInboundSubstream) {
+        self.tasks.add_fallible(
+            heartbeat::run(
+                msg.stream,
+                Duration::from_millis(100),
+                3,
+                || b"alice".to_vec(),
+                |_| {},
+            ),
+            move |e| async move {
+                tracing::warn!("Heartbeat with peer {} failed: {:#}", msg.peer, e);
+            },
+        );
+    }
+}
+
+impl xtra::Actor for Heartbeat {}
+
+struct EventCollector {
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl EventCollector {
+    async fn handle(&mut self, msg: Event) {
+        let _ = self.sender.unbounded_send(msg);
+    }
+}
+
+impl xtra::Actor for EventCollector {}
+
+struct OrderRecorder {
+    sender: mpsc::UnboundedSender<&'static str>,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl OrderRecorder {
+    async fn handle(&mut self, msg: Event) {
+        let label = match msg {
+            Event::ConnectionEstablished { .. } => "established",
+            Event::ConnectionClosed { .. } => "closed",
+            _ => return,
+        };
+        let _ = self.sender.unbounded_send(label);
+    }
+
+    async fn handle(&mut self, _: NewInboundSubstream) {
+        let _ = self.sender.unbounded_send("substream");
+    }
+}
+
+impl xtra::Actor for OrderRecorder {}
+
+struct OutboundSubstreamCollector {
+    sender: mpsc::UnboundedSender<NewOutboundSubstream>,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl OutboundSubstreamCollector {
+    async fn handle(&mut self, msg: NewOutboundSubstream) {
+        let _ = self.sender.unbounded_send(msg);
+    }
+}
+
+impl xtra::Actor for OutboundSubstreamCollector {}
+
+struct FrameCollector {
+    sender: mpsc::UnboundedSender<Bytes>,
+    tasks: Tasks,
+}
+
+#[xtra_productivity(message_impl = false)]
+impl FrameCollector {
+    async fn handle(&mut self, msg: NewInboundSubstream) {
+        let sender = self.sender.clone();
+
+        self.tasks.add_fallible(
+            async move {
+                let mut stream = LengthDelimited::new(msg.stream);
+
+                while let Some(frame) = stream.next_frame().await? {
+                    let _ = sender.unbounded_send(frame);
+                }
+
+                anyhow::Ok(())
+            },
+            |e| async move { tracing::warn!("Failed to read frame: {:#}", e) },
+        );
+    }
+}
+
+impl xtra::Actor for FrameCollector {}
+
+struct Panicking;
+
+#[xtra_productivity(message_impl = false)]
+impl Panicking {
+    async fn handle(&mut self, _: NewInboundSubstream) {
+        panic!("boom")
+    }
+}
+
+impl xtra::Actor for Panicking {}
+
+async fn panicking_handler(_: NewInboundSubstream) -> Result<()> {
+    panic!("boom")
+}
+
+async fn inject(
+    node: &Address<Node>,
+    io: Channel<Vec<u8>>,
+    role: Endpoint,
+) -> futures::channel::oneshot::Receiver<Result<PeerId, libp2p_xtra::Error>> {
+    node.send(InjectConnection {
+        io: Box::new(io),
+        role,
+        expected_peer: None,
+        remote_address: None,
+    })
+    .await
+    .unwrap()
+    .unwrap()
+}
+
+/// Connects two in-memory sockets, e.g. for [`InjectConnection`].
+async fn memory_pipe() -> (Channel<Vec<u8>>, Channel<Vec<u8>>) {
+    let mut listener = MemoryTransport
+        .listen_on(Protocol::Memory(0).into())
+        .unwrap();
+    let address = match listener.next().await {
+        Some(Ok(ListenerEvent::NewAddress(address))) => address,
+        _ => panic!("Memory listener did not report its address"),
+    };
+    let dial = MemoryTransport.dial(address).unwrap();
+    let accept = async {
+        match listener.next().await {
+            Some(Ok(ListenerEvent::Upgrade { upgrade, .. })) => upgrade.await.unwrap(),
+            _ => panic!("Memory listener closed"),
+        }
+    };
+
+    let (dialer, listener) = futures::future::join(dial, accept).await;
+
+    (dialer.unwrap(), listener)
+}
+
 async fn hello_world_dialer(stream: libp2p_xtra::Substream, name: &'static str) -> Result<String> {
     let mut stream = asynchronous_codec::Framed::new(stream, asynchronous_codec::LengthCodec);
 
@@ -251,3 +3311,20 @@ async fn hello_world_listener(stream: libp2p_xtra::Substream) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(feature = "capture")]
+#[derive(Clone, Default)]
+struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(feature = "capture")]
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}